@@ -1,5 +1,5 @@
 //! socket公用的常量标记
-use std::time::Duration;
+use std::{fmt::Display, time::Duration};
 use tokio::{
     io::{self, AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
@@ -12,6 +12,8 @@ pub const EMPTY_INPUT: &str = "EMPTY INPUT";
 pub const EXIT_MSG: &str = "EXIT";
 pub const INPUT_FILE_CONTENT: &str = "INPUT FILE CONTENT";
 pub const COMMAND_CONFIRM: &str = "COMMAND CONFIRM";
+pub const FORMAT_CONFIRM: &str = "FORMAT CONFIRM";
+pub const FORMAT_CONFIRM_TOKEN: &str = "YES";
 pub const COMMAND_FINISHED: &str = "COMMAND OK";
 pub const LOGIN_SUCCESS: &str = "LOGIN_SUCCESS";
 pub const REGIST_SUCCESS: &str = "REGIST SUCCESS";
@@ -19,10 +21,34 @@ pub const RECEIVE_CONTENTS: &str = "RECEIVE CONTENTS";
 pub const READY_RECEIVE_CONTENTS: &str = "READY!";
 pub const HELP_REQUEST: &str = "HELP";
 pub const ERROR_MESSAGE_PREFIX: &str = "ErrMsg:";
+pub const REQUEST_ID_PREFIX: &str = "REQ#";
+pub const TOO_MANY_CONNECTIONS: &str = "server is busy: too many connections";
 pub const SOCKET_BUFFER_SIZE: usize = 128;
+pub const RESYNC_TIMEOUT: u64 = 3; // 两端不同步时等待server结束当前命令的时间（秒）
+
+/// 连接所处的协议状态，两端在状态转换时以debug级别输出日志，便于排查两端不同步的问题
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolState {
+    AwaitLogin,       // 0. 等待登录或注册
+    AwaitCommand,     // 2.1 等待输入或接收命令
+    ExecutingCommand, // 2.2 server执行命令，client等待应答
+    AwaitInput,       // 2.ex client等待用户输入文件内容或确认
+    AwaitAddress,     // 2.3.1 server等待client告知接收内容的地址
+    SendingContent,   // 通过临时socket发送长内容
+    ReceivingContent, // 通过临时socket接收长内容
+    Closed,           // 连接关闭
+}
+
+impl ProtocolState {
+    /// 转换到next状态，peer为对端的描述
+    pub fn transit(&mut self, next: Self, peer: impl Display) {
+        log::debug!("[{}] protocol state {:?} -> {:?}", peer, self, next);
+        *self = next;
+    }
+}
 
 /// 通过addr发送长内容，送达后关闭socket
-pub async fn send_content(content: String, addr: &str) -> io::Result<()> {
+pub async fn send_content(content: impl AsRef<[u8]>, addr: &str) -> io::Result<()> {
     let mut stream;
     let mut retry = 0;
     loop {
@@ -41,22 +67,64 @@ pub async fn send_content(content: String, addr: &str) -> io::Result<()> {
             }
         }
     }
-    stream.write_all(content.as_bytes()).await?;
+    stream.write_all(content.as_ref()).await?;
     stream.shutdown().await
 }
 
-/// 开始临时监听addr，接受长内容，完成后关闭socket
+/// 开始临时监听addr，接受长内容，完成后关闭socket；内容只能是UTF-8文本
 pub async fn receive_content(listener: &TcpListener) -> io::Result<String> {
     let (mut socket, _) = listener.accept().await?;
     // 读取文件内容
-    let mut buffer = String::new();
-    let n = socket.read_to_string(&mut buffer).await?;
+    let mut buffer = Vec::new();
+    let n = socket.read_to_end(&mut buffer).await?;
     if n == 0 {
-        Err(std::io::Error::new(
+        return Err(std::io::Error::new(
             io::ErrorKind::InvalidData,
             "read 0 byte",
-        ))
-    } else {
-        Ok(buffer)
+        ));
+    }
+    String::from_utf8(buffer).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "content is not valid UTF-8, binary content is not supported",
+        )
+    })
+}
+
+/// 从参数中取出 --log-level [level]（off、error、warn、info、debug、trace）并初始化日志，
+/// 默认为info，环境变量RUST_LOG中按模块的设置仍然有效，--log-level覆盖其默认级别；
+/// 级别无效时仍以默认级别初始化，再返回错误交给调用者输出
+pub fn init_logger(args: &mut Vec<String>) -> io::Result<()> {
+    let level = take_log_level(args);
+    let mut builder = pretty_env_logger::formatted_builder();
+    builder
+        .filter_level(log::LevelFilter::Info)
+        .parse_default_env();
+    if let Ok(Some(level)) = level {
+        builder.filter_level(level);
+    }
+    builder.init();
+    level.map(|_| ())
+}
+
+fn take_log_level(args: &mut Vec<String>) -> io::Result<Option<log::LevelFilter>> {
+    let Some(i) = args.iter().position(|arg| arg == "--log-level") else {
+        return Ok(None);
+    };
+    let invalid = |value: &str| {
+        std::io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "invalid --log-level {}, expected off, error, warn, info, debug or trace",
+                value
+            ),
+        )
+    };
+    if i + 1 >= args.len() {
+        args.remove(i);
+        return Err(invalid("(missing)"));
     }
+    let value = args.remove(i + 1);
+    args.remove(i);
+    value.parse().map(Some).map_err(|_| invalid(&value))
 }
@@ -1,46 +1,114 @@
-use std::io::{Error, Write};
+use std::io::{Error, IsTerminal, Write};
+
+use rustyline::{error::ReadlineError, DefaultEditor};
 
-use utils::*;
 use tokio::io::{self, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, ErrorKind, Stdin};
 use tokio::net::{TcpListener, TcpStream};
+use utils::*;
 
 #[macro_use]
 extern crate log;
 
+/// 一次连接中client的状态
+struct Session {
+    stream: TcpStream,
+    io_reader: BufReader<Stdin>,
+    editor: Option<DefaultEditor>, // 标准输入为终端时用于读取命令，支持上下键翻阅本次的历史命令
+    is_login: bool,
+    username: String,
+    password: String, // 两端不同步需要重新连接时用于重新登录
+    server_addr: String,
+    cwd: String,
+    session_id: u128, // 请求id由会话开始的时间和序号组成，server据此识别重发的命令
+    request_seq: u64,
+    state: ProtocolState,
+}
+
+const USAGE: &str = "shell (--connect [addr]) (--log-level [level]) (--user [username] --pass [password] --exec [command])";
+
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    pretty_env_logger::formatted_builder()
-        .filter_level(log::LevelFilter::Info)
-        .init();
-
-    let mut stream = TcpStream::connect(SOCKET_ADDR).await?;
-    info!("Connected to server");
-    let mut io_reader = io::BufReader::new(io::stdin());
-    let mut stream_buffer;
-    let mut is_login = false;
-    let mut username = String::new();
-    let mut cwd = "~".to_string();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print_usage();
+        return Ok(());
+    }
+    if let Err(e) = init_logger(&mut args) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    let server_addr = match take_server_addr(&mut args) {
+        Ok(addr) => addr,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let stream = TcpStream::connect(&server_addr).await?;
+    info!("Connected to server {}", server_addr);
+    let mut session = Session {
+        stream,
+        io_reader: io::BufReader::new(io::stdin()),
+        // 管道输入时可能已被io_reader预读，只在终端中使用行编辑
+        editor: if std::io::stdin().is_terminal() {
+            DefaultEditor::new().ok()
+        } else {
+            None
+        },
+        is_login: false,
+        username: String::new(),
+        password: String::new(),
+        server_addr,
+        cwd: "~".to_string(),
+        session_id: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis()),
+        request_seq: 0,
+        state: ProtocolState::AwaitLogin,
+    };
+
+    // 带有命令行参数时执行一次命令后退出，退出码表示命令是否成功
+    if !args.is_empty() {
+        let ok = match one_shot(&mut session, &args).await {
+            Ok(ok) => ok,
+            Err(e) => {
+                error!("{}", e);
+                false
+            }
+        };
+        // server可能已经关闭连接（如拒绝连接），忽略发送失败
+        let _ = session.stream.write_all(EXIT_MSG.as_bytes()).await;
+        std::process::exit(if ok { 0 } else { 1 });
+    }
 
     loop {
-        if !is_login {
+        if !session.is_login {
             // 0.(1/2).1 选择注册还是登录
             info!("select: \n[1]sign In\n[2]sign Up");
             let mut choice = String::new();
-            io_reader.read_line(&mut choice).await?;
+            session.io_reader.read_line(&mut choice).await?;
             match choice.to_lowercase().trim() {
                 "sign in" | "1" | "i" => {
                     // 向server发送登录信息
-                    if login(&mut username, &mut io_reader, &mut stream)
-                        .await
-                        .is_err()
+                    if login(
+                        &mut session.username,
+                        &mut session.password,
+                        &mut session.io_reader,
+                        &mut session.stream,
+                    )
+                    .await
+                    .is_err()
                     {
                         continue;
                     };
-                    is_login = true;
+                    session.is_login = true;
+                    session
+                        .state
+                        .transit(ProtocolState::AwaitCommand, &session.server_addr);
                 }
                 "sign up" | "2" | "u" => {
                     // 向server发送注册信息
-                    if let Err(e) = regist(&mut io_reader, &mut stream).await {
+                    if let Err(e) = regist(&mut session.io_reader, &mut session.stream).await {
                         error!("{}", e);
                     }
                     continue;
@@ -52,113 +120,356 @@ async fn main() -> io::Result<()> {
             }
         }
 
-        println!("{}", cwd);
-        print!("({}) $ ", username.trim());
-        std::io::stdout().flush()?;
+        println!("{}", session.cwd);
+        let prompt = format!("({}) $ ", session.username.trim());
 
         // 2.0 读取输入指令
-        let mut input = String::new();
-        io_reader.read_line(&mut input).await?;
+        let input = session.read_command(&prompt).await?;
         let input = input.trim();
         if input.is_empty() {
             // 输入为空 发送一个特定消息告诉server放弃接下来的读取
-            stream.write_all(EMPTY_INPUT.as_bytes()).await?;
+            session.stream.write_all(EMPTY_INPUT.as_bytes()).await?;
             continue;
         }
         match input.to_uppercase().trim() {
             EXIT_MSG => {
-                stream.write_all(EXIT_MSG.as_bytes()).await?;
+                session.stream.write_all(EXIT_MSG.as_bytes()).await?;
                 return Ok(());
             }
             HELP_REQUEST => {
-                print_help(&username);
-                stream.write_all(EMPTY_INPUT.as_bytes()).await?;
+                print_help(&session.username);
+                session.stream.write_all(EMPTY_INPUT.as_bytes()).await?;
                 continue;
             }
             _ => {}
         }
+        // source在本地执行脚本中的命令
+        if let Some(("source", args)) = input.split_once(' ').or(Some((input, ""))) {
+            session.source(args).await?;
+            continue;
+        }
+        session.run_command(input).await?;
+    }
+}
 
-        // 2.1 将username+ cwd +指令发给server
-        let cmd = [&username, " ", &cwd, " ", input].concat();
-        stream.write_all(cmd.as_bytes()).await?;
-
-        // 2.3 读取返回信息，如果是需要继续输入信息的，则回复，否则不回复
-        stream_buffer = [0; SOCKET_BUFFER_SIZE];
-        let n = stream.read(&mut stream_buffer).await?;
-        if n == 0 {
-            error!("error reading answer from server");
-            return Err(Error::new(ErrorKind::NotConnected, ""));
+impl Session {
+    /// 显示提示符并读取一行命令；终端中按ctrl+D时视为exit，ctrl+C放弃当前输入
+    async fn read_command(&mut self, prompt: &str) -> io::Result<String> {
+        let Some(editor) = self.editor.as_mut() else {
+            print!("{}", prompt);
+            std::io::stdout().flush()?;
+            let mut input = String::new();
+            self.io_reader.read_line(&mut input).await?;
+            return Ok(input);
+        };
+        // readline会阻塞当前线程
+        match tokio::task::block_in_place(|| editor.readline(prompt)) {
+            Ok(input) => {
+                if !input.trim().is_empty() {
+                    let _ = editor.add_history_entry(input.trim());
+                }
+                Ok(input)
+            }
+            Err(ReadlineError::Interrupted) => Ok(String::new()),
+            Err(ReadlineError::Eof) => Ok(EXIT_MSG.to_string()),
+            Err(e) => Err(Error::other(e)),
         }
-        let msg = String::from_utf8_lossy(&stream_buffer).replace('\0', "");
-        match msg.trim() {
-            // 2. ex1.1 需要输入文件内容
-            input_msg if msg.starts_with(INPUT_FILE_CONTENT) => {
-                let inputs = read_file_content(&mut io_reader).await?;
-                // 解析端口
-                let addr = input_msg.strip_prefix(INPUT_FILE_CONTENT).unwrap();
-                // 2. ex1.2 将得到的文件内容通过给定端口发送给server
-                send_content(inputs, addr).await?;
+    }
+
+    /// 将一条命令发给server并处理server的应答，返回命令是否执行成功
+    async fn run_command(&mut self, input: &str) -> io::Result<bool> {
+        // 以 < 结尾的输入重定向在本地处理，不发给server
+        let (input, input_file) = split_input_redirect(input);
+        // 单独输入..时回到上级目录
+        let input = if input == ".." { "cd .." } else { input };
+        // 2.1 将请求id + username + cwd +指令发给server
+        self.request_seq += 1;
+        let request_id = format!(
+            "{}{}-{}",
+            REQUEST_ID_PREFIX, self.session_id, self.request_seq
+        );
+        let cmd = [&request_id, " ", &self.username, " ", &self.cwd, " ", input].concat();
+        self.stream.write_all(cmd.as_bytes()).await?;
+        self.state
+            .transit(ProtocolState::ExecutingCommand, &self.server_addr);
+
+        // 2.3 读取返回信息，如果是需要继续输入信息的，则回复，直到server宣告命令结束
+        let mut failed = false;
+        loop {
+            let mut stream_buffer = [0; SOCKET_BUFFER_SIZE];
+            let n = self.stream.read(&mut stream_buffer).await?;
+            if n == 0 {
+                error!("error reading answer from server");
+                return Err(Error::new(ErrorKind::NotConnected, ""));
             }
-            // 需要确认是否继续执行
-            COMMAND_CONFIRM => {
-                // 2.ex2 将确认指令回复给server
-                println!("diretory is not empty, continue to remove? [y/n]");
-                let mut answer = String::new();
-                let n = io_reader.read_line(&mut answer).await?;
-                if n == 0 {
-                    stream.write_all("n".as_bytes()).await?;
-                    continue;
+            let msg = String::from_utf8_lossy(&stream_buffer).replace('\0', "");
+            match msg.trim() {
+                // 2. ex1.1 需要输入文件内容
+                input_msg if msg.starts_with(INPUT_FILE_CONTENT) => {
+                    self.state
+                        .transit(ProtocolState::AwaitInput, &self.server_addr);
+                    let inputs = match input_file {
+                        // 原样发送宿主文件的字节，不做按行处理，是否为文本由server检查
+                        Some(path) => tokio::fs::read(path).await.unwrap_or_else(|e| {
+                            // 命令视为失败，发送空内容让server结束等待并放弃执行
+                            error!("cannot read {}: {}", path, e);
+                            failed = true;
+                            Vec::new()
+                        }),
+                        None => read_file_content(&mut self.io_reader).await?.into_bytes(),
+                    };
+                    // 解析端口
+                    let addr = input_msg.strip_prefix(INPUT_FILE_CONTENT).unwrap();
+                    // 2. ex1.2 将得到的文件内容通过给定端口发送给server
+                    self.state
+                        .transit(ProtocolState::SendingContent, &self.server_addr);
+                    send_content(inputs, addr).await?;
+                    self.state
+                        .transit(ProtocolState::ExecutingCommand, &self.server_addr);
                 }
-                stream.write_all(answer.as_bytes()).await?;
-            }
-            // 2.3.1 需要打开文件通道接受内容
-            RECEIVE_CONTENTS => {
-                // 建立临时socket，端口随机
-                let listener = TcpListener::bind("127.0.0.1:0").await?;
-                // 2.3.2 将端口写给server
-                let addr = listener.local_addr()?;
-                stream.write_all(addr.to_string().as_bytes()).await?;
-                // 2.3.3 接受内容
-                let contents = receive_content(&listener).await?;
-                if contents.starts_with(ERROR_MESSAGE_PREFIX) {
-                    error!("{}", contents.strip_prefix(ERROR_MESSAGE_PREFIX).unwrap());
-                } else {
-                    println!("{}", contents);
+                // 需要确认是否继续执行
+                COMMAND_CONFIRM => {
+                    // 2.ex2 将确认指令回复给server
+                    self.state
+                        .transit(ProtocolState::AwaitInput, &self.server_addr);
+                    println!("diretory is not empty, continue to remove? [y/n]");
+                    let answer = read_answer(&mut self.io_reader).await?;
+                    self.stream.write_all(answer.as_bytes()).await?;
+                    self.state
+                        .transit(ProtocolState::ExecutingCommand, &self.server_addr);
                 }
-                // -->跳转到3.
-            }
-            // 4. 本次指令执行完毕
-            COMMAND_FINISHED => {
-                if input.starts_with("cd") {
-                    // 处理cwd情况
-                    deal_with_dir(input, &mut cwd);
-                } else if input == "formatting" {
-                    // 格式化之后要退出登录
-                    is_login = false;
+                // 格式化需要输入确认口令
+                FORMAT_CONFIRM => {
+                    self.state
+                        .transit(ProtocolState::AwaitInput, &self.server_addr);
+                    println!(
+                        "formatting will erase the whole file system, type {} to continue",
+                        FORMAT_CONFIRM_TOKEN
+                    );
+                    let answer = read_answer(&mut self.io_reader).await?;
+                    self.stream.write_all(answer.as_bytes()).await?;
+                    self.state
+                        .transit(ProtocolState::ExecutingCommand, &self.server_addr);
+                }
+                // 2.3.1 需要打开文件通道接受内容
+                RECEIVE_CONTENTS => {
+                    // 建立临时socket，端口随机
+                    // 和连接server的socket使用同一个网卡
+                    let listener = TcpListener::bind((self.stream.local_addr()?.ip(), 0)).await?;
+                    // 2.3.2 将端口写给server
+                    let addr = listener.local_addr()?;
+                    self.stream.write_all(addr.to_string().as_bytes()).await?;
+                    // 2.3.3 接受内容
+                    self.state
+                        .transit(ProtocolState::ReceivingContent, &self.server_addr);
+                    let contents = receive_content(&listener).await?;
+                    self.state
+                        .transit(ProtocolState::ExecutingCommand, &self.server_addr);
+                    if contents.starts_with(ERROR_MESSAGE_PREFIX) {
+                        failed = true;
+                        let err = contents.strip_prefix(ERROR_MESSAGE_PREFIX).unwrap();
+                        if input.split_whitespace().any(|arg| arg == "--json") {
+                            // json错误对象输出到标准输出，便于程序处理
+                            println!("{}", err);
+                        } else {
+                            error!("{}", err);
+                        }
+                    } else {
+                        println!("{}", contents);
+                    }
+                }
+                // 4. 本次指令执行完毕
+                COMMAND_FINISHED => {
+                    self.state
+                        .transit(ProtocolState::AwaitCommand, &self.server_addr);
+                    // 命令出错时不改变本地状态
+                    if failed {
+                        return Ok(false);
+                    }
+                    if input.starts_with("cd") {
+                        // 处理cwd情况
+                        deal_with_dir(input, &mut self.cwd);
+                    } else if input == "formatting" {
+                        // 格式化之后要退出登录
+                        self.is_login = false;
+                        self.state
+                            .transit(ProtocolState::AwaitLogin, &self.server_addr);
+                    }
+                    return Ok(true);
+                }
+                // 两端不同步，记录当时的状态，重新同步后等待下一条命令
+                _ => {
+                    error!(
+                        "unexpected message from server in state {:?}: {}",
+                        self.state,
+                        msg.trim()
+                    );
+                    self.resync().await?;
+                    return Ok(false);
+                }
+            };
+        }
+    }
+
+    /// 收到意外的消息后重新同步：在RESYNC_TIMEOUT内等到命令结束则继续使用当前连接，
+    /// 否则断开连接（server随之结束对该连接的处理），重新连接并登录
+    async fn resync(&mut self) -> io::Result<()> {
+        let wait_finished = async {
+            loop {
+                let mut stream_buffer = [0; SOCKET_BUFFER_SIZE];
+                match self.stream.read(&mut stream_buffer).await {
+                    Ok(0) | Err(_) => return false,
+                    Ok(n) => {
+                        let msg = String::from_utf8_lossy(&stream_buffer[..n]);
+                        if msg.trim_end_matches('\0').ends_with(COMMAND_FINISHED) {
+                            return true;
+                        }
+                        warn!("discard message from server: {}", msg.trim());
+                    }
                 }
-                continue;
-            }
-            _ => {
-                panic!("{}", msg);
             }
         };
-        // 3. 等待server应答
-        stream_buffer = [0; SOCKET_BUFFER_SIZE];
-        let n = stream.read(&mut stream_buffer).await?;
-        if n == 0 {
-            error!("error reading answer from server");
-            return Err(Error::new(ErrorKind::NotConnected, ""));
+        let timeout = std::time::Duration::from_secs(RESYNC_TIMEOUT);
+        if tokio::time::timeout(timeout, wait_finished).await != Ok(true) {
+            warn!("server is not responding, reconnect");
+            self.stream = TcpStream::connect(&self.server_addr).await?;
+            self.state
+                .transit(ProtocolState::AwaitLogin, &self.server_addr);
+            if let Err(e) = send_login(self.username.trim(), &self.password, &mut self.stream).await
+            {
+                // 无法重新登录时回到登录界面
+                self.is_login = false;
+                return Err(e);
+            }
         }
-        let msg = String::from_utf8_lossy(&stream_buffer).replace('\0', "");
-        // 4 宣告结束，否则打印错误信息
-        if msg.trim() != COMMAND_FINISHED {
-            println!("{}", msg);
+        self.state
+            .transit(ProtocolState::AwaitCommand, &self.server_addr);
+        Ok(())
+    }
+
+    /// 逐行执行宿主上脚本文件中的命令，默认遇到第一个出错的命令就停止，-k 时继续执行
+    async fn source(&mut self, args: &str) -> io::Result<()> {
+        let mut keep_going = false;
+        let mut path = None;
+        for arg in args.split_whitespace() {
+            match arg {
+                "-k" => keep_going = true,
+                _ => path = Some(arg),
+            }
         }
+        let Some(path) = path else {
+            error!("usage: source (-k) [host script]");
+            return Ok(());
+        };
+        let script = match tokio::fs::read_to_string(path).await {
+            Ok(script) => script,
+            Err(e) => {
+                error!("cannot read {}: {}", path, e);
+                return Ok(());
+            }
+        };
+        for (i, line) in script.lines().enumerate() {
+            let line = line.trim();
+            // 跳过空行和#开头的注释
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let name = line.split_whitespace().next().unwrap().to_uppercase();
+            if name == EXIT_MSG || name == HELP_REQUEST || name == "SOURCE" {
+                error!("line {}: {} is not allowed in a script", i + 1, line);
+                if keep_going {
+                    continue;
+                }
+                return Ok(());
+            }
+            println!("{} $ {}", self.cwd, line);
+            let ok = self.run_command(line).await?;
+            if !self.is_login {
+                // 格式化之后需要重新登录，不再执行后续命令
+                return Ok(());
+            }
+            if !ok && !keep_going {
+                error!("script stopped at line {}", i + 1);
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 非交互模式：`shell (--connect [addr]) --user [username] --pass [password] --exec [command]`，
+/// 登录后执行一条命令，返回命令是否执行成功
+async fn one_shot(session: &mut Session, args: &[String]) -> io::Result<bool> {
+    let mut user = None;
+    let mut pass = None;
+    let mut exec = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let value = match arg.as_str() {
+            "--user" => &mut user,
+            "--pass" => &mut pass,
+            "--exec" => &mut exec,
+            _ => return Err(Error::new(ErrorKind::InvalidInput, usage_error(arg))),
+        };
+        *value = iter.next();
+    }
+    let (Some(user), Some(pass), Some(exec)) = (user, pass, exec) else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            usage_error("missing args"),
+        ));
+    };
+    // 登录失败的原因已经在send_login中输出
+    if send_login(user, pass, &mut session.stream).await.is_err() {
+        return Ok(false);
+    }
+    session.username = user.to_string();
+    session.password = pass.to_string();
+    session.is_login = true;
+    session
+        .state
+        .transit(ProtocolState::AwaitCommand, &session.server_addr);
+    session.run_command(exec.trim()).await
+}
+
+/// 从参数中取出 --connect [addr]，没有时连接默认地址SOCKET_ADDR
+fn take_server_addr(args: &mut Vec<String>) -> io::Result<String> {
+    let Some(i) = args.iter().position(|arg| arg == "--connect") else {
+        return Ok(SOCKET_ADDR.to_string());
+    };
+    if i + 1 >= args.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--connect requires an address, such as 127.0.0.1:8080",
+        ));
     }
+    let addr = args.remove(i + 1);
+    args.remove(i);
+    Ok(addr)
+}
+
+fn usage_error(msg: &str) -> String {
+    format!("{}, usage: {}", msg, USAGE)
+}
+
+/// 输出启动参数的说明
+fn print_usage() {
+    println!("usage: {}", USAGE);
+    println!("without --exec, log in and enter commands interactively");
+    println!(
+        "  --connect [addr]     server address, default {}",
+        SOCKET_ADDR
+    );
+    println!("  --log-level [level]  off, error, warn, info (default), debug or trace");
+    println!("  --user [username]    log in as username (with --pass and --exec)");
+    println!("  --pass [password]    password of the user");
+    println!("  --exec [command]     run one command and exit, exit code 0 on success");
+    println!("  -h, --help           print this help");
 }
 
 async fn login(
     username: &mut String,
+    password: &mut String,
     io_reader: &mut BufReader<Stdin>,
     stream: &mut TcpStream,
 ) -> io::Result<()> {
@@ -167,12 +478,21 @@ async fn login(
     username.clear();
     io_reader.read_line(username).await?;
     info!("enter password");
-    let mut password = String::new();
-    io_reader.read_line(&mut password).await?;
+    password.clear();
+    io_reader.read_line(password).await?;
+    *password = password.trim().to_string();
+    send_login(username.trim(), password, stream).await
+}
 
+/// 向server发送登录信息并检查结果
+async fn send_login(username: &str, password: &str, stream: &mut TcpStream) -> io::Result<()> {
     //  0.1.1 发送登录信息
     stream
-        .write_all(["login\n", username, &password].concat().as_bytes())
+        .write_all(
+            ["login\n", username, "\n", password, "\n"]
+                .concat()
+                .as_bytes(),
+        )
         .await?;
     // 0.1.2 接受回传信息
     let mut stream_buffer = [0; SOCKET_BUFFER_SIZE];
@@ -231,20 +551,72 @@ async fn read_file_content(io_reader: &mut BufReader<Stdin>) -> io::Result<Strin
     Ok(inputs)
 }
 
+/// 分离输入重定向，`newfile a.txt < /host/file` 返回 ("newfile a.txt", Some("/host/file"))
+fn split_input_redirect(input: &str) -> (&str, Option<&str>) {
+    match input.rsplit_once(" < ") {
+        Some((cmd, path)) if !path.trim().is_empty() => (cmd.trim(), Some(path.trim())),
+        _ => (input, None),
+    }
+}
+
+/// 读取用户的回答，输入结束时视为拒绝
+async fn read_answer(io_reader: &mut BufReader<Stdin>) -> io::Result<String> {
+    let mut answer = String::new();
+    let n = io_reader.read_line(&mut answer).await?;
+    if n == 0 {
+        answer = "n".to_string();
+    }
+    Ok(answer)
+}
+
 fn print_help(username: &str) {
     println!("info");
-    println!("dir (path) (/s)");
+    println!("mount-info");
+    println!("dir (path) (/s) (/i) (-a)");
+    println!("du (path) (--apparent-size)");
+    println!("tree (path) (-L level)");
+    println!("treehash (path)");
+    println!("find (path) (-name pattern) (-exec del|cat|du)");
+    println!("stat [path]");
+    println!("blocks [path]");
+    println!("exists [path]");
     println!("cd [path]");
+    println!("..");
     println!("md [path]");
     println!("rd [path]");
-    println!("newfile [filename]");
-    println!("cat [filename]");
-    println!("copy (<host>)[src path] [dst path]");
+    println!("newfile [filename] (-z) (< host file)");
+    println!("edit [filename] (< host file)");
+    println!("append [filename] (< host file)");
+    println!("mkfile [filename] [size]");
+    println!("cat (--range start:len) [filename]...");
+    println!("del [filename]");
+    println!("undelete [filename]");
+    println!("emptytrash");
+    println!("copy (-f) (--preserve) (<host>)[src path]|- [dst path] (< host file)");
+    println!("diff [path1] [path2]");
+    println!("rename [path] [new name]");
     println!("check");
+    println!("fsck (--repair) (--lost-found)");
+    println!("verify");
+    println!("quota");
+    println!("history (search term)");
+    println!("[command] | grep|wc|head|tail|sort|uniq (args) (| ...)");
+    println!("cat [filename] | <host>sort|uniq|wc|md5sum|sha256sum|base64");
     if username == "root" {
         println!("formatting");
+        println!("resize [size]");
+        println!("defrag [path]");
+        println!("resetsb");
+        println!("stat --inode [id]");
+        println!("bench [size]");
+        println!("mount [host path]");
+        println!("umount");
         println!("users");
+        println!("sessions");
+        println!("chroot [username] (path)");
+        println!("quota [username] (soft hard)");
     }
+    println!("source (-k) [host script]");
     println!("EXIT");
 }
 
@@ -253,7 +625,8 @@ fn deal_with_dir(input: &str, cwd: &mut String) {
     let path = input.split_whitespace().collect::<Vec<&str>>()[1];
     //将路径分割为多段
     let mut paths: Vec<&str> = path.split('/').collect();
-    if paths[0] == "~" {
+    // ~和/开头都是绝对路径，从根目录开始
+    if paths[0] == "~" || paths[0].is_empty() {
         cwd.clear();
         cwd.push('~');
         paths.remove(0);
@@ -261,12 +634,43 @@ fn deal_with_dir(input: &str, cwd: &mut String) {
     // 调整当前目录
     for &path in &paths {
         match path {
-            "." => {}
+            "." | "" => {}
             ".." => {
-                let idx = cwd.rfind('/').unwrap();
-                cwd.replace_range(idx.., "");
+                // 根目录的上级目录仍是根目录
+                if let Some(idx) = cwd.rfind('/') {
+                    cwd.replace_range(idx.., "");
+                }
             }
             _ => cwd.push_str(&["/", path].concat()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cd(cwd: &str, input: &str) -> String {
+        let mut cwd = cwd.to_string();
+        deal_with_dir(input, &mut cwd);
+        cwd
+    }
+
+    #[test]
+    fn slash_and_tilde_paths_start_from_root() {
+        assert_eq!(cd("~/b", "cd /a"), "~/a");
+        assert_eq!(cd("~/b", "cd /"), "~");
+        assert_eq!(cd("~/b", "cd ~/a/c"), "~/a/c");
+        assert_eq!(cd("~/b", "cd a"), "~/b/a");
+        assert_eq!(cd("~/b", "cd /a/../c"), "~/c");
+        assert_eq!(cd("~", "cd .."), "~");
+    }
+
+    #[test]
+    fn parent_of_root_stays_at_root() {
+        assert_eq!(cd("~", "cd ../.."), "~");
+        assert_eq!(cd("~", "cd ../a"), "~/a");
+        assert_eq!(cd("~/a", "cd ../../.."), "~");
+        assert_eq!(cd("~/a/b", "cd .."), "~/a");
+    }
+}
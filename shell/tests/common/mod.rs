@@ -0,0 +1,145 @@
+//! 集成测试的辅助函数：以模拟的server应答shell，检查shell发出的内容
+#![allow(dead_code)]
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    process::{Command, Output, Stdio},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use utils::*;
+
+/// 在本机空闲端口上监听的模拟server
+pub struct MockServer {
+    listener: TcpListener,
+    pub addr: String,
+}
+
+impl MockServer {
+    pub fn new() -> Self {
+        Self::bind("127.0.0.1:0")
+    }
+
+    /// 在指定的地址上监听
+    pub fn bind(addr: &str) -> Self {
+        let listener = TcpListener::bind(addr).unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        Self { listener, addr }
+    }
+
+    /// 接受shell的连接，不处理登录
+    pub fn accept(&self) -> Connection {
+        let (stream, _) = self.listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Connection { stream }
+    }
+
+    /// 接受shell的连接并回复登录成功，返回连接和登录报文
+    pub fn accept_login(&self) -> (Connection, String) {
+        let mut connection = self.accept();
+        let login = connection.read_msg().unwrap();
+        connection.send(LOGIN_SUCCESS);
+        (connection, login)
+    }
+}
+
+/// 和shell之间的一个连接
+pub struct Connection {
+    pub stream: TcpStream,
+}
+
+impl Connection {
+    /// 读取一次消息，连接关闭时返回None
+    pub fn read_msg(&mut self) -> Option<String> {
+        let mut buffer = [0; SOCKET_BUFFER_SIZE];
+        match self.stream.read(&mut buffer) {
+            Ok(0) | Err(_) => None,
+            Ok(n) => Some(String::from_utf8_lossy(&buffer[..n]).to_string()),
+        }
+    }
+
+    pub fn send(&mut self, msg: &str) {
+        self.stream.write_all(msg.as_bytes()).unwrap();
+    }
+
+    /// 要求shell输入文件内容，返回收到的原始字节
+    pub fn request_input(&mut self) -> Vec<u8> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        self.send(&format!("{}{}", INPUT_FILE_CONTENT, addr));
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut contents = Vec::new();
+        stream.read_to_end(&mut contents).unwrap();
+        contents
+    }
+
+    /// 按协议把命令的输出发送给shell
+    pub fn send_contents(&mut self, contents: &str) {
+        self.send(RECEIVE_CONTENTS);
+        let addr = self.read_msg().unwrap();
+        let mut stream = TcpStream::connect(addr.trim()).unwrap();
+        stream.write_all(contents.as_bytes()).unwrap();
+    }
+
+    /// 宣告命令结束
+    pub fn finish(&mut self) {
+        self.send(COMMAND_FINISHED);
+    }
+}
+
+/// 以root的身份连接server执行一条命令，返回shell的输出
+pub fn run_shell(addr: &str, exec: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_shell"))
+        .args(["--connect", addr, "--user", "root", "--pass", "admin"])
+        .args(["--exec", exec])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+/// 交互式运行shell，把input作为标准输入，返回shell的输出
+pub fn run_interactive(addr: &str, input: &str) -> Output {
+    run_interactive_with(addr, &[], input)
+}
+
+/// 带额外的命令行参数（如--log-level）交互式运行shell
+pub fn run_interactive_with(addr: &str, args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_shell"))
+        .args(["--connect", addr])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// 去掉命令行前面的请求id，返回以单个空格分隔的 用户名 cwd 命令
+pub fn strip_request_id(line: &str) -> String {
+    let mut args: Vec<_> = line.split_whitespace().collect();
+    if args
+        .first()
+        .is_some_and(|arg| arg.starts_with(REQUEST_ID_PREFIX))
+    {
+        args.remove(0);
+    }
+    args.join(" ")
+}
+
+/// 在后台运行模拟server的应答逻辑
+pub fn serve<T: Send + 'static>(
+    server: MockServer,
+    handle: impl FnOnce(MockServer) -> T + Send + 'static,
+) -> JoinHandle<T> {
+    thread::spawn(move || handle(server))
+}
@@ -0,0 +1,37 @@
+//! --connect 连接指定地址的server，接收内容的临时socket和连接server的socket使用同一个网卡
+mod common;
+
+use std::{io::Write, net::TcpStream};
+
+use common::{run_shell, serve, MockServer};
+use utils::RECEIVE_CONTENTS;
+
+#[test]
+fn connects_to_the_given_address() {
+    let server = MockServer::bind("127.0.0.3:0");
+    let addr = server.addr.clone();
+    let handle = serve(server, |server| {
+        let (mut connection, _) = server.accept_login();
+        connection.read_msg().unwrap();
+        // 记下shell告知的接收地址，再按协议发送输出
+        connection.send(RECEIVE_CONTENTS);
+        let content_addr = connection.read_msg().unwrap();
+        let mut stream = TcpStream::connect(content_addr.trim()).unwrap();
+        stream.write_all(b"from 127.0.0.3").unwrap();
+        drop(stream);
+        connection.finish();
+        let peer = connection.stream.peer_addr().unwrap();
+        (content_addr, peer.ip().to_string())
+    });
+    let output = run_shell(&addr, "dir");
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "from 127.0.0.3\n");
+    // 临时socket监听在shell连接server时使用的本机地址上
+    let (content_addr, shell_ip) = handle.join().unwrap();
+    assert!(
+        content_addr.starts_with(&[&shell_ip, ":"].concat()),
+        "{}",
+        content_addr
+    );
+}
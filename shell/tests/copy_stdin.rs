@@ -0,0 +1,44 @@
+//! 非交互模式下 copy - [target] 从标准输入读取内容
+mod common;
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use common::{serve, strip_request_id, MockServer};
+
+#[test]
+fn piped_body_is_sent_as_the_copy_source() {
+    let body = "first line\nsecond line\n\nlast line";
+    let server = MockServer::new();
+    let addr = server.addr.clone();
+    let handle = serve(server, |server| {
+        let (mut connection, _) = server.accept_login();
+        let command = strip_request_id(&connection.read_msg().unwrap());
+        let received = connection.request_input();
+        connection.finish();
+        (command, received)
+    });
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_shell"))
+        .args(["--connect", &addr, "--user", "root", "--pass", "admin"])
+        .args(["--exec", "copy - out.txt"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(body.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success(), "{:?}", output);
+    let (command, received) = handle.join().unwrap();
+    assert_eq!(command, "root ~ copy - out.txt");
+    assert_eq!(String::from_utf8(received).unwrap(), body);
+}
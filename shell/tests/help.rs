@@ -0,0 +1,26 @@
+//! --help打印用法后直接退出，不连接server
+use std::process::Command;
+
+#[test]
+fn help_lists_the_flags_and_exits_zero() {
+    for flag in ["--help", "-h"] {
+        // 没有server在监听，连接的话会失败
+        let output = Command::new(env!("CARGO_BIN_EXE_shell"))
+            .args(["--connect", "127.0.0.1:1", flag])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        let usage = String::from_utf8_lossy(&output.stdout);
+        assert!(usage.starts_with("usage: shell"), "{}", usage);
+        for known in [
+            "--connect",
+            "--user",
+            "--pass",
+            "--exec",
+            "--log-level",
+            "--help",
+        ] {
+            assert!(usage.contains(known), "{} missing in {}", known, usage);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! 在终端中交互时可以用上下键翻阅本次输入过的命令，翻出的命令和重新输入的一样发给server
+mod common;
+
+use std::{
+    fs::File,
+    io::{Read, Write},
+    process::{Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::{serve, strip_request_id, MockServer};
+use nix::pty::openpty;
+use utils::EXIT_MSG;
+
+const UP: &str = "\x1b[A";
+const BACKSPACE: &str = "\x7f";
+
+/// 模拟server：每条命令都回复out，直到收到exit，返回收到的命令
+fn reply_until_exit(server: MockServer) -> Vec<String> {
+    let (mut connection, _) = server.accept_login();
+    let mut commands = Vec::new();
+    while let Some(line) = connection.read_msg() {
+        if line.trim() == EXIT_MSG {
+            break;
+        }
+        commands.push(strip_request_id(&line));
+        connection.send_contents("out");
+        connection.finish();
+    }
+    commands
+}
+
+/// 终端的输出
+struct Screen {
+    chunks: Receiver<Vec<u8>>,
+    text: String,
+}
+
+impl Screen {
+    /// 等待shell第n次打印cwd之后显示提示符，此时已进入行编辑
+    fn wait_for_prompt(&mut self, n: usize) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            if let Some((_, after)) = self.nth_cwd(n) {
+                if after.contains("(root) $ ") {
+                    return;
+                }
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.chunks.recv_timeout(left) {
+                Ok(chunk) => self.text.push_str(&String::from_utf8_lossy(&chunk)),
+                Err(_) => panic!("prompt {} not shown: {:?}", n, self.text),
+            }
+        }
+    }
+
+    /// 第n次打印的cwd之前和之后的输出
+    fn nth_cwd(&self, n: usize) -> Option<(&str, &str)> {
+        let (i, cwd) = self.text.match_indices("~\r\n").nth(n - 1)?;
+        Some((&self.text[..i], &self.text[i + cwd.len()..]))
+    }
+}
+
+#[test]
+fn recalled_commands_are_sent_like_typed_ones() {
+    let server = MockServer::new();
+    let addr = server.addr.clone();
+    let handle = serve(server, reply_until_exit);
+
+    // 标准输入为终端时才启用行编辑
+    let pty = openpty(None, None).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_shell"))
+        .args(["--connect", &addr])
+        .stdin(Stdio::from(pty.slave.try_clone().unwrap()))
+        .stdout(Stdio::from(pty.slave.try_clone().unwrap()))
+        .stderr(Stdio::from(pty.slave))
+        .spawn()
+        .unwrap();
+    let mut terminal = File::from(pty.master);
+    let mut reader = terminal.try_clone().unwrap();
+    let (sender, chunks) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        while let Ok(n) = reader.read(&mut buffer) {
+            if n == 0 || sender.send(buffer[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    let mut screen = Screen {
+        chunks,
+        text: String::new(),
+    };
+
+    terminal.write_all(b"1\nroot\nadmin\n").unwrap();
+    let keys = [
+        "dir a\r".to_string(),
+        "cat b\r".to_string(),
+        // 上一条命令
+        [UP, "\r"].concat(),
+        // 连续的重复命令只记录一次，再往前一条是dir a
+        [UP, UP, "\r"].concat(),
+        // 翻出的命令可以编辑后再执行
+        [UP, BACKSPACE, "c\r"].concat(),
+    ];
+    for (i, keys) in keys.iter().enumerate() {
+        screen.wait_for_prompt(i + 1);
+        terminal.write_all(keys.as_bytes()).unwrap();
+    }
+    screen.wait_for_prompt(keys.len() + 1);
+    // ctrl+D视为exit
+    terminal.write_all(b"\x04").unwrap();
+    assert!(child.wait().unwrap().success());
+
+    assert_eq!(
+        handle.join().unwrap(),
+        [
+            "root ~ dir a",
+            "root ~ cat b",
+            "root ~ cat b",
+            "root ~ dir a",
+            "root ~ dir c"
+        ]
+    );
+    let (shown, _) = screen.nth_cwd(keys.len() + 1).unwrap();
+    assert_eq!(shown.matches("out\r\n").count(), keys.len(), "{:?}", shown);
+}
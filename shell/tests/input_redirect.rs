@@ -0,0 +1,71 @@
+//! newfile x < 宿主文件 原样发送文件的字节
+mod common;
+
+use std::fs;
+
+use common::{run_shell, serve, MockServer};
+
+/// 把内容写入宿主文件name后用 < 重定向上传，返回server收到的命令和内容
+fn upload(name: &str, contents: impl AsRef<[u8]>) -> (String, Vec<u8>) {
+    let path = std::env::temp_dir().join(format!("shell-{}-{}", std::process::id(), name));
+    fs::write(&path, contents).unwrap();
+    let server = MockServer::new();
+    let addr = server.addr.clone();
+    let handle = serve(server, |server| {
+        let (mut connection, _) = server.accept_login();
+        let command = connection.read_msg().unwrap();
+        let received = connection.request_input();
+        connection.finish();
+        (command, received)
+    });
+    let output = run_shell(&addr, &format!("newfile x.txt < {}", path.display()));
+    fs::remove_file(&path).unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    handle.join().unwrap()
+}
+
+#[test]
+fn file_without_trailing_newline_is_sent_as_is() {
+    let contents = "first line\nlast line without newline";
+    let (command, received) = upload("no-newline.txt", contents);
+    // 重定向只在本地处理，不发给server
+    assert!(command.ends_with("root ~ newfile x.txt"), "{}", command);
+    assert_eq!(received, contents.as_bytes());
+}
+
+#[test]
+fn line_endings_and_blank_lines_are_kept() {
+    let contents = "a\r\nb\r\n\n\n  ";
+    let (_, received) = upload("crlf.txt", contents);
+    assert_eq!(received, contents.as_bytes());
+}
+
+#[test]
+fn binary_file_is_sent_as_raw_bytes() {
+    let contents = [0xff, 0x00, 0xfe, b'a', 0x80, b'\n'];
+    let (_, received) = upload("binary.dat", contents);
+    assert_eq!(received, contents);
+}
+
+#[test]
+fn unreadable_host_file_fails_the_command() {
+    let server = MockServer::new();
+    let addr = server.addr.clone();
+    let handle = serve(server, |server| {
+        let (mut connection, _) = server.accept_login();
+        connection.read_msg().unwrap();
+        // 读取失败时只发送空内容让server结束等待
+        let received = connection.request_input();
+        connection.finish();
+        received
+    });
+    let output = run_shell(&addr, "newfile x.txt < /nonexistent/input.dat");
+    assert!(!output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("cannot read /nonexistent/input.dat"),
+        "{}",
+        stderr
+    );
+    assert!(handle.join().unwrap().is_empty());
+}
@@ -0,0 +1,74 @@
+//! shell --user --pass --exec 执行一条命令后退出，退出码表示是否成功
+mod common;
+
+use std::process::Command;
+
+use common::{run_shell, serve, strip_request_id, MockServer};
+use utils::{ERROR_MESSAGE_PREFIX, EXIT_MSG};
+
+/// 模拟server：回复一条命令的输出，返回收到的登录报文、命令以及之后是否收到exit
+fn reply_once(server: MockServer, reply: &str) -> (String, String, bool) {
+    let (mut connection, login) = server.accept_login();
+    let command = strip_request_id(&connection.read_msg().unwrap());
+    connection.send_contents(reply);
+    connection.finish();
+    let exited = connection.read_msg().is_some_and(|msg| msg == EXIT_MSG);
+    (login, command, exited)
+}
+
+#[test]
+fn prints_output_and_exits_zero() {
+    let server = MockServer::new();
+    let addr = server.addr.clone();
+    let handle = serve(server, |server| reply_once(server, "a.txt\nb.txt"));
+    let output = run_shell(&addr, "dir");
+
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a.txt\nb.txt\n");
+    let (login, command, exited) = handle.join().unwrap();
+    assert_eq!(login, "login\nroot\nadmin\n");
+    assert_eq!(command, "root ~ dir");
+    assert!(exited);
+}
+
+#[test]
+fn failed_command_exits_non_zero() {
+    let server = MockServer::new();
+    let addr = server.addr.clone();
+    let reply = format!("{}no such file", ERROR_MESSAGE_PREFIX);
+    let handle = serve(server, move |server| reply_once(server, &reply));
+    let output = run_shell(&addr, "cat missing.txt");
+
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no such file"));
+    assert!(handle.join().unwrap().2);
+}
+
+#[test]
+fn failed_login_exits_non_zero() {
+    let server = MockServer::new();
+    let addr = server.addr.clone();
+    let handle = serve(server, |server| {
+        let mut connection = server.accept();
+        connection.read_msg().unwrap();
+        connection.send("wrong password");
+        // 登录失败后不再发送命令
+        connection.read_msg()
+    });
+    let output = run_shell(&addr, "dir");
+
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert_eq!(handle.join().unwrap().as_deref(), Some(EXIT_MSG));
+}
+
+#[test]
+fn missing_args_print_usage() {
+    let server = MockServer::new();
+    let output = Command::new(env!("CARGO_BIN_EXE_shell"))
+        .args(["--connect", &server.addr, "--user", "root", "--exec", "dir"])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("usage"));
+}
@@ -0,0 +1,97 @@
+//! 收到不符合协议的消息时shell记录当时的协议状态，重新同步后继续工作
+mod common;
+
+use std::{thread, time::Duration};
+
+use common::{run_interactive_with, serve, strip_request_id, MockServer};
+use utils::EXIT_MSG;
+
+/// 模拟server：对第一条命令先回复一条意外的消息再宣告结束，之后正常回复，返回收到的命令
+fn garbage_then_normal(server: MockServer) -> Vec<String> {
+    let (mut connection, _) = server.accept_login();
+    let mut commands = Vec::new();
+    while let Some(line) = connection.read_msg() {
+        if line.trim() == EXIT_MSG {
+            break;
+        }
+        if commands.is_empty() {
+            connection.send("garbage");
+            // 分开发送，避免两条消息被shell一次读到
+            thread::sleep(Duration::from_millis(200));
+        } else {
+            connection.send_contents("a.txt");
+        }
+        commands.push(strip_request_id(&line));
+        connection.finish();
+    }
+    commands
+}
+
+#[test]
+fn unexpected_message_is_logged_and_shell_keeps_running() {
+    let server = MockServer::new();
+    let addr = server.addr.clone();
+    let handle = serve(server, garbage_then_normal);
+    let input = "1\nroot\nadmin\ndir\ndir\nexit\n";
+    let output = run_interactive_with(&addr, &["--log-level", "debug"], input);
+
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("unexpected message from server in state ExecutingCommand: garbage"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("protocol state AwaitLogin -> AwaitCommand"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    // 重新同步后的命令正常执行
+    assert!(String::from_utf8_lossy(&output.stdout).contains("a.txt"));
+    assert_eq!(handle.join().unwrap(), ["root ~ dir", "root ~ dir"]);
+}
+
+/// 模拟server：第一条命令只回复一条意外的消息，不再宣告结束；
+/// 返回旧连接是否被shell关闭，以及新连接的登录报文和收到的命令
+fn garbage_without_finish(server: MockServer) -> (bool, String, Vec<String>) {
+    let (mut connection, _) = server.accept_login();
+    connection.read_msg().unwrap();
+    connection.send("garbage");
+    // shell等不到命令结束，断开旧连接后重新连接并登录
+    let (mut reconnected, login) = server.accept_login();
+    let closed = connection.read_msg().is_none();
+    let mut commands = Vec::new();
+    while let Some(line) = reconnected.read_msg() {
+        if line.trim() == EXIT_MSG {
+            break;
+        }
+        commands.push(strip_request_id(&line));
+        reconnected.send_contents("b.txt");
+        reconnected.finish();
+    }
+    (closed, login, commands)
+}
+
+#[test]
+fn shell_reconnects_when_server_does_not_finish() {
+    let server = MockServer::new();
+    let addr = server.addr.clone();
+    let handle = serve(server, garbage_without_finish);
+    let input = "1\nroot\nadmin\ndir\ndir\nexit\n";
+    let output = run_interactive_with(&addr, &[], input);
+
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("server is not responding, reconnect"),
+        "{}",
+        stderr
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("b.txt"));
+    let (closed, login, commands) = handle.join().unwrap();
+    assert!(closed);
+    assert_eq!(login, "login\nroot\nadmin\n");
+    assert_eq!(commands, ["root ~ dir"]);
+}
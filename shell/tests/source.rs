@@ -0,0 +1,84 @@
+//! source逐行执行宿主脚本中的命令
+mod common;
+
+use std::fs;
+
+use common::{run_interactive, serve, strip_request_id, MockServer};
+use utils::{ERROR_MESSAGE_PREFIX, EXIT_MSG};
+
+/// 模拟server：记录收到的命令和上传的内容，命令中含有fail时返回错误
+fn record_commands(server: MockServer) -> Vec<String> {
+    let (mut connection, _) = server.accept_login();
+    let mut commands = Vec::new();
+    while let Some(line) = connection.read_msg() {
+        if line.trim() == EXIT_MSG {
+            break;
+        }
+        let command = strip_request_id(&line);
+        if command.contains("newfile") {
+            let content = connection.request_input();
+            commands.push(format!(
+                "{} <- {}",
+                command,
+                String::from_utf8(content).unwrap()
+            ));
+        } else {
+            commands.push(command.clone());
+        }
+        if command.contains("fail") {
+            connection.send_contents(&format!("{}boom", ERROR_MESSAGE_PREFIX));
+        }
+        connection.finish();
+    }
+    commands
+}
+
+/// 写入脚本并以交互方式执行 source args，返回server收到的命令
+fn source(name: &str, script: &str, args: &str) -> Vec<String> {
+    let dir = std::env::temp_dir().join(format!("shell-{}-{}", std::process::id(), name));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("content.txt"), "from host").unwrap();
+    let script = script.replace("$DIR", dir.to_str().unwrap());
+    fs::write(dir.join("script"), script).unwrap();
+
+    let server = MockServer::new();
+    let addr = server.addr.clone();
+    let handle = serve(server, record_commands);
+    let input = format!(
+        "1\nroot\nadmin\nsource {} {}\nexit\n",
+        args,
+        dir.join("script").display()
+    );
+    let output = run_interactive(&addr, &input);
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    handle.join().unwrap()
+}
+
+#[test]
+fn script_creates_directory_and_file() {
+    let script = "# 建立目录和文件\nmd docs\n\nnewfile docs/a.txt < $DIR/content.txt\n";
+    assert_eq!(
+        source("create", script, ""),
+        ["root ~ md docs", "root ~ newfile docs/a.txt <- from host"]
+    );
+}
+
+#[test]
+fn script_stops_at_first_error_unless_keep_going() {
+    let script = "md a\nfail here\nmd b\nexit\nmd c\n";
+    assert_eq!(
+        source("stop", script, ""),
+        ["root ~ md a", "root ~ fail here"]
+    );
+    // -k 时跳过出错的命令和不允许的exit，继续执行
+    assert_eq!(
+        source("keep", script, "-k"),
+        [
+            "root ~ md a",
+            "root ~ fail here",
+            "root ~ md b",
+            "root ~ md c"
+        ]
+    );
+}
@@ -0,0 +1,557 @@
+//! 命令参数解析
+//!
+//! 每个命令在`COMMAND_SPECS`中声明自己的标志、带值选项和位置参数个数，
+//! 由`parse`统一解析和校验，`do_command`只需要按解析结果分发
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Error, ErrorKind},
+};
+
+/// 命令的参数定义
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub flags: &'static [&'static str],   // 不带值的标志，如 /s
+    pub options: &'static [&'static str], // 需要带一个值的选项
+    pub min_args: usize,                  // 最少位置参数个数
+    pub max_args: Option<usize>,          // 最多位置参数个数，None表示不限
+    pub mutating: bool,                   // 是否会修改文件系统
+}
+
+/// 解析后的命令参数
+#[derive(Debug, Default)]
+pub struct ParsedArgs {
+    pub name: String,
+    pub flags: HashSet<&'static str>,
+    pub options: HashMap<&'static str, String>,
+    pub positionals: Vec<String>,
+}
+
+impl ParsedArgs {
+    /// 是否带有某个标志
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.contains(flag)
+    }
+
+    /// 获取某个选项的值
+    pub fn option(&self, option: &str) -> Option<&str> {
+        self.options.get(option).map(|value| value.as_str())
+    }
+
+    /// 获取第i个位置参数
+    pub fn arg(&self, i: usize) -> Option<&str> {
+        self.positionals.get(i).map(|arg| arg.as_str())
+    }
+
+    /// 命令是否会修改文件系统，find -exec 执行的命令会修改时也算
+    pub fn is_mutating(&self) -> bool {
+        let mutating = |name: &str| find_spec(name).is_some_and(|spec| spec.mutating);
+        mutating(&self.name) || self.option("-exec").is_some_and(mutating)
+    }
+}
+
+pub const COMMAND_SPECS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "info",
+        usage: "info",
+        flags: &[],
+        options: &[],
+        min_args: 0,
+        max_args: Some(0),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "mount-info",
+        usage: "mount-info",
+        flags: &[],
+        options: &[],
+        min_args: 0,
+        max_args: Some(0),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "mount",
+        usage: "mount [host path]",
+        flags: &[],
+        options: &[],
+        min_args: 1,
+        max_args: Some(1),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "umount",
+        usage: "umount",
+        flags: &[],
+        options: &[],
+        min_args: 0,
+        max_args: Some(0),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "dir",
+        usage: "dir (path) (/s) (/i) (-a)",
+        flags: &["/s", "/i", "-a"],
+        options: &[],
+        min_args: 0,
+        max_args: Some(1),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "du",
+        usage: "du (path) (--apparent-size)",
+        flags: &["--apparent-size"],
+        options: &[],
+        min_args: 0,
+        max_args: Some(1),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "tree",
+        usage: "tree (path) (-L level)",
+        flags: &[],
+        options: &["-L"],
+        min_args: 0,
+        max_args: Some(1),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "treehash",
+        usage: "treehash (path)",
+        flags: &[],
+        options: &[],
+        min_args: 0,
+        max_args: Some(1),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "find",
+        usage: "find (path) (-name pattern) (-exec del|cat|du)",
+        flags: &[],
+        options: &["-name", "-exec"],
+        min_args: 0,
+        max_args: Some(1),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "stat",
+        usage: "stat [path] | stat --inode [id]",
+        flags: &[],
+        options: &["--inode"],
+        min_args: 0,
+        max_args: Some(1),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "blocks",
+        usage: "blocks [path]",
+        flags: &[],
+        options: &[],
+        min_args: 1,
+        max_args: Some(1),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "exists",
+        usage: "exists [path]",
+        flags: &[],
+        options: &[],
+        min_args: 1,
+        max_args: Some(1),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "cd",
+        usage: "cd [path]",
+        flags: &[],
+        options: &[],
+        min_args: 1,
+        max_args: Some(1),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "md",
+        usage: "md [path]",
+        flags: &[],
+        options: &[],
+        min_args: 1,
+        max_args: Some(1),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "rd",
+        usage: "rd [path]",
+        flags: &[],
+        options: &[],
+        min_args: 1,
+        max_args: Some(1),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "newfile",
+        usage: "newfile [filename] (-z)",
+        flags: &["-z"],
+        options: &[],
+        min_args: 1,
+        max_args: Some(1),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "edit",
+        usage: "edit [filename] (< host file)",
+        flags: &[],
+        options: &[],
+        min_args: 1,
+        max_args: Some(1),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "append",
+        usage: "append [filename] (< host file)",
+        flags: &[],
+        options: &[],
+        min_args: 1,
+        max_args: Some(1),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "mkfile",
+        usage: "mkfile [filename] [size]",
+        flags: &[],
+        options: &[],
+        min_args: 2,
+        max_args: Some(2),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "cat",
+        usage: "cat (--range start:len) [filename]...",
+        flags: &[],
+        options: &["--range"],
+        min_args: 1,
+        max_args: None,
+        mutating: false,
+    },
+    CommandSpec {
+        name: "del",
+        usage: "del [filename]",
+        flags: &[],
+        options: &[],
+        min_args: 1,
+        max_args: Some(1),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "undelete",
+        usage: "undelete [filename]",
+        flags: &[],
+        options: &[],
+        min_args: 1,
+        max_args: Some(1),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "emptytrash",
+        usage: "emptytrash",
+        flags: &[],
+        options: &[],
+        min_args: 0,
+        max_args: Some(0),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "copy",
+        usage: "copy (-f) (--preserve) (<host>)[src path]|- [dst path]",
+        flags: &["-f", "--preserve"],
+        options: &[],
+        min_args: 2,
+        max_args: Some(2),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "diff",
+        usage: "diff [path1] [path2]",
+        flags: &[],
+        options: &[],
+        min_args: 2,
+        max_args: Some(2),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "rename",
+        usage: "rename [path] [new name]",
+        flags: &[],
+        options: &[],
+        min_args: 2,
+        max_args: Some(2),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "check",
+        usage: "check",
+        flags: &[],
+        options: &[],
+        min_args: 0,
+        max_args: Some(0),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "verify",
+        usage: "verify",
+        flags: &[],
+        options: &[],
+        min_args: 0,
+        max_args: Some(0),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "fsck",
+        usage: "fsck (--repair) (--lost-found)",
+        flags: &["--repair", "--lost-found"],
+        options: &[],
+        min_args: 0,
+        max_args: Some(0),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "setcache",
+        usage: "setcache [instant|exit|tick]",
+        flags: &[],
+        options: &[],
+        min_args: 1,
+        max_args: Some(1),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "users",
+        usage: "users",
+        flags: &[],
+        options: &[],
+        min_args: 0,
+        max_args: Some(0),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "sessions",
+        usage: "sessions",
+        flags: &[],
+        options: &[],
+        min_args: 0,
+        max_args: Some(0),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "chroot",
+        usage: "chroot [username] (path)",
+        flags: &[],
+        options: &[],
+        min_args: 1,
+        max_args: Some(2),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "history",
+        usage: "history (search term)",
+        flags: &[],
+        options: &[],
+        min_args: 0,
+        max_args: Some(2),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "quota",
+        usage: "quota (username) (soft hard)",
+        flags: &[],
+        options: &[],
+        min_args: 0,
+        max_args: Some(3),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "resetsb",
+        usage: "resetsb",
+        flags: &[],
+        options: &[],
+        min_args: 0,
+        max_args: Some(0),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "defrag",
+        usage: "defrag [path]",
+        flags: &[],
+        options: &[],
+        min_args: 1,
+        max_args: Some(1),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "resize",
+        usage: "resize [size]",
+        flags: &[],
+        options: &[],
+        min_args: 1,
+        max_args: Some(1),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "bench",
+        usage: "bench [size]",
+        flags: &[],
+        options: &[],
+        min_args: 1,
+        max_args: Some(1),
+        mutating: true,
+    },
+    CommandSpec {
+        name: "formatting",
+        usage: "formatting",
+        flags: &[],
+        options: &[],
+        min_args: 0,
+        max_args: Some(0),
+        mutating: true,
+    },
+];
+
+/// 根据命令名查找命令定义
+pub fn find_spec(name: &str) -> Option<&'static CommandSpec> {
+    COMMAND_SPECS.iter().find(|spec| spec.name == name)
+}
+
+/// 解析命令，第一个元素为命令名，其余为参数
+pub fn parse(commands: &[String]) -> Result<ParsedArgs, Error> {
+    let name = commands.first().ok_or(Error::new(
+        ErrorKind::InvalidInput,
+        "empty command, input 'help' to see commands",
+    ))?;
+    let spec = find_spec(name).ok_or(Error::new(
+        ErrorKind::InvalidInput,
+        format!("unknown command: {}, input 'help' to see commands", name),
+    ))?;
+    parse_with(spec, commands)
+}
+
+/// 按给定的定义解析命令，第一个元素为命令名
+pub fn parse_with(spec: &'static CommandSpec, commands: &[String]) -> Result<ParsedArgs, Error> {
+    let name = &commands[0];
+    let mut parsed = ParsedArgs {
+        name: name.to_owned(),
+        ..Default::default()
+    };
+    let mut iter = commands[1..].iter();
+    while let Some(arg) = iter.next() {
+        if let Some(&flag) = spec.flags.iter().find(|flag| **flag == arg.as_str()) {
+            parsed.flags.insert(flag);
+        } else if let Some(&option) = spec.options.iter().find(|option| **option == arg.as_str()) {
+            // 选项的值为紧跟着的下一个参数
+            let value = iter.next().ok_or(arg_error(
+                spec,
+                &format!("option {} requires a value", option),
+            ))?;
+            parsed.options.insert(option, value.to_owned());
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            return Err(arg_error(spec, &format!("unknown option {}", arg)));
+        } else {
+            parsed.positionals.push(arg.to_owned());
+        }
+    }
+
+    // 校验位置参数个数
+    let n = parsed.positionals.len();
+    if n < spec.min_args {
+        return Err(arg_error(spec, "missing args"));
+    }
+    if let Some(max) = spec.max_args {
+        if n > max {
+            return Err(arg_error(spec, "too many args"));
+        }
+    }
+    Ok(parsed)
+}
+
+fn arg_error(spec: &CommandSpec, msg: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("{}: {}, usage: {}", spec.name, msg, spec.usage),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_line(line: &str) -> Result<ParsedArgs, Error> {
+        let commands: Vec<String> = line.split_whitespace().map(String::from).collect();
+        parse(&commands)
+    }
+
+    fn error_message(line: &str) -> String {
+        let err = parse_line(line).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        err.to_string()
+    }
+
+    #[test]
+    fn parses_flags_options_and_positionals() {
+        let parsed = parse_line("dir /s -a sub").unwrap();
+        assert_eq!(parsed.name, "dir");
+        assert!(parsed.has_flag("/s") && parsed.has_flag("-a"));
+        assert!(!parsed.has_flag("/i"));
+        assert_eq!(parsed.arg(0), Some("sub"));
+        assert_eq!(parsed.arg(1), None);
+
+        // 选项可以出现在位置参数之后
+        let parsed = parse_line("tree sub -L 2").unwrap();
+        assert_eq!(parsed.option("-L"), Some("2"));
+        assert_eq!(parsed.positionals, vec!["sub"]);
+
+        let parsed = parse_line("copy --preserve a.txt -f b.txt").unwrap();
+        assert!(parsed.has_flag("-f") && parsed.has_flag("--preserve"));
+        assert_eq!(parsed.positionals, vec!["a.txt", "b.txt"]);
+
+        // 单独的 - 是位置参数
+        let parsed = parse_line("copy - out.txt").unwrap();
+        assert_eq!(parsed.positionals, vec!["-", "out.txt"]);
+    }
+
+    #[test]
+    fn reports_missing_and_extra_args() {
+        let msg = error_message("cd");
+        assert!(msg.starts_with("cd: missing args, usage: cd"), "{}", msg);
+        let msg = error_message("copy a.txt");
+        assert!(msg.starts_with("copy: missing args"), "{}", msg);
+        let msg = error_message("info now");
+        assert!(
+            msg.starts_with("info: too many args, usage: info"),
+            "{}",
+            msg
+        );
+        let msg = error_message("rename a b c");
+        assert!(msg.contains("too many args"), "{}", msg);
+    }
+
+    #[test]
+    fn reports_unknown_and_incomplete_options() {
+        let msg = error_message("dir --all");
+        assert!(msg.starts_with("dir: unknown option --all"), "{}", msg);
+        let msg = error_message("tree -L");
+        assert!(
+            msg.starts_with("tree: option -L requires a value"),
+            "{}",
+            msg
+        );
+        let msg = error_message("frobnicate x");
+        assert!(msg.starts_with("unknown command: frobnicate"), "{}", msg);
+        assert!(parse(&[]).is_err());
+    }
+
+    #[test]
+    fn every_spec_accepts_its_minimum() {
+        for spec in COMMAND_SPECS {
+            let mut commands = vec![spec.name.to_string()];
+            commands.extend((0..spec.min_args).map(|i| format!("arg{}", i)));
+            let parsed = parse_with(spec, &commands).unwrap();
+            assert_eq!(parsed.positionals.len(), spec.min_args, "{}", spec.name);
+        }
+    }
+}
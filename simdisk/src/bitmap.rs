@@ -1,35 +1,52 @@
 use std::{
-    collections::HashMap,
+    cmp::min,
+    collections::HashSet,
     io::{self, Error, ErrorKind},
     sync::Arc,
 };
 
+use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 
 use crate::{
     block::{
-        clear_blocks, get_block_mut, get_blocks_buffers, read_blocks_to_cache, Block,
-        BLOCK_CACHE_MANAGER,
+        clear_blocks, deserialize, get_block_buffer, get_block_mut, get_blocks_buffers,
+        read_blocks_to_cache, write_block, BlockCacheManager, BLOCK_CACHE_MANAGER,
     },
+    config::CONFIG,
     fs_constants::*,
+    super_block::SuperBlock,
 };
 
 type BitmapDataType = bitmaps::Bitmap<8>;
 
+/// 上次申请到的位置，写回时存放在0号块的ALLOC_CURSOR_START_BYTE处，
+/// 重启后从这里继续申请，不用每次都从头扫描位图；旧镜像中为0
+#[derive(Serialize, Deserialize)]
+struct AllocCursors {
+    inode_byte_pos: u64,
+    data_byte_pos: u64,
+}
+
 #[derive(Default)]
 pub struct BitmapManager {
-    inodes: Vec<BitmapDataType>, // 以字节为单位存储inode位图缓存
-    datas: Vec<BitmapDataType>,  // 以字节为单位存储data位图缓存
-    last_inode_byte_pos: usize,  // 最后一次alloc inode bit所在的byte的位置
-    last_data_byte_pos: usize,   // 最后一次alloc data bit所在的byte的位置
+    inodes: Vec<BitmapDataType>,    // 以字节为单位存储inode位图缓存
+    datas: Vec<BitmapDataType>,     // 以字节为单位存储data位图缓存
+    last_inode_byte_pos: usize,     // 最后一次alloc inode bit所在的byte的位置
+    last_data_byte_pos: usize,      // 最后一次alloc data bit所在的byte的位置
+    data_bitmap_blocks: Vec<usize>, // data位图所在的块号
+    pub alloc_calls: usize,         // 申请bit的次数，每次申请只持有一次写锁
 }
 
 impl BitmapManager {
+    /// 按超级块中记录的布局读入位图
     pub async fn read(&mut self) -> io::Result<()> {
+        let sb = SuperBlock::read().await?;
+        let data_bitmap_blocks = sb.data_bitmap_blocks();
         // 读入位图区快
-        let range = INODE_BITMAP_START_BLOCK..DATA_BITMAP_START_BLOCK + DATA_BITMAP_NUM;
+        let range = INODE_BITMAP_START_BLOCK..INODE_BITMAP_START_BLOCK + INODE_BITMAP_NUM;
         let mut block_args = Vec::new();
-        for block_id in range {
+        for block_id in range.chain(data_bitmap_blocks.iter().copied()) {
             block_args.push((block_id, 0, BLOCK_SIZE));
         }
         let buffers = get_blocks_buffers(&block_args).await?;
@@ -51,44 +68,143 @@ impl BitmapManager {
                 datas.push(bitmap);
             }
         }
+        // 位图的最后一块可能只用了一部分
+        datas.truncate(sb.data_block_num() / 8);
+
+        // 恢复上次申请到的位置，超出位图范围时（如损坏的镜像）从头开始
+        let buffer = get_block_buffer(0, ALLOC_CURSOR_START_BYTE, BLOCK_SIZE).await?;
+        let cursors: AllocCursors = deserialize(&buffer)?;
+        let clamp = |pos: u64, len: usize| {
+            if (pos as usize) < len {
+                pos as usize
+            } else {
+                0
+            }
+        };
 
         *self = Self {
+            last_inode_byte_pos: clamp(cursors.inode_byte_pos, inodes.len()),
+            last_data_byte_pos: clamp(cursors.data_byte_pos, datas.len()),
             inodes,
             datas,
-            last_inode_byte_pos: 0,
-            last_data_byte_pos: 0,
+            data_bitmap_blocks,
+            alloc_calls: self.alloc_calls,
         };
 
         Ok(())
     }
 
+    /// 扩容后扩展data位图，新增的数据块都是空闲的
+    pub fn grow(&mut self, sb: &SuperBlock) {
+        self.datas
+            .resize(sb.data_block_num() / 8, BitmapDataType::new());
+        self.data_bitmap_blocks = sb.data_bitmap_blocks();
+    }
+
     /// 返回bit_id
     fn alloc_bit(&mut self, bitmap_type: BitmapType) -> io::Result<u32> {
+        Ok(self.alloc_bits(bitmap_type, 1)?[0])
+    }
+
+    /// 一次性申请n个bit，返回bit_id数组；空闲bit不足时不修改位图并返回Err
+    fn alloc_bits(&mut self, bitmap_type: BitmapType, n: usize) -> io::Result<Vec<u32>> {
+        self.alloc_calls += 1;
         let (bitmap, prev_byte_pos) = match bitmap_type {
             BitmapType::Inode => (&mut self.inodes, &mut self.last_inode_byte_pos),
             BitmapType::Data => (&mut self.datas, &mut self.last_data_byte_pos),
         };
 
+        let mut ids = Vec::with_capacity(n);
+        if n == 0 {
+            return Ok(ids);
+        }
         let mut cur_byte_pos = *prev_byte_pos;
         loop {
             let byte = &mut bitmap[cur_byte_pos];
-            // 如果找到了非全满的byte
-            if let Some(bit_pos) = byte.first_false_index() {
-                let id = cur_byte_pos * 8 + bit_pos;
-                byte.set(bit_pos, true); // 设置为已占用
+            // 取完这个byte中所有的空闲位
+            while ids.len() < n {
+                match byte.first_false_index() {
+                    Some(bit_pos) => {
+                        byte.set(bit_pos, true); // 设置为已占用
+                        ids.push((cur_byte_pos * 8 + bit_pos) as u32);
+                    }
+                    None => break,
+                }
+            }
+            if ids.len() == n {
                 *prev_byte_pos = cur_byte_pos; // 更新位置
-                return Ok(id as u32);
+                return Ok(ids);
             }
 
             cur_byte_pos = (cur_byte_pos + 1) % bitmap.len();
             if cur_byte_pos == *prev_byte_pos {
-                // 回到了同一个位置还没找到
+                // 回到了同一个位置还没找到足够的bit
                 break;
             }
         }
+        // 回滚已经占用的bit
+        for id in ids {
+            bitmap[id as usize / 8].set(id as usize % 8, false);
+        }
         Err(Error::new(ErrorKind::OutOfMemory, "no valid bit"))
     }
 
+    /// 申请n个连续的bit，从头查找第一段足够长的空闲bit；找不到时不修改位图并返回Err
+    fn alloc_contiguous_bits(&mut self, bitmap_type: BitmapType, n: usize) -> io::Result<Vec<u32>> {
+        self.alloc_calls += 1;
+        let bitmap = match bitmap_type {
+            BitmapType::Inode => &mut self.inodes,
+            BitmapType::Data => &mut self.datas,
+        };
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        let mut run_start = 0;
+        for bit_id in 0..bitmap.len() * 8 {
+            if bitmap[bit_id / 8].get(bit_id % 8) {
+                run_start = bit_id + 1;
+                continue;
+            }
+            if bit_id + 1 - run_start == n {
+                for id in run_start..=bit_id {
+                    bitmap[id / 8].set(id % 8, true);
+                }
+                return Ok((run_start as u32..=bit_id as u32).collect());
+            }
+        }
+        Err(Error::new(
+            ErrorKind::OutOfMemory,
+            format!("no {} contiguous free bits", n),
+        ))
+    }
+
+    /// 用给定的占用情况覆盖位图，返回(原来为1现在置0的bit, 原来为0现在置1的bit)
+    fn reset_bits(
+        &mut self,
+        bitmap_type: BitmapType,
+        used: &HashSet<usize>,
+    ) -> (Vec<usize>, Vec<usize>) {
+        let bitmap = match bitmap_type {
+            BitmapType::Inode => &mut self.inodes,
+            BitmapType::Data => &mut self.datas,
+        };
+        let mut freed = Vec::new();
+        let mut marked = Vec::new();
+        for (i, byte) in bitmap.iter_mut().enumerate() {
+            for j in 0..8 {
+                let bit_id = i * 8 + j;
+                let in_use = used.contains(&bit_id);
+                match (byte.get(j), in_use) {
+                    (true, false) => freed.push(bit_id),
+                    (false, true) => marked.push(bit_id),
+                    _ => {}
+                }
+                byte.set(j, in_use);
+            }
+        }
+        (freed, marked)
+    }
+
     // 返回false如果bit本身已经是0
     fn dealloc_bit(&mut self, bitmap_type: BitmapType, bit_id: usize) -> bool {
         let bitmap = match bitmap_type {
@@ -102,48 +218,59 @@ impl BitmapManager {
         bitmap[byte_pos].set(bit_pos, false)
     }
 
-    /// 读入所有位图区块缓存
+    /// 读入所有位图区块缓存，同时记录申请到的位置
     pub async fn cache_to_block(&self) -> io::Result<()> {
-        let block_ids: Vec<_> =
-            (INODE_BITMAP_START_BLOCK..DATA_BITMAP_START_BLOCK + DATA_BITMAP_NUM).collect();
+        // 只读模式下旧镜像的用户信息可能还在申请位置处，不能覆盖
+        if !CONFIG.readonly {
+            let cursors = AllocCursors {
+                inode_byte_pos: self.last_inode_byte_pos as u64,
+                data_byte_pos: self.last_data_byte_pos as u64,
+            };
+            write_block(&cursors, 0, ALLOC_CURSOR_START_BYTE).await?;
+        }
+
+        let block_ids: Vec<_> = (INODE_BITMAP_START_BLOCK
+            ..INODE_BITMAP_START_BLOCK + INODE_BITMAP_NUM)
+            .chain(self.data_bitmap_blocks.iter().copied())
+            .collect();
         read_blocks_to_cache(&block_ids).await?;
 
         // 取锁
         let blk = Arc::clone(&BLOCK_CACHE_MANAGER);
         let mut bcm = blk.write().await;
-        let block_cache = &mut bcm.block_cache;
 
         // 写入inode位图块
         let inode_block_ids = &block_ids[..INODE_BITMAP_NUM];
-        self.write_bitmaps_to_blocks(inode_block_ids, BitmapType::Inode, block_cache)?;
+        self.write_bitmaps_to_blocks(inode_block_ids, BitmapType::Inode, &mut bcm)?;
 
         // 写入data位图块
         let data_block_ids = &block_ids[INODE_BITMAP_NUM..];
-        self.write_bitmaps_to_blocks(data_block_ids, BitmapType::Data, block_cache)
+        self.write_bitmaps_to_blocks(data_block_ids, BitmapType::Data, &mut bcm)
     }
 
-    /// 将位图缓存写入块缓存
+    /// 将位图缓存写入块缓存，位图依次存放在block_ids中
     fn write_bitmaps_to_blocks(
         &self,
         block_ids: &[usize],
         bitmap_type: BitmapType,
-        block_cache: &mut HashMap<usize, Block>,
+        bcm: &mut BlockCacheManager,
     ) -> io::Result<()> {
-        let (start_block_id, bitmap) = match bitmap_type {
-            BitmapType::Inode => (INODE_BITMAP_START_BLOCK, &self.inodes),
-            BitmapType::Data => (DATA_BITMAP_START_BLOCK, &self.datas),
+        let bitmap = match bitmap_type {
+            BitmapType::Inode => &self.inodes,
+            BitmapType::Data => &self.datas,
         };
-        for block_id in block_ids {
-            let inner_block_index = block_id - start_block_id; // 计算这个块是对应位图块中的第几块
-            let cache_start_index = inner_block_index * BLOCK_SIZE; // 计算在缓存vec中的起始偏移量
-            let chche_end_index = (inner_block_index + 1) * BLOCK_SIZE; // 计算在缓存vec中的终止偏移量
+        for (inner_block_index, block_id) in block_ids.iter().enumerate() {
+            let cache_start_index = min(inner_block_index * BLOCK_SIZE, bitmap.len()); // 计算在缓存vec中的起始偏移量
+            let chche_end_index = min((inner_block_index + 1) * BLOCK_SIZE, bitmap.len()); // 计算在缓存vec中的终止偏移量
 
-            let buffers: Vec<_> = bitmap[cache_start_index..chche_end_index] // 将位图缓存中的bitmap转换成u8
+            // 将位图缓存中的bitmap转换成u8，位图的最后一块不足的部分补0
+            let mut buffers: Vec<_> = bitmap[cache_start_index..chche_end_index]
                 .iter()
                 .map(|bitmap| bitmap.into_value())
                 .collect();
+            buffers.resize(BLOCK_SIZE, 0);
 
-            let block = get_block_mut(block_id, block_ids, block_cache)?;
+            let block = get_block_mut(block_id, block_ids, bcm)?;
             block.modify_bytes(|bytes| bytes.clone_from_slice(&buffers));
         }
         Ok(())
@@ -162,6 +289,32 @@ pub async fn alloc_bit(bitmap_type: BitmapType) -> Result<u32, Error> {
     Ok(bit_id)
 }
 
+/// 一次性获取n个空闲bit的位置，只持有一次位图的写锁
+pub async fn alloc_bits(bitmap_type: BitmapType, n: usize) -> Result<Vec<u32>, Error> {
+    let bit_ids = Arc::clone(&BITMAP_MANAGER)
+        .write()
+        .await
+        .alloc_bits(bitmap_type, n)?;
+
+    trace!("alloc {} ids for {:?}", bit_ids.len(), bitmap_type);
+    Ok(bit_ids)
+}
+
+/// 一次性申请n个连续的bit，返回bit_id数组
+pub async fn alloc_contiguous_bits(bitmap_type: BitmapType, n: usize) -> Result<Vec<u32>, Error> {
+    let bit_ids = Arc::clone(&BITMAP_MANAGER)
+        .write()
+        .await
+        .alloc_contiguous_bits(bitmap_type, n)?;
+
+    trace!(
+        "alloc {} contiguous ids for {:?}",
+        bit_ids.len(),
+        bitmap_type
+    );
+    Ok(bit_ids)
+}
+
 /// 在inode位图中dealloc对应的bit
 pub async fn dealloc_inode_bit(inode_id: usize) -> bool {
     Arc::clone(&BITMAP_MANAGER)
@@ -195,6 +348,35 @@ pub async fn dealloc_data_bits(block_ids: &[usize]) {
     clear_blocks(&block_to_clear).await.unwrap();
 }
 
+/// 统计位图与给定的占用情况的差异，返回(位图为1但未被占用的bit, 被占用但位图为0的bit)
+pub async fn diff_bits(bitmap_type: BitmapType, used: &HashSet<usize>) -> (Vec<usize>, Vec<usize>) {
+    let bitmap = get_bitmaps(bitmap_type).await;
+    let mut leaked = Vec::new();
+    let mut missing = Vec::new();
+    for (i, byte) in bitmap.iter().enumerate() {
+        for j in 0..8 {
+            let bit_id = i * 8 + j;
+            match (byte.get(j), used.contains(&bit_id)) {
+                (true, false) => leaked.push(bit_id),
+                (false, true) => missing.push(bit_id),
+                _ => {}
+            }
+        }
+    }
+    (leaked, missing)
+}
+
+/// 按给定的占用情况重建位图，返回(被释放的bit, 被重新标记的bit)
+pub async fn reset_bits(
+    bitmap_type: BitmapType,
+    used: &HashSet<usize>,
+) -> (Vec<usize>, Vec<usize>) {
+    Arc::clone(&BITMAP_MANAGER)
+        .write()
+        .await
+        .reset_bits(bitmap_type, used)
+}
+
 async fn count_bits(bitmap_type: BitmapType) -> usize {
     //读取缓存
     let bitmap_manager = Arc::clone(&BITMAP_MANAGER);
@@ -225,21 +407,39 @@ pub async fn get_data_bitmaps() -> Vec<BitmapDataType> {
     get_bitmaps(BitmapType::Data).await
 }
 
+/// inode位图中inode_id对应的位是否为1
+pub async fn is_inode_allocated(inode_id: usize) -> bool {
+    let manager = Arc::clone(&BITMAP_MANAGER);
+    let read_lock = manager.read().await;
+    read_lock.inodes[inode_id / 8].get(inode_id % 8)
+}
+
 /// 统计申请了多少inode,第一个返回值为已申请，第二个返回值为未申请
 pub async fn count_inodes() -> (usize, usize) {
     let alloced = count_bits(BitmapType::Inode).await;
-    (alloced, INODE_MAX_NUM - alloced)
+    // 位图损坏时已申请的数量可能超过inode总数，交给check报告
+    (alloced, INODE_MAX_NUM.saturating_sub(alloced))
 }
 
 /// 统计申请了多少数据块,第一个返回值为已申请，第二个返回值为未申请
 pub async fn count_data_blocks() -> (usize, usize) {
     let alloced = count_bits(BitmapType::Data).await;
-    (alloced, DATA_BLOCK_MAX_NUM - alloced)
+    (alloced, data_block_num().await - alloced)
 }
 
 /// 统计空闲data block数
 pub async fn count_valid_data_blocks() -> usize {
-    DATA_BLOCK_MAX_NUM - count_bits(BitmapType::Data).await
+    data_block_num().await - count_bits(BitmapType::Data).await
+}
+
+/// 位图缓存的位数
+pub async fn bitmap_bits(bitmap_type: BitmapType) -> usize {
+    get_bitmaps(bitmap_type).await.len() * 8
+}
+
+/// 数据区的块数，扩容后会增加
+pub async fn data_block_num() -> usize {
+    Arc::clone(&BITMAP_MANAGER).read().await.datas.len() * 8
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -253,3 +453,208 @@ lazy_static! {
     pub static ref BITMAP_MANAGER: Arc<RwLock<BitmapManager>> =
         Arc::new(RwLock::new(BitmapManager::default()));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block::{get_owned_block_ids, sync_all_block_cache},
+        simple_fs,
+        test_utils::{exec, lookup, mktree, setup, used_blocks},
+    };
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn alloc_bits_returns_distinct_free_ids() {
+        let _fs = setup().await;
+        let before = used_blocks().await;
+        let ids = alloc_bits(BitmapType::Data, 300).await.unwrap();
+        assert_eq!(ids.len(), 300);
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), 300);
+        assert_eq!(used_blocks().await, before + 300);
+        // 再次申请不会得到已申请的位
+        let more = alloc_bits(BitmapType::Data, 10).await.unwrap();
+        assert!(more.iter().all(|id| !ids.contains(id)));
+        assert!(alloc_bits(BitmapType::Data, data_block_num().await)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn file_spanning_first_indirect_gets_valid_blocks() {
+        let _fs = setup().await;
+        let blocks = DIRECT_BLOCK_NUM + 100;
+        let content: String = (0..blocks * BLOCK_SIZE)
+            .map(|i| char::from(b'a' + (i % 26) as u8))
+            .collect();
+        let before = used_blocks().await;
+        let start = std::time::Instant::now();
+        mktree(&[&format!("big.txt:{}", content)]).await;
+        println!("created {} blocks in {:?}", blocks, start.elapsed());
+
+        let ids = get_owned_block_ids(&lookup("big.txt").await).await.unwrap();
+        // 数据块加上一个一级间接块
+        assert_eq!(ids.len(), blocks + 1);
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len());
+        assert!(ids.iter().all(|id| *id >= DATA_START_BLOCK));
+        assert_eq!(used_blocks().await, before + blocks + 1);
+        assert_eq!(exec("cat big.txt").await.unwrap().unwrap(), content);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn large_file_takes_bitmap_lock_once() {
+        let _fs = setup().await;
+        let alloc_calls = || async { BITMAP_MANAGER.read().await.alloc_calls };
+        let blocks = DIRECT_BLOCK_NUM + FISRT_MAX + 300;
+        let before = (alloc_calls().await, used_blocks().await);
+        mktree(&[&format!("big.txt:{}", "b".repeat(blocks * BLOCK_SIZE))]).await;
+        // 数据块和各级间接块一次申请，另外申请一次inode
+        let allocated = used_blocks().await - before.1;
+        assert!(allocated > blocks, "{}", allocated);
+        assert_eq!(alloc_calls().await - before.0, 2);
+    }
+
+    /// 清空块缓存和位图缓存后从镜像读入位图，位图的修改需要事先写回
+    async fn reload_bitmap() {
+        let manager = Arc::clone(&BLOCK_CACHE_MANAGER);
+        manager.write().await.sync_and_clear_cache().await.unwrap();
+        let bitmap_manager = Arc::clone(&BITMAP_MANAGER);
+        let mut bitmap = bitmap_manager.write().await;
+        *bitmap = BitmapManager::default();
+        bitmap.read().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn alloc_resumes_from_cursor_after_restart() {
+        let _fs = setup().await;
+        let ids = alloc_bits(BitmapType::Data, 2000).await.unwrap();
+        let last = *ids.iter().max().unwrap();
+        // 释放前面的一块，从头扫描时会先申请到它
+        let early = ids[10];
+        dealloc_data_bit(early as usize + DATA_START_BLOCK).await;
+        let cursor = BITMAP_MANAGER.read().await.last_data_byte_pos;
+        assert_eq!(cursor, last as usize / 8);
+
+        // 写回后重新读入，相当于重启server
+        sync_all_block_cache().await.unwrap();
+        reload_bitmap().await;
+        assert_eq!(BITMAP_MANAGER.read().await.last_data_byte_pos, cursor);
+        let next = alloc_bits(BitmapType::Data, 1).await.unwrap()[0];
+        assert!(next > last, "{} allocated after restart", next);
+        dealloc_data_bit(next as usize + DATA_START_BLOCK).await;
+
+        // 没有记录位置的旧镜像从头开始
+        let zeros = AllocCursors {
+            inode_byte_pos: 0,
+            data_byte_pos: 0,
+        };
+        sync_all_block_cache().await.unwrap();
+        write_block(&zeros, 0, ALLOC_CURSOR_START_BYTE)
+            .await
+            .unwrap();
+        reload_bitmap().await;
+        assert_eq!(alloc_bits(BitmapType::Data, 1).await.unwrap()[0], early);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn out_of_range_cursor_is_clamped() {
+        let _fs = setup().await;
+        sync_all_block_cache().await.unwrap();
+        let cursors = AllocCursors {
+            inode_byte_pos: u64::MAX,
+            data_byte_pos: (data_block_num().await / 8) as u64,
+        };
+        write_block(&cursors, 0, ALLOC_CURSOR_START_BYTE)
+            .await
+            .unwrap();
+        let bitmap_manager = Arc::clone(&BITMAP_MANAGER);
+        let mut bitmap = bitmap_manager.write().await;
+        bitmap.read().await.unwrap();
+        assert_eq!(bitmap.last_inode_byte_pos, 0);
+        assert_eq!(bitmap.last_data_byte_pos, 0);
+        drop(bitmap);
+        assert!(alloc_bits(BitmapType::Data, 1).await.is_ok());
+    }
+
+    /// 把位图缓存全部置1，并在末尾追加extra字节全为1的位，
+    /// 使位图比超级块声明的更长、已申请的数量超过区域的大小，返回原来的位图
+    async fn over_count(bitmap_type: BitmapType, extra: usize) -> Vec<BitmapDataType> {
+        let mut manager = BITMAP_MANAGER.write().await;
+        let bitmap = match bitmap_type {
+            BitmapType::Inode => &mut manager.inodes,
+            BitmapType::Data => &mut manager.datas,
+        };
+        let mut full = BitmapDataType::new();
+        full.invert();
+        let len = bitmap.len();
+        std::mem::replace(bitmap, vec![full; len + extra])
+    }
+
+    /// 恢复over_count之前的位图
+    async fn restore(bitmap_type: BitmapType, original: Vec<BitmapDataType>) {
+        let mut manager = BITMAP_MANAGER.write().await;
+        match bitmap_type {
+            BitmapType::Inode => manager.inodes = original,
+            BitmapType::Data => manager.datas = original,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn check_flags_over_counted_bitmaps() {
+        let _fs = setup().await;
+        mktree(&["a.txt:a"]).await;
+        assert!(simple_fs::check_bitmap_counts().await.unwrap().is_empty());
+        exec("check").await.unwrap();
+
+        let original = over_count(BitmapType::Inode, 1).await;
+        let problems = simple_fs::check_bitmap_counts().await.unwrap();
+        assert_eq!(
+            problems,
+            [
+                format!(
+                    "inode bitmap has {} bits, super block declares {}, expected {}",
+                    INODE_MAX_NUM + 8,
+                    INODE_MAX_NUM,
+                    INODE_MAX_NUM
+                ),
+                format!(
+                    "{} inodes allocated, but at most {} fit in the inode area",
+                    INODE_MAX_NUM + 8,
+                    INODE_MAX_NUM
+                ),
+            ]
+        );
+        restore(BitmapType::Inode, original).await;
+
+        let blocks = data_block_num().await;
+        let original = over_count(BitmapType::Data, 2).await;
+        let problems = simple_fs::check_bitmap_counts().await.unwrap();
+        assert_eq!(
+            problems,
+            [
+                format!(
+                    "data bitmap has {} bits, super block declares {} data blocks",
+                    blocks + 16,
+                    blocks
+                ),
+                format!(
+                    "{} data blocks allocated, but the data area has {} blocks",
+                    blocks + 16,
+                    blocks
+                ),
+            ]
+        );
+        restore(BitmapType::Data, original).await;
+
+        // check命令报告位图和超级块大小不一致，无法自动修复
+        BITMAP_MANAGER
+            .write()
+            .await
+            .inodes
+            .push(BitmapDataType::new());
+        let err = exec("check").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("inode bitmap has"), "{}", err);
+        BITMAP_MANAGER.write().await.inodes.pop();
+        exec("check").await.unwrap();
+    }
+}
@@ -1,20 +1,19 @@
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
+    cmp::min,
     collections::HashMap,
-    fs::File,
-    io::{self, Error, ErrorKind, Read, Seek},
+    io::{self, Error, ErrorKind},
     mem::size_of,
     sync::Arc,
 };
-use tokio::{
-    io::{AsyncSeekExt, AsyncWriteExt},
-    sync::RwLock,
-};
+use tokio::sync::RwLock;
 
 use crate::{
-    bitmap::{self, alloc_bit, dealloc_data_bit, BitmapType, BITMAP_MANAGER},
+    bitmap::{self, alloc_bits, dealloc_data_bit, dealloc_data_bits, BitmapType, BITMAP_MANAGER},
+    config::CONFIG,
+    device::{self, BlockDevice},
     fs_constants::*,
-    inode::Inode,
+    inode::{blocks_for_size, Inode},
     simple_fs::SFS,
 };
 
@@ -42,49 +41,174 @@ impl Block {
     }
 }
 
+/// 块读写的统计信息
+#[derive(Default, Clone, Copy, Debug)]
+pub struct BlockIoStats {
+    pub cache_hits: usize,      // 命中缓存的块数
+    pub disk_reads: usize,      // 从磁盘读入的块数
+    pub disk_read_calls: usize, // 读磁盘的次数，连续的块合并为一次读取
+    pub disk_writes: usize,     // 写入磁盘的块数
+}
+
 pub struct BlockCacheManager {
     pub block_cache: HashMap<usize, Block>,
     pub cahce_method: CacheMethod,
+    pub stats: BlockIoStats,
+    device: Box<dyn BlockDevice>, // 块实际存放的设备
 }
 
 impl BlockCacheManager {
-    pub fn new() -> Self {
+    pub fn new(device: Box<dyn BlockDevice>) -> Self {
         Self {
             block_cache: HashMap::new(),
             cahce_method: CacheMethod::Immediately,
+            stats: BlockIoStats::default(),
+            device,
+        }
+    }
+
+    /// 清零块读写统计
+    pub fn reset_stats(&mut self) {
+        self.stats = BlockIoStats::default();
+    }
+
+    /// 在已经持有锁的情况下读取缓存（不再加锁），
+    /// 不在缓存中的块按块号排序后，连续的一段块只用一次读取
+    fn read_blocks_to_cache_unblocking(&mut self, block_id_addrs: &[usize]) -> Result<(), Error> {
+        let mut missing = Vec::new();
+        for block_id in block_id_addrs {
+            if self.block_cache.contains_key(block_id) {
+                self.stats.cache_hits += 1;
+            } else {
+                missing.push(*block_id);
+            }
+        }
+        if missing.is_empty() {
+            return Ok(());
+        }
+        missing.sort_unstable();
+        missing.dedup();
+
+        let mut start = 0;
+        while start < missing.len() {
+            // 找到从missing[start]开始的连续块
+            let mut end = start + 1;
+            while end < missing.len()
+                && end - start < READ_AHEAD_MAX_BLOCKS
+                && missing[end] == missing[end - 1] + 1
+            {
+                end += 1;
+            }
+            let run = &missing[start..end];
+            let offset = run[0] * BLOCK_SIZE;
+            let mut buffer = vec![0; run.len() * BLOCK_SIZE];
+            if self.device.read_blocks(run[0], &mut buffer).is_err() {
+                let e = format!("cannot read buffer at {}", offset);
+                error!("{}", e);
+                return Err(Error::new(ErrorKind::AddrNotAvailable, e));
+            }
+            self.stats.disk_read_calls += 1;
+            for (block_id, bytes) in run.iter().zip(buffer.chunks(BLOCK_SIZE)) {
+                let mut block = Block {
+                    block_id: *block_id,
+                    bytes: [0; BLOCK_SIZE],
+                    modified: false,
+                };
+                block.bytes.copy_from_slice(bytes);
+                self.stats.disk_reads += 1;
+                self.block_cache.insert(*block_id, block);
+            }
+            trace!("blocks {}..={} push to cache", run[0], run[run.len() - 1]);
+            start = end;
+        }
+        Ok(())
+    }
+
+    /// 在已经持有锁的情况下将内容依次写入对应的块（不再加锁），
+    /// 整块覆盖且不在缓存中的块直接放入缓存，不需要先从磁盘读出
+    fn write_contents_unblocking<T: AsRef<[u8]>>(
+        &mut self,
+        contents: &[T],
+        block_ids: &[usize],
+    ) -> Result<(), Error> {
+        let partial: Vec<_> = block_ids
+            .iter()
+            .zip(contents)
+            .filter(|(_, content)| content.as_ref().len() < BLOCK_SIZE)
+            .map(|(block_id, _)| *block_id)
+            .collect();
+        self.read_blocks_to_cache_unblocking(&partial)?;
+        for (block_id, content) in block_ids.iter().zip(contents) {
+            let content = content.as_ref();
+            assert!(BLOCK_SIZE >= content.len());
+            let block = self.block_cache.entry(*block_id).or_insert(Block {
+                block_id: *block_id,
+                bytes: [0; BLOCK_SIZE],
+                modified: false,
+            });
+            block.modify_bytes(|bytes_arr| {
+                bytes_arr[..content.len()].clone_from_slice(content);
+            });
         }
+        Ok(())
     }
 
     /// 将所有块缓存写入磁盘，同时清空缓存
     pub async fn sync_and_clear_cache(&mut self) -> Result<(), Error> {
-        let mut file: Option<tokio::fs::File> = None;
+        self.sync_cache(false)
+    }
+
+    /// 将修改过的块写入磁盘。keep_warm为true时保留缓存，只清除修改位，之后读这些块不需要再读磁盘；
+    /// 缓存没有淘汰机制，超过WARM_CACHE_MAX_BLOCKS块时仍然清空
+    pub fn sync_cache(&mut self, keep_warm: bool) -> Result<(), Error> {
         for block in self.block_cache.values_mut() {
             if !block.modified {
                 continue;
             }
+            trace!("sync block {}", block.block_id);
+            self.device.write_block(block.block_id, &block.bytes)?;
+            self.stats.disk_writes += 1;
+            block.modified = false;
+        }
 
-            if file.is_none() {
-                file = Some(
-                    tokio::fs::OpenOptions::new()
-                        .write(true)
-                        .open(FS_FILE_NAME)
-                        .await?,
-                )
-            }
-
-            if let Some(file) = &mut file {
-                let buf = block.bytes;
-                trace!("sync block {}", block.block_id);
-                let offset = block.block_id * BLOCK_SIZE;
-                let pos = tokio::io::SeekFrom::Start(offset as u64);
-                file.seek(pos).await?;
-                file.write_all(&buf).await?;
-            }
+        if !keep_warm || self.block_cache.len() > WARM_CACHE_MAX_BLOCKS {
+            self.block_cache.clear();
         }
+        Ok(())
+    }
 
+    /// 清空块设备和缓存，用于格式化
+    pub fn reset_device(&mut self) -> Result<(), Error> {
+        self.device.reset()?;
         self.block_cache.clear();
         Ok(())
     }
+
+    /// 将块设备扩大到size字节
+    pub fn grow_device(&mut self, size: u64) -> Result<(), Error> {
+        self.device.grow(size)
+    }
+
+    /// 块设备的名字和大小（字节）
+    pub fn describe_device(&self) -> Result<(String, u64), Error> {
+        self.device.describe()
+    }
+
+    /// 换上新的块设备并清空缓存，返回原来的设备；缓存中修改过的块需要事先写回
+    pub fn replace_device(&mut self, device: Box<dyn BlockDevice>) -> Box<dyn BlockDevice> {
+        self.block_cache.clear();
+        std::mem::replace(&mut self.device, device)
+    }
+
+    /// 是否挂载了镜像
+    pub fn is_attached(&self) -> bool {
+        self.device.is_attached()
+    }
+}
+
+/// 是否挂载了镜像
+pub async fn is_attached() -> bool {
+    Arc::clone(&BLOCK_CACHE_MANAGER).read().await.is_attached()
 }
 
 pub async fn is_sync_scheduled() -> bool {
@@ -112,43 +236,7 @@ pub async fn is_sync_immediately() -> bool {
 pub async fn read_blocks_to_cache(block_id_addrs: &[usize]) -> Result<(), Error> {
     let blk = Arc::clone(&BLOCK_CACHE_MANAGER);
     let mut w = blk.write().await;
-    read_blocks_to_cache_unblocking(block_id_addrs, &mut w.block_cache)
-}
-
-/// 在已经持有锁的情况下读取缓存（不再加锁）
-fn read_blocks_to_cache_unblocking(
-    block_id_addrs: &[usize],
-    block_cache: &mut HashMap<usize, Block>,
-) -> Result<(), Error> {
-    let mut file = None;
-    for block_id in block_id_addrs {
-        if block_cache.contains_key(block_id) {
-            continue;
-        }
-
-        if file.is_none() {
-            file = Some(File::open(FS_FILE_NAME)?);
-        }
-
-        let mut block = Block {
-            block_id: *block_id,
-            bytes: [0; BLOCK_SIZE],
-            modified: false,
-        };
-
-        let offset = block_id * BLOCK_SIZE;
-        if let Some(file) = &mut file {
-            file.seek(std::io::SeekFrom::Start(offset as u64))?;
-            if file.read_exact(&mut block.bytes).is_err() {
-                let e = format!("cannot read buffer at {}", offset);
-                error!("{}", e);
-                return Err(Error::new(ErrorKind::AddrNotAvailable, e));
-            }
-            block_cache.insert(*block_id, block);
-            trace!("block {} push to cache", block_id);
-        }
-    }
-    Ok(())
+    w.read_blocks_to_cache_unblocking(block_id_addrs)
 }
 
 /// 获取指定块中的某一段缓存
@@ -194,31 +282,37 @@ pub async fn get_blocks_buffers(
     }
 }
 
-/// 将文件内容分组批量写入缓存
-pub async fn write_file_content_to_blocks(
-    contents: &[String],
+/// 将文件内容分组批量写入缓存，整个过程只持有一次写锁
+pub async fn write_file_content_to_blocks<T: AsRef<[u8]>>(
+    contents: &[T],
     block_ids: &[usize],
 ) -> Result<(), Error> {
     trace!("write block{:?}", block_ids);
-    // 当块不在缓存中时 读入缓存
-    read_blocks_to_cache(block_ids).await?;
     let blk = Arc::clone(&BLOCK_CACHE_MANAGER);
     let mut bcm = blk.write().await;
-    let block_cache = &mut bcm.block_cache;
-    for (i, block_id) in block_ids.iter().enumerate() {
-        let block = get_block_mut(block_id, block_ids, block_cache)?;
-        let content = contents[i].clone();
-        assert!(BLOCK_SIZE >= content.len());
-        block.modify_bytes(|bytes_arr| {
-            let end = content.len();
-            bytes_arr[..end].clone_from_slice(content.as_bytes());
-        });
-    }
+    bcm.write_contents_unblocking(contents, block_ids)
+}
+
+/// 将字节直接写入指定块的`start_byte`处
+pub async fn write_bytes_to_block(
+    bytes: &[u8],
+    block_id: usize,
+    start_byte: usize,
+) -> Result<(), Error> {
+    check_overflow(block_id, start_byte, bytes.len())?;
+    let block_ids = [block_id];
+    read_blocks_to_cache(&block_ids).await?;
+    let blk = Arc::clone(&BLOCK_CACHE_MANAGER);
+    let mut bcm = blk.write().await;
+    let block = get_block_mut(&block_id, &block_ids, &mut bcm)?;
+    block.modify_bytes(|bytes_arr| {
+        bytes_arr[start_byte..start_byte + bytes.len()].clone_from_slice(bytes);
+    });
     Ok(())
 }
 
 /// 将`object`序列化并写入指定的`block_id`中，
-/// 用`start_byte`指示出该`object`会在块中的字节起始位置
+/// 用`start_byte`指示出该`object`会在块中的字节起始位置，超出块时返回err
 pub async fn write_block<T: serde::Serialize>(
     object: &T,
     block_id: usize,
@@ -228,10 +322,23 @@ pub async fn write_block<T: serde::Serialize>(
     write_blocks(&arg).await
 }
 
-/// 批量将object写入块中， args为（object，block_id, start_byte）数组
+/// 批量将object写入块中， args为（object，block_id, start_byte）数组，
+/// 有object超出所在块时返回err，不写入任何块
 pub async fn write_blocks<T: serde::Serialize>(
     object_args: &[(&T, usize, usize)],
 ) -> Result<(), Error> {
+    // 先全部序列化并检查边界
+    let mut writes = Vec::with_capacity(object_args.len());
+    for (object, block_id, start_byte) in object_args {
+        let obj_bytes = bincode::serialize(*object).map_err(|err| {
+            let e = format!("cannot serialize:{}", err);
+            error!("{e}");
+            Error::other(e)
+        })?;
+        check_overflow(*block_id, *start_byte, obj_bytes.len())?;
+        writes.push((obj_bytes, *block_id, *start_byte));
+    }
+
     let ids: Vec<_> = object_args
         .iter()
         .map(|(_, block_id, _)| *block_id)
@@ -239,27 +346,25 @@ pub async fn write_blocks<T: serde::Serialize>(
     read_blocks_to_cache(&ids).await?;
     let blk = Arc::clone(&BLOCK_CACHE_MANAGER);
     let mut bcm = blk.write().await;
-    let block_cache = &mut bcm.block_cache;
 
-    for (object, block_id, start_byte) in object_args {
-        trace!("write block{}", *block_id);
-        let block = get_block_mut(block_id, &ids, block_cache)?;
-        // 将 object 序列化
-        match bincode::serialize(*object) {
-            Ok(obj_bytes) => {
-                let end_byte = obj_bytes.len() + start_byte;
-                assert!(end_byte <= BLOCK_SIZE);
-                trace!("write block{}, len {}B", block_id, obj_bytes.len());
-                block.modify_bytes(|bytes_arr| {
-                    bytes_arr[*start_byte..end_byte].clone_from_slice(&obj_bytes);
-                });
-            }
-            Err(err) => {
-                let e = format!("cannot serialize:{}", err);
-                error!("{e}");
-                return Err(Error::new(ErrorKind::Other, e));
-            }
-        }
+    for (obj_bytes, block_id, start_byte) in writes {
+        trace!("write block{}, len {}B", block_id, obj_bytes.len());
+        let block = get_block_mut(&block_id, &ids, &mut bcm)?;
+        block.modify_bytes(|bytes_arr| {
+            bytes_arr[start_byte..start_byte + obj_bytes.len()].clone_from_slice(&obj_bytes);
+        });
+    }
+    Ok(())
+}
+
+/// 从start_byte起写入len字节会超出块时返回err
+fn check_overflow(block_id: usize, start_byte: usize, len: usize) -> Result<(), Error> {
+    if start_byte + len > BLOCK_SIZE {
+        error!(
+            "object of {}B at byte {} overflows block{}",
+            len, start_byte, block_id
+        );
+        return Err(Error::other("object overflows block"));
     }
     Ok(())
 }
@@ -276,46 +381,54 @@ pub async fn insert_object<T: Serialize + Default + DeserializeOwned + PartialEq
         }
         // 如果该块没有空余，继续找
     }
-    // 没有空余的，申请
+    // 没有空余的，申请新块，块地址变化后写回inode
     let last_level = &all_blocks.last().unwrap().0;
     match *last_level {
         BlockLevel::Direct => {
             //申请一个块
-            for i in 0..DIRECT_BLOCK_NUM {
-                if inode.addr[i] == 0 {
-                    let new_block_id = alloc_bit(BitmapType::Data).await?;
-                    trace!("add a new direct block {}", new_block_id);
-                    // 将地址写回inode中
-                    inode.addr[i] = new_block_id;
-                    write_block(object, new_block_id as usize, 0).await?;
-                    return Ok(());
-                }
+            if let Some(i) = (0..DIRECT_BLOCK_NUM).find(|&i| inode.addr[i] == 0) {
+                let new_block_id = alloc_data_blocks(1).await?[0];
+                trace!("add a new direct block {}", new_block_id);
+                let result = write_block(object, new_block_id as usize, 0).await;
+                free_if_unlinked(result, &[new_block_id]).await?;
+                // 将地址写回inode中
+                inode.addr[i] = new_block_id;
+                return inode.save().await;
             }
-            // 直接块用完了，要申请一个新的一级块
-            let new_first_id = alloc_bit(BitmapType::Data).await?;
-            trace!("add a new first block {}", new_first_id);
+            // 直接块用完了，一次申请新的一级块和其下的数据块
+            let ids = alloc_data_blocks(2).await?;
+            trace!("add a new first block {}", ids[0]);
+            let result = alloc_new_in_first(ids[0] as usize, ids[1], object).await;
+            free_if_unlinked(result, &ids).await?;
             // 将一级地址写回inode中
-            inode.set_first_id(new_first_id);
-            alloc_new_in_first(new_first_id as usize, object).await
+            inode.set_first_id(ids[0]);
+            inode.save().await
         }
         BlockLevel::FirstIndirect => {
             // 一级间接块的已有的所有直接块没有空间了
             if all_blocks.len() < FISRT_MAX + DIRECT_BLOCK_NUM {
                 // 一级间接块本身还有空间，直接附加
-                alloc_new_in_first(inode.get_first_id(), object).await
+                let new_block_id = alloc_data_blocks(1).await?[0];
+                let result = alloc_new_in_first(inode.get_first_id(), new_block_id, object).await;
+                free_if_unlinked(result, &[new_block_id]).await
             } else {
                 // 一级块没空间了，要找二级块（返回的是最后一块一级块）
-                // 申请一块新的二级块
-                let new_second_id = alloc_bit(BitmapType::Data).await?;
+                // 一次申请新的二级块、一级块和数据块
+                let ids = alloc_data_blocks(3).await?;
+                let result = alloc_new_in_second(ids[0] as usize, ids[1], ids[2], object).await;
+                free_if_unlinked(result, &ids).await?;
                 // 将二级地址写回inode中
-                inode.set_second_id(new_second_id);
-                alloc_new_in_second(new_second_id as usize, object).await
+                inode.set_second_id(ids[0]);
+                inode.save().await
             }
         }
         BlockLevel::SecondIndirect => {
             if all_blocks.len() < SECOND_MAX + FISRT_MAX + DIRECT_BLOCK_NUM {
                 // 最后非空块填满了，申请一块新的一级块
-                return alloc_new_in_second(inode.get_second_id(), object).await;
+                let ids = alloc_data_blocks(2).await?;
+                let result =
+                    alloc_new_in_second(inode.get_second_id(), ids[0], ids[1], object).await;
+                return free_if_unlinked(result, &ids).await;
             }
             // 超限
             Err(Error::new(ErrorKind::OutOfMemory, "no valid block"))
@@ -323,33 +436,48 @@ pub async fn insert_object<T: Serialize + Default + DeserializeOwned + PartialEq
     }
 }
 
-/// 批量清空block的内容
+/// 一次申请n个数据块（只持有一次位图的写锁），返回块号
+async fn alloc_data_blocks(n: usize) -> Result<Vec<BlockIDType>, Error> {
+    let start = DATA_START_BLOCK as BlockIDType;
+    let ids = alloc_bits(BitmapType::Data, n).await?;
+    Ok(ids.into_iter().map(|bit_id| bit_id + start).collect())
+}
+
+/// 新申请的块没能挂到inode上时没有任何地方引用，出错时释放，避免泄漏
+async fn free_if_unlinked(result: Result<(), Error>, ids: &[BlockIDType]) -> Result<(), Error> {
+    if result.is_err() {
+        let ids: Vec<_> = ids.iter().map(|id| *id as usize).collect();
+        dealloc_data_bits(&ids).await;
+    }
+    result
+}
+
+/// 批量清空block的内容；整块覆盖，不需要先读入，回滚时设备读取出错也能释放
 pub async fn clear_blocks(block_ids: &[usize]) -> Result<(), Error> {
-    read_blocks_to_cache(block_ids).await?;
+    let zeros = vec![[0u8; BLOCK_SIZE]; block_ids.len()];
     let blk = Arc::clone(&BLOCK_CACHE_MANAGER);
     let mut bcm = blk.write().await;
-    let block_cache = &mut bcm.block_cache;
-
-    for block_id in block_ids {
-        let block = get_block_mut(block_id, block_ids, block_cache)?;
-        block.bytes = [0; BLOCK_SIZE];
-        block.modified = true;
-    }
-    Ok(())
+    bcm.write_contents_unblocking(&zeros, block_ids)
 }
 
-/// 在二级块中alloc一块新的一级块，并在新的一级块中alloc一块新块
-async fn alloc_new_in_second<T: Serialize>(second_id: usize, object: &T) -> Result<(), Error> {
-    let new_first_block = alloc_bit(BitmapType::Data).await?;
-    alloc_new_in_first(new_first_block as usize, object).await?;
+/// 把已申请的新一级块挂到二级块中，并在新的一级块中附加新块
+async fn alloc_new_in_second<T: Serialize>(
+    second_id: usize,
+    new_first_block: BlockIDType,
+    new_block_id: BlockIDType,
+    object: &T,
+) -> Result<(), Error> {
+    alloc_new_in_first(new_first_block as usize, new_block_id, object).await?;
     try_insert_to_block(&new_first_block, second_id).await?;
     Ok(())
 }
 
-/// 在新的一级块中alloc一块新块
-async fn alloc_new_in_first<T: Serialize>(first_id: usize, object: &T) -> Result<(), Error> {
-    // 申请一块新块
-    let new_block_id = alloc_bit(BitmapType::Data).await?;
+/// 把已申请的新块附加到一级块中
+async fn alloc_new_in_first<T: Serialize>(
+    first_id: usize,
+    new_block_id: BlockIDType,
+    object: &T,
+) -> Result<(), Error> {
     trace!("add a new block {}", new_block_id);
     // 将object 写入新块
     write_block(object, new_block_id as usize, 0).await?;
@@ -466,6 +594,10 @@ pub async fn get_all_blocks(
     inode: &Inode,
 ) -> Result<Vec<(BlockLevel, BlockIDType, Vec<u8>)>, Error> {
     let mut v = Vec::new();
+    if inode.is_packed() {
+        // 打包文件没有独占的块
+        return Ok(v);
+    }
     // 直接块
     let mut l = DIRECT_BLOCK_NUM;
     for i in 0..DIRECT_BLOCK_NUM {
@@ -501,6 +633,42 @@ pub async fn get_all_blocks(
     Ok(v)
 }
 
+/// 获取inode占用的所有块号，包括间接块本身，空洞和越界的地址不计入；
+/// 打包文件返回其所在的打包块
+pub async fn get_owned_block_ids(inode: &Inode) -> Result<Vec<usize>, Error> {
+    if inode.is_packed() {
+        return Ok(vec![inode.get_packed_location().0]);
+    }
+    let mut ids: Vec<BlockIDType> = inode.addr[..DIRECT_BLOCK_NUM].to_vec();
+    // 块号是否位于数据区
+    let data_end = DATA_START_BLOCK + bitmap::data_block_num().await;
+    let is_data_block =
+        |block_id: BlockIDType| (DATA_START_BLOCK..data_end).contains(&(block_id as usize));
+    // 一级
+    let first_id = inode.get_first_id() as BlockIDType;
+    if is_data_block(first_id) {
+        ids.push(first_id);
+        ids.append(&mut get_addrs_of_indirect(first_id, INDIRECT_ADDR_NUM).await?);
+    }
+    // 二级
+    let second_id = inode.get_second_id() as BlockIDType;
+    if is_data_block(second_id) {
+        ids.push(second_id);
+        for first_id in get_addrs_of_indirect(second_id, INDIRECT_ADDR_NUM).await? {
+            if !is_data_block(first_id) {
+                continue;
+            }
+            ids.push(first_id);
+            ids.append(&mut get_addrs_of_indirect(first_id, INDIRECT_ADDR_NUM).await?);
+        }
+    }
+    Ok(ids
+        .into_iter()
+        .filter(|id| is_data_block(*id))
+        .map(|id| id as usize)
+        .collect())
+}
+
 /// 获取所有非空块
 pub async fn get_all_valid_blocks(
     inode: &Inode,
@@ -511,17 +679,90 @@ pub async fn get_all_valid_blocks(
     Ok(v)
 }
 
-/// 移除一个object，如果这是唯一的object，那么释放这个block
+/// 按逻辑块号顺序读取文件size范围内的所有数据块，
+/// 地址为0的空洞块不读盘，直接用0填充
+pub async fn get_file_blocks(inode: &Inode) -> Result<Vec<Vec<u8>>, Error> {
+    let ids = get_file_block_ids(inode, blocks_for_size(inode.get_size())).await?;
+
+    // 只读取已分配的块
+    let args: Vec<_> = ids
+        .iter()
+        .filter(|id| **id != 0)
+        .map(|id| (*id as usize, 0, BLOCK_SIZE))
+        .collect();
+    let mut buffers = get_blocks_buffers(&args).await?.into_iter();
+    let blocks = ids
+        .iter()
+        .map(|id| match id {
+            0 => vec![0; BLOCK_SIZE],
+            _ => buffers.next().unwrap(),
+        })
+        .collect();
+    Ok(blocks)
+}
+
+/// 按逻辑块号顺序获取文件前block_nums个数据块的块号，只读取间接块，
+/// 空洞和未分配的位置为0
+pub async fn get_file_block_ids(
+    inode: &Inode,
+    block_nums: usize,
+) -> Result<Vec<BlockIDType>, Error> {
+    let block_nums = min(block_nums, DIRECT_BLOCK_NUM + FISRT_MAX + SECOND_MAX);
+    let mut ids: Vec<BlockIDType> = vec![0; block_nums];
+
+    // 直接块
+    let direct_nums = min(block_nums, DIRECT_BLOCK_NUM);
+    ids[..direct_nums].copy_from_slice(&inode.addr[..direct_nums]);
+
+    // 一级
+    let first_id = inode.get_first_id() as BlockIDType;
+    if block_nums > DIRECT_BLOCK_NUM && first_id != 0 {
+        let nums = min(block_nums - DIRECT_BLOCK_NUM, FISRT_MAX);
+        let addrs = get_addrs_of_indirect(first_id, nums).await?;
+        ids[DIRECT_BLOCK_NUM..DIRECT_BLOCK_NUM + nums].copy_from_slice(&addrs);
+    }
+
+    // 二级
+    let second_start = DIRECT_BLOCK_NUM + FISRT_MAX;
+    let second_id = inode.get_second_id() as BlockIDType;
+    if block_nums > second_start && second_id != 0 {
+        let first_nums = (block_nums - second_start - 1) / INDIRECT_ADDR_NUM + 1;
+        let first_ids = get_addrs_of_indirect(second_id, first_nums).await?;
+        for (i, first_id) in first_ids.into_iter().enumerate() {
+            if first_id == 0 {
+                continue; // 整个一级块都是空洞
+            }
+            let start = second_start + i * INDIRECT_ADDR_NUM;
+            let nums = min(block_nums - start, INDIRECT_ADDR_NUM);
+            let addrs = get_addrs_of_indirect(first_id, nums).await?;
+            ids[start..start + nums].copy_from_slice(&addrs);
+        }
+    }
+    Ok(ids)
+}
+
+/// 读取间接块中的前nums个地址
+async fn get_addrs_of_indirect(
+    block_id: BlockIDType,
+    nums: usize,
+) -> Result<Vec<BlockIDType>, Error> {
+    let args: Vec<_> = (0..nums)
+        .map(|i| {
+            let start = i * BLOCK_ADDR_SIZE;
+            (block_id as usize, start, start + BLOCK_ADDR_SIZE)
+        })
+        .collect();
+    let buffers = get_blocks_buffers(&args).await?;
+    buffers.iter().map(|buffer| deserialize(buffer)).collect()
+}
+
+/// 移除一个object；目录不会收缩，空出的块仍属于该目录，留给之后插入的目录项
 pub async fn remove_object<T: Serialize + Default + PartialEq + DeserializeOwned>(
     object: &T,
     block_id: usize,
-    level: BlockLevel,
-    inode: &mut Inode,
 ) -> Result<(), Error> {
-    //1.序列化这个block，一一比较
+    //序列化这个block，一一比较
     let size = size_of::<T>();
-    let mut exist = false;
-
     let mut block_args = Vec::new();
     for i in 0..BLOCK_SIZE / size {
         let start = i * size;
@@ -532,125 +773,14 @@ pub async fn remove_object<T: Serialize + Default + PartialEq + DeserializeOwned
 
     for (i, buffer) in buffers.iter().enumerate() {
         if *object == deserialize(buffer)? {
-            exist = true;
             // 覆盖该位置
             let start = i * size;
             write_block(&T::default(), block_id, start).await?;
-            break;
-        }
-    }
-
-    if !exist {
-        return Err(Error::new(ErrorKind::NotFound, ""));
-    }
-
-    //2. 再次序列化，判断是否已空, 如果全空 dealloc
-    let block = get_block_buffer(block_id, 0, BLOCK_SIZE).await?;
-    if !block_is_empty(&block) {
-        return Ok(());
-    }
-
-    dealloc_data_bit(block_id).await;
-    trace!("dealloc data bit ok");
-
-    match level {
-        BlockLevel::Direct => {
-            //3.1. 如果是直接块，去inode将地址置空
-            for i in 0..DIRECT_BLOCK_NUM {
-                if block_id == inode.addr[i] as usize {
-                    inode.addr[i] = 0;
-                    return Ok(());
-                }
-            }
-            panic!("unreachable");
-        }
-        BlockLevel::FirstIndirect => {
-            //3.2. 如果是在一级块，那么还要清除在一级块中的地址，判断释放这个block addr之后一级块是否已空
-            let first_id = inode.get_first_id();
-            // 在一级块中清除该块的地址
-            remove_block_addr_in_first_block(first_id, block_id).await?;
-            inode.set_first_id(0);
-        }
-        BlockLevel::SecondIndirect => {
-            //3.3. 如果是在二级块，判断二级块是否已空
-            let second_id = inode.get_second_id();
-            let mut first_id = 0;
-            let mut start = 0; // 记录二级块中的一级块条目偏移量
-
-            // 首先对二级块的每个一级地址所记录的直接块去清除记录
-            let mut second_args = Vec::new();
-            for i in 0..BLOCK_SIZE / BLOCK_ADDR_SIZE {
-                let start = i * BLOCK_ADDR_SIZE;
-                let end = start + BLOCK_ADDR_SIZE;
-                second_args.push((second_id, start, end));
-            }
-            let first_addrs = get_blocks_buffers(&second_args).await?;
-
-            for (i, first_addr) in first_addrs.iter().enumerate() {
-                first_id = deserialize(first_addr)?;
-                if remove_block_addr_in_first_block(first_id, block_id)
-                    .await
-                    .is_ok()
-                {
-                    // 找到并清除了，跳出循环
-                    start = i * BLOCK_ADDR_SIZE;
-                    break;
-                }
-            }
-
-            // 然后检查找到的那个一级块是否空，空了就清掉那个一级块在二级块中的记录
-            let first_block = get_block_buffer(first_id, 0, BLOCK_SIZE).await?;
-            if !block_is_empty(&first_block) {
-                // 那个一级块还有条目，直接返回
-                return Ok(());
-            }
-            // 在二级块中清除一级块记录
-            write_block(&(0 as BlockIDType), second_id, start).await?;
-
-            // 最后检查二级块 如果二级块空了就把二级块也清空
-            let second_block = get_block_buffer(second_id, 0, BLOCK_SIZE).await?;
-            if !block_is_empty(&second_block) {
-                return Ok(());
-            }
-            // 全空, 释放二级块
-            dealloc_data_bit(second_id).await;
-            inode.set_second_id(0);
-        }
-    }
-    trace!("remove obj ok");
-    Ok(())
-}
-
-/// 清除一级块中的直接块地址条目，同时一级块变空时dealloc一级块
-async fn remove_block_addr_in_first_block(first_id: usize, block_id: usize) -> Result<(), Error> {
-    let mut exist = false;
-    let mut first_args = Vec::new();
-    for i in 0..BLOCK_SIZE / BLOCK_ADDR_SIZE {
-        let start = i * BLOCK_ADDR_SIZE;
-        let end = start + BLOCK_ADDR_SIZE;
-        first_args.push((first_id, start, end));
-    }
-    let direct_addrs = get_blocks_buffers(&first_args).await?;
-
-    for (i, direct_addr) in direct_addrs.iter().enumerate() {
-        // 在一级块中找到了这个块的地址，清除
-        if *direct_addr == serialize(&(block_id as BlockIDType))? {
-            exist = true;
-            let start = i * BLOCK_ADDR_SIZE;
-            write_block(&(0 as BlockIDType), first_id, start).await?;
-            break;
+            trace!("remove obj ok");
+            return Ok(());
         }
     }
-
-    if !exist {
-        return Err(Error::new(ErrorKind::NotFound, ""));
-    }
-    let first_block = get_block_buffer(first_id, 0, BLOCK_SIZE).await?;
-    if !block_is_empty(&first_block) {
-        return Ok(());
-    }
-    dealloc_data_bit(first_id).await;
-    Ok(())
+    Err(Error::new(ErrorKind::NotFound, ""))
 }
 
 /// 判断block是否是全0
@@ -663,8 +793,9 @@ pub fn block_is_empty(block: &[u8]) -> bool {
     true
 }
 
-/// 检查data位图对应的区域是否出错
-pub async fn check_data_and_fix() -> Result<(), Error> {
+/// 检查data位图对应的区域是否出错，返回出错的块号，fix为true时把对应的位置0
+pub async fn check_data_and_fix(fix: bool) -> Result<Vec<usize>, Error> {
+    let mut invalid = Vec::new();
     let data_bitmap = bitmap::get_data_bitmaps().await;
     for (i, byte) in data_bitmap.iter().enumerate() {
         for j in 0..8 {
@@ -675,13 +806,16 @@ pub async fn check_data_and_fix() -> Result<(), Error> {
                 // 检查对应区域是否为空，为空则置0
                 let block = get_block_buffer(block_id, 0, BLOCK_SIZE).await?;
                 if block.is_empty() {
-                    dealloc_data_bit(block_id).await;
-                    info!("fix data bit:{}", bit_id);
+                    if fix {
+                        dealloc_data_bit(block_id).await;
+                        info!("fix data bit:{}", bit_id);
+                    }
+                    invalid.push(block_id);
                 }
             }
         }
     }
-    Ok(())
+    Ok(invalid)
 }
 
 /// 从缓存中获取块的可变引用，
@@ -689,26 +823,24 @@ pub async fn check_data_and_fix() -> Result<(), Error> {
 /// 当contain为false时直接重新读入块缓存，再尝试获取
 pub fn get_block_mut<'a>(
     block_id: &usize,
-    block_ids: &'a [usize],
-    block_cache: &'a mut HashMap<usize, Block>,
+    block_ids: &[usize],
+    bcm: &'a mut BlockCacheManager,
 ) -> io::Result<&'a mut Block> {
-    Ok(if block_cache.contains_key(block_id) {
-        block_cache.get_mut(block_id).unwrap()
-    } else {
+    if !bcm.block_cache.contains_key(block_id) {
         // 可能会因为他人持有写锁，写完后清空了缓存导致读不到缓存，所以要重读
         info!("re-read caches when getting block mut");
-        read_blocks_to_cache_unblocking(block_ids, block_cache)?; //因为函数外层会持有写锁，所以这里不能获得锁
-        block_cache.get_mut(block_id).unwrap()
-    })
+        bcm.read_blocks_to_cache_unblocking(block_ids)?; //因为函数外层会持有写锁，所以这里不能获得锁
+    }
+    Ok(bcm.block_cache.get_mut(block_id).unwrap())
 }
 
 //延迟加载全局变量 BLOCK_CACHE_MANAGER
 lazy_static! {
     pub static ref BLOCK_CACHE_MANAGER: Arc<RwLock<BlockCacheManager>> =
-        Arc::new(RwLock::new(BlockCacheManager::new()));
+        Arc::new(RwLock::new(BlockCacheManager::new(device::open())));
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub enum BlockLevel {
     Direct,
     FirstIndirect,
@@ -723,6 +855,10 @@ pub enum CacheMethod {
 
 /// 清空块缓存，写入磁盘中
 pub async fn sync_all_block_cache() -> Result<(), Error> {
+    // umount之后没有可以写入的镜像
+    if !is_attached().await {
+        return Ok(());
+    }
     // 将位图缓存入读块缓存中
     Arc::clone(&BITMAP_MANAGER)
         .read()
@@ -733,8 +869,7 @@ pub async fn sync_all_block_cache() -> Result<(), Error> {
     Arc::clone(&BLOCK_CACHE_MANAGER)
         .write()
         .await
-        .sync_and_clear_cache()
-        .await?;
+        .sync_cache(CONFIG.warm_cache)?;
     // 重新读取已写入的信息
     Arc::clone(&SFS).write().await.update().await;
     info!("sync all blocks ok");
@@ -745,6 +880,216 @@ pub fn deserialize<'a, T: Deserialize<'a>>(buffer: &'a [u8]) -> Result<T, Error>
     bincode::deserialize(buffer).map_err(|err| Error::new(ErrorKind::Other, err))
 }
 
-pub fn serialize<T: Serialize>(object: &T) -> Result<Vec<u8>, Error> {
-    bincode::serialize(object).map_err(|err| Error::new(ErrorKind::Other, err))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{exec, exec_as, lookup, mktree, setup};
+
+    /// 写回并清空块缓存后清零统计，之后的读取都要读磁盘
+    async fn cold_cache() {
+        let manager = Arc::clone(&BLOCK_CACHE_MANAGER);
+        let mut manager = manager.write().await;
+        manager.sync_and_clear_cache().await.unwrap();
+        manager.reset_stats();
+    }
+
+    async fn stats() -> BlockIoStats {
+        Arc::clone(&BLOCK_CACHE_MANAGER).read().await.stats
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cat_counts_block_reads() {
+        let _fs = setup().await;
+        let content = "z".repeat(5 * BLOCK_SIZE);
+        mktree(&[&format!("big.txt:{}", content)]).await;
+
+        cold_cache().await;
+        assert_eq!(exec("cat big.txt").await.unwrap().unwrap(), content);
+        let first = stats().await;
+        // 至少读入文件的5个数据块
+        assert!(first.disk_reads >= 5, "{:?}", first);
+
+        // 再次读取全部命中缓存
+        exec("cat big.txt").await.unwrap();
+        let second = stats().await;
+        assert_eq!(second.disk_reads, first.disk_reads, "{:?}", second);
+        assert!(second.cache_hits >= first.cache_hits + 5, "{:?}", second);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn holes_read_as_zeros_without_disk_reads() {
+        let _fs = setup().await;
+        let content: String = ["a", "b", "c", "d"]
+            .iter()
+            .map(|c| c.repeat(BLOCK_SIZE))
+            .collect();
+        mktree(&[&format!("sparse.bin:{}", content)]).await;
+        // 把中间两块改为空洞
+        let mut inode = lookup("sparse.bin").await;
+        let holes = [inode.addr[1] as usize, inode.addr[2] as usize];
+        inode.addr[1] = 0;
+        inode.addr[2] = 0;
+        inode.save().await.unwrap();
+        bitmap::dealloc_data_bits(&holes).await;
+
+        cold_cache().await;
+        let blocks = get_file_blocks(&inode).await.unwrap();
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[0], vec![b'a'; BLOCK_SIZE]);
+        assert_eq!(blocks[1], vec![0; BLOCK_SIZE]);
+        assert_eq!(blocks[2], vec![0; BLOCK_SIZE]);
+        assert_eq!(blocks[3], vec![b'd'; BLOCK_SIZE]);
+        // 只读入了两个已分配的块
+        assert_eq!(stats().await.disk_reads, 2);
+
+        let read = exec("cat sparse.bin").await.unwrap().unwrap();
+        let expected = [
+            "a".repeat(BLOCK_SIZE),
+            "\0".repeat(2 * BLOCK_SIZE),
+            "d".repeat(BLOCK_SIZE),
+        ];
+        assert_eq!(read, expected.concat());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn contiguous_blocks_are_read_together() {
+        let _fs = setup().await;
+        let content: String = (0..300)
+            .map(|i| {
+                char::from(b'a' + (i % 26) as u8)
+                    .to_string()
+                    .repeat(BLOCK_SIZE)
+            })
+            .collect();
+        mktree(&[&format!("big.bin:{}", content)]).await;
+        let inode = lookup("big.bin").await;
+        let ids = get_file_block_ids(&inode, 300).await.unwrap();
+        let contiguous = ids.windows(2).filter(|pair| pair[1] == pair[0] + 1).count();
+        assert!(
+            contiguous > 250,
+            "{} of {} blocks are contiguous",
+            contiguous,
+            ids.len()
+        );
+
+        cold_cache().await;
+        assert_eq!(exec("cat big.bin").await.unwrap().unwrap(), content);
+        let stats = stats().await;
+        // 连续的块合并为一次读取，读磁盘的次数远少于读入的块数
+        assert!(stats.disk_reads >= 300, "{:?}", stats);
+        assert!(stats.disk_read_calls * 10 < stats.disk_reads, "{:?}", stats);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn oversized_write_is_an_error() {
+        let _fs = setup().await;
+        let block_id =
+            DATA_START_BLOCK + bitmap::alloc_bit(BitmapType::Data).await.unwrap() as usize;
+        let before = get_block_buffer(block_id, 0, BLOCK_SIZE).await.unwrap();
+
+        let object = [7u64; 4]; // 序列化后32字节
+        for start_byte in [BLOCK_SIZE - 31, BLOCK_SIZE, BLOCK_SIZE + 8] {
+            let err = write_block(&object, block_id, start_byte)
+                .await
+                .unwrap_err();
+            assert_eq!(err.to_string(), "object overflows block");
+        }
+        let err = write_bytes_to_block(&[1; BLOCK_SIZE + 1], block_id, 0)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "object overflows block");
+        // 批量写入中有一项越界时不写入任何块
+        let args = [(&object, block_id, 0), (&object, block_id, BLOCK_SIZE - 8)];
+        assert!(write_blocks(&args).await.is_err());
+        assert_eq!(
+            get_block_buffer(block_id, 0, BLOCK_SIZE).await.unwrap(),
+            before
+        );
+
+        // 恰好写到块末尾是允许的
+        write_block(&object, block_id, BLOCK_SIZE - 32)
+            .await
+            .unwrap();
+        let tail = get_block_buffer(block_id, BLOCK_SIZE - 32, BLOCK_SIZE)
+            .await
+            .unwrap();
+        assert_eq!(deserialize::<[u64; 4]>(&tail).unwrap(), object);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn many_block_file_is_written_in_one_pass() {
+        let _fs = setup().await;
+        // 每块内容不同，写错位置时能发现；最后一块不满，跨过一级间接块
+        let blocks = DIRECT_BLOCK_NUM + 300;
+        let content: String = (0..blocks)
+            .map(|i| {
+                char::from(b'a' + (i % 26) as u8)
+                    .to_string()
+                    .repeat(BLOCK_SIZE)
+            })
+            .collect::<String>()
+            + "tail";
+
+        cold_cache().await;
+        exec_as("root", "~", "newfile many.txt", &[&content])
+            .await
+            .unwrap();
+        let written = stats().await;
+        // 整块覆盖的数据块不需要先读入，读磁盘的块数和文件的块数无关
+        assert!(written.disk_reads < blocks / 4, "{:?}", written);
+
+        let inode = lookup("many.txt").await;
+        assert_eq!(inode.get_size(), content.len());
+        cold_cache().await;
+        assert_eq!(exec("cat many.txt").await.unwrap().unwrap(), content);
+        assert!(stats().await.disk_reads > blocks);
+    }
+
+    /// 同步块缓存，keep_warm为true时保留缓存，之后清零统计
+    async fn sync(keep_warm: bool) {
+        let manager = Arc::clone(&BLOCK_CACHE_MANAGER);
+        let mut manager = manager.write().await;
+        manager.sync_cache(keep_warm).unwrap();
+        manager.reset_stats();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn warm_sync_keeps_written_blocks_cached() {
+        let _fs = setup().await;
+        let ids: Vec<_> = (DATA_START_BLOCK..DATA_START_BLOCK + 3).collect();
+        let whole: Vec<_> = ids.iter().map(|id| (*id, 0, BLOCK_SIZE)).collect();
+        let contents = vec![[7u8; BLOCK_SIZE]; ids.len()];
+        write_file_content_to_blocks(&contents, &ids).await.unwrap();
+
+        // 写回磁盘但保留缓存，读刚写入的块全部命中
+        sync(true).await;
+        {
+            let manager = BLOCK_CACHE_MANAGER.read().await;
+            for id in &ids {
+                assert!(!manager.block_cache[id].modified);
+            }
+        }
+        let buffers = get_blocks_buffers(&whole).await.unwrap();
+        assert!(buffers.iter().all(|buffer| buffer == &contents[0]));
+        let warm = stats().await;
+        assert_eq!(warm.disk_reads, 0, "{:?}", warm);
+        assert_eq!(warm.cache_hits, ids.len(), "{:?}", warm);
+
+        // 确实已经写入磁盘：清空缓存后从磁盘读出相同的内容
+        sync(false).await;
+        let buffers = get_blocks_buffers(&whole).await.unwrap();
+        assert!(buffers.iter().all(|buffer| buffer == &contents[0]));
+        assert_eq!(stats().await.disk_reads, ids.len());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn warm_sync_clears_an_oversized_cache() {
+        let _fs = setup().await;
+        let ids: Vec<_> =
+            (DATA_START_BLOCK..DATA_START_BLOCK + WARM_CACHE_MAX_BLOCKS + 1).collect();
+        let contents = vec![[1u8; BLOCK_SIZE]; ids.len()];
+        write_file_content_to_blocks(&contents, &ids).await.unwrap();
+        sync(true).await;
+        assert!(BLOCK_CACHE_MANAGER.read().await.block_cache.is_empty());
+    }
 }
@@ -0,0 +1,188 @@
+//! simdisk 启动参数
+use utils::SOCKET_ADDR;
+
+use crate::fs_constants::{
+    DEFAULT_IDLE_TIMEOUT, DEFAULT_MAX_CONNECTIONS, FS_FILE_NAME, HISTORY_FILE_NAME,
+};
+
+#[derive(Debug)]
+pub struct ServerConfig {
+    pub verbose: bool,           // 是否输出每条命令的块读写统计
+    pub readonly: bool,          // 只读模式，拒绝所有会修改文件系统的命令
+    pub webhook: Option<String>, // 文件系统事件的通知地址
+    pub max_connections: usize,  // 最多同时保持的连接数
+    pub idle_timeout: u64,       // 连接空闲多久（秒）后关闭，0表示不限制
+    pub memory: bool,            // 文件系统存放在内存中，不读写镜像文件
+    pub http_port: Option<u16>,  // 只读目录浏览器监听的本机端口
+    pub addr: String,            // server监听的地址
+    pub image: String,           // 启动时挂载的镜像文件路径
+    pub format: bool,            // 启动时直接格式化，不读取原有的镜像
+    pub cwd_cache: bool,         // 每个连接缓存最近进入的目录
+    pub warm_cache: bool,        // 同步块缓存后保留缓存，不清空
+    pub history: Option<String>, // 命令历史文件的路径，None时只保存在内存中
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            verbose: false,
+            readonly: false,
+            webhook: None,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            memory: false,
+            http_port: None,
+            addr: SOCKET_ADDR.to_string(),
+            image: FS_FILE_NAME.to_string(),
+            format: false,
+            cwd_cache: true,
+            warm_cache: false,
+            history: Some(HISTORY_FILE_NAME.to_string()),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// 从命令行参数中读取配置
+    pub fn from_args() -> Self {
+        let mut config = Self::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--verbose" | "-v" => config.verbose = true,
+                "--readonly" | "-r" => config.readonly = true,
+                "--memory" => config.memory = true,
+                "--format" => config.format = true,
+                "--no-cwd-cache" => config.cwd_cache = false,
+                "--warm-cache" => config.warm_cache = true,
+                "--webhook" => match args.next() {
+                    Some(url) => config.webhook = Some(url),
+                    None => warn!("--webhook requires a url"),
+                },
+                "--max-connections" => match args.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => config.max_connections = n,
+                    _ => warn!("--max-connections requires a positive number"),
+                },
+                "--idle-timeout" => match args.next().map(|secs| secs.parse::<u64>()) {
+                    Some(Ok(secs)) => config.idle_timeout = secs,
+                    _ => warn!("--idle-timeout requires a number of seconds"),
+                },
+                "--addr" => match args.next() {
+                    Some(addr) => config.addr = addr,
+                    None => warn!("--addr requires an address"),
+                },
+                "--image" => match args.next() {
+                    Some(path) => config.image = path,
+                    None => warn!("--image requires a path"),
+                },
+                "--history" => match args.next() {
+                    Some(path) => config.history = Some(path),
+                    None => warn!("--history requires a path"),
+                },
+                "--help" | "-h" => {
+                    print_usage();
+                    std::process::exit(0);
+                }
+                // 初始化日志时已经处理
+                "--log-level" => {
+                    args.next();
+                }
+                "--http" => match args.next().map(|port| port.parse::<u16>()) {
+                    Some(Ok(port)) => config.http_port = Some(port),
+                    _ => warn!("--http requires a port"),
+                },
+                _ => warn!("unknown arg: {}", arg),
+            }
+        }
+        config
+    }
+}
+
+/// 输出启动参数的说明
+fn print_usage() {
+    println!("usage: simdisk [options]");
+    let options = [
+        (
+            "-v, --verbose",
+            "log block io and cache statistics of every command".to_string(),
+        ),
+        (
+            "-r, --readonly",
+            "reject commands that modify the file system".to_string(),
+        ),
+        (
+            "--memory",
+            "keep the file system in memory instead of an image file".to_string(),
+        ),
+        (
+            "--format",
+            "format the image on startup instead of reading it".to_string(),
+        ),
+        (
+            "--image [path]",
+            format!("image file to mount on startup, default {}", FS_FILE_NAME),
+        ),
+        (
+            "--history [path]",
+            format!(
+                "file to keep command history in, default {}",
+                HISTORY_FILE_NAME
+            ),
+        ),
+        (
+            "--addr [addr]",
+            format!("address to listen on, default {}", SOCKET_ADDR),
+        ),
+        (
+            "--http [port]",
+            "serve a read-only directory browser on 127.0.0.1:port".to_string(),
+        ),
+        (
+            "--webhook [url]",
+            "POST file system events to url".to_string(),
+        ),
+        (
+            "--max-connections [n]",
+            format!(
+                "connections kept at the same time, default {}",
+                DEFAULT_MAX_CONNECTIONS
+            ),
+        ),
+        (
+            "--idle-timeout [secs]",
+            format!(
+                "close idle connections after secs, 0 for never, default {}",
+                DEFAULT_IDLE_TIMEOUT
+            ),
+        ),
+        (
+            "--no-cwd-cache",
+            "do not cache the last resolved directory of each connection".to_string(),
+        ),
+        (
+            "--warm-cache",
+            "keep the block cache after writing it back to disk".to_string(),
+        ),
+        (
+            "--log-level [level]",
+            "off, error, warn, info (default), debug or trace".to_string(),
+        ),
+        ("-h, --help", "print this help".to_string()),
+    ];
+    for (option, description) in options {
+        println!("  {:<24}{}", option, description);
+    }
+}
+
+//延迟加载全局变量 CONFIG，测试时文件系统放在内存中，不读取测试程序的参数
+lazy_static! {
+    pub static ref CONFIG: ServerConfig = if cfg!(test) {
+        ServerConfig {
+            memory: true,
+            history: None,
+            ..Default::default()
+        }
+    } else {
+        ServerConfig::from_args()
+    };
+}
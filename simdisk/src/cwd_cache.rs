@@ -0,0 +1,145 @@
+//! 每个连接缓存最近进入的目录
+//!
+//! client每条命令都带上cwd，server按绝对路径从根目录逐级查找。每个连接记住最近一次
+//! 查找的目录路径（通常就是当前目录）和它的inode号，同一目录下的后续命令不再逐级查找。
+//! 每次释放写锁时代数加一，之前缓存的目录全部失效
+use std::{
+    cell::RefCell,
+    future::Future,
+    io::Error,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use crate::{
+    config::CONFIG,
+    dirent,
+    inode::{Inode, InodeIdType},
+};
+
+/// 缓存的目录
+struct CachedDir {
+    path: String,
+    generation: u64, // 缓存时的代数，和当前代数不同时失效
+    inode_id: InodeIdType,
+}
+
+tokio::task_local! {
+    static CWD: RefCell<Option<CachedDir>>;
+}
+
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+static PATH_WALKS: AtomicUsize = AtomicUsize::new(0); // 从根目录逐级查找的次数
+static HITS: AtomicUsize = AtomicUsize::new(0); // 命中缓存的次数
+
+/// 在连接的任务中运行f，期间查找的目录缓存在该连接中
+pub fn scope<F: Future>(f: F) -> impl Future<Output = F::Output> {
+    CWD.scope(RefCell::new(None), f)
+}
+
+/// 从根目录root进入绝对路径path所指的目录，和缓存的目录相同时直接读出其inode；
+/// 不在连接中（如http浏览器）或用 --no-cwd-cache 关闭时总是逐级查找
+pub async fn cd(path: &str, root: &Inode) -> Result<Inode, Error> {
+    // 根目录不需要查找
+    if !path.contains('/') {
+        return dirent::cd(path, root).await;
+    }
+    let generation = GENERATION.load(Ordering::SeqCst);
+    let cached = CWD
+        .try_with(|cwd| match &*cwd.borrow() {
+            Some(dir) if dir.path == path && dir.generation == generation => Some(dir.inode_id),
+            _ => None,
+        })
+        .ok()
+        .flatten();
+    if let Some(inode_id) = cached.filter(|_| CONFIG.cwd_cache) {
+        HITS.fetch_add(1, Ordering::Relaxed);
+        return Inode::read(inode_id as usize).await;
+    }
+    PATH_WALKS.fetch_add(1, Ordering::Relaxed);
+    let inode = dirent::cd(path, root).await?;
+    let _ = CWD.try_with(|cwd| {
+        *cwd.borrow_mut() = Some(CachedDir {
+            path: path.to_owned(),
+            generation,
+            inode_id: inode.inode_id,
+        })
+    });
+    Ok(inode)
+}
+
+/// 文件系统可能被修改，所有连接缓存的目录失效
+pub fn invalidate() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// 返回逐级查找和命中缓存的次数，包含所有连接
+pub fn stats() -> (usize, usize) {
+    (
+        PATH_WALKS.load(Ordering::Relaxed),
+        HITS.load(Ordering::Relaxed),
+    )
+}
+
+pub fn reset_stats() {
+    PATH_WALKS.store(0, Ordering::Relaxed);
+    HITS.store(0, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        syscall,
+        test_utils::{mktree, setup},
+    };
+
+    const DEEP: &str = "~/a/b/c/d/e";
+
+    /// 在同一个连接中依次执行dir和stat，返回逐级查找和命中缓存的次数
+    async fn run_in_deep_dir(times: usize) -> (usize, usize) {
+        reset_stats();
+        scope(async {
+            for _ in 0..times {
+                syscall::ls("root", DEEP, false, false, false)
+                    .await
+                    .unwrap();
+                let file = [DEEP, "/x.txt"].concat();
+                syscall::stat(&file, &file).await.unwrap();
+            }
+        })
+        .await;
+        stats()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn repeated_commands_skip_path_walks() {
+        let _fs = setup().await;
+        mktree(&["a/b/c/d/e/x.txt:x"]).await;
+
+        // 只有第一次逐级查找
+        assert_eq!(run_in_deep_dir(5).await, (1, 9));
+
+        // 每个连接有自己的缓存
+        reset_stats();
+        for _ in 0..3 {
+            scope(syscall::ls("root", DEEP, false, false, false))
+                .await
+                .unwrap();
+        }
+        assert_eq!(stats(), (3, 0));
+
+        // 写锁释放后缓存失效
+        reset_stats();
+        scope(async {
+            syscall::ls("root", DEEP, false, false, false)
+                .await
+                .unwrap();
+            mktree(&["a/b/c/d/e/y.txt:y"]).await;
+            syscall::ls("root", DEEP, false, false, false)
+                .await
+                .unwrap();
+        })
+        .await;
+        assert_eq!(stats(), (2, 0));
+    }
+}
@@ -0,0 +1,278 @@
+//! 块设备
+//!
+//! 块缓存只通过`BlockDevice`读写底层的块，不关心块实际存放在哪里：
+//! `FileDevice`把块存放在宿主上的镜像文件中，`MemoryDevice`把块存放在内存中，
+//! umount之后换上`DetachedDevice`，其他后端（如加密、远程存储）实现该trait即可接入文件系统
+use std::{
+    fs::{File, OpenOptions},
+    io::{Error, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+use crate::{config::CONFIG, fs_constants::*};
+
+/// 以块为单位读写的存储设备
+///
+/// 块缓存在持有锁的情况下同步地读入缺失的块，所以接口是同步的
+pub trait BlockDevice: Send + Sync {
+    /// 从first_block开始读取连续的块，填满buffer（长度为BLOCK_SIZE的整数倍）
+    fn read_blocks(&mut self, first_block: usize, buffer: &mut [u8]) -> Result<(), Error>;
+
+    /// 将一个块的内容写入block_id
+    fn write_block(&mut self, block_id: usize, bytes: &[u8; BLOCK_SIZE]) -> Result<(), Error>;
+
+    /// 清空设备并分配FS_SIZE大小的空间，用于格式化
+    fn reset(&mut self) -> Result<(), Error>;
+
+    /// 将设备扩大到size字节，原有内容不变，新增部分为0
+    fn grow(&mut self, size: u64) -> Result<(), Error>;
+
+    /// 返回设备的名字和大小（字节），用于展示
+    fn describe(&self) -> Result<(String, u64), Error>;
+
+    /// 是否挂载了镜像，umount后为false
+    fn is_attached(&self) -> bool {
+        true
+    }
+}
+
+/// 根据启动参数打开块设备
+pub fn open() -> Box<dyn BlockDevice> {
+    if CONFIG.memory {
+        Box::<MemoryDevice>::default()
+    } else {
+        Box::new(FileDevice::new(&CONFIG.image))
+    }
+}
+
+/// 存放在宿主镜像文件中的块设备，文件在第一次读写时才打开
+pub struct FileDevice {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl FileDevice {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: PathBuf::from(path),
+            file: None,
+        }
+    }
+
+    fn file(&mut self) -> Result<&mut File, Error> {
+        if self.file.is_none() {
+            let file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+            self.file = Some(file);
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+}
+
+impl BlockDevice for FileDevice {
+    fn read_blocks(&mut self, first_block: usize, buffer: &mut [u8]) -> Result<(), Error> {
+        let file = self.file()?;
+        file.seek(SeekFrom::Start((first_block * BLOCK_SIZE) as u64))?;
+        file.read_exact(buffer)
+    }
+
+    fn write_block(&mut self, block_id: usize, bytes: &[u8; BLOCK_SIZE]) -> Result<(), Error> {
+        let file = self.file()?;
+        file.seek(SeekFrom::Start((block_id * BLOCK_SIZE) as u64))?;
+        file.write_all(bytes)
+    }
+
+    fn reset(&mut self) -> Result<(), Error> {
+        self.file = None;
+        File::create(&self.path)?.set_len(FS_SIZE as u64)
+    }
+
+    fn grow(&mut self, size: u64) -> Result<(), Error> {
+        self.file()?.set_len(size)
+    }
+
+    fn describe(&self) -> Result<(String, u64), Error> {
+        let path = std::fs::canonicalize(&self.path)?;
+        let size = std::fs::metadata(&path)?.len();
+        Ok((path.display().to_string(), size))
+    }
+}
+
+/// 存放在内存中的块设备，server退出后内容丢失
+#[derive(Default)]
+pub struct MemoryDevice {
+    bytes: Vec<u8>, // 格式化之前为空
+}
+
+impl MemoryDevice {
+    /// 检查块是否越界，返回块在bytes中的起始位置
+    fn check_range(&self, first_block: usize, len: usize) -> Result<usize, Error> {
+        let start = first_block * BLOCK_SIZE;
+        if start + len > self.bytes.len() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("block {} is out of memory device", first_block),
+            ));
+        }
+        Ok(start)
+    }
+}
+
+impl BlockDevice for MemoryDevice {
+    fn read_blocks(&mut self, first_block: usize, buffer: &mut [u8]) -> Result<(), Error> {
+        let start = self.check_range(first_block, buffer.len())?;
+        buffer.copy_from_slice(&self.bytes[start..start + buffer.len()]);
+        Ok(())
+    }
+
+    fn write_block(&mut self, block_id: usize, bytes: &[u8; BLOCK_SIZE]) -> Result<(), Error> {
+        let start = self.check_range(block_id, BLOCK_SIZE)?;
+        self.bytes[start..start + BLOCK_SIZE].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<(), Error> {
+        self.bytes = vec![0; FS_SIZE];
+        Ok(())
+    }
+
+    fn grow(&mut self, size: u64) -> Result<(), Error> {
+        self.bytes.resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn describe(&self) -> Result<(String, u64), Error> {
+        Ok(("<memory>".to_string(), self.bytes.len() as u64))
+    }
+}
+
+/// umount之后使用的空设备，所有读写都返回err
+pub struct DetachedDevice;
+
+impl DetachedDevice {
+    fn error() -> Error {
+        Error::new(
+            ErrorKind::NotConnected,
+            "no image mounted, use mount [host path]",
+        )
+    }
+}
+
+impl BlockDevice for DetachedDevice {
+    fn read_blocks(&mut self, _: usize, _: &mut [u8]) -> Result<(), Error> {
+        Err(Self::error())
+    }
+
+    fn write_block(&mut self, _: usize, _: &[u8; BLOCK_SIZE]) -> Result<(), Error> {
+        Err(Self::error())
+    }
+
+    fn reset(&mut self) -> Result<(), Error> {
+        Err(Self::error())
+    }
+
+    fn grow(&mut self, _: u64) -> Result<(), Error> {
+        Err(Self::error())
+    }
+
+    fn describe(&self) -> Result<(String, u64), Error> {
+        Err(Self::error())
+    }
+
+    fn is_attached(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use super::*;
+    use crate::{
+        block::BLOCK_CACHE_MANAGER,
+        simple_fs::SFS,
+        test_utils::{exec, exec_as, setup},
+    };
+
+    /// 只保存写过的块的稀疏内存设备，记录读写的块数
+    #[derive(Default)]
+    struct SparseDevice {
+        blocks: HashMap<usize, [u8; BLOCK_SIZE]>,
+        size: u64,
+        reads: Arc<AtomicUsize>,
+        writes: Arc<AtomicUsize>,
+    }
+
+    impl BlockDevice for SparseDevice {
+        fn read_blocks(&mut self, first_block: usize, buffer: &mut [u8]) -> Result<(), Error> {
+            for (i, chunk) in buffer.chunks_mut(BLOCK_SIZE).enumerate() {
+                match self.blocks.get(&(first_block + i)) {
+                    Some(block) => chunk.copy_from_slice(block),
+                    None => chunk.fill(0),
+                }
+                self.reads.fetch_add(1, Ordering::SeqCst);
+            }
+            Ok(())
+        }
+
+        fn write_block(&mut self, block_id: usize, bytes: &[u8; BLOCK_SIZE]) -> Result<(), Error> {
+            self.blocks.insert(block_id, *bytes);
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn reset(&mut self) -> Result<(), Error> {
+            self.blocks.clear();
+            self.size = FS_SIZE as u64;
+            Ok(())
+        }
+
+        fn grow(&mut self, size: u64) -> Result<(), Error> {
+            self.size = size;
+            Ok(())
+        }
+
+        fn describe(&self) -> Result<(String, u64), Error> {
+            Ok(("<sparse>".to_string(), self.size))
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn file_system_runs_on_another_device() {
+        let _fs = setup().await;
+        let device = SparseDevice::default();
+        let (reads, writes) = (device.reads.clone(), device.writes.clone());
+        BLOCK_CACHE_MANAGER
+            .write()
+            .await
+            .replace_device(Box::new(device));
+        SFS.write().await.force_clear().await;
+
+        let content = "s".repeat(3 * BLOCK_SIZE);
+        exec("md docs").await.unwrap();
+        exec_as("root", "~", "newfile docs/a.txt", &[&content])
+            .await
+            .unwrap();
+        exec("copy docs/a.txt docs/b.txt").await.unwrap();
+        exec("rename docs/b.txt b.md").await.unwrap();
+        exec("del docs/a.txt").await.unwrap();
+        exec("check").await.unwrap();
+        assert!(writes.load(Ordering::SeqCst) > 0);
+        let mount_info = exec("mount-info").await.unwrap().unwrap();
+        assert!(mount_info.contains("<sparse>"), "{}", mount_info);
+
+        // 清空块缓存后从设备读回
+        let manager = Arc::clone(&BLOCK_CACHE_MANAGER);
+        manager.write().await.sync_and_clear_cache().await.unwrap();
+        let before = reads.load(Ordering::SeqCst);
+        assert_eq!(exec("cat docs/b.md").await.unwrap().unwrap(), content);
+        assert!(exec("cat docs/a.txt").await.is_err());
+        assert!(reads.load(Ordering::SeqCst) > before);
+    }
+}
@@ -0,0 +1,201 @@
+//! 按行比较文本，输出统一格式（unified）的差异
+
+use crate::fs_constants::{DIFF_CONTEXT_LINES, DIFF_MAX_CELLS};
+
+/// 编辑脚本中的一步，保存行在原文本中的下标
+#[derive(Clone, Copy)]
+enum Op {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// 比较两段文本，相同时返回None；
+/// 去掉相同的首尾后仍然过大时只报告第一处不同的行
+pub fn unified(name1: &str, name2: &str, text1: &str, text2: &str) -> Option<String> {
+    let old: Vec<_> = text1.lines().collect();
+    let new: Vec<_> = text2.lines().collect();
+    if old == new {
+        return None;
+    }
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+    if (old_mid.len() + 1) * (new_mid.len() + 1) > DIFF_MAX_CELLS {
+        return Some(format!(
+            "{} and {} differ at line {}\n< {}\n> {}",
+            name1,
+            name2,
+            prefix + 1,
+            old.get(prefix).unwrap_or(&""),
+            new.get(prefix).unwrap_or(&""),
+        ));
+    }
+
+    // 相同的首尾也作为上下文参与分块
+    let mut ops: Vec<_> = (0..prefix).map(|i| Op::Equal(i, i)).collect();
+    ops.extend(lcs_ops(old_mid, new_mid).into_iter().map(|op| match op {
+        Op::Equal(i, j) => Op::Equal(i + prefix, j + prefix),
+        Op::Delete(i) => Op::Delete(i + prefix),
+        Op::Insert(j) => Op::Insert(j + prefix),
+    }));
+    ops.extend((0..suffix).map(|k| Op::Equal(old.len() - suffix + k, new.len() - suffix + k)));
+
+    let mut lines = vec![format!("--- {}", name1), format!("+++ {}", name2)];
+    for (start, end) in hunks(&ops) {
+        lines.push(hunk_header(&ops, start, end));
+        for op in &ops[start..end] {
+            lines.push(match *op {
+                Op::Equal(i, _) => [" ", old[i]].concat(),
+                Op::Delete(i) => ["-", old[i]].concat(),
+                Op::Insert(j) => ["+", new[j]].concat(),
+            });
+        }
+    }
+    Some(lines.join("\n"))
+}
+
+/// 用最长公共子序列求出编辑脚本，删除排在插入之前
+fn lcs_ops(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let (n, m) = (old.len(), new.len());
+    // lcs[i][j]为old[i..]和new[j..]的最长公共子序列长度
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut ops = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            ops.push(Op::Equal(i, j));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(Op::Delete(i));
+            i += 1;
+        } else {
+            ops.push(Op::Insert(j));
+            j += 1;
+        }
+    }
+    ops
+}
+
+/// 把改动及其前后的上下文划分为若干块，返回每块在ops中的范围
+fn hunks(ops: &[Op]) -> Vec<(usize, usize)> {
+    let changes: Vec<_> = (0..ops.len())
+        .filter(|&k| !matches!(ops[k], Op::Equal(..)))
+        .collect();
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for k in changes {
+        let start = k.saturating_sub(DIFF_CONTEXT_LINES);
+        let end = (k + DIFF_CONTEXT_LINES + 1).min(ops.len());
+        match hunks.last_mut() {
+            // 上下文相接或重叠时合并为一块
+            Some((_, last_end)) if start <= *last_end => *last_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    hunks
+}
+
+/// 块头 @@ -起始行,行数 +起始行,行数 @@，行号从1开始，行数为0时起始行为前一行
+fn hunk_header(ops: &[Op], start: usize, end: usize) -> String {
+    // 统计ops中属于旧文本（或新文本）的行数
+    let count = |ops: &[Op], is_old: bool| {
+        ops.iter()
+            .filter(|op| match op {
+                Op::Equal(..) => true,
+                Op::Delete(_) => is_old,
+                Op::Insert(_) => !is_old,
+            })
+            .count()
+    };
+    let range = |is_old: bool| {
+        let before = count(&ops[..start], is_old);
+        let lines = count(&ops[start..end], is_old);
+        let first = if lines == 0 { before } else { before + 1 };
+        format!("{},{}", first, lines)
+    };
+    format!("@@ -{} +{} @@", range(true), range(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unified;
+    use crate::test_utils::{exec, mktree, setup};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn diff_copy_and_modified_file() {
+        let _fs = setup().await;
+        let lines: Vec<_> = (1..=10).map(|i| format!("line {}", i)).collect();
+        let original = lines.join("\n");
+        let modified = original.replace("line 5", "line five");
+        mktree(&[
+            &format!("a.txt:{}", original),
+            &format!("b.txt:{}", modified),
+            "d/",
+        ])
+        .await;
+
+        exec("copy a.txt c.txt").await.unwrap();
+        assert_eq!(
+            exec("diff a.txt c.txt").await.unwrap().unwrap(),
+            "identical"
+        );
+        let output = exec("diff a.txt b.txt").await.unwrap().unwrap();
+        let expected = [
+            "--- ~/a.txt",
+            "+++ ~/b.txt",
+            "@@ -2,7 +2,7 @@",
+            " line 2",
+            " line 3",
+            " line 4",
+            "-line 5",
+            "+line five",
+            " line 6",
+            " line 7",
+            " line 8",
+        ];
+        assert_eq!(output, expected.join("\n"));
+
+        // 目录和不存在的文件报错，错误信息中注明是哪个路径
+        let err = exec("diff a.txt d").await.unwrap_err();
+        assert!(err.to_string().starts_with("diff: ~/d:"), "{}", err);
+        let err = exec("diff missing.txt a.txt").await.unwrap_err();
+        assert!(
+            err.to_string().starts_with("diff: ~/missing.txt:"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn separate_changes_form_separate_hunks() {
+        let old: Vec<_> = (1..=20).map(|i| i.to_string()).collect();
+        let mut new = old.clone();
+        new.remove(1);
+        new.push("21".to_string());
+        let output = unified("old", "new", &old.join("\n"), &new.join("\n")).unwrap();
+        let headers: Vec<_> = output
+            .lines()
+            .filter(|line| line.starts_with("@@"))
+            .collect();
+        assert_eq!(headers, ["@@ -1,5 +1,4 @@", "@@ -18,3 +17,4 @@"]);
+        assert!(output.contains("\n-2\n"), "{}", output);
+        assert!(output.ends_with("\n+21"), "{}", output);
+        assert_eq!(unified("old", "new", "same\n", "same"), None);
+    }
+}
@@ -16,6 +16,7 @@ use crate::{
         self, deserialize, get_all_valid_blocks, get_blocks_buffers, insert_object, remove_object,
     },
     fs_constants::*,
+    fs_lock,
     inode::{Inode, InodeIdType, InodeType},
     user::{self, UserIdType},
 };
@@ -24,9 +25,10 @@ use crate::{
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct DirEntry {
     filename: [u8; NAME_LENGTH_LIMIT],       //文件名：10B
-    extension: [u8; EXTENSION_LENGTH_LIMIT], //扩展名: 3B
+    extension: [u8; EXTENSION_LENGTH_LIMIT], //扩展名: 8B
     pub is_dir: bool,                        //目录标志：1B
     pub inode_id: InodeIdType,               //inode号: 2B
+    reserved: [u8; 11],                      //保留，使目录项为32B，整块能放下整数个
 }
 
 impl PartialEq for DirEntry {
@@ -70,6 +72,7 @@ impl DirEntry {
                 extension: extension_,
                 inode_id,
                 is_dir,
+                reserved: [0; 11],
             })
         }
     }
@@ -80,28 +83,28 @@ impl DirEntry {
     }
 
     /// 创建特殊目录.
-    pub async fn create_dot(inode: &mut Inode) -> Self {
-        let dirent = Self::new(".", "", true, inode.inode_id).unwrap();
-        inode.linkat().await;
-        dirent
+    pub async fn create_dot(inode: &mut Inode) -> Result<Self, Error> {
+        let dirent = Self::new(".", "", true, inode.inode_id)?;
+        inode.linkat().await?;
+        Ok(dirent)
     }
 
     /// 创建特殊目录..
-    pub async fn create_dot_dot(inode: &mut Inode) -> Self {
-        let dirent = Self::new("..", "", true, inode.inode_id).unwrap();
-        inode.linkat().await;
-        dirent
+    pub async fn create_dot_dot(inode: &mut Inode) -> Result<Self, Error> {
+        let dirent = Self::new("..", "", true, inode.inode_id)?;
+        inode.linkat().await?;
+        Ok(dirent)
     }
 
     /// 创建两个特殊目录
     pub async fn create_special_diretories(
         current_inode: &mut Inode,
         parent_inode: &mut Inode,
-    ) -> (Self, Self) {
-        (
-            Self::create_dot(current_inode).await,
-            Self::create_dot_dot(parent_inode).await,
-        )
+    ) -> Result<(Self, Self), Error> {
+        Ok((
+            Self::create_dot(current_inode).await?,
+            Self::create_dot_dot(parent_inode).await?,
+        ))
     }
 
     /// 返回一个数组，包含块等级，所在的block id，以及目录项
@@ -109,26 +112,17 @@ impl DirEntry {
         inode: &Inode,
     ) -> Result<Vec<(block::BlockLevel, block::BlockIDType, Self)>, Error> {
         let mut dirs = Vec::new();
-        for (level, block_id, _) in &get_all_valid_blocks(inode).await? {
+        for (level, block_id, bytes) in &get_all_valid_blocks(inode).await? {
             if *block_id == 0 {
                 break;
             }
-            // 取出对应block的所有dirent buffer
-            let mut block_args = Vec::new();
-            for i in 0..BLOCK_SIZE / DIRENTRY_SIZE {
-                let start = i * DIRENTRY_SIZE;
-                let end = start + DIRENTRY_SIZE;
-                block_args.push((*block_id as usize, start, end));
-            }
-            let buffers = get_blocks_buffers(&block_args).await?;
-
-            // 将buffer反序列化成dirent
-            for buffer in buffers {
+            // 块的内容已经读出，按目录项大小切分后反序列化成dirent
+            for buffer in bytes.chunks(DIRENTRY_SIZE) {
                 // 名字第一个字节为空 说明不是dirent
                 if buffer[0] == 0 {
                     continue;
                 }
-                let dir: DirEntry = deserialize(&buffer)?;
+                let dir: DirEntry = deserialize(buffer)?;
                 dirs.push((*level, *block_id, dir));
             }
         }
@@ -180,9 +174,10 @@ impl DirEntry {
         }
     }
 
-    /// 递归清空该目录下的所有inode和dirent
+    /// 递归清空该目录下的所有inode和dirent，depth为当前递归的层数
     #[async_recursion]
-    pub async fn clear_dir(&mut self) {
+    pub async fn clear_dir(&mut self, depth: usize) -> Result<(), Error> {
+        check_path_depth(depth)?;
         //0. 收集目录下的inode并分类
         let inode = Inode::read(self.inode_id as usize).await.unwrap();
         let mut dirents = Self::get_all_dirent(&inode).await.unwrap();
@@ -200,7 +195,7 @@ impl DirEntry {
                 InodeType::Diretory => {
                     // 单独为上级目录unlinkat
                     if dirent.is_parent() {
-                        inode_inside.unlinkat().await;
+                        inode_inside.unlinkat().await?;
                     }
                     // 不要把特殊目录放进去,以免重复删除
                     if !dirent.is_special() {
@@ -227,7 +222,7 @@ impl DirEntry {
             let fname = dir.filename;
             let name = String::from_utf8_lossy(&fname);
             trace!("try clear {}", name);
-            dir.clear_dir().await;
+            dir.clear_dir(depth + 1).await?;
             trace!("clear {} ok", name);
         }
 
@@ -238,6 +233,7 @@ impl DirEntry {
             trace!("dealloc {} ok", dnode.inode_id);
         }
         trace!("clear ok");
+        Ok(())
     }
 
     pub fn is_current(&self) -> bool {
@@ -251,6 +247,40 @@ impl DirEntry {
     pub fn is_special(&self) -> bool {
         is_special_dir(&self.get_filename())
     }
+
+    /// 判断是否是隐藏的目录项：特殊目录和以.开头的文件或目录
+    pub fn is_hidden(&self) -> bool {
+        self.get_filename().starts_with('.')
+    }
+}
+
+/// 目录的目录项（含特殊目录）已达到可寻址的上限时返回directory full，
+/// 在申请inode之前检查，避免插入目录项时才失败
+pub async fn check_dir_not_full(parent_inode: &Inode) -> Result<(), Error> {
+    // 还有可以申请的块时一定能插入，只读取间接块，不用逐个统计目录项
+    let max_blocks = MAX_DIR_ENTRIES * DIRENTRY_SIZE / BLOCK_SIZE;
+    let ids = block::get_file_block_ids(parent_inode, max_blocks).await?;
+    if ids.contains(&0) {
+        return Ok(());
+    }
+    // 所有块都已申请时统计已用的位置，名字第一个字节为空的位置是空闲的
+    let args: Vec<_> = ids.iter().map(|id| (*id as usize, 0, BLOCK_SIZE)).collect();
+    let entries = get_blocks_buffers(&args)
+        .await?
+        .iter()
+        .flat_map(|buffer| buffer.chunks(DIRENTRY_SIZE))
+        .filter(|entry| entry[0] != 0)
+        .count();
+    if entries >= MAX_DIR_ENTRIES {
+        return Err(Error::new(
+            ErrorKind::OutOfMemory,
+            format!(
+                "directory full: {} of {} entries used",
+                entries, MAX_DIR_ENTRIES
+            ),
+        ));
+    }
+    Ok(())
 }
 
 /// 创建目录，失败时返回错误信息
@@ -278,9 +308,10 @@ pub async fn make_directory(
         let err = format!("diretory {} already exist", name);
         return Err(Error::new(ErrorKind::AlreadyExists, err));
     }
+    check_dir_not_full(parent_inode).await?;
     // 为新生成的目录项 申请inode
     let mut new_node = Inode::alloc_dir_inode(parent_inode, gid, uid).await?;
-    new_node.linkat().await;
+    new_node.linkat().await?;
     // 录入新的到的inode id
     dirent.inode_id = new_node.inode_id;
     // 为当前父节点持有的block添加一个目录项
@@ -289,13 +320,13 @@ pub async fn make_directory(
     Ok(())
 }
 
-/// 删除目录
+/// 删除目录，返回是否真的删除了（client可能取消删除）
 pub async fn remove_directory(
     name: &str,
     parent_inode: &mut Inode,
     socket: &mut TcpStream,
     gid: UserIdType,
-) -> Result<(), Error> {
+) -> Result<bool, Error> {
     if is_special_dir(name) {
         return Err(Error::new(
             ErrorKind::PermissionDenied,
@@ -307,13 +338,13 @@ pub async fn remove_directory(
     let mut dirent = DirEntry::new_temp(filename, ext, true)?;
     match dirent.get_block_id_and_try_update(parent_inode).await {
         // 判断目录是否非空
-        Ok((level, block_id)) => {
+        Ok(_) => {
             //找到了同名目录项
             if !dirent.is_dir {
                 // 不是目录，不能删除
                 return Err(Error::new(ErrorKind::PermissionDenied, "cannot rd a file"));
             }
-            let mut dir_inode = Inode::read(dirent.inode_id as usize).await?;
+            let dir_inode = Inode::read(dirent.inode_id as usize).await?;
             // 不能越权
             if !user::able_to_modify(gid, dir_inode.gid) {
                 return Err(Error::new(
@@ -340,18 +371,24 @@ pub async fn remove_directory(
                         "y" | "Y" => break,
                         _ => {
                             info!("remove cancel, input {}", response);
-                            return Ok(());
+                            return Ok(false);
                         }
                     }
                 }
             }
             trace!("answer is YES, do remove");
-            remove_object(&dirent, block_id as usize, level, parent_inode).await?;
-            dirent.clear_dir().await;
+            // 确认之后才取写锁，等待确认时不阻塞其他连接
+            let _lock = fs_lock::write().await;
+            // 等待确认期间目录可能已经被其他连接修改，重新查找
+            let (_, block_id) = dirent.get_block_id_and_try_update(parent_inode).await?;
+            let mut dir_inode = Inode::read(dirent.inode_id as usize).await?;
+            // 先清空目录，目录过深时不会移除目录项
+            dirent.clear_dir(1).await?;
+            remove_object(&dirent, block_id as usize).await?;
             // 最后dealloc一下目录自己的inode
             dir_inode.dealloc().await;
             trace!("remove dir ok");
-            Ok(())
+            Ok(true)
         }
         Err(err) => Err(err),
     }
@@ -361,6 +398,7 @@ pub async fn remove_directory(
 pub async fn cd(path: &str, current_inode: &Inode) -> Result<Inode, Error> {
     //将绝对路径分割为多段
     let paths: Vec<&str> = path.split('/').collect();
+    check_path_depth(paths.len() - 1)?;
     let mut current_inode = current_inode.clone();
     // 循环复合目录(除去~)
     for &path in &paths[1..] {
@@ -375,8 +413,23 @@ pub async fn cd(path: &str, current_inode: &Inode) -> Result<Inode, Error> {
     Ok(current_inode)
 }
 
+/// 检查目录深度是否超过MAX_PATH_DEPTH
+pub fn check_path_depth(depth: usize) -> Result<(), Error> {
+    if depth > MAX_PATH_DEPTH {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("path is deeper than {} levels", MAX_PATH_DEPTH),
+        ));
+    }
+    Ok(())
+}
+
 /// 尝试进入某目录
 async fn try_cd(name: &str, current_inode: &Inode) -> Result<Inode, Error> {
+    // 根目录没有..目录项，根目录的上级目录仍是根目录
+    if name == ".." && current_inode.inode_id == 0 {
+        return Ok(current_inode.clone());
+    }
     let (filename, ext) = if is_special_dir(name) {
         (name, "")
     } else {
@@ -401,14 +454,327 @@ async fn try_cd(name: &str, current_inode: &Inode) -> Result<Inode, Error> {
     }
 }
 
+/// 只修改文件的扩展名（如notes改为notes.md），文件名部分必须保持不变，目录项原地更新
+pub async fn change_extension(
+    name: &str,
+    new_name: &str,
+    parent_inode: &Inode,
+    gid: UserIdType,
+) -> Result<(), Error> {
+    if is_special_dir(name) || is_special_dir(new_name) {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "cannot rename special diretory",
+        ));
+    }
+    let (filename, ext) = split_name(name);
+    let (new_filename, new_ext) = split_name(new_name);
+    if filename != new_filename {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "only the extension can be changed",
+        ));
+    }
+    // 目录项按文件名和扩展名比较，必须用旧名字查找
+    let mut dirent = DirEntry::new_temp(filename, ext, false)?;
+    let (_, block_id) = dirent.get_block_id_and_try_update(parent_inode).await?;
+    let inode = Inode::read(dirent.inode_id as usize).await?;
+    if !user::able_to_modify(gid, inode.gid) {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "Insufficient user permissions",
+        ));
+    }
+    // 新的扩展名同样受长度限制，且不能和已有的目录项重名
+    let renamed = DirEntry::new(new_filename, new_ext, dirent.is_dir, dirent.inode_id)?;
+    let mut same_name = DirEntry::new_temp(new_filename, new_ext, false)?;
+    if same_name
+        .get_block_id_and_try_update(parent_inode)
+        .await
+        .is_ok()
+    {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} already exists", new_name),
+        ));
+    }
+    overwrite_in_block(&dirent, &renamed, block_id).await
+}
+
+/// 目录项记录的类型和所指inode的类型不一致时，按inode的类型原地修正，block_id为目录项所在的块
+pub async fn fix_is_dir(
+    dirent: &DirEntry,
+    is_dir: bool,
+    block_id: block::BlockIDType,
+) -> Result<(), Error> {
+    let mut fixed = dirent.clone();
+    fixed.is_dir = is_dir;
+    overwrite_in_block(dirent, &fixed, block_id).await
+}
+
+/// 将目录的..改为指向parent_inode，用于把目录挂到新的上级目录下
+pub async fn set_parent(dir_inode: &Inode, parent_inode: &mut Inode) -> Result<(), Error> {
+    for (_, block_id, dirent) in DirEntry::get_all_dirent(dir_inode).await? {
+        if dirent.is_parent() {
+            let mut new_dirent = dirent.clone();
+            new_dirent.inode_id = parent_inode.inode_id;
+            overwrite_in_block(&dirent, &new_dirent, block_id).await?;
+            // 和create_dot_dot相同，..计入上级目录的连接数
+            return parent_inode.linkat().await;
+        }
+    }
+    Err(Error::new(ErrorKind::NotFound, "no .. in directory"))
+}
+
+/// 在目录项所在的块中找到它的位置，原地覆盖为new_dirent
+async fn overwrite_in_block(
+    dirent: &DirEntry,
+    new_dirent: &DirEntry,
+    block_id: block::BlockIDType,
+) -> Result<(), Error> {
+    let block_args: Vec<_> = (0..BLOCK_SIZE / DIRENTRY_SIZE)
+        .map(|i| {
+            (
+                block_id as usize,
+                i * DIRENTRY_SIZE,
+                (i + 1) * DIRENTRY_SIZE,
+            )
+        })
+        .collect();
+    let buffers = get_blocks_buffers(&block_args).await?;
+    for (i, buffer) in buffers.iter().enumerate() {
+        // 名字第一个字节为空 说明不是dirent
+        if buffer[0] != 0 && deserialize::<DirEntry>(buffer)? == *dirent {
+            return block::write_block(new_dirent, block_id as usize, i * DIRENTRY_SIZE).await;
+        }
+    }
+    Err(Error::new(ErrorKind::NotFound, "dirent not found"))
+}
+
+/// 查找目录下名为name的目录项所指向的inode，文件和目录均可
+pub async fn find_inode(name: &str, parent_inode: &Inode) -> Result<Inode, Error> {
+    let (filename, ext) = if is_special_dir(name) {
+        (name, "")
+    } else {
+        split_name(name)
+    };
+    let mut dirent = DirEntry::new_temp(filename, ext, false)?;
+    dirent.get_block_id_and_try_update(parent_inode).await?;
+    Inode::read(dirent.inode_id as usize).await
+}
+
+/// 查找目录下名为name的目录项，存在时返回是否为目录，不存在时返回None而不是错误
+pub async fn lookup(name: &str, parent_inode: &Inode) -> Option<bool> {
+    let (filename, ext) = if is_special_dir(name) {
+        (name, "")
+    } else {
+        split_name(name)
+    };
+    // 名字不合法的目录项也不可能存在
+    let mut dirent = DirEntry::new_temp(filename, ext, false).ok()?;
+    dirent
+        .get_block_id_and_try_update(parent_inode)
+        .await
+        .ok()?;
+    Some(dirent.is_dir)
+}
+
 fn is_special_dir(name: &str) -> bool {
     name == "." || name == ".."
 }
 
+/// 通配符匹配，*匹配任意个字符，?匹配一个字符
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // 最近一次*的位置，以及此时name匹配到的位置
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // 让*多匹配一个字符后重试
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    // 剩下的只能是*
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
 // 分割输入的名字
 pub fn split_name(name: &str) -> (&str, &str) {
     match name.rsplit_once('.') {
+        // 以.开头且没有其他.的名字（如.trash）整体作为文件名
+        Some(("", _)) => (name, ""),
         Some(it) => it,
         None => (name, ""),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{exec, exec_as, lookup, mktree, setup};
+
+    #[test]
+    fn wildcards_match_names() {
+        assert!(wildcard_match("*.txt", "a.txt"));
+        assert!(wildcard_match("*.txt", ".txt"));
+        assert!(!wildcard_match("*.txt", "a.md"));
+        assert!(wildcard_match("a?c*", "abcde"));
+        assert!(!wildcard_match("a?c", "ac"));
+        assert!(wildcard_match("*a*b", "xxaxxb"));
+        assert!(!wildcard_match("*a*b", "xxaxxbc"));
+    }
+
+    fn chain(depth: usize) -> String {
+        ["~".to_string(), vec!["d"; depth].join("/")].join("/")
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn deep_chain_fails_cleanly_past_limit() {
+        let _fs = setup().await;
+        // 直接建立比限制更深的目录链，模拟构造出的镜像
+        let deep = format!("{}/", vec!["d"; MAX_PATH_DEPTH + 2].join("/"));
+        mktree(&[&deep]).await;
+
+        exec(&format!("cd {}", chain(MAX_PATH_DEPTH)))
+            .await
+            .unwrap();
+        let err = exec(&format!("cd {}", chain(MAX_PATH_DEPTH + 1)))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("deeper than"), "{}", err);
+
+        // 递归的命令遇到过深的目录时报错而不是栈溢出
+        for command in ["tree d", "du d", "treehash d"] {
+            let err = exec(command).await.unwrap_err();
+            assert!(
+                err.to_string().contains("deeper than"),
+                "{}: {}",
+                command,
+                err
+            );
+        }
+        let err = exec(&format!("md {}/x", chain(MAX_PATH_DEPTH)))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("deeper than"), "{}", err);
+        exec(&format!("md {}/x", chain(MAX_PATH_DEPTH - 1)))
+            .await
+            .unwrap();
+        let err = exec("rd d").await.unwrap_err();
+        assert!(err.to_string().contains("deeper than"), "{}", err);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rename_changes_only_the_extension() {
+        let _fs = setup().await;
+        mktree(&["notes:my notes", "other.md:other"]).await;
+
+        exec("rename notes notes.md").await.unwrap();
+        assert_eq!(exec("cat notes.md").await.unwrap().unwrap(), "my notes");
+        assert!(exec("cat notes").await.is_err());
+
+        // 文件名部分不能改变，扩展名受长度限制，不能和已有文件重名
+        assert!(exec("rename notes.md memo.md").await.is_err());
+        let ext = "x".repeat(EXTENSION_LENGTH_LIMIT + 1);
+        assert!(exec(&format!("rename notes.md notes.{}", ext))
+            .await
+            .is_err());
+        assert!(exec("rename other.md other.md").await.is_err());
+        assert_eq!(exec("cat notes.md").await.unwrap().unwrap(), "my notes");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn parent_of_root_is_root() {
+        let _fs = setup().await;
+        let root = mktree(&["a/b/", "a/x.txt:x"]).await;
+
+        assert_eq!(cd("~/..", &root).await.unwrap().inode_id, 0);
+        assert_eq!(cd("~/../../..", &root).await.unwrap().inode_id, 0);
+        let a = cd("~/a", &root).await.unwrap();
+        assert_eq!(cd("~/../a", &root).await.unwrap().inode_id, a.inode_id);
+        let b = cd("~/a/b", &root).await.unwrap();
+        assert_eq!(cd("~/../..", &b).await.unwrap().inode_id, 0);
+
+        exec("cd ..").await.unwrap();
+        assert_eq!(exec("cat ../a/x.txt").await.unwrap().unwrap(), "x");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn long_extensions_are_listed_in_full() {
+        let _fs = setup().await;
+        exec_as("root", "~", "newfile data.config", &["key=value"])
+            .await
+            .unwrap();
+        exec_as("root", "~", "newfile x.json", &["{}"])
+            .await
+            .unwrap();
+
+        let listing = exec("dir").await.unwrap().unwrap();
+        for name in ["data.config", "x.json"] {
+            assert!(listing.contains(name), "{} missing in {}", name, listing);
+        }
+        assert_eq!(exec("cat data.config").await.unwrap().unwrap(), "key=value");
+        let dirent = DirEntry::new("data", "config", false, 1).unwrap();
+        assert_eq!(dirent.get_filename(), "data.config");
+
+        // 最长8字节
+        let ext = "x".repeat(EXTENSION_LENGTH_LIMIT);
+        assert!(DirEntry::new("data", &ext, false, 1).is_ok());
+        let ext = "x".repeat(EXTENSION_LENGTH_LIMIT + 1);
+        assert!(DirEntry::new("data", &ext, false, 1).is_err());
+        assert!(exec(&format!("newfile data.{}", ext)).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn full_directory_rejects_new_entries() {
+        let _fs = setup().await;
+        mktree(&["target.txt:t", "full/"]).await;
+        let target = lookup("target.txt").await.inode_id;
+
+        // 目录能寻址的块全部写满指向target.txt的目录项：先申请最大文件的块，
+        // 写入目录项后把这些块交给目录
+        exec(&format!("mkfile filler {}", MAX_FILE_SIZE))
+            .await
+            .unwrap();
+        let filler = lookup("filler").await;
+        let mut dirent = DirEntry::new_temp("x", "", false).unwrap();
+        dirent.inode_id = target;
+        let entry = bincode::serialize(&dirent).unwrap();
+        let block = entry.repeat(BLOCK_SIZE / DIRENTRY_SIZE);
+        let ids = block::get_file_block_ids(&filler, MAX_FILE_SIZE / BLOCK_SIZE)
+            .await
+            .unwrap();
+        let ids: Vec<_> = ids.iter().map(|id| *id as usize).collect();
+        let contents = vec![block; ids.len()];
+        block::write_file_content_to_blocks(&contents, &ids)
+            .await
+            .unwrap();
+        let mut full = lookup("full").await;
+        full.addr = filler.addr;
+        full.save().await.unwrap();
+
+        let expected = format!("directory full: {0} of {0} entries used", MAX_DIR_ENTRIES);
+        // 每条命令都要在满的目录中查找同名目录项，只检查目录和文件两种
+        let err = exec("md full/sub").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OutOfMemory, "{}", err);
+        assert_eq!(err.to_string(), expected);
+        let err = exec_as("root", "~", "newfile full/b.txt", &["b"])
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), expected);
+        assert_eq!(exec("cat target.txt").await.unwrap().unwrap(), "t");
+    }
+}
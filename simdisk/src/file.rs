@@ -1,4 +1,10 @@
-use std::io::{Error, ErrorKind};
+use std::{
+    cmp::min,
+    io::{Error, ErrorKind, Write},
+    sync::Arc,
+};
+
+use flate2::{write::ZlibEncoder, Compression, Decompress, FlushDecompress, Status};
 
 use tokio::{
     io::AsyncWriteExt,
@@ -6,81 +12,562 @@ use tokio::{
 };
 
 use crate::{
+    bitmap::dealloc_data_bits,
     block::{
-        get_all_blocks, get_all_valid_blocks, insert_object, remove_object,
-        write_file_content_to_blocks,
+        get_blocks_buffers, get_file_block_ids, get_file_blocks, get_owned_block_ids,
+        insert_object, remove_object, write_blocks, write_bytes_to_block,
+        write_file_content_to_blocks, BlockIDType,
     },
     dirent::{self, DirEntry},
     fs_constants::*,
-    inode::{FileMode, Inode, InodeType},
+    fs_lock,
+    inode::{self, FileMode, Inode, InodeType},
+    packing,
+    simple_fs::SFS,
     user::{self, UserIdType},
 };
 
-/// 创建文件，存在同名文件时err
+/// 创建文件，存在同名文件时err；overwrite为true时覆盖同名文件
+///
+/// content为None时从client读取文件内容，超过软配额时返回告警信息
 pub async fn create_file(
     name: &str,
     mode: FileMode,
     parent_inode: &mut Inode,
-    is_copy: bool,
-    content: &str,
+    content: Option<&str>,
+    overwrite: bool,
     socket: &mut TcpStream,
     user_id: (UserIdType, UserIdType),
-) -> Result<(), Error> {
+) -> Result<Option<String>, Error> {
+    let inputs = match content {
+        // 如果是copy模式，则不需要使用stdio
+        Some(content) => content.to_owned(),
+        None => {
+            // 不能创建时不必等待client输入
+            check_new_file(name, parent_inode, overwrite, user_id.0).await?;
+            receive_input(socket).await?
+        }
+    };
+    create_file_with_content(name, mode, parent_inode, &inputs, overwrite, user_id).await
+}
+
+/// 用给定的内容创建文件，不需要client输入，其余和create_file相同
+pub async fn create_file_with_content(
+    name: &str,
+    mode: FileMode,
+    parent_inode: &mut Inode,
+    inputs: &str,
+    overwrite: bool,
+    user_id: (UserIdType, UserIdType),
+) -> Result<Option<String>, Error> {
+    // 等待输入期间目录可能已经改变，重新检查
+    let (dirent, exists) = check_new_file(name, parent_inode, overwrite, user_id.0).await?;
+    // 内容读取完毕后才取写锁，等待输入时不阻塞其他连接
+    let _lock = fs_lock::write().await;
+    let size = inputs.len() as u32;
+    let compressed = mode.contains(FileMode::COMPRESSED);
+    // 按block大小分割，压缩模式下写入的是压缩后的字节
+    let input_vecs = if compressed {
+        split_inputs(&compress(inputs.as_bytes())?)
+    } else {
+        split_inputs(inputs.as_bytes())
+    };
+    let stored_size = input_vecs.iter().map(|chunk| chunk.len()).sum::<usize>();
+    // 小文件和其他小文件打包存储
+    let mode = if !compressed && packing::should_pack(stored_size) {
+        mode | FileMode::PACKED
+    } else {
+        mode
+    };
+    // 切块数必须和按大小申请的块数一致
+    if input_vecs.len() > inode::blocks_for_size(stored_size) {
+        return Err(block_count_error());
+    }
+    let new_file = NewFile {
+        name,
+        dirent,
+        exists,
+        mode,
+        stored_size,
+        size: size as usize,
+    };
+    add_file(
+        new_file,
+        parent_inode,
+        user_id,
+        NewContent::Chunks(&input_vecs),
+    )
+    .await
+}
+
+/// 在文件系统内复制文件，存在同名文件时err；overwrite为true时覆盖同名文件。
+/// 按块复制源文件实际存储的字节，压缩、打包的存储方式保持不变，
+/// 内容不经过String，也不会整个读入内存，超过软配额时返回告警信息
+pub async fn copy_file(
+    source: &Inode,
+    name: &str,
+    parent_inode: &mut Inode,
+    overwrite: bool,
+    user_id: (UserIdType, UserIdType),
+) -> Result<Option<String>, Error> {
+    let (dirent, exists) = check_new_file(name, parent_inode, overwrite, user_id.0).await?;
+    let _lock = fs_lock::write().await;
+    // 源文件可能在取写锁之前被修改
+    let source = Inode::read(source.inode_id as usize).await?;
+    let storage = source.get_mode() & (FileMode::COMPRESSED | FileMode::PACKED);
+    let new_file = NewFile {
+        name,
+        dirent,
+        exists,
+        mode: FileMode::RDWR | storage,
+        stored_size: stored_size(&source).await?,
+        size: source.get_size(),
+    };
+    add_file(new_file, parent_inode, user_id, NewContent::Copy(&source)).await
+}
+
+/// 要创建的文件
+struct NewFile<'a> {
+    name: &'a str,
+    dirent: DirEntry,
+    exists: bool,       // 是否存在要被覆盖的同名文件
+    mode: FileMode,     // 包含压缩、打包等存储方式
+    stored_size: usize, // 实际存储的字节数
+    size: usize,        // （压缩前的）文件大小
+}
+
+/// 新文件内容的来源
+enum NewContent<'a> {
+    Chunks(&'a [Vec<u8>]), // 按块切分好的内容，压缩文件为压缩后的字节
+    Copy(&'a Inode),       // 按块复制另一个文件存储的字节
+}
+
+/// 检查能否在父目录中创建文件，返回新文件的目录项和是否存在同名文件
+async fn check_new_file(
+    name: &str,
+    parent_inode: &Inode,
+    overwrite: bool,
+    gid: UserIdType,
+) -> Result<(DirEntry, bool), Error> {
     let (filename, extension) = dirent::split_name(name);
     // 查找重名文件
+    let dirent = DirEntry::new_temp(filename, extension, false)?;
+    let mut same_name = DirEntry::new_temp(filename, extension, false)?;
+    let exists = same_name
+        .get_block_id_and_try_update(parent_inode)
+        .await
+        .is_ok();
+    if exists {
+        if !overwrite {
+            return Err(Error::new(ErrorKind::AlreadyExists, "file already exists"));
+        }
+        // 覆盖前先检查能否删除旧文件，以免写完新文件才失败
+        if same_name.is_dir {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{} is a directory", name),
+            ));
+        }
+        let old_inode = Inode::read(same_name.inode_id as usize).await?;
+        if !user::able_to_modify(gid, old_inode.gid) {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "Insufficient user permissions",
+            ));
+        }
+    } else {
+        // 覆盖时旧目录项先被删除，不需要新的位置
+        dirent::check_dir_not_full(parent_inode).await?;
+    }
+    Ok((dirent, exists))
+}
+
+/// 在持有写锁时检查配额、申请inode并写入内容，最后插入目录项
+async fn add_file(
+    new_file: NewFile<'_>,
+    parent_inode: &mut Inode,
+    user_id: (UserIdType, UserIdType),
+    content: NewContent<'_>,
+) -> Result<Option<String>, Error> {
+    let NewFile {
+        name,
+        mut dirent,
+        exists,
+        mode,
+        stored_size,
+        size,
+    } = new_file;
+    // 检查属主的配额，按写入后的用量判定
+    let usage = if mode.contains(FileMode::PACKED) {
+        stored_size
+    } else {
+        inode::blocks_for_size(stored_size) * BLOCK_SIZE
+    };
+    let usage = (inode::user_usage(user_id.1).await? + usage) as u64;
+    let warning = Arc::clone(&SFS)
+        .write()
+        .await
+        .user_infos
+        .check_quota(user_id.1, usage)
+        .await?;
+    // 按实际存储的大小申请inode
+    let compressed = mode.contains(FileMode::COMPRESSED);
+    let mut inode = Inode::alloc(
+        InodeType::File,
+        parent_inode,
+        mode,
+        stored_size as u32,
+        user_id.0,
+        user_id.1,
+    )
+    .await?;
+
+    dirent.inode_id = inode.inode_id;
+    // 内容全部写入、旧文件删除之后才插入目录项，
+    // 中途任何一步出错都释放新的inode和块，不留下写了一半的文件
+    let written = async {
+        if compressed {
+            // 记录压缩前的大小
+            inode.set_content_size(size as u32).await?;
+        }
+        inode.linkat().await?;
+        // 将文件写入block中
+        match content {
+            NewContent::Chunks(input_vecs) if inode.is_packed() => {
+                packing::write_packed(&inode, &input_vecs.concat()).await?
+            }
+            NewContent::Chunks(input_vecs) => write_blocks_of(&inode, input_vecs).await?,
+            NewContent::Copy(source) if inode.is_packed() => {
+                packing::write_packed(&inode, &packing::read_packed(source).await?).await?
+            }
+            NewContent::Copy(source) => {
+                copy_blocks_of(source, &inode, inode::blocks_for_size(stored_size)).await?
+            }
+        }
+        // 新文件写入成功后才删除旧文件
+        if exists {
+            remove_file(name, parent_inode, user_id.0).await?;
+        }
+        // 将目录项写入目录中
+        // 为当前父节点持有的block添加一个目录项
+        insert_object(&dirent, parent_inode).await
+    }
+    .await;
+    if let Err(err) = written {
+        inode.dealloc().await;
+        return Err(err);
+    }
+    Ok(warning)
+}
+
+/// 文件实际存储的字节数：普通文件和打包文件为文件大小，压缩文件为其占用的所有数据块
+async fn stored_size(inode: &Inode) -> Result<usize, Error> {
+    if !inode.is_compressed() {
+        return Ok(inode.get_size());
+    }
+    // 压缩后的长度没有记录，按申请时的块数计算
+    let ids = get_file_block_ids(inode, MAX_FILE_SIZE / BLOCK_SIZE).await?;
+    let blocks = ids.iter().filter(|id| **id != 0).count();
+    Ok(blocks * BLOCK_SIZE)
+}
+
+/// 将source的前block_nums个数据块逐批复制到inode新申请的块中，每批最多COPY_BATCH_BLOCKS块
+async fn copy_blocks_of(source: &Inode, inode: &Inode, block_nums: usize) -> Result<(), Error> {
+    let source_ids = get_file_block_ids(source, block_nums).await?;
+    let target_ids = get_file_block_ids(inode, block_nums).await?;
+    for (sources, targets) in source_ids
+        .chunks(COPY_BATCH_BLOCKS)
+        .zip(target_ids.chunks(COPY_BATCH_BLOCKS))
+    {
+        // 新申请的块已经清空，源文件中的空洞不需要写入
+        let (sources, targets): (Vec<_>, Vec<_>) = sources
+            .iter()
+            .zip(targets)
+            .filter(|(source_id, _)| **source_id != 0)
+            .map(|(source_id, target_id)| {
+                ((*source_id as usize, 0, BLOCK_SIZE), *target_id as usize)
+            })
+            .unzip();
+        if targets.contains(&0) {
+            return Err(block_count_error());
+        }
+        let buffers = get_blocks_buffers(&sources).await?;
+        write_file_content_to_blocks(&buffers, &targets).await?;
+    }
+    Ok(())
+}
+
+/// 通过临时socket从client读取文件内容
+async fn receive_input(socket: &mut TcpStream) -> Result<String, Error> {
+    // 建立临时socket，端口随机
+    // 和主连接使用同一个网卡
+    let listener = TcpListener::bind((socket.local_addr()?.ip(), 0)).await?;
+    // 2.ex1.1 向client告知需要输入内容，同时发送端口
+    let addr = listener.local_addr()?.to_string();
+    let msg = [utils::INPUT_FILE_CONTENT, &addr].concat();
+    socket.write_all(msg.as_bytes()).await?;
+    // 2.ex1.2 client 读取文件内容
+    info!("receiving contents through {}", addr);
+    let inputs = utils::receive_content(&listener).await?;
+    if inputs.len() > MAX_FILE_SIZE {
+        return Err(Error::new(ErrorKind::OutOfMemory, "File size limit exceed"));
+    }
+    Ok(inputs)
+}
+
+/// 原地替换文件的内容，inode保持不变，硬连接、属主和权限都不受影响，时间戳更新为当前时间；
+/// 压缩文件仍然压缩存储。按新内容重新申请块，写入成功后才释放原来的块，超过软配额时返回告警信息
+pub async fn edit_file(
+    name: &str,
+    parent_inode: &Inode,
+    socket: &mut TcpStream,
+    gid: UserIdType,
+) -> Result<Option<String>, Error> {
+    let inode = open_file(name, parent_inode).await?;
+    check_writable(&inode, gid)?;
+    let inputs = receive_input(socket).await?;
+    // 内容读取完毕后才取写锁，等待输入期间文件可能已经改变，重新查找
+    let _lock = fs_lock::write().await;
+    let inode = open_file(name, parent_inode).await?;
+    check_writable(&inode, gid)?;
+    replace_content(inode, inputs.as_bytes()).await
+}
+
+/// 在文件末尾追加内容，其余和edit_file相同
+///
+/// 文件大小在持有写锁时重新读取，并发的追加依次进行，每次都追加在前一次追加之后，不会互相覆盖；
+/// 普通文件只写最后一个块和新申请的块，打包、压缩或末尾是空洞的文件读出原内容后整体重写
+pub async fn append_file(
+    name: &str,
+    parent_inode: &Inode,
+    socket: &mut TcpStream,
+    gid: UserIdType,
+) -> Result<Option<String>, Error> {
+    let inode = open_file(name, parent_inode).await?;
+    check_writable(&inode, gid)?;
+    let inputs = receive_input(socket).await?;
+    // 等待输入期间其他连接可能已经追加过，取锁后重新读出文件
+    let _lock = fs_lock::write().await;
+    let inode = open_file(name, parent_inode).await?;
+    check_writable(&inode, gid)?;
+    if inode.get_size() + inputs.len() > MAX_FILE_SIZE {
+        return Err(Error::new(ErrorKind::OutOfMemory, "File size limit exceed"));
+    }
+    let last_id = get_file_block_ids(&inode, inode::blocks_for_size(inode.get_size()))
+        .await?
+        .last()
+        .copied();
+    if inode.is_packed() || inode.is_compressed() || last_id.unwrap_or(0) == 0 {
+        let content = [read_bytes(&inode).await?, inputs.into_bytes()].concat();
+        return replace_content(inode, &content).await;
+    }
+    append_in_place(inode, inputs.as_bytes()).await
+}
+
+/// 将bytes写到普通文件的末尾：最后一个块的剩余空间写入开头部分，其余写入新申请的块，
+/// 原有的块不读出也不改动；写入成功后才更新大小，失败时释放新申请的块。调用者需要持有写锁
+async fn append_in_place(mut inode: Inode, bytes: &[u8]) -> Result<Option<String>, Error> {
+    let size = inode.get_size();
+    let old_nums = inode::blocks_for_size(size);
+    let new_nums = inode::blocks_for_size(size + bytes.len());
+    let uid = inode.get_uid();
+    let usage = inode::user_usage(uid).await? + (new_nums - old_nums) * BLOCK_SIZE;
+    let warning = Arc::clone(&SFS)
+        .write()
+        .await
+        .user_infos
+        .check_quota(uid, usage as u64)
+        .await?;
+
+    let last_id = get_file_block_ids(&inode, old_nums).await?[old_nums - 1] as usize;
+    let more = inode.alloc_more_blocks(new_nums).await?;
+    let (head, rest) = bytes.split_at(min(old_nums * BLOCK_SIZE - size, bytes.len()));
+    let written = async {
+        write_bytes_to_block(head, last_id, size - (old_nums - 1) * BLOCK_SIZE).await?;
+        write_file_content_to_blocks(&split_inputs(rest), &more.data_ids).await?;
+        let addr_writes: Vec<_> = more
+            .addr_writes
+            .iter()
+            .map(|(id, block_id, start)| (id, *block_id, *start))
+            .collect();
+        write_blocks(&addr_writes).await
+    }
+    .await;
+    if let Err(err) = written {
+        dealloc_data_bits(&more.all_ids).await;
+        return Err(err);
+    }
+    inode.set_appended_size((size + bytes.len()) as u32).await?;
+    Ok(warning)
+}
+
+/// 用inputs替换文件的内容，调用者需要持有写锁
+async fn replace_content(inode: Inode, inputs: &[u8]) -> Result<Option<String>, Error> {
+    let compressed = inode.is_compressed();
+    let input_vecs = if compressed {
+        split_inputs(&compress(inputs)?)
+    } else {
+        split_inputs(inputs)
+    };
+    let stored_size = input_vecs.iter().map(|chunk| chunk.len()).sum::<usize>();
+    let packed = !compressed && packing::should_pack(stored_size);
+    if input_vecs.len() > inode::blocks_for_size(stored_size) {
+        return Err(block_count_error());
+    }
+    // 检查属主的配额，用量中扣除原来的内容
+    let old_usage = if inode.is_packed() {
+        inode.get_size()
+    } else {
+        get_owned_block_ids(&inode).await?.len() * BLOCK_SIZE
+    };
+    let new_usage = if packed {
+        stored_size
+    } else {
+        inode::blocks_for_size(stored_size) * BLOCK_SIZE
+    };
+    let uid = inode.get_uid();
+    let usage = (inode::user_usage(uid).await? + new_usage).saturating_sub(old_usage) as u64;
+    let warning = Arc::clone(&SFS)
+        .write()
+        .await
+        .user_infos
+        .check_quota(uid, usage)
+        .await?;
+
+    let new_inode = inode
+        .alloc_new_storage(packed, stored_size as u32, inputs.len() as u32)
+        .await?;
+    let written = if packed {
+        packing::write_packed(&new_inode, &input_vecs.concat()).await
+    } else {
+        write_blocks_of(&new_inode, &input_vecs).await
+    };
+    if let Err(err) = written {
+        new_inode.release_storage().await;
+        return Err(err);
+    }
+    // 新内容写入后才让inode指向新块，并释放原来的块
+    new_inode.save().await?;
+    inode.release_storage().await;
+    Ok(warning)
+}
+
+/// 将文件的数据块搬到一段连续的空闲块中，连续读取时可以合并为更少的读盘次数，调用者需要持有写锁
+///
+/// 先在新块中写好内容，再让inode指向新块并释放原来的块，中途失败时文件保持原样；
+/// 内容、大小、存储方式和时间戳都不变，文件中的空洞会被补上
+pub async fn defrag_file(name: &str, parent_inode: &Inode) -> Result<String, Error> {
+    let inode = open_file(name, parent_inode).await?;
+    if inode.is_packed() {
+        return Ok(format!("{} is packed, nothing to defrag", name));
+    }
+    let stored_size = stored_size(&inode).await?;
+    let block_nums = inode::blocks_for_size(stored_size);
+    let old_ids = get_file_block_ids(&inode, block_nums).await?;
+    let contiguous = |ids: &[BlockIDType]| {
+        ids.first() != Some(&0) && ids.windows(2).all(|pair| pair[1] == pair[0] + 1)
+    };
+    if contiguous(&old_ids) {
+        return Ok(format!("{} is already contiguous", name));
+    }
+    let new_inode = inode
+        .alloc_contiguous_storage(stored_size as u32, inode.get_size() as u32)
+        .await?;
+    if let Err(err) = copy_blocks_of(&inode, &new_inode, block_nums).await {
+        new_inode.release_storage().await;
+        return Err(err);
+    }
+    // 新块写好后才让inode指向新块，并释放原来的块
+    new_inode.save().await?;
+    inode.release_storage().await;
+    let new_ids = get_file_block_ids(&new_inode, block_nums).await?;
+    Ok(format!(
+        "{}: {} blocks moved to {}..={}",
+        name,
+        block_nums,
+        new_ids[0],
+        new_ids[block_nums - 1]
+    ))
+}
+
+/// 检查当前用户能否修改文件，以及文件的权限是否允许写入
+fn check_writable(inode: &Inode, gid: UserIdType) -> Result<(), Error> {
+    if !user::able_to_modify(gid, inode.gid) {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "Insufficient user permissions",
+        ));
+    }
+    if !inode
+        .get_mode()
+        .intersects(FileMode::WRONLY | FileMode::RDWR)
+    {
+        return Err(Error::new(ErrorKind::PermissionDenied, "file is read only"));
+    }
+    Ok(())
+}
+
+/// 将分好块的内容依次写入inode的数据块；只读取间接块得到块号，数据块整块覆盖时不需要读出
+async fn write_blocks_of(inode: &Inode, input_vecs: &[Vec<u8>]) -> Result<(), Error> {
+    let ids = get_file_block_ids(inode, input_vecs.len()).await?;
+    if ids.len() < input_vecs.len() || ids.contains(&0) {
+        return Err(block_count_error());
+    }
+    let block_ids: Vec<_> = ids.iter().map(|id| *id as usize).collect();
+    write_file_content_to_blocks(input_vecs, &block_ids).await
+}
+
+/// 创建指定大小、内容全为0的文件，存在同名文件时err，超过软配额时返回告警信息
+///
+/// 释放的块都会被清空，所以新申请的块不需要再写入
+pub async fn create_zero_file(
+    name: &str,
+    parent_inode: &mut Inode,
+    size: usize,
+    user_id: (UserIdType, UserIdType),
+) -> Result<Option<String>, Error> {
+    if size > MAX_FILE_SIZE {
+        return Err(Error::new(ErrorKind::OutOfMemory, "File size limit exceed"));
+    }
+    let (filename, extension) = dirent::split_name(name);
     let mut dirent = DirEntry::new_temp(filename, extension, false)?;
-    if dirent
+    let mut same_name = DirEntry::new_temp(filename, extension, false)?;
+    if same_name
         .get_block_id_and_try_update(parent_inode)
         .await
         .is_ok()
     {
         return Err(Error::new(ErrorKind::AlreadyExists, "file already exists"));
     }
+    dirent::check_dir_not_full(parent_inode).await?;
 
-    let inputs;
-    // 如果是copy模式，则不需要使用stdio
-    if is_copy {
-        inputs = content.to_owned();
-    } else {
-        // 建立临时socket，端口随机
-        let listener = TcpListener::bind("127.0.0.1:0").await?;
-        // 2.ex1.1 向client告知需要输入内容，同时发送端口
-        let addr = listener.local_addr()?.to_string();
-        let msg = [utils::INPUT_FILE_CONTENT, &addr].concat();
-        socket.write_all(msg.as_bytes()).await?;
-        // 2.ex1.2 client 读取文件内容
-        info!("receiving contents through {}", addr);
-        inputs = utils::receive_content(&listener).await?;
-        if inputs.len() > MAX_FILE_SIZE {
-            return Err(Error::new(ErrorKind::OutOfMemory, "File size limit exceed"));
-        }
-    }
-    let size = inputs.len() as u32;
-    // 按block大小分割
-    let input_vecs = split_inputs(inputs);
-    // 按大小申请inode
+    let _lock = fs_lock::write().await;
+    let usage = inode::blocks_for_size(size) * BLOCK_SIZE;
+    let usage = (inode::user_usage(user_id.1).await? + usage) as u64;
+    let warning = Arc::clone(&SFS)
+        .write()
+        .await
+        .user_infos
+        .check_quota(user_id.1, usage)
+        .await?;
+    // 不打包存储，按大小直接申请直接块和间接块
     let mut inode = Inode::alloc(
         InodeType::File,
         parent_inode,
-        mode,
-        size,
+        FileMode::RDWR,
+        size as u32,
         user_id.0,
         user_id.1,
     )
     .await?;
-    inode.linkat().await;
+    inode.linkat().await?;
 
     dirent.inode_id = inode.inode_id;
-    // 将文件写入block中
-    let blocks = get_all_blocks(&inode).await?;
-    assert!(blocks.len() >= input_vecs.len());
-    let block_ids: Vec<_> = blocks.iter().map(|(_, id, _)| *id as usize).collect();
-    write_file_content_to_blocks(&input_vecs, &block_ids).await?;
-
-    // 将目录项写入目录中
-    // 为当前父节点持有的block添加一个目录项
-    insert_object(&dirent, parent_inode).await?;
-    Ok(())
+    if let Err(err) = insert_object(&dirent, parent_inode).await {
+        inode.dealloc().await;
+        return Err(err);
+    }
+    Ok(warning)
 }
 
 /// 删除文件，不存在时err
@@ -94,7 +581,7 @@ pub async fn remove_file(
     let mut dirent = DirEntry::new_temp(filename, extension, false)?;
     match dirent.get_block_id_and_try_update(parent_inode).await {
         Err(err) => Err(err),
-        Ok((level, block_id)) => {
+        Ok((_, block_id)) => {
             if dirent.is_dir {
                 return Err(Error::new(
                     ErrorKind::PermissionDenied,
@@ -108,17 +595,99 @@ pub async fn remove_file(
                     "Insufficient user permissions",
                 ));
             }
-            // 释放inode
-            inode.dealloc().await;
+            if inode.get_nlink() > 1 {
+                // 仍有其他硬连接时只减少连接数
+                inode.unlinkat().await?;
+            } else {
+                // 释放inode
+                inode.dealloc().await;
+            }
             // 删除目录项
-            remove_object(&dirent, block_id as usize, level, parent_inode).await?;
+            remove_object(&dirent, block_id as usize).await?;
             Ok(())
         }
     }
 }
 
+/// 将文件的目录项从parent_inode移动到target_inode下，inode和数据块保持不变，
+/// 任何时候文件都至少可以从一个目录项访问到
+pub async fn move_file(
+    name: &str,
+    parent_inode: &mut Inode,
+    target_inode: &mut Inode,
+    gid: UserIdType,
+) -> Result<(), Error> {
+    let (filename, extension) = dirent::split_name(name);
+    let mut dirent = DirEntry::new_temp(filename, extension, false)?;
+    let (_, block_id) = dirent.get_block_id_and_try_update(parent_inode).await?;
+    if dirent.is_dir {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} is not a file", name),
+        ));
+    }
+    let inode = Inode::read(dirent.inode_id as usize).await?;
+    if !user::able_to_modify(gid, inode.gid) {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "Insufficient user permissions",
+        ));
+    }
+    // 目标目录下不能有同名目录项
+    let mut same_name = DirEntry::new_temp(filename, extension, false)?;
+    if same_name
+        .get_block_id_and_try_update(target_inode)
+        .await
+        .is_ok()
+    {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} already exists", name),
+        ));
+    }
+    dirent::check_dir_not_full(target_inode).await?;
+    // 先插入新目录项再删除原目录项：中途失败时最多留下两个指向同一inode的目录项，
+    // fsck能发现并修正硬连接数，而先删除后插入失败时文件就丢失了
+    insert_object(&dirent, target_inode).await?;
+    remove_object(&dirent, block_id as usize).await
+}
+
+/// 清空回收站中当前用户有权限删除的文件，返回删除的数量
+pub async fn empty_trash(trash_inode: &mut Inode, gid: UserIdType) -> Result<usize, Error> {
+    let mut count = 0;
+    for (_, _, dirent) in DirEntry::get_all_dirent(trash_inode).await? {
+        if dirent.is_dir {
+            continue;
+        }
+        let inode = Inode::read(dirent.inode_id as usize).await?;
+        if !user::able_to_modify(gid, inode.gid) {
+            continue; // 跳过其他用户的文件
+        }
+        remove_file(&dirent.get_filename(), trash_inode, gid).await?;
+        count += 1;
+    }
+    Ok(count)
+}
+
 /// 获取文件内容
 pub async fn get_file_content(name: &str, parent_inode: &Inode) -> Result<String, Error> {
+    let inode = open_file(name, parent_inode).await?;
+    read_content(&inode).await
+}
+
+/// 读取文件从start字节开始的len个字节，超出文件末尾的部分被忽略
+pub async fn get_file_range(
+    name: &str,
+    parent_inode: &Inode,
+    start: usize,
+    len: usize,
+) -> Result<String, Error> {
+    let inode = open_file(name, parent_inode).await?;
+    read_range(&inode, start, len).await
+}
+
+/// 在父目录中查找文件，返回文件的inode
+pub async fn open_file(name: &str, parent_inode: &Inode) -> Result<Inode, Error> {
     let (filename, extension) = dirent::split_name(name);
     // 查找重名文件
     let mut dirent = DirEntry::new_temp(filename, extension, false)?;
@@ -134,24 +703,773 @@ pub async fn get_file_content(name: &str, parent_inode: &Inode) -> Result<String
             "cannot open a directory",
         ))
     } else {
-        //获取内容
-        let inode = Inode::read(dirent.inode_id as usize).await?;
-        let blocks = get_all_valid_blocks(&inode).await?;
-        let bytes: Vec<_> = blocks.into_iter().flat_map(|(_, _, block)| block).collect();
+        Inode::read(dirent.inode_id as usize).await
+    }
+}
+
+/// 读取文件inode的内容
+pub async fn read_content(inode: &Inode) -> Result<String, Error> {
+    if !inode.is_compressed() {
+        let bytes = read_bytes(inode).await?;
         let content = String::from_utf8_lossy(&bytes)
             .trim_end_matches('\0')
             .to_string();
-        Ok(content)
+        return Ok(content);
+    }
+    // 边解压边解码，不保留整个解压结果
+    let mut inflater = Inflater::new(inode).await?;
+    let mut content = String::with_capacity(inode.get_size());
+    let mut pending = Vec::new();
+    while let Some(chunk) = inflater.next_chunk().await? {
+        push_lossy(&mut content, &mut pending, &chunk);
+    }
+    if !pending.is_empty() {
+        content.push(char::REPLACEMENT_CHARACTER);
+    }
+    content.truncate(content.trim_end_matches('\0').len());
+    Ok(content)
+}
+
+/// 将bytes按UTF-8解码后追加到content，无效的字节替换为U+FFFD，和String::from_utf8_lossy一致；
+/// 末尾不完整的字符留在pending中，和下一段拼接后再解码
+fn push_lossy(content: &mut String, pending: &mut Vec<u8>, bytes: &[u8]) {
+    pending.extend_from_slice(bytes);
+    let mut rest = &pending[..];
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                content.push_str(valid);
+                rest = &[];
+                break;
+            }
+            Err(err) => {
+                let (valid, invalid) = rest.split_at(err.valid_up_to());
+                content.push_str(std::str::from_utf8(valid).unwrap());
+                match err.error_len() {
+                    Some(len) => {
+                        content.push(char::REPLACEMENT_CHARACTER);
+                        rest = &invalid[len..];
+                    }
+                    None => {
+                        rest = invalid;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    *pending = rest.to_vec();
+}
+
+/// 读取文件inode的原始字节，长度和文件大小一致
+async fn read_bytes(inode: &Inode) -> Result<Vec<u8>, Error> {
+    let bytes = if inode.is_packed() {
+        packing::read_packed(inode).await?
+    } else if inode.is_compressed() {
+        read_compressed(inode).await?
+    } else {
+        // 空洞块由0填充，不会读盘
+        let mut bytes: Vec<_> = get_file_blocks(inode).await?.concat();
+        bytes.truncate(inode.get_size());
+        bytes
+    };
+    Ok(bytes)
+}
+
+/// 读取文件inode中[start, start+len)的内容，普通文件只读取覆盖该范围的块；
+/// 压缩文件解压到end为止，只保留范围内的内容；打包文件读出全部内容后截取
+pub async fn read_range(inode: &Inode, start: usize, len: usize) -> Result<String, Error> {
+    let end = min(start.saturating_add(len), inode.get_size());
+    if start >= end {
+        return Ok(String::new());
+    }
+    let bytes = if inode.is_packed() {
+        let content = packing::read_packed(inode).await?;
+        content[min(start, content.len())..min(end, content.len())].to_vec()
+    } else if inode.is_compressed() {
+        read_compressed_range(inode, start, end).await?
+    } else {
+        let first = start / BLOCK_SIZE;
+        let ids = get_file_block_ids(inode, end.div_ceil(BLOCK_SIZE)).await?;
+        let ids = &ids[first..];
+        // 空洞块由0填充，不会读盘
+        let args: Vec<_> = ids
+            .iter()
+            .filter(|id| **id != 0)
+            .map(|id| (*id as usize, 0, BLOCK_SIZE))
+            .collect();
+        let mut buffers = get_blocks_buffers(&args).await?.into_iter();
+        let bytes: Vec<_> = ids
+            .iter()
+            .flat_map(|id| match id {
+                0 => vec![0; BLOCK_SIZE],
+                _ => buffers.next().unwrap(),
+            })
+            .collect();
+        let offset = first * BLOCK_SIZE;
+        bytes[start - offset..end - offset].to_vec()
+    };
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+/// 使用zlib压缩内容
+fn compress(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+/// 解压压缩文件的全部内容
+async fn read_compressed(inode: &Inode) -> Result<Vec<u8>, Error> {
+    let mut inflater = Inflater::new(inode).await?;
+    // inode.size记录的是压缩前的大小
+    let mut content = Vec::with_capacity(inode.get_size());
+    while let Some(chunk) = inflater.next_chunk().await? {
+        content.extend_from_slice(&chunk);
+    }
+    Ok(content)
+}
+
+/// 解压压缩文件中[start, end)的内容，到达end后不再读入和解压之后的块
+async fn read_compressed_range(inode: &Inode, start: usize, end: usize) -> Result<Vec<u8>, Error> {
+    let mut inflater = Inflater::new(inode).await?;
+    let mut bytes = Vec::with_capacity(end - start);
+    let mut offset = 0;
+    while offset < end {
+        let Some(chunk) = inflater.next_chunk().await? else {
+            break;
+        };
+        let chunk_end = offset + chunk.len();
+        if chunk_end > start {
+            bytes.extend_from_slice(
+                &chunk[start.saturating_sub(offset)..min(end, chunk_end) - offset],
+            );
+        }
+        offset = chunk_end;
+    }
+    Ok(bytes)
+}
+
+/// 压缩文件内容的惰性解压流：每次只读入一批压缩块，解压出的内容按不超过INFLATE_CHUNK_SIZE的片段依次取出，
+/// 占用的内存和文件大小无关；调用者可以随时停止，之后的块不会被读入和解压
+struct Inflater {
+    decoder: Decompress,
+    block_ids: Vec<BlockIDType>,
+    next_block: usize, // 下一批的第一个块在block_ids中的位置
+    input: Vec<u8>,    // 当前这批压缩块
+    consumed: usize,   // input中已经解压的字节数
+    finished: bool,
+}
+
+impl Inflater {
+    async fn new(inode: &Inode) -> Result<Self, Error> {
+        // 压缩后的块数没有记录，按地址遍历到第一个未分配的块为止
+        let block_ids = get_file_block_ids(inode, usize::MAX)
+            .await?
+            .into_iter()
+            .take_while(|id| *id != 0)
+            .collect();
+        Ok(Self {
+            decoder: Decompress::new(true),
+            block_ids,
+            next_block: 0,
+            input: Vec::new(),
+            consumed: 0,
+            finished: false,
+        })
+    }
+
+    /// 取出下一段解压后的内容，压缩流结束后返回None，块末尾填充的0会被忽略
+    async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        if self.finished {
+            return Ok(None);
+        }
+        let mut chunk = Vec::with_capacity(INFLATE_CHUNK_SIZE);
+        while chunk.len() < INFLATE_CHUNK_SIZE {
+            if self.consumed == self.input.len() {
+                self.read_batch().await?;
+            }
+            let total_in = self.decoder.total_in();
+            let status = self
+                .decoder
+                .decompress_vec(
+                    &self.input[self.consumed..],
+                    &mut chunk,
+                    FlushDecompress::None,
+                )
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            self.consumed += (self.decoder.total_in() - total_in) as usize;
+            if status == Status::StreamEnd {
+                self.finished = true;
+                break;
+            }
+        }
+        Ok((!chunk.is_empty()).then_some(chunk))
     }
+
+    /// 读入下一批压缩块，替换已经解压完的一批
+    async fn read_batch(&mut self) -> Result<(), Error> {
+        let end = min(
+            self.next_block + DECOMPRESS_BATCH_BLOCKS,
+            self.block_ids.len(),
+        );
+        if self.next_block == end {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "compressed content is truncated",
+            ));
+        }
+        let args: Vec<_> = self.block_ids[self.next_block..end]
+            .iter()
+            .map(|id| (*id as usize, 0, BLOCK_SIZE))
+            .collect();
+        self.input = get_blocks_buffers(&args).await?.concat();
+        self.consumed = 0;
+        self.next_block = end;
+        Ok(())
+    }
+}
+
+fn block_count_error() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "content blocks do not match file size",
+    )
 }
 
-/// 将input string按块大小分割成数组
-fn split_inputs(inputs: String) -> Vec<String> {
-    let ch = inputs.as_bytes().chunks(BLOCK_SIZE);
-    let mut result = Vec::new();
-    for chunk in ch {
-        let chunk_str = std::str::from_utf8(chunk).expect("Invalid UTF-8 sequence");
-        result.push(chunk_str.to_string());
+/// 将输入的字节按块大小分割成数组，多字节字符可能跨越块的边界，读取时拼接所有块后再解码
+fn split_inputs(inputs: &[u8]) -> Vec<Vec<u8>> {
+    inputs
+        .chunks(BLOCK_SIZE)
+        .map(|chunk| chunk.to_vec())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::{
+        bitmap,
+        block::BLOCK_CACHE_MANAGER,
+        device::{self, BlockDevice},
+        test_utils::{exec, exec_as, lookup, mktree, setup, used_blocks},
+    };
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn compressed_file_round_trips_in_fewer_blocks() {
+        let _fs = setup().await;
+        let content = "compressible line\n".repeat(1000);
+
+        let before = used_blocks().await;
+        exec_as("root", "~", "newfile plain.txt", &[&content])
+            .await
+            .unwrap();
+        let plain_blocks = used_blocks().await - before;
+
+        let before = used_blocks().await;
+        exec_as("root", "~", "newfile -z compressed.txt", &[&content])
+            .await
+            .unwrap();
+        let compressed_blocks = used_blocks().await - before;
+
+        assert!(plain_blocks >= content.len() / crate::fs_constants::BLOCK_SIZE);
+        assert!(
+            compressed_blocks < plain_blocks,
+            "compressed {} blocks, plain {} blocks",
+            compressed_blocks,
+            plain_blocks
+        );
+        assert_eq!(exec("cat compressed.txt").await.unwrap().unwrap(), content);
+        assert_eq!(exec("cat plain.txt").await.unwrap().unwrap(), content);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn boundary_sizes_do_not_panic() {
+        let _fs = setup().await;
+        let sizes = [
+            0,
+            1,
+            PACK_MAX_SIZE,
+            PACK_MAX_SIZE + 1,
+            BLOCK_SIZE - 1,
+            BLOCK_SIZE,
+            BLOCK_SIZE + 1,
+            DIRECT_BLOCK_NUM * BLOCK_SIZE,
+            DIRECT_BLOCK_NUM * BLOCK_SIZE + 1,
+        ];
+        let mut root = Inode::read(0).await.unwrap();
+        for size in sizes {
+            let content = "b".repeat(size);
+            for (suffix, mode) in [
+                ("", FileMode::RDWR),
+                ("z", FileMode::RDWR | FileMode::COMPRESSED),
+            ] {
+                let name = format!("s{}{}.txt", size, suffix);
+                create_file_with_content(&name, mode, &mut root, &content, false, (0, 0))
+                    .await
+                    .unwrap();
+                let read = exec(&format!("cat {}", name)).await.unwrap().unwrap();
+                assert_eq!(read, content, "{}", name);
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn large_compressed_file_is_inflated_in_bounded_chunks() {
+        let _fs = setup().await;
+        // 3字节的字符会跨越解压片段的边界
+        let content: String = (0..150_000).map(|i| format!("{}中\n", i * 7919)).collect();
+        exec_as("root", "~", "newfile -z big.txt", &[&content])
+            .await
+            .unwrap();
+        let inode = lookup("big.txt").await;
+        assert!(inode.is_compressed());
+        let mut inflater = Inflater::new(&inode).await.unwrap();
+        let blocks = inflater.block_ids.len();
+        assert!(blocks > 2 * DECOMPRESS_BATCH_BLOCKS, "{} blocks", blocks);
+
+        // 每次只持有一批压缩块和一段解压内容
+        let manager = Arc::clone(&BLOCK_CACHE_MANAGER);
+        manager.write().await.sync_and_clear_cache().await.unwrap();
+        manager.write().await.reset_stats();
+        let mut inflated = Vec::new();
+        let mut chunks = 0;
+        while let Some(chunk) = inflater.next_chunk().await.unwrap() {
+            assert!(chunk.capacity() <= INFLATE_CHUNK_SIZE);
+            assert!(inflater.input.capacity() <= DECOMPRESS_BATCH_BLOCKS * BLOCK_SIZE);
+            inflated.extend_from_slice(&chunk);
+            chunks += 1;
+        }
+        assert!(chunks >= content.len().div_ceil(INFLATE_CHUNK_SIZE));
+        assert!(inflated == content.as_bytes());
+        let stats = manager.read().await.stats;
+        assert!(stats.disk_read_calls >= blocks.div_ceil(DECOMPRESS_BATCH_BLOCKS));
+
+        assert_eq!(exec("cat big.txt").await.unwrap().unwrap(), content);
+        // 只读取开头时，之后的块不会读入
+        manager.write().await.reset_stats();
+        let len = content.floor_char_boundary(100);
+        let head = exec(&format!("cat --range 0:{} big.txt", len))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(head, content[..len]);
+        let stats = manager.read().await.stats;
+        assert!(stats.disk_reads < blocks / 2, "{:?}", stats);
+        let start = content.floor_char_boundary(content.len() - 100);
+        let tail = exec(&format!("cat --range {}:200 big.txt", start))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tail, content[start..]);
+    }
+
+    #[test]
+    fn chunked_decoding_matches_from_utf8_lossy() {
+        let bytes = [
+            "a中🦀".as_bytes(),
+            &[0xff, b'b', 0xe4, 0xb8],
+            "c".as_bytes(),
+            &[0xf0, 0x9f],
+        ]
+        .concat();
+        for size in 1..bytes.len() {
+            let mut content = String::new();
+            let mut pending = Vec::new();
+            for chunk in bytes.chunks(size) {
+                push_lossy(&mut content, &mut pending, chunk);
+            }
+            if !pending.is_empty() {
+                content.push(char::REPLACEMENT_CHARACTER);
+            }
+            assert_eq!(
+                content,
+                String::from_utf8_lossy(&bytes),
+                "chunk size {}",
+                size
+            );
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn multibyte_characters_across_block_boundaries() {
+        let _fs = setup().await;
+        // “中”占3字节，从BLOCK_SIZE - 1开始，跨越第一个块的边界；
+        // emoji占4字节，从2 * BLOCK_SIZE - 2开始，跨越第二个块的边界
+        let mut content = "a".repeat(BLOCK_SIZE - 1);
+        content.push('中');
+        content.push_str(&"b".repeat(2 * BLOCK_SIZE - 2 - content.len()));
+        content.push('🦀');
+        content.push_str("tail");
+        assert!(!content.is_char_boundary(BLOCK_SIZE));
+        assert!(!content.is_char_boundary(2 * BLOCK_SIZE));
+
+        let chunks = split_inputs(content.as_bytes());
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks[..2].iter().all(|chunk| chunk.len() == BLOCK_SIZE));
+        assert_eq!(chunks.concat(), content.as_bytes());
+
+        exec_as("root", "~", "newfile wide.txt", &[&content])
+            .await
+            .unwrap();
+        assert_eq!(exec("cat wide.txt").await.unwrap().unwrap(), content);
+        exec("copy wide.txt copied.txt").await.unwrap();
+        assert_eq!(exec("cat copied.txt").await.unwrap().unwrap(), content);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cat_range_reads_a_byte_window() {
+        let _fs = setup().await;
+        let content: String = (0..2 * BLOCK_SIZE - 100)
+            .map(|i| char::from(b'a' + (i % 26) as u8))
+            .collect();
+        exec_as("root", "~", "newfile two.txt", &[&content])
+            .await
+            .unwrap();
+        exec_as("root", "~", "newfile -z compressed.txt", &[&content])
+            .await
+            .unwrap();
+        assert_eq!(lookup("two.txt").await.get_size(), content.len());
+
+        for file in ["two.txt", "compressed.txt"] {
+            let range = |range: String| async move {
+                exec(&format!("cat --range {} {}", range, file)).await
+            };
+            // 跨越块边界的窗口
+            assert_eq!(
+                range("1000:50".into()).await.unwrap().unwrap(),
+                content[1000..1050]
+            );
+            assert_eq!(range("0:10".into()).await.unwrap().unwrap(), content[..10]);
+            // 超出文件末尾的部分截去
+            let tail = &content[content.len() - 20..];
+            let start = content.len() - 20;
+            assert_eq!(range(format!("{}:1K", start)).await.unwrap().unwrap(), tail);
+            assert_eq!(range("2K:10".into()).await.unwrap().unwrap(), "");
+        }
+        assert!(exec("cat --range 1000 two.txt").await.is_err());
+        assert!(exec("cat --range 0:10 two.txt compressed.txt")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn edit_shrinks_and_grows_in_place() {
+        let _fs = setup().await;
+        crate::test_utils::add_user("alice").await;
+        let content = |len: usize, c: char| c.to_string().repeat(len);
+        exec_as(
+            "root",
+            "~",
+            "newfile f.txt",
+            &[&content(2 * BLOCK_SIZE, 'a')],
+        )
+        .await
+        .unwrap();
+        let inode_id = lookup("f.txt").await.inode_id;
+        let base = used_blocks().await - 2;
+
+        // (新内容, 占用的数据块数)
+        let steps = [
+            (content(BLOCK_SIZE / 2, 'b'), 1),
+            (content(3 * BLOCK_SIZE, 'c'), 3),
+            (content(BLOCK_SIZE + 1, 'd'), 2),
+        ];
+        for (new, blocks) in steps {
+            exec_as("root", "~", "edit f.txt", &[&new]).await.unwrap();
+            let inode = lookup("f.txt").await;
+            assert_eq!(inode.inode_id, inode_id);
+            assert_eq!(inode.get_size(), new.len());
+            assert_eq!(get_owned_block_ids(&inode).await.unwrap().len(), blocks);
+            assert_eq!(used_blocks().await, base + blocks);
+            assert_eq!(exec("cat f.txt").await.unwrap().unwrap(), new);
+        }
+
+        // 没有写权限时不修改
+        let err = exec_as("alice", "~", "edit f.txt", &["x"])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied, "{}", err);
+        assert_eq!(lookup("f.txt").await.get_size(), BLOCK_SIZE + 1);
+    }
+
+    /// 读取超过limit号的块时失败的设备，用于在写入内容的中途注入错误
+    struct FailingDevice {
+        inner: Box<dyn BlockDevice>,
+        limit: Arc<AtomicUsize>,
+    }
+
+    impl BlockDevice for FailingDevice {
+        fn read_blocks(&mut self, first_block: usize, buffer: &mut [u8]) -> Result<(), Error> {
+            let last_block = first_block + buffer.len() / BLOCK_SIZE - 1;
+            if last_block > self.limit.load(Ordering::SeqCst) {
+                return Err(Error::other("injected read failure"));
+            }
+            self.inner.read_blocks(first_block, buffer)
+        }
+
+        fn write_block(&mut self, block_id: usize, bytes: &[u8; BLOCK_SIZE]) -> Result<(), Error> {
+            self.inner.write_block(block_id, bytes)
+        }
+
+        fn reset(&mut self) -> Result<(), Error> {
+            self.inner.reset()
+        }
+
+        fn grow(&mut self, size: u64) -> Result<(), Error> {
+            self.inner.grow(size)
+        }
+
+        fn describe(&self) -> Result<(String, u64), Error> {
+            self.inner.describe()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn failed_content_write_leaves_nothing_behind() {
+        let _fs = setup().await;
+        exec_as("root", "~", "newfile probe.txt", &[&"p".repeat(BLOCK_SIZE)])
+            .await
+            .unwrap();
+        let probe = lookup("probe.txt").await;
+        let last_used = *get_owned_block_ids(&probe).await.unwrap().last().unwrap();
+
+        // 新文件的块都在probe之后，最后一块不满，写入前要读入，此时失败
+        let limit = Arc::new(AtomicUsize::new(last_used as usize));
+        let manager = Arc::clone(&BLOCK_CACHE_MANAGER);
+        let mut bcm = manager.write().await;
+        bcm.sync_and_clear_cache().await.unwrap();
+        let inner = bcm.replace_device(Box::new(device::DetachedDevice));
+        bcm.replace_device(Box::new(FailingDevice {
+            inner,
+            limit: limit.clone(),
+        }));
+        drop(bcm);
+
+        let blocks = used_blocks().await;
+        let (inodes, _) = bitmap::count_inodes().await;
+        let content = "x".repeat(2 * BLOCK_SIZE + BLOCK_SIZE / 2);
+        let err = exec_as("root", "~", "newfile big.txt", &[&content])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AddrNotAvailable, "{}", err);
+        let listing = exec("dir").await.unwrap().unwrap();
+        assert!(!listing.contains("big.txt"), "{}", listing);
+        assert_eq!(used_blocks().await, blocks);
+        assert_eq!(bitmap::count_inodes().await.0, inodes);
+
+        // 恢复之后可以正常创建，没有泄漏
+        limit.store(usize::MAX, Ordering::SeqCst);
+        exec_as("root", "~", "newfile big.txt", &[&content])
+            .await
+            .unwrap();
+        assert_eq!(exec("cat big.txt").await.unwrap().unwrap(), content);
+        let report = exec("fsck").await.unwrap().unwrap();
+        assert!(report.contains("leaked: 0 inodes, 0 blocks"), "{}", report);
+    }
+
+    /// 文件size范围内实际存储的字节
+    async fn raw_bytes(path: &str) -> Vec<u8> {
+        let inode = lookup(path).await;
+        let mut bytes = get_file_blocks(&inode).await.unwrap().concat();
+        bytes.truncate(inode.get_size());
+        bytes
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn copy_preserves_binary_bytes() {
+        let _fs = setup().await;
+        // 不是合法UTF-8的内容，直接写入数据块
+        let size = 3 * BLOCK_SIZE + BLOCK_SIZE / 2;
+        let bytes: Vec<u8> = (0..size).map(|i| (i * 7 % 256) as u8).collect();
+        assert!(String::from_utf8(bytes.clone()).is_err());
+        exec(&format!("mkfile a.bin {}", size)).await.unwrap();
+        let source = lookup("a.bin").await;
+        let ids = get_file_block_ids(&source, size.div_ceil(BLOCK_SIZE))
+            .await
+            .unwrap();
+        let ids: Vec<_> = ids.iter().map(|id| *id as usize).collect();
+        let chunks: Vec<_> = bytes.chunks(BLOCK_SIZE).collect();
+        write_file_content_to_blocks(&chunks, &ids).await.unwrap();
+        assert_eq!(raw_bytes("a.bin").await, bytes);
+
+        exec("md dst").await.unwrap();
+        exec("copy a.bin dst/b.bin").await.unwrap();
+        let copy = lookup("dst/b.bin").await;
+        assert_ne!(copy.inode_id, source.inode_id);
+        assert_eq!(copy.get_size(), size);
+        assert_eq!(raw_bytes("dst/b.bin").await, bytes);
+        // 复制到新的块中，修改副本不影响源文件
+        let copy_ids = get_owned_block_ids(&copy).await.unwrap();
+        assert!(copy_ids.iter().all(|id| !ids.contains(id)));
+        exec_as("root", "~", "edit dst/b.bin", &["changed"])
+            .await
+            .unwrap();
+        assert_eq!(raw_bytes("a.bin").await, bytes);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn interrupted_move_keeps_the_file_reachable() {
+        let _fs = setup().await;
+        let root = mktree(&["a.txt:moving", "d/"]).await;
+        let mut target = lookup("d").await;
+
+        // move_file插入新目录项之后、删除原目录项之前中断
+        let (_, _, dirent) = DirEntry::get_all_dirent(&root)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|(_, _, dirent)| dirent.get_filename() == "a.txt")
+            .unwrap();
+        {
+            let _lock = fs_lock::write().await;
+            insert_object(&dirent, &mut target).await.unwrap();
+        }
+        assert_eq!(exec("cat a.txt").await.unwrap().unwrap(), "moving");
+        assert_eq!(exec("cat d/a.txt").await.unwrap().unwrap(), "moving");
+
+        // fsck按目录项数修正硬连接数后，删除其中一个目录项不影响另一个
+        let report = exec("fsck").await.unwrap().unwrap();
+        assert!(
+            report.contains("link count mismatches: 1 (run fsck --repair to fix)"),
+            "{}",
+            report
+        );
+        exec("fsck --repair").await.unwrap();
+        assert_eq!(lookup("a.txt").await.get_nlink(), 2);
+        exec("del a.txt").await.unwrap();
+        exec("emptytrash").await.unwrap();
+        assert!(exec("cat a.txt").await.is_err());
+        assert_eq!(exec("cat d/a.txt").await.unwrap().unwrap(), "moving");
+        let report = exec("fsck").await.unwrap().unwrap();
+        assert!(report.contains("leaked: 0 inodes, 0 blocks"), "{}", report);
+        assert!(report.contains("link count mismatches: 0"), "{}", report);
+
+        // 完整的移动只留下一个目录项
+        exec("del d/a.txt").await.unwrap();
+        assert!(exec("cat d/a.txt").await.is_err());
+        exec("undelete d/a.txt").await.unwrap();
+        assert_eq!(exec("cat d/a.txt").await.unwrap().unwrap(), "moving");
+        assert_eq!(lookup("d/a.txt").await.get_nlink(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_appends_both_land() {
+        let _fs = setup().await;
+        mktree(&["log.txt:head\n"]).await;
+        // 跨越块边界的两段内容，同时等待输入，之后依次追加
+        let a = "a".repeat(BLOCK_SIZE + 100);
+        let b = "b".repeat(BLOCK_SIZE + 100);
+        let (input_a, input_b) = ([a.as_str()], [b.as_str()]);
+        let (first, second) = tokio::join!(
+            exec_as("root", "~", "append log.txt", &input_a),
+            exec_as("root", "~", "append log.txt", &input_b),
+        );
+        first.unwrap();
+        second.unwrap();
+
+        let content = exec("cat log.txt").await.unwrap().unwrap();
+        assert_eq!(content.len(), "head\n".len() + a.len() + b.len());
+        let tail = content.strip_prefix("head\n").unwrap();
+        assert!(
+            tail == [a.as_str(), &b].concat() || tail == [b.as_str(), &a].concat(),
+            "appends interleaved"
+        );
+        assert_eq!(lookup("log.txt").await.get_size(), content.len());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn append_keeps_binary_content_and_old_blocks() {
+        let _fs = setup().await;
+        exec("mkfile z.bin 3000").await.unwrap();
+        let old_ids = get_owned_block_ids(&lookup("z.bin").await).await.unwrap();
+        let used = used_blocks().await;
+        exec_as("root", "~", "append z.bin", &["tail"])
+            .await
+            .unwrap();
+
+        let inode = lookup("z.bin").await;
+        assert_eq!(inode.get_size(), 3004);
+        assert!(!inode.is_packed());
+        let bytes = raw_bytes("z.bin").await;
+        assert!(bytes[..3000].iter().all(|byte| *byte == 0));
+        assert_eq!(&bytes[3000..], b"tail");
+        // 写入最后一个块的剩余空间，原有的块不变，也不申请新块
+        assert_eq!(get_owned_block_ids(&inode).await.unwrap(), old_ids);
+        assert_eq!(used_blocks().await, used);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn append_grows_into_indirect_blocks() {
+        let _fs = setup().await;
+        // 追加后跨过一级和二级间接块的边界
+        let size = (DIRECT_BLOCK_NUM + FISRT_MAX - 1) * BLOCK_SIZE + 10;
+        exec(&format!("mkfile z.bin {}", size)).await.unwrap();
+        let tail: String = (0..(INDIRECT_ADDR_NUM + 2) * BLOCK_SIZE)
+            .map(|i| (b'a' + (i % 26) as u8) as char)
+            .collect();
+        let used = used_blocks().await;
+        exec_as("root", "~", "append z.bin", &[&tail])
+            .await
+            .unwrap();
+
+        let inode = lookup("z.bin").await;
+        assert_eq!(inode.get_size(), size + tail.len());
+        let bytes = raw_bytes("z.bin").await;
+        assert!(bytes[..size].iter().all(|byte| *byte == 0));
+        assert_eq!(&bytes[size..], tail.as_bytes());
+        // 新数据块加上二级间接块和它下面的两个一级块
+        let grown = inode::blocks_for_size(size + tail.len()) - inode::blocks_for_size(size);
+        assert_eq!(used_blocks().await, used + grown + 3);
+        let report = exec("fsck").await.unwrap().unwrap();
+        assert!(report.contains("leaked: 0 inodes, 0 blocks"), "{}", report);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn defrag_moves_a_fragmented_file_into_contiguous_blocks() {
+        let _fs = setup().await;
+        crate::test_utils::add_user("alice").await;
+        let two_blocks = |c: &str| c.repeat(2 * BLOCK_SIZE);
+        mktree(&[
+            &format!("a.txt:{}", two_blocks("a")),
+            &format!("b.txt:{}", two_blocks("b")),
+            &format!("c.txt:{}", two_blocks("c")),
+        ])
+        .await;
+        // 释放b.txt的块后加长a.txt，新增的块一部分落在b.txt空出的位置，一部分在c.txt之后
+        exec("del b.txt").await.unwrap();
+        exec("emptytrash").await.unwrap();
+        let content: String = (0..6 * BLOCK_SIZE - 10)
+            .map(|i| char::from(b'a' + (i % 26) as u8))
+            .collect();
+        exec_as("root", "~", "edit a.txt", &[&content])
+            .await
+            .unwrap();
+        let block_ids = |inode: Inode| async move { get_file_block_ids(&inode, 6).await.unwrap() };
+        let contiguous = |ids: &[BlockIDType]| ids.windows(2).all(|pair| pair[1] == pair[0] + 1);
+        let before = block_ids(lookup("a.txt").await).await;
+        assert!(!contiguous(&before), "{:?}", before);
+        let used = used_blocks().await;
+
+        assert!(exec_as("alice", "~", "defrag a.txt", &[]).await.is_err());
+        let output = exec("defrag a.txt").await.unwrap().unwrap();
+        let after = block_ids(lookup("a.txt").await).await;
+        assert!(contiguous(&after), "{:?}", after);
+        assert_eq!(
+            output,
+            format!("a.txt: 6 blocks moved to {}..={}", after[0], after[5])
+        );
+        assert_eq!(exec("cat a.txt").await.unwrap().unwrap(), content);
+        assert_eq!(lookup("a.txt").await.get_size(), content.len());
+        // 原来的块已释放
+        assert_eq!(used_blocks().await, used);
+        assert_eq!(exec("cat c.txt").await.unwrap().unwrap(), two_blocks("c"));
+        let report = exec("fsck").await.unwrap().unwrap();
+        assert!(report.contains("leaked: 0 inodes, 0 blocks"), "{}", report);
+
+        let output = exec("defrag a.txt").await.unwrap().unwrap();
+        assert_eq!(output, "a.txt is already contiguous");
     }
-    result
 }
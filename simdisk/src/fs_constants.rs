@@ -1,16 +1,32 @@
 use std::mem::size_of;
 
-use crate::{block::BlockIDType, dirent::DirEntry, inode::Inode, super_block::SuperBlock};
+use crate::{
+    block::BlockIDType,
+    dirent::DirEntry,
+    inode::{Inode, InodeIdType},
+    super_block::SuperBlock,
+};
 
 pub const FS_FILE_NAME: &str = "SIMPLE_FS";
+pub const HISTORY_FILE_NAME: &str = "SIMPLE_FS_HISTORY"; // 命令历史，和镜像放在同一目录下
 
 pub const MAGIC: usize = 0x2F02BA345D;
 
+/// 磁盘布局版本，记录在超级块中，和当前版本不同的镜像拒绝挂载
+///
+/// Inode、DirEntry、SuperBlock、User等以bincode存储的结构增删或调整字段，或者布局常量改变时递增；
+/// 超级块的magic和layout_version的位置不能改变，新字段只能加在超级块末尾
+///
+/// 1：初始版本（旧镜像中为0）；2：扩展名加长到8字节，目录项变为32B
+pub const LAYOUT_VERSION: usize = 2;
+
 //* 布局 */
 pub const BLOCK_SIZE: usize = 1024; // 设块大小为 1KB
 
 pub const FS_SIZE: usize = 100 * 1024 * 1024; // 文件系统大小为 100MB
 
+pub const MAX_FS_SIZE: usize = 4 * 1024 * 1024 * 1024; // 在线扩容后文件系统的最大大小
+
 pub const INODE_BITMAP_NUM: usize = 1; // inode bitmap块数
 
 pub const DATA_BITMAP_NUM: usize = 12; // data bitmap块数
@@ -19,7 +35,7 @@ pub const INODE_MAX_NUM: usize = INODE_BITMAP_NUM * BLOCK_SIZE * 8; // inode 总
 
 pub const INODE_BLOCK_NUM: usize = INODE_MAX_NUM * INODE_SIZE / BLOCK_SIZE; // inode 区块数
 
-pub const DATA_BLOCK_MAX_NUM: usize = DATA_BITMAP_NUM * BLOCK_SIZE * 8; // data块总数
+pub const DATA_BLOCK_MAX_NUM: usize = DATA_BITMAP_NUM * BLOCK_SIZE * 8; // 扩容前的data块总数
 
 //* 块号分配 */
 pub const INODE_BITMAP_START_BLOCK: usize = INODE_BITMAP_NUM; // inode bitmap起始块号
@@ -30,7 +46,11 @@ pub const INODE_START_BLOCK: usize = DATA_BITMAP_START_BLOCK + DATA_BITMAP_NUM;
 
 pub const DATA_START_BLOCK: usize = INODE_START_BLOCK + INODE_BLOCK_NUM; // data 区起始块号
 
-pub const USER_START_BYTE: usize = size_of::<SuperBlock>() + 16; // 用户信息起始位置，加一些偏移防止重叠
+pub const USER_START_BYTE: usize = 96; // 用户信息起始位置，固定不变以兼容旧镜像，超级块不能超过该位置
+
+pub const USER_BLOCKS_MARKER: u64 = u64::MAX; // 用户信息放不下0号块时写在USER_START_BYTE处的标记，旧格式此处为用户数
+
+pub const ALLOC_CURSOR_START_BYTE: usize = BLOCK_SIZE - 2 * size_of::<u64>(); // 0号块末尾记录位图的申请位置，用户信息不能超过该位置
 
 //* 寻址 */
 pub const DIRECT_BLOCK_NUM: usize = 8; // 直接块数
@@ -42,14 +62,156 @@ pub const BLOCK_ADDR_SIZE: usize = size_of::<BlockIDType>(); // 块地址大小
 pub const INODE_SIZE: usize = size_of::<Inode>();
 pub const DIRENTRY_SIZE: usize = size_of::<DirEntry>();
 
+pub const INODES_PER_BLOCK: usize = BLOCK_SIZE / INODE_SIZE; // 每个块可以存放的inode数量
+
 pub const INDIRECT_ADDR_NUM: usize = BLOCK_SIZE / BLOCK_ADDR_SIZE; // 间接块可以存下的块地址的数量pub
 pub const FISRT_MAX: usize = FIRST_INDIRECT_NUM * INDIRECT_ADDR_NUM; //一级间接块最大可表示的块数量
 pub const SECOND_MAX: usize = (SECOND_INDIRECT_NUM * INDIRECT_ADDR_NUM) * FISRT_MAX; //二级间接块最大可表示的块数量
 
 // 文件名和扩展名长度限制（字节）
 pub const NAME_LENGTH_LIMIT: usize = 10;
-pub const EXTENSION_LENGTH_LIMIT: usize = 3;
+pub const EXTENSION_LENGTH_LIMIT: usize = 8;
+
+pub const TRASH_DIR_NAME: &str = ".trash"; // 回收站目录名，位于根目录下
+pub const LOST_FOUND_DIR_NAME: &str = "lost+found"; // fsck找回的孤立inode所在的目录，位于根目录下
+pub const BENCH_FILE_NAME: &str = ".bench"; // bench测试时在根目录下创建的临时文件
+
+pub const MAX_PATH_DEPTH: usize = 64; // 目录的最大深度
+
+pub const PACK_MAX_SIZE: usize = BLOCK_SIZE / 4; // 不超过该大小（字节）的文件会和其他小文件打包存储
 
 pub const MAX_FILE_SIZE: usize = BLOCK_SIZE * (DIRECT_BLOCK_NUM + FISRT_MAX + SECOND_MAX); //可表示文件的最大大小（字节）
 
+pub const MAX_DIR_ENTRIES: usize = MAX_FILE_SIZE / DIRENTRY_SIZE; // 一个目录可寻址的最多目录项数（含特殊目录）
+
 pub const SYNC_BLOCK_DURATION: u64 = 60;
+
+pub const INODE_CACHE_SIZE: usize = 1024; // inode缓存的最大条数
+
+pub const WARM_CACHE_MAX_BLOCKS: usize = 16 * 1024; // --warm-cache时同步后最多保留的缓存块数（16MB）
+
+pub const DECOMPRESS_BATCH_BLOCKS: usize = 64; // 流式解压时每批读入的块数
+pub const INFLATE_CHUNK_SIZE: usize = 64 * BLOCK_SIZE; // 流式解压时每次取出的解压内容的最大字节数
+pub const READ_AHEAD_MAX_BLOCKS: usize = 256; // 连续的块合并读取时单次最多读取的块数
+pub const COPY_BATCH_BLOCKS: usize = 256; // 文件系统内复制文件时每批读写的块数
+
+pub const WEBHOOK_RETRY_TIMES: u32 = 3; // webhook发送失败时的最多尝试次数
+pub const WEBHOOK_TIMEOUT: u64 = 5; // 单次发送webhook的超时时间（秒）
+
+pub const REQUEST_CACHE_SIZE: usize = 256; // 幂等请求结果缓存的最大条数
+
+pub const HISTORY_SIZE: usize = 1000; // 每个用户最多保存的历史命令数
+
+pub const DEFAULT_MAX_CONNECTIONS: usize = 64; // 默认最多同时保持的连接数
+pub const DEFAULT_IDLE_TIMEOUT: u64 = 30 * 60; // 默认连接空闲多久（秒）后关闭
+
+pub const QUOTA_GRACE_PERIOD: u64 = 7 * 24 * 60 * 60; // 超过软配额后的宽限期（秒）
+
+pub const FIND_EXEC_COMMANDS: &[&str] = &["del", "cat", "du"]; // 允许find -exec执行的命令
+
+pub const HTTP_REQUEST_MAX_SIZE: usize = 8 * 1024; // 目录浏览器接受的请求头的最大字节数
+
+pub const DIFF_CONTEXT_LINES: usize = 3; // diff每处改动前后显示的上下文行数
+pub const DIFF_MAX_CELLS: usize = 16 * 1024 * 1024; // diff求最长公共子序列时表格的最大格数，超过时只报告第一处不同
+
+//* 布局校验 */
+// 修改上面的常量导致布局自相矛盾时，下面的断言会让编译直接失败，
+// 例如把 DATA_BITMAP_NUM 改为 13 后 data 区会超出 FS_SIZE，编译报错
+// "data area exceeds FS_SIZE"
+
+// 超级块和用户信息都放在0号块中，互不重叠
+const _: () = assert!(
+    size_of::<SuperBlock>() <= USER_START_BYTE,
+    "super block overlaps user info"
+);
+const _: () = assert!(
+    INODE_BITMAP_START_BLOCK >= 1,
+    "block 0 is reserved for super block"
+);
+const _: () = assert!(
+    FS_SIZE.is_multiple_of(BLOCK_SIZE),
+    "FS_SIZE must be a multiple of BLOCK_SIZE"
+);
+// 位图位数要覆盖对应的inode数和data块数
+const _: () = assert!(
+    INODE_BITMAP_NUM * BLOCK_SIZE * 8 >= INODE_MAX_NUM,
+    "inode bitmap cannot cover all inodes"
+);
+const _: () = assert!(
+    DATA_BITMAP_NUM * BLOCK_SIZE * 8 >= DATA_BLOCK_MAX_NUM,
+    "data bitmap cannot cover all data blocks"
+);
+// inode不能跨块存放，inode区要放得下所有inode
+const _: () = assert!(INODE_SIZE == 64, "inode must be 64 bytes");
+const _: () = assert!(
+    BLOCK_SIZE.is_multiple_of(INODE_SIZE),
+    "inode must not span two blocks"
+);
+const _: () = assert!(
+    INODE_BLOCK_NUM * INODES_PER_BLOCK >= INODE_MAX_NUM,
+    "inode area cannot hold all inodes"
+);
+const _: () = assert!(DIRENTRY_SIZE == 32, "dirent must be 32 bytes");
+const _: () = assert!(
+    BLOCK_SIZE.is_multiple_of(DIRENTRY_SIZE),
+    "dirent must not span two blocks"
+);
+// 各区按顺序排列，互不重叠
+const _: () = assert!(
+    DATA_BITMAP_START_BLOCK >= INODE_BITMAP_START_BLOCK + INODE_BITMAP_NUM,
+    "data bitmap overlaps inode bitmap"
+);
+const _: () = assert!(
+    INODE_START_BLOCK >= DATA_BITMAP_START_BLOCK + DATA_BITMAP_NUM,
+    "inode area overlaps data bitmap"
+);
+const _: () = assert!(
+    DATA_START_BLOCK >= INODE_START_BLOCK + INODE_BLOCK_NUM,
+    "data area overlaps inode area"
+);
+const _: () = assert!(
+    DATA_START_BLOCK + DATA_BLOCK_MAX_NUM <= FS_SIZE / BLOCK_SIZE,
+    "data area exceeds FS_SIZE"
+);
+// 块号和inode号的类型要能表示所有的块和inode
+const _: () = assert!(
+    FS_SIZE / BLOCK_SIZE <= BlockIDType::MAX as usize,
+    "BlockIDType is too small"
+);
+const _: () = assert!(
+    INODE_MAX_NUM <= InodeIdType::MAX as usize + 1,
+    "InodeIdType is too small"
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{exec, setup};
+
+    #[test]
+    fn layout_is_consistent() {
+        // 各区按顺序首尾相接：0号块、inode位图、data位图、inode区、data区
+        let areas = [
+            (0, 1),
+            (INODE_BITMAP_START_BLOCK, INODE_BITMAP_NUM),
+            (DATA_BITMAP_START_BLOCK, DATA_BITMAP_NUM),
+            (INODE_START_BLOCK, INODE_BLOCK_NUM),
+            (DATA_START_BLOCK, DATA_BLOCK_MAX_NUM),
+        ];
+        for pair in areas.windows(2) {
+            let ((start, num), (next, _)) = (pair[0], pair[1]);
+            assert_eq!(start + num, next);
+        }
+        let end = areas.iter().map(|(start, num)| start + num).max().unwrap();
+        assert!(end <= FS_SIZE / BLOCK_SIZE);
+        // inode区恰好放下所有inode
+        let inode_bytes = INODE_MAX_NUM * INODE_SIZE;
+        assert_eq!(inode_bytes.div_ceil(BLOCK_SIZE), INODE_BLOCK_NUM);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn formatted_super_block_matches_layout() {
+        let _fs = setup().await;
+        assert_eq!(exec("verify").await.unwrap().unwrap(), "super block ok");
+    }
+}
@@ -0,0 +1,71 @@
+//! 文件系统的全局读写锁
+//!
+//! 读命令在整个执行过程中持有读锁，写操作持有写锁，两者互斥：读命令执行期间所有写操作都要等待，
+//! 所以读到的总是某次写操作之前或之后的完整状态。这不是快照隔离，不保留旧版本，
+//! 读命令运行越久，写操作等待越久。需要和client交互的写操作（如等待输入文件内容、确认删除）
+//! 在交互完成后才取写锁，以免阻塞其他连接的读命令
+use std::sync::Arc;
+
+use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+
+use crate::cwd_cache;
+
+/// 写锁，释放时各连接缓存的目录失效
+pub struct WriteGuard {
+    _guard: OwnedRwLockWriteGuard<()>,
+}
+
+impl Drop for WriteGuard {
+    fn drop(&mut self) {
+        cwd_cache::invalidate();
+    }
+}
+
+/// 取得全局读锁，返回的guard释放前所有写操作都会等待
+pub async fn read() -> OwnedRwLockReadGuard<()> {
+    Arc::clone(&FS_LOCK).read_owned().await
+}
+
+/// 取得全局写锁，返回的guard释放前所有读命令和其他写操作都会等待
+pub async fn write() -> WriteGuard {
+    WriteGuard {
+        _guard: Arc::clone(&FS_LOCK).write_owned().await,
+    }
+}
+
+//延迟加载全局变量 FS_LOCK
+lazy_static! {
+    pub static ref FS_LOCK: Arc<RwLock<()>> = Arc::new(RwLock::new(()));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{exec, mktree, setup};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reads_never_interleave_with_writes() {
+        let _fs = setup().await;
+        let size = 20 * 1024;
+        let a = format!("a.txt:{}", "a".repeat(size));
+        let b = format!("b.txt:{}", "b".repeat(size));
+        mktree(&[&a, &b, &a.replacen("a.txt", "t.txt", 1)]).await;
+
+        // 不断用两个内容不同的文件覆盖t.txt
+        let writer = tokio::spawn(async {
+            for i in 0..30 {
+                let source = if i % 2 == 0 { "b.txt" } else { "a.txt" };
+                exec(&format!("copy -f {} t.txt", source)).await.unwrap();
+            }
+        });
+        // 读写互斥，读到的总是某一次覆盖前或后的完整内容
+        let mut reads = 0;
+        while !writer.is_finished() || reads < 30 {
+            let content = exec("cat t.txt").await.unwrap().unwrap();
+            assert_eq!(content.len(), size);
+            let first = content.as_bytes()[0];
+            assert!(content.bytes().all(|c| c == first), "mixed content");
+            reads += 1;
+        }
+        writer.await.unwrap();
+    }
+}
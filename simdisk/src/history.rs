@@ -0,0 +1,216 @@
+//! 命令历史
+//!
+//! 每个用户执行过的命令按顺序追加到历史文件中（默认为镜像旁的`HISTORY_FILE_NAME`，
+//! 每行一条记录），重启server或重新登录后仍然可以查看和搜索；
+//! 启动时只保留每个用户最近的`HISTORY_SIZE`条命令，并用临时文件整体替换历史文件
+use std::{
+    collections::HashMap,
+    io::{Error, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::AsyncWriteExt,
+    sync::{Mutex, RwLock},
+};
+
+use crate::{config::CONFIG, fs_constants::HISTORY_SIZE};
+
+/// 历史文件中的一行
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    user: String,
+    command: String,
+}
+
+#[derive(Default)]
+pub struct History {
+    commands: HashMap<String, Vec<String>>, // map{username: 按执行顺序排列的命令}
+    file: Option<Arc<Mutex<File>>>,         // 追加记录的历史文件，None时只保存在内存中
+}
+
+impl History {
+    /// 从历史文件读取历史并整理后打开，文件不存在时从空历史开始，损坏的行被忽略；
+    /// path为None或文件无法打开时只在内存中记录
+    pub fn open(path: Option<&Path>) -> Self {
+        let Some(path) = path else {
+            return Self::default();
+        };
+        let mut history = Self::default();
+        if let Ok(lines) = std::fs::read_to_string(path) {
+            for line in lines.lines() {
+                match serde_json::from_str::<Entry>(line) {
+                    Ok(entry) => history.push(&entry.user, &entry.command),
+                    Err(e) => warn!("ignore broken history line: {}", e),
+                }
+            }
+        }
+        match history.compact(path) {
+            Ok(file) => history.file = Some(Arc::new(Mutex::new(File::from_std(file)))),
+            Err(e) => error!("cannot open history file {}: {}", path.display(), e),
+        }
+        history
+    }
+
+    /// 将保留的历史写入临时文件后替换原文件，写入中途出错时原文件不受影响，返回以追加方式打开的新文件
+    fn compact(&self, path: &Path) -> Result<std::fs::File, Error> {
+        let mut tmp_path = PathBuf::from(path).into_os_string();
+        tmp_path.push(".tmp");
+        let mut tmp = std::fs::File::create(&tmp_path)?;
+        for (user, commands) in &self.commands {
+            for command in commands {
+                tmp.write_all(&to_line(user, command)?)?;
+            }
+        }
+        tmp.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+        std::fs::OpenOptions::new().append(true).open(path)
+    }
+
+    /// 在内存中记录命令，超出容量时丢弃最旧的命令
+    fn push(&mut self, username: &str, command: &str) {
+        let commands = self.commands.entry(username.to_owned()).or_default();
+        commands.push(command.to_owned());
+        if commands.len() > HISTORY_SIZE {
+            commands.remove(0);
+        }
+    }
+
+    /// 记录用户执行的命令并追加到历史文件
+    ///
+    /// 只在更新内存时持有history的写锁，取得文件锁后即释放，写文件期间不阻塞其他连接查看历史，
+    /// 文件中的顺序和内存中一致
+    pub async fn record(history: &RwLock<Self>, username: &str, command: &str) {
+        let mut file = {
+            let mut history = history.write().await;
+            history.push(username, command);
+            match &history.file {
+                Some(file) => Arc::clone(file).lock_owned().await,
+                None => return,
+            }
+        };
+        let appended = async {
+            file.write_all(&to_line(username, command)?).await?;
+            file.flush().await
+        }
+        .await;
+        if let Err(e) = appended {
+            error!("failed to save history: {}", e);
+        }
+    }
+
+    /// 获取用户的历史命令，返回(序号, 命令)，term为Some时只返回包含term的命令
+    pub fn search(&self, username: &str, term: Option<&str>) -> Vec<(usize, String)> {
+        self.commands
+            .get(username)
+            .map(|commands| {
+                commands
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, command)| term.is_none_or(|term| command.contains(term)))
+                    .map(|(i, command)| (i + 1, command.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// 一条命令在历史文件中的一行
+fn to_line(user: &str, command: &str) -> Result<Vec<u8>, Error> {
+    let entry = Entry {
+        user: user.to_owned(),
+        command: command.to_owned(),
+    };
+    let mut line = serde_json::to_vec(&entry)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// 记录用户执行的命令
+pub async fn record(username: &str, command: &str) {
+    History::record(&HISTORY, username, command).await
+}
+
+/// 获取用户的历史命令，返回(序号, 命令)，term为Some时只返回包含term的命令
+pub async fn search(username: &str, term: Option<&str>) -> Vec<(usize, String)> {
+    let history = Arc::clone(&HISTORY);
+    let history = history.read().await;
+    history.search(username, term)
+}
+
+//延迟加载全局变量 HISTORY
+lazy_static! {
+    pub static ref HISTORY: Arc<RwLock<History>> = Arc::new(RwLock::new(History::open(
+        CONFIG.history.as_deref().map(Path::new)
+    )));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("simdisk-history-{}-{}", std::process::id(), name))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn history_is_appended_and_reloaded() {
+        let path = temp_path("reload");
+        let _ = std::fs::remove_file(&path);
+        let history = RwLock::new(History::open(Some(&path)));
+        History::record(&history, "root", "md docs").await;
+        History::record(&history, "alice", "dir").await;
+        History::record(&history, "root", "cat docs/a.txt").await;
+        // 每条命令追加一行，不重写整个文件
+        let lines = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(lines.lines().count(), 3, "{}", lines);
+        drop(history);
+
+        let history = History::open(Some(&path));
+        assert_eq!(
+            history.search("root", None),
+            [
+                (1, "md docs".to_string()),
+                (2, "cat docs/a.txt".to_string())
+            ]
+        );
+        assert_eq!(
+            history.search("alice", Some("dir")),
+            [(1, "dir".to_string())]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reopening_keeps_the_latest_commands_and_skips_broken_lines() {
+        let path = temp_path("compact");
+        let mut lines: Vec<_> = (0..HISTORY_SIZE + 5)
+            .map(|i| String::from_utf8(to_line("root", &format!("cmd {}", i)).unwrap()).unwrap())
+            .collect();
+        // 写到一半中断的行
+        lines.insert(3, "{\"user\":\"root\",\"comm".to_string() + "\n");
+        std::fs::write(&path, lines.concat()).unwrap();
+
+        let history = RwLock::new(History::open(Some(&path)));
+        let commands = history.read().await.search("root", None);
+        assert_eq!(commands.len(), HISTORY_SIZE);
+        assert_eq!(commands[0].1, "cmd 5");
+        // 整理后的文件只包含保留的命令，之后继续追加
+        History::record(&history, "root", "latest").await;
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(saved.lines().count(), HISTORY_SIZE + 1);
+        assert!(!saved.contains("\"cmd 4\""), "old commands kept");
+        assert!(saved.ends_with("\"latest\"}\n"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn history_without_path_stays_in_memory() {
+        let history = RwLock::new(History::open(None));
+        History::record(&history, "root", "dir").await;
+        assert_eq!(history.read().await.search("root", None).len(), 1);
+    }
+}
@@ -0,0 +1,210 @@
+//! 只读的HTTP目录浏览器
+//!
+//! 通过 --http [port] 开启，只监听本机，列出目录、进入子目录、查看或下载文件，
+//! 不需要登录，也不提供任何修改文件系统的操作。目录和文件内容都通过syscall的只读接口获取
+use std::io;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{fs_constants::HTTP_REQUEST_MAX_SIZE, fs_lock, syscall};
+
+/// 在本机的port端口上提供目录浏览服务
+pub async fn serve(port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    info!("http browser listening to 127.0.0.1:{}", port);
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        tokio::spawn(async move {
+            if let Err(e) = handle(socket).await {
+                warn!("http request from {:?} failed: {}", addr, e);
+            }
+        });
+    }
+}
+
+/// 处理一个请求，回应后关闭连接
+async fn handle(mut socket: TcpStream) -> io::Result<()> {
+    // 读取到请求头结束，请求体不需要
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = socket.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..n]);
+        if request.len() > HTTP_REQUEST_MAX_SIZE {
+            return respond(
+                &mut socket,
+                "413 Payload Too Large",
+                &[],
+                b"request too large",
+            )
+            .await;
+        }
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.lines().next().unwrap_or("").split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    if method != "GET" && method != "HEAD" {
+        return respond(
+            &mut socket,
+            "405 Method Not Allowed",
+            &[("Allow", "GET, HEAD")],
+            b"read only",
+        )
+        .await;
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let path = match percent_decode(path) {
+        Some(path) if path.starts_with('/') => path,
+        _ => return respond(&mut socket, "400 Bad Request", &[], b"bad path").await,
+    };
+    let fs_path = crate::normalize_path(&["~", &path].concat());
+
+    // 整个请求持有全局读锁，期间写操作等待
+    let read_lock = fs_lock::read().await;
+    let (status, headers, body) = if path.ends_with('/') {
+        match syscall::ls("root", &fs_path, false, false, false).await {
+            Ok(listing) => (
+                "200 OK",
+                vec![("Content-Type", "text/html; charset=utf-8".to_string())],
+                render_dir(&fs_path, &listing.unwrap_or_default()).into_bytes(),
+            ),
+            Err(e) => error_response(e),
+        }
+    } else {
+        match syscall::cat(&fs_path).await {
+            Ok(content) => {
+                let name = path.rsplit('/').next().unwrap_or("");
+                let mut headers = vec![("Content-Type", "text/plain; charset=utf-8".to_string())];
+                // ?download 时让浏览器下载而不是显示
+                if query.split('&').any(|param| param == "download") {
+                    headers.push((
+                        "Content-Disposition",
+                        format!("attachment; filename=\"{}\"", name.replace('"', "")),
+                    ));
+                }
+                ("200 OK", headers, content.unwrap_or_default().into_bytes())
+            }
+            // 不带/访问目录时跳转到目录页
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => (
+                "301 Moved Permanently",
+                vec![("Location", percent_encode_path(&[&path, "/"].concat()))],
+                Vec::new(),
+            ),
+            Err(e) => error_response(e),
+        }
+    };
+    drop(read_lock);
+
+    let headers: Vec<_> = headers
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect();
+    // HEAD只回应头部
+    let body = if method == "HEAD" { &[][..] } else { &body };
+    respond(&mut socket, status, &headers, body).await
+}
+
+fn error_response(e: io::Error) -> (&'static str, Vec<(&'static str, String)>, Vec<u8>) {
+    let status = match e.kind() {
+        io::ErrorKind::NotFound => "404 Not Found",
+        _ => "500 Internal Server Error",
+    };
+    let headers = vec![("Content-Type", "text/plain; charset=utf-8".to_string())];
+    (status, headers, e.to_string().into_bytes())
+}
+
+async fn respond(
+    socket: &mut TcpStream,
+    status: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> io::Result<()> {
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        status,
+        body.len()
+    );
+    for (name, value) in headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str("\r\n");
+    socket.write_all(response.as_bytes()).await?;
+    socket.write_all(body).await?;
+    socket.shutdown().await
+}
+
+/// 将syscall::ls的输出渲染为目录页，目录名以/结尾，最后一行为统计信息
+fn render_dir(fs_path: &str, listing: &str) -> String {
+    let mut lines: Vec<_> = listing.lines().collect();
+    let summary = lines.pop().unwrap_or("");
+    let title = html_escape(fs_path);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title></head><body>\n<h1>{}</h1>\n<ul>\n",
+        title, title
+    );
+    if fs_path != "~" {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for name in lines {
+        let href = percent_encode_path(name);
+        let entry = if name.ends_with('/') {
+            format!("<a href=\"{}\">{}</a>", href, html_escape(name))
+        } else {
+            format!(
+                "<a href=\"{}\">{}</a> (<a href=\"{}?download\">download</a>)",
+                href,
+                html_escape(name),
+                href
+            )
+        };
+        html.push_str(&format!("<li>{}</li>\n", entry));
+    }
+    html.push_str(&format!(
+        "</ul>\n<p>{}</p>\n</body></html>\n",
+        html_escape(summary)
+    ));
+    html
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// 对路径中除/以外的保留字符进行百分号编码
+fn percent_encode_path(path: &str) -> String {
+    path.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// 解码百分号编码，格式错误或不是UTF-8时返回None
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
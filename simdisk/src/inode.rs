@@ -1,24 +1,96 @@
+use async_recursion::async_recursion;
 use bitflags::bitflags;
 
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
     cmp::min,
+    collections::HashMap,
     io::{Error, ErrorKind},
     sync::Arc,
     time::SystemTime,
 };
+use tokio::sync::RwLock;
 
 use crate::{
-    bitmap::{self, alloc_bit, dealloc_data_bit, dealloc_data_bits, dealloc_inode_bit, BitmapType},
-    block::{deserialize, get_block_buffer, get_blocks_buffers, write_block, BlockIDType},
-    dirent::DirEntry,
+    bitmap::{
+        self, alloc_bit, alloc_bits, alloc_contiguous_bits, dealloc_data_bit, dealloc_data_bits,
+        dealloc_inode_bit, BitmapType,
+    },
+    block::{
+        deserialize, get_all_blocks, get_all_valid_blocks, get_block_buffer, get_blocks_buffers,
+        get_owned_block_ids, write_block, write_blocks, BlockIDType,
+    },
+    dirent::{self, DirEntry},
+    file,
     fs_constants::*,
+    packing,
     simple_fs::{show_unit, SFS},
     user::{self, UserIdType},
 };
 
 pub type InodeIdType = u16;
 
+/// Inode::alloc_more_blocks新申请的块
+#[derive(Default)]
+pub struct MoreBlocks {
+    pub data_ids: Vec<usize>, // 按逻辑顺序排列的新数据块
+    pub all_ids: Vec<usize>,  // 包括间接块在内的所有新块，失败时用于释放
+    pub addr_writes: Vec<(BlockIDType, usize, usize)>, // 需要写入间接块的地址 (地址, 间接块号, 块内偏移)
+}
+
+/// 已经反序列化的inode的缓存，与块缓存中的内容保持一致
+#[derive(Default)]
+pub struct InodeCache {
+    inodes: HashMap<usize, Inode>,
+    pub hits: usize,   // 命中缓存的次数
+    pub misses: usize, // 需要重新反序列化的次数
+}
+
+impl InodeCache {
+    fn get(&mut self, inode_id: usize) -> Option<Inode> {
+        match self.inodes.get(&inode_id) {
+            Some(inode) => {
+                self.hits += 1;
+                Some(inode.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, inode: Inode) {
+        // 超过容量时直接清空，避免维护淘汰顺序
+        if self.inodes.len() >= INODE_CACHE_SIZE {
+            self.inodes.clear();
+        }
+        self.inodes.insert(inode.inode_id as usize, inode);
+    }
+
+    fn invalidate(&mut self, inode_id: usize) {
+        self.inodes.remove(&inode_id);
+    }
+
+    /// 清空缓存，用于格式化
+    pub fn clear(&mut self) {
+        self.inodes.clear();
+    }
+
+    /// 清零命中统计
+    pub fn reset_stats(&mut self) {
+        self.hits = 0;
+        self.misses = 0;
+    }
+}
+
+//延迟加载全局变量 INODE_CACHE
+lazy_static! {
+    pub static ref INODE_CACHE: Arc<RwLock<InodeCache>> =
+        Arc::new(RwLock::new(InodeCache::default()));
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Inode {
     // 内存要对齐！
@@ -59,6 +131,10 @@ bitflags! {
          const RDWR = 1 << 2;
          /// 可执行
          const EXCUTE = 1 << 3;
+         /// 内容经过zlib压缩
+         const COMPRESSED = 1 << 4;
+         /// 内容和其他小文件打包在同一个块中
+         const PACKED = 1 << 5;
     }
 }
 
@@ -83,11 +159,11 @@ impl Inode {
         root.alloc_data_blocks().await.unwrap();
         assert_eq!(DATA_START_BLOCK, root.addr[0] as usize);
 
-        let current_dirent = DirEntry::create_dot(&mut root).await;
+        let current_dirent = DirEntry::create_dot(&mut root).await.unwrap();
         write_block(&current_dirent, root.addr[0] as usize, 0)
             .await
             .unwrap();
-        root.cache().await;
+        root.cache().await.unwrap();
         root
     }
 
@@ -113,16 +189,23 @@ impl Inode {
             addr: [0; ADDR_TOTAL_SIZE],
             time_info: now_secs(),
         };
-        // 申请对应大小的data block
-        inode.alloc_data_blocks().await?;
+        if inode.mode.contains(FileMode::PACKED) {
+            // 小文件只申请打包块中的位置
+            let (block_id, offset) = packing::alloc(size as usize).await?;
+            inode.addr[0] = block_id as BlockIDType;
+            inode.addr[1] = offset as BlockIDType;
+        } else {
+            // 申请对应大小的data block
+            inode.alloc_data_blocks().await?;
+        }
 
         if let InodeType::Diretory = inode_type {
             // 申请两个目录项并存放到块中
-            let dirs = DirEntry::create_special_diretories(&mut inode, parent_inode).await;
-            write_block(&dirs, inode.addr[0] as usize, 0).await.unwrap();
+            let dirs = DirEntry::create_special_diretories(&mut inode, parent_inode).await?;
+            write_block(&dirs, inode.addr[0] as usize, 0).await?;
         }
         // 写入缓存块
-        inode.cache().await;
+        inode.cache().await?;
         Ok(inode)
     }
 
@@ -147,8 +230,55 @@ impl Inode {
     pub async fn dealloc(&mut self) {
         //0.1 dealloc 自己
         assert!(dealloc_inode_bit(self.inode_id as usize).await);
-        //0.2 unlink(主要针对目录.和..)
-        self.unlinkat().await;
+        //0.2 unlink(主要针对目录.和..)，inode已经释放，写入失败时只记录
+        if let Err(e) = self.unlinkat().await {
+            error!("failed to unlink inode {}: {}", self.inode_id, e);
+        }
+        //0.3 使inode缓存失效
+        Arc::clone(&INODE_CACHE)
+            .write()
+            .await
+            .invalidate(self.inode_id as usize);
+        self.release_storage().await;
+    }
+
+    /// 为同一个inode按新内容重新申请存储，返回持有新块的inode，不写回；
+    /// 原来的块仍由self持有，新内容写入成功后再用release_storage释放。
+    /// stored_size为实际存储的字节数，content_size为（压缩前的）文件大小
+    pub async fn alloc_new_storage(
+        &self,
+        packed: bool,
+        stored_size: u32,
+        content_size: u32,
+    ) -> Result<Self, Error> {
+        let mut mode = self.mode.clone() - FileMode::PACKED;
+        if packed {
+            mode |= FileMode::PACKED;
+        }
+        let mut inode = Self {
+            mode,
+            size: stored_size,
+            addr: [0; ADDR_TOTAL_SIZE],
+            time_info: now_secs(),
+            ..self.clone()
+        };
+        if packed {
+            let (block_id, offset) = packing::alloc(stored_size as usize).await?;
+            inode.addr[0] = block_id as BlockIDType;
+            inode.addr[1] = offset as BlockIDType;
+        } else {
+            inode.alloc_data_blocks().await?;
+        }
+        inode.size = content_size;
+        Ok(inode)
+    }
+
+    /// 释放inode持有的数据块（递归dealloc所拥有的block及其内容）或打包块中的位置，inode本身保留
+    pub async fn release_storage(&self) {
+        if self.is_packed() {
+            packing::dealloc(self.addr[0] as usize).await;
+            return;
+        }
 
         //1. dealloc直接块
         for i in 0..DIRECT_BLOCK_NUM {
@@ -197,6 +327,62 @@ impl Inode {
         self.addr[DIRECT_BLOCK_NUM] as usize
     }
 
+    /// 获取打包文件所在的打包块号和块内偏移
+    pub fn get_packed_location(&self) -> (usize, usize) {
+        (self.addr[0] as usize, self.addr[1] as usize)
+    }
+
+    /// 获取硬连接数
+    pub fn get_nlink(&self) -> u8 {
+        self.nlink
+    }
+
+    /// 获取文件大小
+    pub fn get_size(&self) -> usize {
+        self.size as usize
+    }
+
+    /// 展示用的大小（字节）：文件为文件大小；目录为其中目录项（不含.和..）占用的字节数，
+    /// 即目录项数 * DIRENTRY_SIZE。每次从目录块中统计，不保存在inode中
+    pub async fn computed_size(&self) -> Result<usize, Error> {
+        if !self.is_dir() {
+            return Ok(self.get_size());
+        }
+        let entries = DirEntry::get_all_dirent(self)
+            .await?
+            .iter()
+            .filter(|(_, _, dir)| !dir.is_special())
+            .count();
+        Ok(entries * DIRENTRY_SIZE)
+    }
+
+    /// 获取权限，包含压缩、打包等存储方式的标记
+    pub fn get_mode(&self) -> FileMode {
+        self.mode.clone()
+    }
+
+    /// 设置权限，存储方式（压缩、打包）由写入内容时决定，保持不变，需要调用save写回
+    pub fn set_mode(&mut self, mode: FileMode) {
+        let storage = FileMode::COMPRESSED | FileMode::PACKED;
+        self.mode = (mode - storage.clone()) | (self.mode.clone() & storage);
+    }
+
+    /// 获取创建者的用户id
+    pub fn get_uid(&self) -> UserIdType {
+        self.uid
+    }
+
+    /// 设置属主，需要调用save写回
+    pub fn set_owner(&mut self, gid: UserIdType, uid: UserIdType) {
+        self.gid = gid;
+        self.uid = uid;
+    }
+
+    /// 获取时间戳：创建时记录，edit修改内容时更新，复制元数据时随之复制
+    pub fn get_created_at(&self) -> u64 {
+        self.time_info
+    }
+
     /// 设置一级块id
     pub fn set_first_id(&mut self, first_id: BlockIDType) {
         self.addr[DIRECT_BLOCK_NUM] = first_id;
@@ -212,13 +398,31 @@ impl Inode {
         self.addr[DIRECT_BLOCK_NUM + FIRST_INDIRECT_NUM] = second_id;
     }
 
+    /// 和alloc_new_storage相同，但数据块申请在一段连续的空闲块中，权限和时间戳保持不变
+    pub async fn alloc_contiguous_storage(
+        &self,
+        stored_size: u32,
+        content_size: u32,
+    ) -> Result<Self, Error> {
+        let mut inode = Self {
+            size: stored_size,
+            addr: [0; ADDR_TOTAL_SIZE],
+            ..self.clone()
+        };
+        inode.alloc_data_blocks_in(true).await?;
+        inode.size = content_size;
+        Ok(inode)
+    }
+
     /// 一次性为inode申请inode.size大小的block
     async fn alloc_data_blocks(&mut self) -> Result<(), Error> {
-        let block_nums = if self.size == 0 {
-            1
-        } else {
-            (self.size as usize - 1) / BLOCK_SIZE + 1 // 向上取整
-        };
+        self.alloc_data_blocks_in(false).await
+    }
+
+    /// 一次性为inode申请inode.size大小的block，contiguous为true时所有块位于一段连续的空闲块中；
+    /// 申请到的块中前面的用作数据块，之后的用作间接块，数据块按逻辑顺序排列
+    async fn alloc_data_blocks_in(&mut self, contiguous: bool) -> Result<(), Error> {
+        let block_nums = blocks_for_size(self.size as usize);
         if block_nums > bitmap::count_valid_data_blocks().await {
             // 没有足够的剩余空间
             error!("data not enough");
@@ -245,46 +449,65 @@ impl Inode {
             0
         };
 
-        let ty = BitmapType::Data;
+        // 计算二级间接块下需要的一级块的数量
+        let second_first_nums = if second_nums > 0 {
+            (second_nums - 1) / INDIRECT_ADDR_NUM + 1
+        } else {
+            0
+        };
+        // 计算存放地址的间接块本身的数量
+        let mut index_nums = second_first_nums;
+        if first_nums > 0 {
+            index_nums += 1;
+        }
+        if second_nums > 0 {
+            index_nums += 1;
+        }
+
+        // 一次性申请所有需要的块
         let start = DATA_START_BLOCK as BlockIDType;
-        // 为直接块申请
+        let bit_ids = if contiguous {
+            alloc_contiguous_bits(BitmapType::Data, block_nums + index_nums).await?
+        } else {
+            alloc_bits(BitmapType::Data, block_nums + index_nums).await?
+        };
+        let mut ids = bit_ids[..block_nums].iter().map(|bit_id| bit_id + start);
+        let mut index_ids = bit_ids[block_nums..].iter().map(|bit_id| bit_id + start);
+        // 记录需要写入间接块的地址 (地址, 间接块号, 块内偏移)
+        let mut addr_writes: Vec<(BlockIDType, usize, usize)> = Vec::new();
+
+        // 分配直接块
         for i in 0..direct_nums {
-            let block_id = alloc_bit(ty).await? + start;
-            self.addr[i] = block_id;
+            self.addr[i] = ids.next().unwrap();
         }
 
-        // 为一级间接块申请
+        // 分配一级间接块
         if first_nums > 0 {
-            let first_id = alloc_bit(ty).await? + start;
+            let first_id = index_ids.next().unwrap();
             self.set_first_id(first_id);
 
-            // 在一级间接块中申请需要的数据块地址
+            // 将直接块地址写入一级间接块中
             for i in 0..first_nums {
-                let id = alloc_bit(ty).await? + start;
-                // 将申请得到的直接块地址写入间接块中
-                write_block(&id, first_id as usize, i * 4).await?;
+                let id = ids.next().unwrap();
+                addr_writes.push((id, first_id as usize, i * BLOCK_ADDR_SIZE));
             }
         }
 
-        // 为二级间接块申请
+        // 分配二级间接块
         if second_nums > 0 {
-            let second_id = alloc_bit(ty).await? + start;
-            self.addr[DIRECT_BLOCK_NUM + FIRST_INDIRECT_NUM] = second_id;
-
-            // 计算需要申请的一级块的数量
-            let first_nums = (second_nums - 1) / INDIRECT_ADDR_NUM + 1;
+            let second_id = index_ids.next().unwrap();
+            self.set_second_id(second_id);
             let mut rest_nums = second_nums;
 
-            for i in 0..first_nums {
-                // 申请一级间接地址
-                let first_id = alloc_bit(ty).await? + start;
-                // 将二级间接块申请得到的地址写入二级块中
-                write_block(&first_id, second_id as usize, i * 4).await?;
+            for i in 0..second_first_nums {
+                // 将一级间接块地址写入二级块中
+                let first_id = index_ids.next().unwrap();
+                addr_writes.push((first_id, second_id as usize, i * BLOCK_ADDR_SIZE));
 
-                // 在一级间接块中申请需要的数据块地址
+                // 将直接块地址写入一级间接块中
                 for j in 0..min(rest_nums, FISRT_MAX) {
-                    let id = alloc_bit(ty).await? + start;
-                    write_block(&id, first_id as usize, j * 4).await?;
+                    let id = ids.next().unwrap();
+                    addr_writes.push((id, first_id as usize, j * BLOCK_ADDR_SIZE));
                 }
                 if rest_nums < FISRT_MAX {
                     break;
@@ -292,11 +515,113 @@ impl Inode {
                 rest_nums -= FISRT_MAX;
             }
         }
+
+        // 批量写入间接块中的地址
+        let args: Vec<_> = addr_writes
+            .iter()
+            .map(|(id, block_id, start_byte)| (id, *block_id, *start_byte))
+            .collect();
+        write_blocks(&args).await?;
         Ok(())
     }
 
+    /// 在文件末尾增加数据块，使数据块数从blocks_for_size(size)增加到block_nums；
+    /// 已有的数据块和间接块保持不变，缺少的间接块一起申请。addr随之更新但不写回，
+    /// 间接块中的地址由调用者在新数据块写好后写入
+    pub async fn alloc_more_blocks(&mut self, block_nums: usize) -> Result<MoreBlocks, Error> {
+        let old_nums = blocks_for_size(self.size as usize);
+        if block_nums <= old_nums {
+            return Ok(MoreBlocks::default());
+        }
+        if block_nums > DIRECT_BLOCK_NUM + FISRT_MAX + SECOND_MAX {
+            error!("file size is too large");
+            return Err(Error::new(ErrorKind::OutOfMemory, "file size is too large"));
+        }
+        // 二级间接块下的一级块数
+        let second_firsts = |nums: usize| {
+            (nums.saturating_sub(DIRECT_BLOCK_NUM + FISRT_MAX)).div_ceil(INDIRECT_ADDR_NUM)
+        };
+        let new_first = block_nums > DIRECT_BLOCK_NUM && old_nums <= DIRECT_BLOCK_NUM;
+        let new_second =
+            block_nums > DIRECT_BLOCK_NUM + FISRT_MAX && old_nums <= DIRECT_BLOCK_NUM + FISRT_MAX;
+        let index_nums = second_firsts(block_nums) - second_firsts(old_nums)
+            + new_first as usize
+            + new_second as usize;
+        let count = block_nums - old_nums + index_nums;
+        if count > bitmap::count_valid_data_blocks().await {
+            error!("data not enough");
+            return Err(Error::new(ErrorKind::OutOfMemory, "no enough block"));
+        }
+
+        let all_ids: Vec<_> = alloc_bits(BitmapType::Data, count)
+            .await?
+            .iter()
+            .map(|bit_id| *bit_id as usize + DATA_START_BLOCK)
+            .collect();
+        let (data_ids, index_ids) = all_ids.split_at(block_nums - old_nums);
+        let mut index_ids = index_ids.iter();
+        if new_first {
+            self.set_first_id(*index_ids.next().unwrap() as BlockIDType);
+        }
+        if new_second {
+            self.set_second_id(*index_ids.next().unwrap() as BlockIDType);
+        }
+        let mut addr_writes = Vec::new();
+
+        // 二级间接块下已有的一级块，再接上新申请的
+        let second_id = self.get_second_id();
+        let mut first_ids = Vec::new();
+        for i in 0..second_firsts(old_nums) {
+            let start = i * BLOCK_ADDR_SIZE;
+            let buffer = get_block_buffer(second_id, start, start + BLOCK_ADDR_SIZE).await?;
+            first_ids.push(deserialize::<BlockIDType>(&buffer)? as usize);
+        }
+        for i in second_firsts(old_nums)..second_firsts(block_nums) {
+            let first_id = *index_ids.next().unwrap();
+            addr_writes.push((first_id as BlockIDType, second_id, i * BLOCK_ADDR_SIZE));
+            first_ids.push(first_id);
+        }
+
+        for (i, id) in (old_nums..block_nums).zip(data_ids) {
+            let id = *id as BlockIDType;
+            if i < DIRECT_BLOCK_NUM {
+                self.addr[i] = id;
+            } else if i < DIRECT_BLOCK_NUM + FISRT_MAX {
+                let start = (i - DIRECT_BLOCK_NUM) * BLOCK_ADDR_SIZE;
+                addr_writes.push((id, self.get_first_id(), start));
+            } else {
+                let j = i - DIRECT_BLOCK_NUM - FISRT_MAX;
+                let start = (j % INDIRECT_ADDR_NUM) * BLOCK_ADDR_SIZE;
+                addr_writes.push((id, first_ids[j / INDIRECT_ADDR_NUM], start));
+            }
+        }
+        Ok(MoreBlocks {
+            data_ids: data_ids.to_vec(),
+            all_ids: all_ids.clone(),
+            addr_writes,
+        })
+    }
+
+    /// 追加内容后更新文件大小和修改时间并写回
+    pub async fn set_appended_size(&mut self, size: u32) -> Result<(), Error> {
+        self.size = size;
+        self.time_info = now_secs();
+        self.cache().await
+    }
+
     /// 直接从block读取inode信息
     pub async fn read(inode_id: usize) -> Result<Self, Error> {
+        if let Some(inode) = Arc::clone(&INODE_CACHE).write().await.get(inode_id) {
+            return Ok(inode);
+        }
+        let inode = Self::read_raw(inode_id).await?;
+        Arc::clone(&INODE_CACHE).write().await.insert(inode.clone());
+        Ok(inode)
+    }
+
+    /// 不经过inode缓存，直接从inode区读出第inode_id个inode，也不放入缓存；
+    /// 用于查看未申请或损坏的inode，其中记录的inode号可能和inode_id不同
+    pub async fn read_raw(inode_id: usize) -> Result<Self, Error> {
         let (block_id, start_byte) = cal_offset(inode_id);
         let end_byte = start_byte + INODE_SIZE;
         // 一个Inode 64B
@@ -305,75 +630,317 @@ impl Inode {
     }
 
     ///将inode写入缓存中
-    async fn cache(&self) {
+    async fn cache(&self) -> Result<(), Error> {
         let inode_id = self.inode_id as usize;
         let (block_id, start_byte) = cal_offset(inode_id);
         trace!("write inode {} to block {} cache\n", inode_id, block_id);
-        write_block(self, block_id, start_byte).await.unwrap();
+        write_block(self, block_id, start_byte).await?;
+        Arc::clone(&INODE_CACHE).write().await.insert(self.clone());
+        Ok(())
+    }
+
+    /// 块地址或元数据变化后写回inode
+    pub async fn save(&self) -> Result<(), Error> {
+        self.cache().await
     }
 
     /// 添加硬连接数
-    pub async fn linkat(&mut self) {
+    pub async fn linkat(&mut self) -> Result<(), Error> {
         self.nlink += 1;
-        self.cache().await;
+        self.cache().await
     }
 
     /// 减小硬连接数
-    pub async fn unlinkat(&mut self) {
+    pub async fn unlinkat(&mut self) -> Result<(), Error> {
         self.nlink -= 1;
-        self.cache().await;
+        self.cache().await
+    }
+
+    /// 直接设置硬连接数，用于fsck按目录项数修正
+    pub async fn set_nlink(&mut self, nlink: u8) -> Result<(), Error> {
+        self.nlink = nlink;
+        self.cache().await
     }
 
-    fn is_dir(&self) -> bool {
+    pub fn is_dir(&self) -> bool {
         matches!(self.inode_type, InodeType::Diretory)
     }
 
-    /// 展示当前inode目录的信息
-    pub async fn ls(&self, username: &str, detail: bool) -> String {
+    /// 文件内容是否经过压缩
+    pub fn is_compressed(&self) -> bool {
+        self.mode.contains(FileMode::COMPRESSED)
+    }
+
+    /// 是否和其他小文件打包存储
+    pub fn is_packed(&self) -> bool {
+        self.mode.contains(FileMode::PACKED)
+    }
+
+    /// 设置文件（压缩前）的大小，块的数量在申请时已经确定，不受影响
+    pub async fn set_content_size(&mut self, size: u32) -> Result<(), Error> {
+        self.size = size;
+        self.cache().await
+    }
+
+    /// 复制另一个inode的权限、属主和时间，存储方式（压缩、打包）保持不变
+    pub async fn copy_metadata(&mut self, other: &Inode) -> Result<(), Error> {
+        self.set_mode(other.get_mode());
+        self.set_owner(other.gid, other.uid);
+        self.time_info = other.time_info;
+        self.cache().await
+    }
+
+    /// 统计inode及其子目录下所有文件占用的空间（字节），
+    /// apparent为true时统计文件大小（inode.size）之和，否则统计实际占用的块数 * BLOCK_SIZE，
+    /// depth为当前递归的层数
+    #[async_recursion]
+    pub async fn disk_usage(&self, apparent: bool, depth: usize) -> Result<usize, Error> {
+        dirent::check_path_depth(depth)?;
+        let mut total = if apparent {
+            if self.is_dir() {
+                0
+            } else {
+                self.size as usize
+            }
+        } else if self.is_packed() {
+            // 打包文件只占用打包块中的一段
+            self.size as usize
+        } else {
+            get_all_blocks(self).await?.len() * BLOCK_SIZE
+        };
+        if self.is_dir() {
+            for (_, _, dirent) in DirEntry::get_all_dirent(self).await? {
+                // 跳过特殊目录 以免重复统计
+                if dirent.is_special() {
+                    continue;
+                }
+                let inode = Self::read(dirent.inode_id as usize).await?;
+                total += inode.disk_usage(apparent, depth + 1).await?;
+            }
+        }
+        Ok(total)
+    }
+
+    /// 计算merkle哈希：文件为其内容的哈希，目录为按名字排序的子项名字和子项哈希的哈希，
+    /// 内容和结构都相同的两棵目录树哈希相同；depth为当前递归的层数
+    #[async_recursion]
+    pub async fn tree_hash(&self, depth: usize) -> Result<[u8; 32], Error> {
+        dirent::check_path_depth(depth)?;
+        let mut hasher = Sha256::new();
+        if !self.is_dir() {
+            hasher.update(b"file\0");
+            hasher.update(file::read_content(self).await?.as_bytes());
+            return Ok(hasher.finalize().into());
+        }
+        let mut children = Vec::new();
+        for (_, _, dirent) in DirEntry::get_all_dirent(self).await? {
+            // 跳过特殊目录
+            if dirent.is_special() {
+                continue;
+            }
+            let inode = Self::read(dirent.inode_id as usize).await?;
+            children.push((dirent.get_filename(), inode.tree_hash(depth + 1).await?));
+        }
+        // 目录项的顺序和插入顺序有关，排序后哈希才只取决于内容
+        children.sort();
+        hasher.update(b"dir\0");
+        for (name, hash) in children {
+            hasher.update(name.as_bytes());
+            hasher.update(b"\0");
+            hasher.update(hash);
+        }
+        Ok(hasher.finalize().into())
+    }
+
+    /// 以树形展示当前目录，depth为当前所在的层数（从1开始），
+    /// max_depth为最多展开的层数，更深的目录折叠显示为`[...]`
+    #[async_recursion]
+    pub async fn tree(
+        &self,
+        prefix: &str,
+        depth: usize,
+        max_depth: Option<usize>,
+    ) -> Result<String, Error> {
+        dirent::check_path_depth(depth)?;
+        let dirents: Vec<_> = DirEntry::get_all_dirent(self)
+            .await?
+            .into_iter()
+            .map(|(_, _, dirent)| dirent)
+            .filter(|dirent| !dirent.is_special())
+            .collect();
+        let mut lines = String::new();
+        for (i, dirent) in dirents.iter().enumerate() {
+            let is_last = i + 1 == dirents.len();
+            let branch = if is_last { "└── " } else { "├── " };
+            lines.push_str(&[prefix, branch, &dirent.get_filename()].concat());
+            if !dirent.is_dir {
+                lines.push('\n');
+                continue;
+            }
+            if max_depth.is_some_and(|max| depth >= max) {
+                // 超过展开层数，折叠
+                lines.push_str("/ [...]\n");
+                continue;
+            }
+            lines.push_str("/\n");
+            let child_prefix = [prefix, if is_last { "    " } else { "│   " }].concat();
+            let inode = Self::read(dirent.inode_id as usize).await?;
+            lines.push_str(&inode.tree(&child_prefix, depth + 1, max_depth).await?);
+        }
+        Ok(lines)
+    }
+
+    /// 在当前目录下递归查找名字匹配pattern的文件和目录，
+    /// path为当前目录的绝对路径，depth为当前递归的层数，返回匹配项的绝对路径
+    #[async_recursion]
+    pub async fn find(
+        &self,
+        path: &str,
+        pattern: &str,
+        depth: usize,
+    ) -> Result<Vec<String>, Error> {
+        dirent::check_path_depth(depth)?;
+        let mut paths = Vec::new();
+        for (_, _, dirent) in DirEntry::get_all_dirent(self).await? {
+            if dirent.is_special() {
+                continue;
+            }
+            let name = dirent.get_filename();
+            let child_path = [path, "/", &name].concat();
+            if dirent::wildcard_match(pattern, &name) {
+                paths.push(child_path.clone());
+            }
+            if dirent.is_dir {
+                let inode = Self::read(dirent.inode_id as usize).await?;
+                paths.append(&mut inode.find(&child_path, pattern, depth + 1).await?);
+            }
+        }
+        Ok(paths)
+    }
+
+    /// 展示当前inode目录的信息，all为false时不展示隐藏的目录项，
+    /// inode_ids为true时在名字前加上目录项中记录的inode号（不读取inode）
+    pub async fn ls(&self, username: &str, detail: bool, inode_ids: bool, all: bool) -> String {
         assert!(self.is_dir());
         let mut dir_infos = String::new();
+        // 展示详情需要的用户信息在循环外一次取出，随即释放SFS读锁，
+        // 之后读inode和块时不再持有SFS锁，避免锁的嵌套
+        let (current_user_gid, user_names) = if detail {
+            let fs = Arc::clone(&SFS);
+            let fs_read_lock = fs.read().await;
+            // 用户在执行命令前已经校验过，取不到时按权限最低处理
+            let gid = fs_read_lock
+                .get_user_gid(username)
+                .unwrap_or(UserIdType::MAX);
+            (gid, fs_read_lock.user_infos.get_user_names())
+        } else {
+            (UserIdType::MAX, HashMap::new())
+        };
+        // 统计目录项数和文件大小之和，不包括.和..
+        let (mut dirs, mut files, mut total_size) = (0, 0, 0);
         for (_, _, dir) in DirEntry::get_all_dirent(self).await.unwrap().iter() {
-            let mut name = dir.get_filename();
+            if !all && dir.is_hidden() {
+                continue;
+            }
+            if dir.is_dir && !dir.is_special() {
+                dirs += 1;
+            } else if !dir.is_dir {
+                files += 1;
+                // 损坏的inode不计入大小
+                if let Ok(inode) = Self::read_dirent_inode(dir).await {
+                    total_size += inode.get_size();
+                }
+            }
+            let mut name = if inode_ids {
+                format!("{} {}", dir.inode_id, dir.get_filename())
+            } else {
+                dir.get_filename()
+            };
             if dir.is_dir {
                 name.push('/');
             }
             if detail {
-                // 获取dirent的各种信息
-                let inode = Self::read(dir.inode_id as usize).await.unwrap();
-                let addr = inode.addr;
-                let time = cal_date(inode.time_info);
-                let fs = Arc::clone(&SFS);
-                let fs_read_lock = fs.read().await;
-                let current_user_gid = fs_read_lock.get_user_gid(username).unwrap();
-                let creator_name = fs_read_lock.get_username(inode.uid).unwrap();
-                // 对于权限不足的用户展示只读，否则展示原本的模式
-                let mode = if user::able_to_modify(current_user_gid, inode.gid) {
-                    inode.mode
-                } else {
-                    FileMode::RDONLY
-                };
-
-                let mut infos = format!(
-                    "\taddr:{:X?}\n\tInode:{}\tcreated: {:#?}\t{:?}  \tBy: {:?}",
-                    addr, inode.inode_id, time, mode, creator_name,
-                );
-                if !dir.is_dir {
-                    // 是文件 加上文件大小
-                    let (size, unit) = show_unit(inode.size as usize);
-                    infos.push_str(&format!("\t{}{}", size, unit));
+                // 单个目录项损坏时展示占位信息，继续展示其他目录项
+                match Self::dirent_detail(dir, current_user_gid, &user_names).await {
+                    Ok(infos) => name.push_str(&infos),
+                    Err(e) => {
+                        warn!("failed to read inode {}: {}", dir.inode_id, e);
+                        name.push_str(&format!("\t<corrupt inode {}>", dir.inode_id));
+                    }
                 }
-                name.push_str(&infos);
             }
             dir_infos.push_str(&name);
             dir_infos.push('\n');
         }
+        let (size, unit) = show_unit(total_size);
+        dir_infos.push_str(&format!(
+            "{} entries ({} dirs, {} files), total {:.1}{}\n",
+            dirs + files,
+            dirs,
+            files,
+            size,
+            unit
+        ));
         trace!("ls ok");
         dir_infos
     }
+
+    /// 读取目录项所指的inode，inode号越界或对不上时err
+    async fn read_dirent_inode(dir: &DirEntry) -> Result<Self, Error> {
+        if dir.inode_id as usize >= INODE_MAX_NUM {
+            return Err(Error::new(ErrorKind::InvalidData, "inode id out of range"));
+        }
+        let inode = Self::read(dir.inode_id as usize).await?;
+        if inode.inode_id != dir.inode_id {
+            return Err(Error::new(ErrorKind::InvalidData, "inode id mismatch"));
+        }
+        Ok(inode)
+    }
+
+    /// 获取目录项所指inode的详细信息
+    async fn dirent_detail(
+        dir: &DirEntry,
+        current_user_gid: UserIdType,
+        user_names: &HashMap<UserIdType, String>,
+    ) -> Result<String, Error> {
+        let inode = Self::read_dirent_inode(dir).await?;
+        let addr = inode.addr;
+        let time = cal_date(inode.time_info);
+        let creator_name = user_names
+            .get(&inode.uid)
+            .ok_or(Error::new(ErrorKind::NotFound, "user not exists"))?;
+        // 对于权限不足的用户展示只读，否则展示原本的模式
+        let mode = if user::able_to_modify(current_user_gid, inode.gid) {
+            inode.mode.clone()
+        } else {
+            FileMode::RDONLY
+        };
+
+        let mut infos = format!(
+            "\taddr:{:X?}\n\tInode:{}\tLinks:{}\tcreated: {:#?}\t{:?}  \tBy: {:?}",
+            addr,
+            inode.inode_id,
+            inode.get_nlink(),
+            time,
+            mode,
+            creator_name,
+        );
+        // 文件为文件大小，目录为目录项占用的大小
+        let (size, unit) = show_unit(inode.computed_size().await?);
+        infos.push_str(&format!("\t{}{}", size, unit));
+        if inode.is_compressed() {
+            // 压缩文件额外展示实际占用的大小
+            let blocks = get_all_valid_blocks(&inode).await?;
+            let (size, unit) = show_unit(blocks.len() * BLOCK_SIZE);
+            infos.push_str(&format!(" (stored {}{})", size, unit));
+        }
+        Ok(infos)
+    }
 }
 
-/// 检查inode位图对应的区域是否出错
-pub async fn check_inodes_and_fix() -> Result<(), Error> {
+/// 检查inode位图对应的区域是否出错，返回出错的inode号，fix为true时把对应的位置0
+pub async fn check_inodes_and_fix(fix: bool) -> Result<Vec<usize>, Error> {
+    let mut invalid = Vec::new();
     let inode_bitmap = bitmap::get_inode_bitmaps().await;
     for (i, byte) in inode_bitmap.iter().enumerate() {
         for j in 0..8 {
@@ -384,12 +951,38 @@ pub async fn check_inodes_and_fix() -> Result<(), Error> {
                 let inode = Inode::read(id).await?;
                 if inode.inode_id as usize != id {
                     // 说明对不上，出错了
-                    dealloc_inode_bit(id).await;
+                    if fix {
+                        dealloc_inode_bit(id).await;
+                    }
+                    invalid.push(id);
                 }
             }
         }
     }
-    Ok(())
+    Ok(invalid)
+}
+
+/// 统计用户uid创建的所有文件占用的空间（字节），按实际占用的块数计算，打包文件按文件大小计算
+pub async fn user_usage(uid: UserIdType) -> Result<usize, Error> {
+    let mut usage = 0;
+    let inode_bitmap = bitmap::get_inode_bitmaps().await;
+    for (i, byte) in inode_bitmap.iter().enumerate() {
+        for j in 0..8 {
+            if !byte.get(j) {
+                continue;
+            }
+            let inode = Inode::read(i * 8 + j).await?;
+            if inode.uid != uid || inode.is_dir() {
+                continue;
+            }
+            usage += if inode.is_packed() {
+                inode.get_size()
+            } else {
+                get_owned_block_ids(&inode).await?.len() * BLOCK_SIZE
+            };
+        }
+    }
+    Ok(usage)
 }
 
 /// dealloc 一级块以及其拥有的直接块
@@ -420,14 +1013,24 @@ async fn dealloc_first_arr_blocks(first_ids: &[usize]) {
     bitmap::dealloc_data_bits(&direct_ids).await;
 }
 
+/// 计算size大小的内容需要的数据块数量，空文件也占用一个块
+pub fn blocks_for_size(size: usize) -> usize {
+    if size == 0 {
+        1
+    } else {
+        (size - 1) / BLOCK_SIZE + 1 // 向上取整
+    }
+}
+
+/// 计算inode所在的块号和块内的起始字节
 fn cal_offset(inode_id: usize) -> (usize, usize) {
-    let block_id = inode_id / BLOCK_SIZE + INODE_START_BLOCK;
-    let inode_pos = inode_id % 16;
+    let block_id = inode_id / INODES_PER_BLOCK + INODE_START_BLOCK;
+    let inode_pos = inode_id % INODES_PER_BLOCK;
     let start_byte = inode_pos * INODE_SIZE;
     (block_id, start_byte)
 }
 
-fn now_secs() -> u64 {
+pub fn now_secs() -> u64 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
@@ -439,3 +1042,352 @@ fn cal_date(timestamp: u64) -> chrono::NaiveDate {
         .unwrap()
         .date()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block::insert_object,
+        fs_lock,
+        test_utils::{add_user, exec, lookup, mktree, redirect_dirent, setup},
+    };
+
+    #[test]
+    fn offset_follows_inode_size() {
+        assert_eq!(cal_offset(0), (INODE_START_BLOCK, 0));
+        assert_eq!(
+            cal_offset(20),
+            (
+                INODE_START_BLOCK + 20 / INODES_PER_BLOCK,
+                20 % INODES_PER_BLOCK * INODE_SIZE
+            )
+        );
+        // 每块最后一个inode不越过块尾
+        let (_, start) = cal_offset(INODES_PER_BLOCK - 1);
+        assert_eq!(start + INODE_SIZE, BLOCK_SIZE);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reads_back_inode_20() {
+        let _fs = setup().await;
+        let files: Vec<_> = (0..30)
+            .map(|i| format!("f{}.txt:{}", i, "x".repeat(i)))
+            .collect();
+        let files: Vec<_> = files.iter().map(|file| file.as_str()).collect();
+        mktree(&files).await;
+        // 不经过缓存，从inode区读出
+        Arc::clone(&INODE_CACHE).write().await.clear();
+        let inode = Inode::read_raw(20).await.unwrap();
+        assert_eq!(inode.inode_id, 20);
+        let stat = exec("stat --inode 20").await.unwrap().unwrap();
+        assert!(stat.contains(&format!("size: {},", inode.size)), "{}", stat);
+
+        let inode = Inode::read(20).await.unwrap();
+        assert_eq!(inode.inode_id, 20);
+        assert!(!inode.is_dir());
+        let name = format!("f{}.txt", inode.size);
+        let stat = exec(&format!("stat {}", name)).await.unwrap().unwrap();
+        assert!(stat.contains("Inode: 20\t"), "{}", stat);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn du_apparent_size_differs_from_allocated_blocks() {
+        let _fs = setup().await;
+        // 1500字节占用两个块，尾部的块不满
+        let content = "x".repeat(1500);
+        mktree(&["dir/", &format!("dir/tail.txt:{}", content)]).await;
+
+        let usage = exec("du dir/tail.txt").await.unwrap().unwrap();
+        assert_eq!(usage, "2.0KiB\t~/dir/tail.txt");
+        let apparent = exec("du --apparent-size dir/tail.txt")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(apparent, "1.5KiB\t~/dir/tail.txt");
+
+        // 目录的统计包含其中的文件，目录本身的块只计入实际占用
+        let usage = exec("du dir").await.unwrap().unwrap();
+        let apparent = exec("du --apparent-size dir").await.unwrap().unwrap();
+        assert_eq!(apparent, "1.5KiB\t~/dir");
+        assert_ne!(usage, apparent);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tree_level_limits_depth() {
+        let _fs = setup().await;
+        mktree(&["top/mid/low/deep.txt:x", "top/side.txt:y"]).await;
+        let full = exec("tree top").await.unwrap().unwrap();
+        assert!(full.contains("deep.txt"), "{}", full);
+        assert!(!full.contains("[...]"), "{}", full);
+
+        // 只展开两层，第二层中未展开的目录带有标记
+        let limited = exec("tree -L 2 top").await.unwrap().unwrap();
+        let expected = "~/top\n├── mid/\n│   └── low/ [...]\n└── side.txt";
+        assert_eq!(limited.trim_end(), expected);
+
+        let limited = exec("tree -L 1 top").await.unwrap().unwrap();
+        assert!(
+            limited.contains("mid/ [...]") && !limited.contains("low"),
+            "{}",
+            limited
+        );
+        assert!(exec("tree -L 0 top").await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn second_read_hits_inode_cache() {
+        let _fs = setup().await;
+        mktree(&["a.txt:hello"]).await;
+        let id = crate::test_utils::lookup("a.txt").await.inode_id as usize;
+        {
+            let mut cache = INODE_CACHE.write().await;
+            cache.clear();
+            cache.reset_stats();
+        }
+        let first = Inode::read(id).await.unwrap();
+        let second = Inode::read(id).await.unwrap();
+        assert_eq!(format!("{:?}", first), format!("{:?}", second));
+        let cache = INODE_CACHE.read().await;
+        assert_eq!((cache.hits, cache.misses), (1, 1));
+        drop(cache);
+
+        // 写回inode后缓存和块中的内容一致
+        crate::test_utils::exec_as("root", "~", "append a.txt", &[" world"])
+            .await
+            .unwrap();
+        let cached = Inode::read(id).await.unwrap();
+        assert_eq!(cached.size, 11);
+        let raw = Inode::read_raw(id).await.unwrap();
+        assert_eq!(format!("{:?}", cached), format!("{:?}", raw));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn detailed_ls_flags_corrupt_entry() {
+        let _fs = setup().await;
+        mktree(&["a/", "a/good.txt:fine", "a/bad.txt:lost", "a/sub/"]).await;
+        redirect_dirent("a/bad.txt", (INODE_MAX_NUM + 5) as InodeIdType).await;
+
+        let listing = exec("dir /s a").await.unwrap().unwrap();
+        let line = |name: &str| {
+            listing
+                .lines()
+                .find(|line| line.starts_with(name))
+                .unwrap_or_else(|| panic!("{} missing in {}", name, listing))
+                .to_string()
+        };
+        assert!(line("bad.txt").contains("<corrupt inode"), "{}", listing);
+        for name in ["good.txt", "sub/"] {
+            assert!(!line(name).contains("corrupt"), "{}", listing);
+        }
+        assert!(listing.contains("3 entries"), "{}", listing);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn ls_footer_counts_entries() {
+        let _fs = setup().await;
+        mktree(&[
+            "d/a.txt:12345",
+            &format!("d/b.txt:{}", "b".repeat(2043)),
+            &format!("d/.hidden:{}", "h".repeat(1024)),
+            "d/x/",
+            "d/y/z.txt:not counted",
+        ])
+        .await;
+
+        // 不包括.和..以及隐藏文件，子目录中的文件不计入
+        for command in ["dir d", "dir /s d"] {
+            let listing = exec(command).await.unwrap().unwrap();
+            let footer = "4 entries (2 dirs, 2 files), total 2.0KiB\n";
+            assert!(listing.ends_with(footer), "{}", listing);
+        }
+        let listing = exec("dir -a d").await.unwrap().unwrap();
+        let footer = "5 entries (2 dirs, 3 files), total 3.0KiB\n";
+        assert!(listing.ends_with(footer), "{}", listing);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_detailed_ls_does_not_deadlock() {
+        let _fs = setup().await;
+        let files: Vec<_> = (0..200).map(|i| format!("big/f{}.txt:{}", i, i)).collect();
+        let files: Vec<_> = files.iter().map(|file| file.as_str()).collect();
+        mktree(&files).await;
+
+        // 详细列表和需要SFS写锁的注册、需要写锁的建目录同时进行
+        let mut tasks = Vec::new();
+        for i in 0..8 {
+            tasks.push(tokio::spawn(async move {
+                let listing = exec("dir /s big").await.unwrap().unwrap();
+                assert!(listing.ends_with("200 entries (0 dirs, 200 files), total 490.0B\n"));
+                assert!(!listing.contains("corrupt"));
+                add_user(&format!("user{}", i)).await;
+                exec(&format!("md other{}", i)).await.unwrap();
+            }));
+        }
+        let all = async {
+            for task in tasks {
+                task.await.unwrap();
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(30), all)
+            .await
+            .expect("detailed ls deadlocked");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn ls_hides_special_and_dot_entries_unless_all() {
+        let _fs = setup().await;
+        mktree(&["a/", "b.txt:b", ".hidden.txt:h", ".cfg/"]).await;
+        let names = |listing: String| {
+            let mut lines: Vec<_> = listing.lines().map(|line| line.to_string()).collect();
+            let footer = lines.pop().unwrap();
+            lines.sort();
+            (lines, footer)
+        };
+
+        let (lines, footer) = names(exec("dir").await.unwrap().unwrap());
+        assert_eq!(lines, ["a/", "b.txt"]);
+        assert_eq!(footer, "2 entries (1 dirs, 1 files), total 1.0B");
+
+        let (lines, footer) = names(exec("dir -a").await.unwrap().unwrap());
+        assert_eq!(lines, ["./", ".cfg/", ".hidden.txt", "a/", "b.txt"]);
+        assert_eq!(footer, "4 entries (2 dirs, 2 files), total 2.0B");
+        // 子目录中的.和..同样默认隐藏
+        let (lines, _) = names(exec("dir a").await.unwrap().unwrap());
+        assert!(lines.is_empty(), "{:?}", lines);
+        let (lines, footer) = names(exec("dir -a a").await.unwrap().unwrap());
+        assert_eq!(lines, ["../", "./"]);
+        assert_eq!(footer, "0 entries (0 dirs, 0 files), total 0.0B");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn accessors_expose_file_metadata() {
+        let _fs = setup().await;
+        add_user("alice").await;
+        let ids = SFS.read().await.get_user_ids("alice").unwrap();
+        let mut root = Inode::read(0).await.unwrap();
+        let before = now_secs();
+        let mode = FileMode::RDONLY | FileMode::COMPRESSED;
+        file::create_file_with_content(
+            "a.txt",
+            mode,
+            &mut root,
+            "hello",
+            false,
+            (ids.gid, ids.uid),
+        )
+        .await
+        .unwrap();
+
+        let mut inode = lookup("a.txt").await;
+        assert_eq!(inode.get_size(), 5);
+        assert_eq!(inode.get_mode(), FileMode::RDONLY | FileMode::COMPRESSED);
+        assert_eq!(inode.get_uid(), ids.uid);
+        assert_eq!(inode.gid, ids.gid);
+        assert_eq!(inode.get_nlink(), 1);
+        assert!((before..=now_secs()).contains(&inode.get_created_at()));
+
+        // 设置权限时存储方式保持不变，写回后重新读出仍然生效
+        inode.set_mode(FileMode::RDWR);
+        inode.set_owner(0, 0);
+        inode.save().await.unwrap();
+        let inode = Inode::read(inode.inode_id as usize).await.unwrap();
+        assert_eq!(inode.get_mode(), FileMode::RDWR | FileMode::COMPRESSED);
+        assert_eq!((inode.gid, inode.get_uid()), (0, 0));
+        assert_eq!(exec("cat a.txt").await.unwrap().unwrap(), "hello");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn directory_size_counts_its_entries() {
+        let _fs = setup().await;
+        mktree(&["d/a.txt:a", "d/b.txt:bb", "d/sub/", "empty/"]).await;
+        let expected = 3 * DIRENTRY_SIZE;
+
+        let d = lookup("d").await;
+        assert_eq!(d.get_size(), 0);
+        assert_eq!(d.computed_size().await.unwrap(), expected);
+        assert_eq!(lookup("empty").await.computed_size().await.unwrap(), 0);
+        assert_eq!(lookup("d/b.txt").await.computed_size().await.unwrap(), 2);
+
+        let listing = exec("dir /s").await.unwrap().unwrap();
+        // 每个目录项占两行，大小在第二行末尾
+        let size_of = |name: &str| {
+            let mut lines = listing.lines();
+            lines.find(|line| line.starts_with(&format!("{}/\t", name)));
+            lines
+                .next()
+                .unwrap()
+                .rsplit('\t')
+                .next()
+                .unwrap()
+                .to_string()
+        };
+        let (size, unit) = show_unit(expected);
+        assert_eq!(size_of("d"), format!("{}{}", size, unit), "{}", listing);
+        assert_eq!(size_of("empty"), "0B", "{}", listing);
+
+        let stat = exec("stat d").await.unwrap().unwrap();
+        assert!(stat.contains(&format!("Size: {}\t", expected)), "{}", stat);
+    }
+
+    /// 在目录dir下加一个名为name.txt、指向inode_id的目录项，即硬连接
+    async fn hard_link(dir: &str, name: &str, inode_id: InodeIdType) {
+        let _lock = fs_lock::write().await;
+        let mut dirent = DirEntry::new_temp(name, "txt", false).unwrap();
+        dirent.inode_id = inode_id;
+        let mut dir = lookup(dir).await;
+        insert_object(&dirent, &mut dir).await.unwrap();
+        let mut target = Inode::read(inode_id as usize).await.unwrap();
+        target.linkat().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn hard_links_show_the_same_inode_id() {
+        let _fs = setup().await;
+        mktree(&["a.txt:shared", "c.txt:other", "docs/"]).await;
+        let target = lookup("a.txt").await;
+
+        hard_link("docs", "b", target.inode_id).await;
+        assert_eq!(exec("cat docs/b.txt").await.unwrap().unwrap(), "shared");
+
+        let root = exec("dir /i").await.unwrap().unwrap();
+        let docs = exec("dir docs /i").await.unwrap().unwrap();
+        let id = target.inode_id;
+        assert!(root.contains(&format!("{} a.txt", id)), "{}", root);
+        assert!(docs.contains(&format!("{} b.txt", id)), "{}", docs);
+        let other = lookup("c.txt").await.inode_id;
+        assert_ne!(other, id);
+        assert!(root.contains(&format!("{} c.txt", other)), "{}", root);
+        let docs_id = lookup("docs").await.inode_id;
+        assert!(root.contains(&format!("{} docs/", docs_id)), "{}", root);
+
+        // 不带/i时不展示inode号
+        let plain = exec("dir").await.unwrap().unwrap();
+        assert!(!plain.contains(&format!("{} a.txt", id)), "{}", plain);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn detail_listing_shows_link_counts() {
+        let _fs = setup().await;
+        mktree(&["a.txt:shared", "fresh.txt:new", "docs/", "other/"]).await;
+        let target = lookup("a.txt").await.inode_id;
+        hard_link("docs", "b", target).await;
+        hard_link("other", "c", target).await;
+        assert_eq!(lookup("a.txt").await.get_nlink(), 3);
+
+        // 每个目录项占两行，硬连接数在第二行
+        let listing = exec("dir /s").await.unwrap().unwrap();
+        let links_of = |name: &str| {
+            let mut lines = listing.lines();
+            lines.find(|line| line.starts_with(&format!("{}\t", name)));
+            let details = lines.next().unwrap();
+            let links = details.split("Links:").nth(1).unwrap();
+            links.split('\t').next().unwrap().to_string()
+        };
+        assert_eq!(links_of("a.txt"), "3", "{}", listing);
+        assert_eq!(links_of("fresh.txt"), "1", "{}", listing);
+        assert_eq!(links_of("docs/"), "2", "{}", listing);
+
+        let listing = exec("dir docs /s").await.unwrap().unwrap();
+        assert!(listing.contains("Links:3"), "{}", listing);
+    }
+}
@@ -2,22 +2,42 @@ use std::sync::Arc;
 
 use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
 
-use block::sync_all_block_cache;
-use inode::FileMode;
+use block::{sync_all_block_cache, BLOCK_CACHE_MANAGER};
+use config::CONFIG;
+use fs_constants::{FIND_EXEC_COMMANDS, TRASH_DIR_NAME};
+use inode::{FileMode, INODE_CACHE};
+use request_cache::REQUEST_CACHE;
+use serde::Serialize;
 use simple_fs::SFS;
 use utils::*;
 
+mod args;
 mod bitmap;
 mod block;
+mod config;
+mod cwd_cache;
+mod device;
+mod diff;
 mod dirent;
 mod file;
 mod fs_constants;
+mod fs_lock;
+mod history;
+mod http;
 mod inode;
+mod packing;
+mod pipeline;
+mod request_cache;
+mod sessions;
 mod simple_fs;
 mod super_block;
 mod syscall;
+#[cfg(test)]
+mod test_utils;
 mod user;
+mod webhook;
 
 #[macro_use]
 extern crate lazy_static;
@@ -27,69 +47,133 @@ extern crate log;
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    pretty_env_logger::formatted_builder()
-        .filter_level(log::LevelFilter::Info)
-        .init();
+    // 日志级别要在读取其他参数之前确定，否则参数错误的告警不会输出
+    if let Err(e) = init_logger(&mut std::env::args().skip(1).collect()) {
+        error!("{}", e);
+        return Err(e);
+    }
+
+    info!("server config: {:?}", *CONFIG);
 
     let fs = Arc::clone(&SFS);
     let mut w = fs.write().await;
-    if w.init().await.is_err() {
+    if CONFIG.format {
+        // 不读取原有的镜像，直接格式化
+        if CONFIG.readonly {
+            let e = io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--format cannot be used with --readonly",
+            );
+            error!("{}", e);
+            return Err(e);
+        }
+        w.force_clear().await;
+        info!("SFS formatted");
+    } else if let Err(e) = w.init().await {
+        if e.kind() == io::ErrorKind::Unsupported {
+            // 镜像中可能有数据，不自动格式化
+            error!("{}", e);
+            return Err(e);
+        }
+        if CONFIG.readonly {
+            // 只读模式下不能重新格式化
+            error!("failed to read SFS in readonly mode: {}", e);
+            return Err(e);
+        }
         w.force_clear().await;
         info!("SFS init successfully");
     };
     drop(w);
 
-    let listener = TcpListener::bind(SOCKET_ADDR).await?;
-    info!("server listening to {}", SOCKET_ADDR);
+    let listener = TcpListener::bind(&CONFIG.addr).await?;
+    info!("server listening to {}", CONFIG.addr);
+    if let Some(port) = CONFIG.http_port {
+        tokio::spawn(async move {
+            if let Err(e) = http::serve(port).await {
+                error!("http browser stopped: {}", e);
+            }
+        });
+    }
 
+    // 每个连接占用一个许可，连接断开时归还
+    let connection_permits = Arc::new(Semaphore::new(CONFIG.max_connections));
     loop {
         let (mut socket, addr) = listener.accept().await?;
+        // 超过最大连接数时直接拒绝，不为其创建任务
+        let permit = match Arc::clone(&connection_permits).try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                warn!(
+                    "refuse {:?}: reached max connections {}",
+                    addr, CONFIG.max_connections
+                );
+                let _ = socket.write_all(TOO_MANY_CONNECTIONS.as_bytes()).await;
+                continue;
+            }
+        };
         info!("connected to {:?}", addr);
         // spawn一个线程
-        tokio::spawn(async move {
+        // 每个连接在自己的任务中缓存最近进入的目录
+        tokio::spawn(cwd_cache::scope(async move {
+            let _permit = permit;
             let mut cmd_buffer;
             let mut is_login = false;
+            // 当前连接登录的用户，命令中的用户名必须与之一致
+            let mut session_user = String::new();
+            let mut state = ProtocolState::AwaitLogin;
             loop {
                 if !is_login {
                     // 0.(1/2).1 等待client 发送信息
                     cmd_buffer = [0; SOCKET_BUFFER_SIZE];
-                    let n = match socket.read(&mut cmd_buffer).await {
-                        Ok(n) => n,
-                        Err(e) => {
-                            error!("failed to read from socket; err = {:?}", e);
-                            return;
-                        }
+                    let n = match read_before_idle(&mut socket, &mut cmd_buffer).await {
+                        Some(n) => n,
+                        None => break,
                     };
                     let response = String::from_utf8_lossy(&cmd_buffer[..n]);
                     let res_vec: Vec<&str> = response.lines().collect();
+                    let kind = res_vec.first().map_or("", |kind| kind.trim());
                     //  0.(1/2).2 验证信息并回信
-                    match res_vec[0].trim() {
+                    match kind {
+                        "login" | "regist" if res_vec.len() < 3 => {
+                            error!("incomplete {} message from {:?}", kind, addr);
+                            socket
+                                .write_all(b"incomplete username or password")
+                                .await
+                                .unwrap();
+                            continue;
+                        }
                         "login" => {
                             if login(&res_vec[1..], &mut socket).await.is_err() {
                                 continue;
                             }
                             is_login = true;
+                            session_user = res_vec[1].trim().to_string();
+                            sessions::login(addr, &session_user).await;
+                            state.transit(ProtocolState::AwaitCommand, addr);
+                        }
+                        "regist" if CONFIG.readonly => {
+                            socket
+                                .write_all(b"cannot regist in readonly mode")
+                                .await
+                                .unwrap();
+                            continue;
                         }
                         "regist" => {
                             regist(&res_vec[1..], &mut socket).await;
                             continue;
                         }
                         _ => {
-                            error!("invalid {}", res_vec[0]);
-                            return;
+                            error!("invalid {}", kind);
+                            break;
                         }
                     }
                 }
 
                 // 2.1 接受client的"cwd + 指令"
                 cmd_buffer = [0; SOCKET_BUFFER_SIZE];
-                let n = match socket.read(&mut cmd_buffer).await {
-                    Ok(n) if n == 0 => return,
-                    Ok(n) => n,
-                    Err(e) => {
-                        error!("failed to read from socket; err = {:?}", e);
-                        return;
-                    }
+                let n = match read_before_idle(&mut socket, &mut cmd_buffer).await {
+                    Some(n) => n,
+                    None => break,
                 };
                 let cmd = String::from_utf8_lossy(&cmd_buffer[..n]).replace('\0', "");
                 let command = cmd.trim();
@@ -98,143 +182,652 @@ async fn main() -> io::Result<()> {
                     if block::is_sync_exit().await {
                         sync_all_block_cache().await.unwrap();
                     }
-                    return;
+                    break;
                 } else if command == EMPTY_INPUT {
                     continue;
                 }
+                state.transit(ProtocolState::ExecutingCommand, addr);
                 // args[0]为username args[1]为cwd
-                let args: Vec<&str> = command.split_whitespace().collect();
+                let mut args: Vec<&str> = command.split_whitespace().collect();
+                // 带有请求id的命令，请求id位于最前面
+                let request_id = if args
+                    .first()
+                    .is_some_and(|arg| arg.starts_with(REQUEST_ID_PREFIX))
+                {
+                    Some(args.remove(0))
+                } else {
+                    None
+                };
+                // --json时出错返回json格式的错误对象
+                let json = strip_json_flag(&mut args);
+                let error_path = if json { get_error_path(&args) } else { None };
+                // 只缓存会修改文件系统的命令的结果，请求id按用户区分
+                let mutating = is_mutating(&args);
+                let cache_key = match request_id {
+                    Some(id) if mutating => args.first().map(|user| [user, ":", id].concat()),
+                    _ => None,
+                };
 
-                if args[0] == "root" && args[2] == "formatting" {
-                    is_login = false;
-                }
+                let is_formatting =
+                    args.first() == Some(&"root") && args.get(2) == Some(&"formatting");
 
+                if CONFIG.verbose {
+                    Arc::clone(&BLOCK_CACHE_MANAGER).write().await.reset_stats();
+                    Arc::clone(&INODE_CACHE).write().await.reset_stats();
+                    cwd_cache::reset_stats();
+                }
                 let start = tokio::time::Instant::now();
-                // 2.2 传输命令执行后的信息
-                let msg = match do_command(args, &mut socket).await {
-                    Ok(result) => result,
-                    Err(err) => {
-                        error!("send err back to socket: {:?}, err= {}", addr, err);
-                        Some([ERROR_MESSAGE_PREFIX, &err.to_string()].concat())
+                // 2.2 传输命令执行后的信息，已经执行过的请求直接返回缓存的结果
+                let cached = match &cache_key {
+                    Some(key) => Arc::clone(&REQUEST_CACHE).read().await.get(key),
+                    None => None,
+                };
+                let msg = match cached {
+                    Some(result) => {
+                        info!(
+                            "request {:?} already executed, reply cached result",
+                            cache_key
+                        );
+                        result
                     }
+                    None => match do_command(args, &session_user, &mut socket).await {
+                        Ok(result) => {
+                            // 格式化成功后需要重新登录
+                            if is_formatting {
+                                is_login = false;
+                                sessions::logout(addr).await;
+                            }
+                            if let Some(key) = cache_key {
+                                Arc::clone(&REQUEST_CACHE)
+                                    .write()
+                                    .await
+                                    .insert(key, result.clone());
+                            }
+                            result
+                        }
+                        Err(err) => {
+                            error!("send err back to socket: {:?}, err= {}", addr, err);
+                            let msg = if json {
+                                to_json_error(&err, error_path)
+                            } else {
+                                err.to_string()
+                            };
+                            Some([ERROR_MESSAGE_PREFIX, &msg].concat())
+                        }
+                    },
                 };
                 // 2.3 如果有信息要传输
                 if let Some(msg) = msg {
                     // 2.3.1 通知对方准备接受内容，等待地址
                     socket.write_all(RECEIVE_CONTENTS.as_bytes()).await.unwrap();
+                    state.transit(ProtocolState::AwaitAddress, addr);
                     // 2.3.2 接受地址
                     cmd_buffer = [0; SOCKET_BUFFER_SIZE];
                     let n = match socket.read(&mut cmd_buffer).await {
-                        Ok(n) if n == 0 => return,
+                        Ok(0) => break,
                         Ok(n) => n,
                         Err(e) => {
                             error!("failed to read from socket; err = {:?}", e);
-                            return;
+                            break;
                         }
                     };
-                    let addr = String::from_utf8_lossy(&cmd_buffer[..n]);
-                    info!("sending contents through {}", addr);
+                    let content_addr = String::from_utf8_lossy(&cmd_buffer[..n]);
+                    info!("sending contents through {}", content_addr);
+                    state.transit(ProtocolState::SendingContent, addr);
                     // 2.3.3 发送内容
-                    if let Err(e) = send_content(msg, &addr).await {
+                    if let Err(e) = send_content(msg, &content_addr).await {
                         error!("{}", e);
-                        return;
+                        break;
                     }
                 }
 
                 // 4 宣告结束
                 let duration = start.elapsed();
-                info!("cmd finished in {:?}", duration);
+                if CONFIG.verbose {
+                    // 统计包含了期间其他连接的读写
+                    let stats = Arc::clone(&BLOCK_CACHE_MANAGER).read().await.stats;
+                    let inode_cache = Arc::clone(&INODE_CACHE);
+                    let inode_cache = inode_cache.read().await;
+                    let (path_walks, cwd_hits) = cwd_cache::stats();
+                    info!(
+                        "cmd finished in {:?}, block io: {:?}, inode cache hits: {}, misses: {}, path walks: {}, cwd cache hits: {}",
+                        duration, stats, inode_cache.hits, inode_cache.misses, path_walks, cwd_hits
+                    );
+                } else {
+                    info!("cmd finished in {:?}", duration);
+                }
                 socket.write_all(COMMAND_FINISHED.as_bytes()).await.unwrap();
+                // 格式化成功后需要重新登录
+                let next = if is_login {
+                    ProtocolState::AwaitCommand
+                } else {
+                    ProtocolState::AwaitLogin
+                };
+                state.transit(next, addr);
             }
-        });
+            sessions::logout(addr).await;
+            state.transit(ProtocolState::Closed, addr);
+        }));
     }
 }
 
+/// 等待client的下一条消息，连接断开、出错或空闲超时时返回None，调用者应关闭连接
+async fn read_before_idle(socket: &mut TcpStream, buffer: &mut [u8]) -> Option<usize> {
+    let read = if CONFIG.idle_timeout == 0 {
+        Ok(socket.read(buffer).await)
+    } else {
+        tokio::time::timeout(
+            std::time::Duration::from_secs(CONFIG.idle_timeout),
+            socket.read(buffer),
+        )
+        .await
+    };
+    match read {
+        Ok(Ok(0)) => None,
+        Ok(Ok(n)) => Some(n),
+        Ok(Err(e)) => {
+            error!("failed to read from socket; err = {:?}", e);
+            None
+        }
+        Err(_) => {
+            info!(
+                "socket {:?} idle for {}s, close",
+                socket.peer_addr(),
+                CONFIG.idle_timeout
+            );
+            // 和client退出时一样写回缓存
+            if block::is_sync_exit().await {
+                if let Err(e) = sync_all_block_cache().await {
+                    error!("{}", e);
+                }
+            }
+            None
+        }
+    }
+}
+
+/// 执行一条命令，session_user为连接登录的用户
 async fn do_command(
     args: Vec<&str>,
+    session_user: &str,
     socket: &mut TcpStream,
 ) -> Result<Option<String>, std::io::Error> {
     info!(
         "received args: '{:?}' from socket: {:?}",
         args,
-        socket.peer_addr().unwrap()
+        socket.peer_addr()
     );
+    // 至少需要username、cwd和命令名
+    if args.len() < 3 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "incomplete request, expect [username] [cwd] [command]",
+        ));
+    }
     let username = args[0];
     let cwd = args[1];
-    let commands: Vec<String> = args[2..]
+    // 用户名由client发送，不能冒充其他用户绕过权限和专属根目录的限制
+    if username != session_user {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{}: not the logged in user", username),
+        ));
+    }
+    // 用户必须存在，否则后续获取用户id时会出错
+    Arc::clone(&SFS).read().await.get_user_gid(username)?;
+    let line: Vec<String> = args[2..]
         .iter()
         .map(|&arg| arg.replace('\0', "").trim().to_string())
         .collect();
+    // 命令管道中第一段为要执行的命令，之后为server内置的过滤器
+    let (commands, filters) = pipeline::split(&line)?;
+    let parsed = args::parse(&commands)?;
+    // umount之后只能重新mount
+    if parsed.name != "mount" && !block::is_attached().await {
+        return Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "no image mounted, use mount [host path]",
+        ));
+    }
+    if parsed.name != "history" {
+        history::record(username, &line.join(" ")).await;
+    }
+    // 过滤器只处理命令的输出，会修改文件系统或需要交互的命令不能用于管道
+    if !filters.is_empty() && parsed.is_mutating() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}: cannot be used in pipeline", parsed.name),
+        ));
+    }
+    if pipeline::has_host_program(&filters) && parsed.name != "cat" {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{}: only cat can pipe to <host> programs", parsed.name),
+        ));
+    }
+    // 只读模式下拒绝会修改文件系统的命令，check只读时只报告、fsck不带--repair时只检查，
+    // mount、umount不修改镜像内容，仍然允许
+    let readonly_allowed = parsed.name == "check"
+        || (parsed.name == "fsck" && !parsed.has_flag("--repair"))
+        || (parsed.name == "quota" && parsed.arg(1).is_none())
+        || parsed.name == "mount"
+        || parsed.name == "umount";
+    if CONFIG.readonly && !readonly_allowed && parsed.is_mutating() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{}: server is in readonly mode", parsed.name),
+        ));
+    }
+    // 读命令整个过程持有全局读锁，期间写操作等待；写操作在执行时自行取写锁，
+    // find -exec 可能执行写操作，不能持有读锁
+    let read_lock = if parsed.is_mutating() || parsed.option("-exec").is_some() {
+        None
+    } else {
+        Some(fs_lock::read().await)
+    };
+    // 被限制在专属根目录下的用户，其路径都相对于专属根目录
+    let root = Arc::clone(&SFS).read().await.user_infos.get_root(username);
+    let resolve = |path: &str| {
+        let path = normalize_path(&get_absolute_path(cwd, path));
+        to_real_path(root.as_deref(), &path)
+    };
+    // 回收站位于（专属）根目录下
+    let trash_path = resolve(&["~/", TRASH_DIR_NAME].concat());
+    // 将第i个位置参数转换为绝对路径
+    let path_arg = |i: usize| resolve(parsed.arg(i).unwrap());
 
-    if commands[0].as_str() == "dir" {
-        if commands.last().unwrap() == "/s" {
-            match commands.len() {
-                2 => syscall::ls(username, cwd, true).await,
-                3 => {
-                    let target_path = get_absolute_path(cwd, &commands[1]);
-                    syscall::ls(username, &target_path, true).await
+    let output = match parsed.name.as_str() {
+        "info" => syscall::info().await,
+        "mount-info" => syscall::mount_info().await,
+        "mount" => syscall::mount(username, parsed.arg(0).unwrap()).await,
+        "umount" => syscall::umount(username).await,
+        "dir" => {
+            let target_path = match parsed.arg(0) {
+                Some(_) => path_arg(0),
+                None => resolve(cwd),
+            };
+            syscall::ls(
+                username,
+                &target_path,
+                parsed.has_flag("/s"),
+                parsed.has_flag("/i"),
+                parsed.has_flag("-a"),
+            )
+            .await
+        }
+        "du" => {
+            let target_path = match parsed.arg(0) {
+                Some(_) => path_arg(0),
+                None => resolve(cwd),
+            };
+            syscall::du(&target_path, parsed.has_flag("--apparent-size")).await
+        }
+        "treehash" => {
+            let target_path = match parsed.arg(0) {
+                Some(_) => path_arg(0),
+                None => resolve(cwd),
+            };
+            syscall::treehash(&target_path).await
+        }
+        "tree" => {
+            let target_path = match parsed.arg(0) {
+                Some(_) => path_arg(0),
+                None => resolve(cwd),
+            };
+            let level = match parsed.option("-L") {
+                Some(level) => match level.parse::<usize>() {
+                    Ok(level) if level > 0 => Some(level),
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "tree: level must be a positive number",
+                        ))
+                    }
+                },
+                None => None,
+            };
+            syscall::tree(&target_path, level).await
+        }
+        "find" => {
+            let target_path = match parsed.arg(0) {
+                Some(_) => path_arg(0),
+                None => resolve(cwd),
+            };
+            let pattern = parsed.option("-name").unwrap_or("*");
+            let mut paths = syscall::find(&target_path, pattern).await?;
+            let cmd = match parsed.option("-exec") {
+                Some(cmd) => cmd,
+                None => {
+                    let paths: Vec<_> = paths
+                        .iter()
+                        .map(|path| to_view_path(root.as_deref(), path))
+                        .collect();
+                    return pipeline::run(&filters, paths.join("\n")).await.map(Some);
                 }
-                _ => Err(error_arg()),
+            };
+            // -exec 只能执行白名单中的命令
+            if !FIND_EXEC_COMMANDS.contains(&cmd) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "find: cannot exec {}, allowed commands: {}",
+                        cmd,
+                        FIND_EXEC_COMMANDS.join(" ")
+                    ),
+                ));
             }
-        } else {
-            match commands.len() {
-                1 => syscall::ls(username, cwd, false).await,
-                2 => {
-                    let target_path = get_absolute_path(cwd, &commands[1]);
-                    syscall::ls(username, &target_path, false).await
-                }
-                _ => Err(error_arg()),
+            if cmd == "del" {
+                // 回收站中的文件不再删除
+                paths.retain(|path| !path.starts_with(&trash_path));
             }
+            exec_on_paths(cmd, &paths, username, &trash_path, root.as_deref())
+                .await
+                .map(Some)
         }
-    } else {
-        match commands.len() {
-            1 => match commands[0].as_str() {
-                "info" => syscall::info().await,
-                "check" => syscall::check().await.map(|_| None),
-                "users" => syscall::get_users_info(username).await,
-                "formatting" => syscall::formatting(username).await.map(|_| None),
-                _ => Err(error_arg()),
-            },
-            2 => {
-                let absolut_path = get_absolute_path(cwd, &commands[1]);
-                match commands[0].as_str() {
-                    "cd" => syscall::cd(&absolut_path).await.map(|_| None),
-                    "md" => syscall::mkdir(username, &absolut_path).await.map(|_| None),
-                    // 对于rd 要等待client确认是否删除
-                    "rd" => syscall::rmdir(username, &absolut_path, socket)
-                        .await
-                        .map(|_| None),
-                    // 对于newfile 需要输入文件内容，要等待client传输内容
-                    "newfile" => syscall::new_file(username, &absolut_path, FileMode::RDWR, socket)
-                        .await
-                        .map(|_| None),
-                    "cat" => syscall::cat(&absolut_path).await,
-                    "del" => syscall::del(username, &absolut_path).await.map(|_| None),
-                    "setcache" => syscall::set_block_cache_method(&commands[1])
-                        .await
-                        .map(|_| None),
-                    _ => Err(error_arg()),
+        "stat" if parsed.option("--inode").is_some() => {
+            if parsed.arg(0).is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "stat: --inode cannot be used with a path",
+                ));
+            }
+            let inode_id = parsed.option("--inode").unwrap();
+            let inode_id = inode_id.parse::<usize>().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("stat: invalid inode id: {}", inode_id),
+                )
+            })?;
+            syscall::stat_inode(username, inode_id).await
+        }
+        "stat" if parsed.arg(0).is_none() => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "stat: missing path, usage: stat [path] | stat --inode [id]",
+        )),
+        "stat" => {
+            let view_path = to_view_path(root.as_deref(), &path_arg(0));
+            syscall::stat(&path_arg(0), &view_path).await
+        }
+        "blocks" => syscall::blocks(&path_arg(0)).await,
+        "exists" => syscall::exists(&path_arg(0)).await,
+        "cd" => syscall::cd(&path_arg(0)).await.map(|_| None),
+        "md" => syscall::mkdir(username, &path_arg(0)).await.map(|_| None),
+        // 不能删除根目录（包括专属根目录）
+        "rd" if path_arg(0) == resolve("~") => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "cannot remove root directory",
+        )),
+        // 对于rd 要等待client确认是否删除
+        "rd" => syscall::rmdir(username, &path_arg(0), socket)
+            .await
+            .map(|_| None),
+        // 对于newfile 需要输入文件内容，要等待client传输内容
+        "newfile" => {
+            // -z 压缩存储文件内容
+            let mode = if parsed.has_flag("-z") {
+                FileMode::RDWR | FileMode::COMPRESSED
+            } else {
+                FileMode::RDWR
+            };
+            syscall::new_file(username, &path_arg(0), mode, socket).await
+        }
+        "edit" => syscall::edit(username, &path_arg(0), socket).await,
+        "append" => syscall::append(username, &path_arg(0), socket).await,
+        "mkfile" => {
+            let size = parse_size(parsed.arg(1).unwrap())?;
+            syscall::mkfile(username, &path_arg(0), size as usize).await
+        }
+        "cat" if parsed.option("--range").is_some() => {
+            if has_wildcard(parsed.arg(0).unwrap()) || parsed.positionals.len() > 1 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cat: --range cannot be used with wildcards or multiple files",
+                ));
+            }
+            let (start, len) = parse_range(parsed.option("--range").unwrap())?;
+            syscall::cat_range(&path_arg(0), start, len).await
+        }
+        // 多个文件依次输出，个别文件出错时继续输出其余文件
+        "cat" if parsed.positionals.len() > 1 => {
+            let mut paths = Vec::new();
+            for (i, arg) in parsed.positionals.iter().enumerate() {
+                if has_wildcard(arg) {
+                    paths.extend(syscall::expand_wildcard(&path_arg(i)).await?);
+                } else {
+                    paths.push(path_arg(i));
                 }
             }
-            3 => match commands[0].as_str() {
-                "copy" => {
-                    let source_path = if commands[1].starts_with("<host>") {
-                        commands[1].clone()
-                    } else {
-                        get_absolute_path(cwd, &commands[1])
-                    };
-                    let target_path = get_absolute_path(cwd, &commands[2]);
-                    syscall::copy(username, &source_path, &target_path, socket)
-                        .await
-                        .map(|_| None)
+            exec_on_paths("cat", &paths, username, &trash_path, root.as_deref())
+                .await
+                .map(Some)
+        }
+        // 最后一段含有通配符时，对父目录下所有匹配的文件执行
+        "del" | "cat" if has_wildcard(parsed.arg(0).unwrap()) => {
+            let paths = syscall::expand_wildcard(&path_arg(0)).await?;
+            exec_on_paths(&parsed.name, &paths, username, &trash_path, root.as_deref())
+                .await
+                .map(Some)
+        }
+        "cat" => syscall::cat(&path_arg(0)).await,
+        "del" => syscall::del(username, &path_arg(0), &trash_path)
+            .await
+            .map(|_| None),
+        "undelete" => syscall::undelete(username, &path_arg(0), &trash_path)
+            .await
+            .map(|_| None),
+        "emptytrash" => syscall::empty_trash(username, &trash_path).await,
+        "copy" => {
+            let source = parsed.arg(0).unwrap();
+            // <host>路径和表示从client读取内容的 - 不是文件系统内的路径
+            let source_path = if source.starts_with("<host>") || source == "-" {
+                source.to_string()
+            } else {
+                path_arg(0)
+            };
+            // 保留结尾的/，表示目标必须是目录
+            let target_path = if parsed.arg(1).unwrap().ends_with('/') {
+                path_arg(1) + "/"
+            } else {
+                path_arg(1)
+            };
+            syscall::copy(
+                username,
+                &source_path,
+                &target_path,
+                parsed.has_flag("-f"),
+                parsed.has_flag("--preserve"),
+                socket,
+            )
+            .await
+        }
+        "diff" => syscall::diff(&path_arg(0), &path_arg(1)).await,
+        "rename" => syscall::rename(username, &path_arg(0), parsed.arg(1).unwrap())
+            .await
+            .map(|_| None),
+        "check" => syscall::check().await.map(|_| None),
+        "fsck" => syscall::fsck(parsed.has_flag("--repair"), parsed.has_flag("--lost-found")).await,
+        "verify" => syscall::verify().await,
+        "setcache" => syscall::set_block_cache_method(parsed.arg(0).unwrap())
+            .await
+            .map(|_| None),
+        "users" => syscall::get_users_info(username).await,
+        "sessions" => syscall::sessions(username, socket.peer_addr().ok()).await,
+        "chroot" => {
+            let path = parsed.arg(1).map(|_| path_arg(1));
+            syscall::chroot(username, parsed.arg(0).unwrap(), path.as_deref())
+                .await
+                .map(|_| None)
+        }
+        "quota" => {
+            let limits = match (parsed.arg(1), parsed.arg(2)) {
+                (None, None) => None,
+                (Some(soft), Some(hard)) => Some((parse_size(soft)?, parse_size(hard)?)),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "quota: both soft and hard limits are required",
+                    ))
                 }
-                _ => Err(error_arg()),
-            },
-            _ => Err(error_arg()),
+            };
+            syscall::quota(username, parsed.arg(0), limits).await
+        }
+        "history" => {
+            let term = match (parsed.arg(0), parsed.arg(1)) {
+                (None, _) => None,
+                (Some("search"), Some(term)) => Some(term),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "history: invalid args, usage: history (search term)",
+                    ))
+                }
+            };
+            syscall::history(username, term).await
+        }
+        "resetsb" => syscall::resetsb(username).await,
+        "defrag" => syscall::defrag(username, &path_arg(0)).await,
+        "resize" => {
+            let size = parse_size(parsed.arg(0).unwrap())?;
+            syscall::resize(username, size as usize).await
+        }
+        "bench" => {
+            let size = parse_size(parsed.arg(0).unwrap())?;
+            syscall::bench(username, size as usize, socket).await
+        }
+        "formatting" => syscall::formatting(username, socket).await.map(|_| None),
+        _ => Err(error_arg()),
+    }?;
+    if filters.is_empty() {
+        return Ok(output);
+    }
+    // 宿主程序可能运行较久，处理输出前先释放读锁
+    drop(read_lock);
+    pipeline::run(&filters, output.unwrap_or_default())
+        .await
+        .map(Some)
+}
+
+/// --json模式下返回给client的错误对象
+#[derive(Serialize)]
+struct JsonError {
+    code: String,         // io::ErrorKind
+    message: String,      // 错误信息
+    path: Option<String>, // 命令的第一个位置参数
+}
+
+/// 移除命令中的--json，返回是否带有--json；| 之后的参数属于管道中的过滤器，不做处理
+fn strip_json_flag(args: &mut Vec<&str>) -> bool {
+    // args[2]为命令名
+    let end = args
+        .iter()
+        .position(|arg| *arg == "|")
+        .unwrap_or(args.len());
+    let len = args.len();
+    let mut i = 0;
+    args.retain(|arg| {
+        i += 1;
+        !(i > 3 && i <= end && *arg == "--json")
+    });
+    args.len() != len
+}
+
+/// 命令是否会修改文件系统，无法解析的命令不会执行，视为不修改
+fn is_mutating(args: &[&str]) -> bool {
+    let Some(commands) = args.get(2..) else {
+        return false;
+    };
+    let commands: Vec<String> = commands.iter().map(|arg| arg.to_string()).collect();
+    pipeline::split(&commands)
+        .and_then(|(commands, _)| args::parse(&commands))
+        .is_ok_and(|parsed| parsed.is_mutating())
+}
+
+/// 获取命令的第一个位置参数，作为错误对象的path
+fn get_error_path(args: &[&str]) -> Option<String> {
+    let commands: Vec<String> = args.get(2..)?.iter().map(|arg| arg.to_string()).collect();
+    let (commands, _) = pipeline::split(&commands).ok()?;
+    args::parse(&commands)
+        .ok()?
+        .arg(0)
+        .map(|arg| arg.to_string())
+}
+
+fn to_json_error(err: &io::Error, path: Option<String>) -> String {
+    let error = JsonError {
+        code: format!("{:?}", err.kind()),
+        message: err.to_string(),
+        path,
+    };
+    serde_json::to_string(&error).unwrap_or_else(|_| err.to_string())
+}
+
+/// 批量命令的结果汇总，单项失败不影响其他项
+#[derive(Default)]
+struct BatchReport {
+    outputs: Vec<String>,  // 成功项的输出
+    succeeded: usize,      // 成功的项数
+    warnings: Vec<String>, // 没有执行但不算失败的项，如已被删除的文件
+    errors: Vec<String>,   // 失败的项及原因
+}
+
+impl BatchReport {
+    /// 记录一项的结果，找不到的文件（可能已被其他连接删除）视为警告
+    fn record(&mut self, cmd: &str, view_path: &str, result: io::Result<Option<String>>) {
+        match result {
+            Ok(output) => {
+                self.succeeded += 1;
+                self.outputs
+                    .push(output.unwrap_or_else(|| [cmd, " ", view_path].concat()));
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.warnings.push(format!("{} {}: {}", cmd, view_path, e))
+            }
+            Err(e) => self.errors.push(format!("{} {}: {}", cmd, view_path, e)),
+        }
+    }
+
+    /// 先输出成功项的输出，再按错误、警告分级汇总；有失败项时整个报告作为错误返回，
+    /// client据此以非0退出码结束
+    fn render(self) -> io::Result<String> {
+        let failed = !self.errors.is_empty();
+        let mut lines = self.outputs;
+        lines.push(format!(
+            "{} succeeded, {} warnings, {} failed",
+            self.succeeded,
+            self.warnings.len(),
+            self.errors.len()
+        ));
+        lines.extend(self.errors.iter().map(|e| ["error: ", e].concat()));
+        lines.extend(self.warnings.iter().map(|w| ["warning: ", w].concat()));
+        let report = lines.join("\n");
+        if failed {
+            return Err(io::Error::other(report));
         }
+        Ok(report)
     }
 }
 
+/// 对每个路径执行命令，出错时记录错误并继续，返回所有输出和结果汇总
+async fn exec_on_paths(
+    cmd: &str,
+    paths: &[String],
+    username: &str,
+    trash_path: &str,
+    root: Option<&str>,
+) -> io::Result<String> {
+    let mut report = BatchReport::default();
+    for path in paths {
+        let result = match cmd {
+            "del" => syscall::del(username, path, trash_path).await.map(|_| None),
+            "cat" => syscall::cat(path).await,
+            "du" => syscall::du(path, false).await,
+            _ => Err(error_arg()),
+        };
+        report.record(cmd, &to_view_path(root, path), result);
+    }
+    report.render()
+}
+
 async fn login(user: &[&str], socket: &mut TcpStream) -> Result<(), ()> {
     let fs = Arc::clone(&SFS);
     let mut fs_write_lock = fs.write().await;
@@ -249,13 +842,18 @@ async fn login(user: &[&str], socket: &mut TcpStream) -> Result<(), ()> {
 }
 
 async fn regist(user: &[&str], socket: &mut TcpStream) {
+    // 用户信息较多时会申请数据块，需要和其他写命令互斥
+    let _lock = fs_lock::write().await;
     let fs = Arc::clone(&SFS);
-    let mut fs_write_lock = fs.write().await;
-    if let Err(e) = fs_write_lock.sign_up(user[0], user[1]).await {
+    let result = fs.write().await.sign_up(user[0], user[1]).await;
+    if let Err(e) = result {
         // 回信client注册失败
         socket.write_all(e.to_string().as_bytes()).await.unwrap();
         return;
     }
+    if block::is_sync_immediately().await {
+        sync_all_block_cache().await.unwrap();
+    }
     info!("user: {} signed up", user[0]);
     // 0.2.2 回信成功
     socket.write_all(REGIST_SUCCESS.as_bytes()).await.unwrap();
@@ -272,8 +870,296 @@ fn get_absolute_path(cwd: &str, path: &str) -> String {
     if path.starts_with('~') {
         // 绝对路径
         path.to_string()
+    } else if let Some(path) = path.strip_prefix('/') {
+        // 以/开头的绝对路径，/对应根目录~
+        if path.is_empty() {
+            String::from("~")
+        } else {
+            ["~/", path].concat()
+        }
     } else {
         // 相对路径
         [cwd, "/", path].concat()
     }
 }
+
+/// 按路径分段消去.和..，..不会越过根目录~
+fn normalize_path(path: &str) -> String {
+    let mut paths = vec!["~"];
+    for name in path.split('/').skip(1) {
+        match name {
+            "" | "." => {}
+            ".." => {
+                if paths.len() > 1 {
+                    paths.pop();
+                }
+            }
+            _ => paths.push(name),
+        }
+    }
+    paths.join("/")
+}
+
+/// 将用户看到的路径转换为文件系统中真实的路径
+fn to_real_path(root: Option<&str>, path: &str) -> String {
+    match root {
+        Some(root) => [root, &path[1..]].concat(),
+        None => path.to_string(),
+    }
+}
+
+/// 将文件系统中真实的路径转换为用户看到的路径
+fn to_view_path(root: Option<&str>, path: &str) -> String {
+    match root.and_then(|root| path.strip_prefix(root)) {
+        Some(path) => ["~", path].concat(),
+        None => path.to_string(),
+    }
+}
+
+/// 解析以字节为单位的大小，支持K、M、G后缀，如 512K、10M、1G
+fn parse_size(size: &str) -> io::Result<u64> {
+    let (number, unit) = match size.to_uppercase().chars().last() {
+        Some('K') => (&size[..size.len() - 1], 1024),
+        Some('M') => (&size[..size.len() - 1], 1024 * 1024),
+        Some('G') => (&size[..size.len() - 1], 1024 * 1024 * 1024),
+        _ => (size, 1),
+    };
+    // 数字本身不合法或乘上单位后溢出都视为无效的大小
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(unit))
+        .ok_or(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid size: {}", size),
+        ))
+}
+
+/// 解析 start:len 形式的字节范围，start和len都支持K、M、G单位
+fn parse_range(range: &str) -> io::Result<(usize, usize)> {
+    let (start, len) = range.split_once(':').ok_or(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid range: {}, expected start:len", range),
+    ))?;
+    Ok((parse_size(start)? as usize, parse_size(len)? as usize))
+}
+
+/// 路径的最后一段是否含有通配符
+fn has_wildcard(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.contains('*') || name.contains('?')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{add_user, exec, exec_as, mktree, setup};
+
+    #[test]
+    fn slash_paths_are_absolute() {
+        assert_eq!(get_absolute_path("~/b", "/a"), "~/a");
+        assert_eq!(get_absolute_path("~/b", "/"), "~");
+        assert_eq!(get_absolute_path("~/b", "~/a/x.txt"), "~/a/x.txt");
+        assert_eq!(get_absolute_path("~/b", "a"), "~/b/a");
+        assert_eq!(normalize_path(&get_absolute_path("~/b", "/a/../c")), "~/c");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn slash_paths_resolve_from_root() {
+        let _fs = setup().await;
+        mktree(&["a/", "b/"]).await;
+        exec_as("root", "~/b", "cd /a", &[]).await.unwrap();
+        assert!(exec_as("root", "~/b", "cd /missing", &[]).await.is_err());
+
+        exec_as("root", "~/b", "newfile /a/x.txt", &["from b"])
+            .await
+            .unwrap();
+        assert_eq!(exec("cat ~/a/x.txt").await.unwrap().unwrap(), "from b");
+        assert_eq!(
+            exec_as("root", "~/a", "cat x.txt", &[])
+                .await
+                .unwrap()
+                .unwrap(),
+            "from b"
+        );
+
+        // 混用/、~和相对路径
+        exec_as("root", "~/b", "copy /a/x.txt ~/b/y.txt", &[])
+            .await
+            .unwrap();
+        assert_eq!(
+            exec_as("root", "~/b", "cat y.txt", &[])
+                .await
+                .unwrap()
+                .unwrap(),
+            "from b"
+        );
+        let listing = exec_as("root", "~/a", "dir /b", &[])
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(listing.contains("y.txt"), "{}", listing);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn jailed_user_cannot_leave_own_root() {
+        let _fs = setup().await;
+        mktree(&["secret.txt:root only", "home/alice/notes.txt:mine"]).await;
+        add_user("alice").await;
+        exec("chroot alice ~/home/alice").await.unwrap();
+
+        let alice = |line: &'static str| exec_as("alice", "~", line, &[]);
+        // 专属根目录的上级仍是专属根目录
+        for path in ["..", "../..", "/", "~", "/../.."] {
+            exec_as("alice", "~", &format!("cd {}", path), &[])
+                .await
+                .unwrap();
+            let listing = exec_as("alice", "~", &format!("dir {}", path), &[])
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(listing.contains("notes.txt"), "{}: {}", path, listing);
+            assert!(!listing.contains("home"), "{}: {}", path, listing);
+        }
+        assert_eq!(alice("cat notes.txt").await.unwrap().unwrap(), "mine");
+        assert!(alice("cat ../secret.txt").await.is_err());
+        assert!(alice("cat ~/secret.txt").await.is_err());
+        assert!(alice("cd home").await.is_err());
+
+        // 取消限制后可以访问整个文件系统
+        exec("chroot alice").await.unwrap();
+        assert_eq!(
+            alice("cat ~/home/alice/notes.txt").await.unwrap().unwrap(),
+            "mine"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn find_exec_del_removes_every_match() {
+        let _fs = setup().await;
+        mktree(&[
+            "a.log:1",
+            "keep.txt:2",
+            "logs/b.log:3",
+            "logs/deep/c.log:4",
+            "logs/deep/d.txt:5",
+        ])
+        .await;
+
+        exec("find ~ -name *.log -exec del").await.unwrap();
+        for path in ["a.log", "logs/b.log", "logs/deep/c.log"] {
+            assert!(exec(&format!("cat {}", path)).await.is_err(), "{}", path);
+        }
+        assert_eq!(exec("cat keep.txt").await.unwrap().unwrap(), "2");
+        assert_eq!(exec("cat logs/deep/d.txt").await.unwrap().unwrap(), "5");
+        let found = exec("find ~ -name *.log").await.unwrap().unwrap();
+        // 删除的文件只在回收站中
+        for line in found.lines() {
+            assert!(line.starts_with("~/.trash"), "{}", found);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn del_glob_removes_only_matches() {
+        let _fs = setup().await;
+        mktree(&["d/a.txt:1", "d/b.txt:2", "d/c.md:3", "d/sub/"]).await;
+
+        let report = exec("del d/*.txt").await.unwrap().unwrap();
+        assert!(
+            report.contains("a.txt") && report.contains("b.txt"),
+            "{}",
+            report
+        );
+        assert!(!report.contains("c.md"), "{}", report);
+        assert!(exec("cat d/a.txt").await.is_err());
+        assert!(exec("cat d/b.txt").await.is_err());
+        assert_eq!(exec("cat d/c.md").await.unwrap().unwrap(), "3");
+
+        // 通配符不会匹配.和..，只匹配到c.md和sub
+        let report = exec("cat d/*").await.unwrap_err().to_string();
+        assert!(
+            report.contains("1 succeeded, 0 warnings, 1 failed"),
+            "{}",
+            report
+        );
+        assert!(report.contains("~/d/sub"), "{}", report);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn partially_failed_del_lists_every_item() {
+        let _fs = setup().await;
+        mktree(&["d/a.txt:1", "d/b.txt:2", "d/sub/"]).await;
+
+        let report = exec("del d/*").await.unwrap_err().to_string();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines.len(), 4, "{}", report);
+        let mut succeeded = lines[..2].to_vec();
+        succeeded.sort();
+        assert_eq!(succeeded, ["del ~/d/a.txt", "del ~/d/b.txt"]);
+        assert_eq!(lines[2], "2 succeeded, 0 warnings, 1 failed");
+        assert!(lines[3].starts_with("error: del ~/d/sub: "), "{}", report);
+        // 失败项不影响其他项
+        assert!(exec("cat d/a.txt").await.is_err());
+        assert!(exec("dir d/sub").await.is_ok());
+    }
+
+    #[test]
+    fn batch_report_groups_errors_and_warnings() {
+        let mut report = BatchReport::default();
+        let err = |kind, msg| Err(io::Error::new(kind, msg));
+        report.record("del", "~/a", Ok(None));
+        report.record("del", "~/b", err(io::ErrorKind::NotFound, "gone"));
+        report.record("del", "~/c", err(io::ErrorKind::PermissionDenied, "denied"));
+        report.record("del", "~/d", Ok(None));
+        let expected = [
+            "del ~/a",
+            "del ~/d",
+            "2 succeeded, 1 warnings, 1 failed",
+            "error: del ~/c: denied",
+            "warning: del ~/b: gone",
+        ];
+        assert_eq!(
+            report.render().unwrap_err().to_string(),
+            expected.join("\n")
+        );
+
+        // 只有警告时不算失败
+        let mut report = BatchReport::default();
+        report.record("cat", "~/a", Ok(Some("content".to_string())));
+        report.record("cat", "~/b", err(io::ErrorKind::NotFound, "gone"));
+        assert_eq!(
+            report.render().unwrap(),
+            "content\n1 succeeded, 1 warnings, 0 failed\nwarning: cat ~/b: gone"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cat_concatenates_files_past_a_missing_one() {
+        let _fs = setup().await;
+        mktree(&["a.txt:first", "d/b.txt:second", "c.txt:third"]).await;
+
+        let output = exec("cat a.txt missing.txt d/b.txt c.txt")
+            .await
+            .unwrap()
+            .unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "first",
+                "second",
+                "third",
+                "3 succeeded, 1 warnings, 0 failed",
+                "warning: cat ~/missing.txt: no such file",
+            ],
+            "{}",
+            output
+        );
+
+        // 读取失败的文件单独列出，其余文件照常输出
+        let report = exec("cat a.txt d c.txt").await.unwrap_err().to_string();
+        assert!(report.starts_with("first\nthird\n2 succeeded, 0 warnings, 1 failed\n"));
+        assert!(report.contains("error: cat ~/d: "), "{}", report);
+        assert!(exec("cat --range 0:1 a.txt c.txt").await.is_err());
+    }
+}
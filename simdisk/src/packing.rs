@@ -0,0 +1,163 @@
+//! 小文件打包（tail packing）
+//!
+//! 大小不超过`PACK_MAX_SIZE`的文件不单独占用数据块，而是和其他小文件共用一个打包块，
+//! inode的addr[0]记录打包块号，addr[1]记录内容在块内的起始字节
+use std::{collections::HashMap, io::Error, sync::Arc};
+
+use tokio::sync::RwLock;
+
+use crate::{
+    bitmap::{self, alloc_bit, BitmapType},
+    block::{get_block_buffer, write_bytes_to_block},
+    fs_constants::*,
+    inode::Inode,
+};
+
+/// 打包块的使用情况
+#[derive(Default, Debug)]
+struct PackBlock {
+    used: usize, // 已经使用到的字节位置
+    refs: usize, // 打包在该块中的文件数
+}
+
+#[derive(Default)]
+pub struct PackManager {
+    blocks: HashMap<usize, PackBlock>,
+}
+
+impl PackManager {
+    /// 为len字节的内容找到打包块中的位置，返回(块号, 块内偏移)
+    async fn alloc(&mut self, len: usize) -> Result<(usize, usize), Error> {
+        let found = self
+            .blocks
+            .iter()
+            .find(|(_, block)| block.used + len <= BLOCK_SIZE)
+            .map(|(block_id, _)| *block_id);
+        let block_id = match found {
+            Some(block_id) => block_id,
+            None => {
+                // 没有空余的打包块，申请一个新的
+                let block_id = alloc_bit(BitmapType::Data).await? as usize + DATA_START_BLOCK;
+                self.blocks.insert(block_id, PackBlock::default());
+                block_id
+            }
+        };
+        let block = self.blocks.get_mut(&block_id).unwrap();
+        let offset = block.used;
+        block.used += len;
+        block.refs += 1;
+        Ok((block_id, offset))
+    }
+
+    /// 移除一个打包的文件，块中没有文件时释放整个块
+    async fn dealloc(&mut self, block_id: usize) {
+        if let Some(block) = self.blocks.get_mut(&block_id) {
+            block.refs -= 1;
+            if block.refs == 0 {
+                self.blocks.remove(&block_id);
+                bitmap::dealloc_data_bit(block_id).await;
+            }
+        }
+    }
+
+    /// 记录一个已经存在的打包文件
+    fn record(&mut self, block_id: usize, end: usize) {
+        let block = self.blocks.entry(block_id).or_default();
+        block.used = block.used.max(end);
+        block.refs += 1;
+    }
+}
+
+/// 文件是否应该打包存储
+pub fn should_pack(size: usize) -> bool {
+    size <= PACK_MAX_SIZE
+}
+
+/// 为len字节的内容申请打包位置
+pub async fn alloc(len: usize) -> Result<(usize, usize), Error> {
+    Arc::clone(&PACK_MANAGER).write().await.alloc(len).await
+}
+
+/// 移除打包在block_id中的一个文件
+pub async fn dealloc(block_id: usize) {
+    Arc::clone(&PACK_MANAGER)
+        .write()
+        .await
+        .dealloc(block_id)
+        .await
+}
+
+/// 将打包文件的内容写入打包块
+pub async fn write_packed(inode: &Inode, content: &[u8]) -> Result<(), Error> {
+    let (block_id, offset) = inode.get_packed_location();
+    write_bytes_to_block(content, block_id, offset).await
+}
+
+/// 读取打包文件的内容
+pub async fn read_packed(inode: &Inode) -> Result<Vec<u8>, Error> {
+    let (block_id, offset) = inode.get_packed_location();
+    get_block_buffer(block_id, offset, offset + inode.get_size()).await
+}
+
+/// 扫描所有inode，重建打包块的使用情况
+pub async fn rebuild() -> Result<(), Error> {
+    let mut manager = PackManager::default();
+    let inode_bitmap = bitmap::get_inode_bitmaps().await;
+    for (i, byte) in inode_bitmap.iter().enumerate() {
+        for j in 0..8 {
+            if !byte.get(j) {
+                continue;
+            }
+            let inode = Inode::read(i * 8 + j).await?;
+            if inode.is_packed() {
+                let (block_id, offset) = inode.get_packed_location();
+                manager.record(block_id, offset + inode.get_size());
+            }
+        }
+    }
+    trace!("rebuild {} pack blocks", manager.blocks.len());
+    *Arc::clone(&PACK_MANAGER).write().await = manager;
+    Ok(())
+}
+
+/// 清空打包块记录，用于格式化
+pub async fn clear() {
+    *Arc::clone(&PACK_MANAGER).write().await = PackManager::default();
+}
+
+//延迟加载全局变量 PACK_MANAGER
+lazy_static! {
+    pub static ref PACK_MANAGER: Arc<RwLock<PackManager>> =
+        Arc::new(RwLock::new(PackManager::default()));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{exec, mktree, setup, used_blocks};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn small_files_share_blocks() {
+        let _fs = setup().await;
+        mktree(&["small/"]).await;
+        let before = used_blocks().await;
+        let files: Vec<_> = (0..100)
+            .map(|i| format!("small/f{}.txt:content of file {}", i, i))
+            .collect();
+        mktree(&files.iter().map(|file| file.as_str()).collect::<Vec<_>>()).await;
+        let used = used_blocks().await - before;
+        // 包括目录项增加的块在内，也远少于文件数
+        assert!(used < 20, "100 small files use {} blocks", used);
+        for i in [0, 42, 99] {
+            let content = exec(&format!("cat small/f{}.txt", i)).await.unwrap();
+            assert_eq!(content.unwrap(), format!("content of file {}", i));
+        }
+        let stat = exec("stat small/f7.txt").await.unwrap().unwrap();
+        assert!(stat.contains("packed"), "{}", stat);
+
+        // 删除其中一个文件不影响同一块中的其他文件
+        exec("del small/f41.txt").await.unwrap();
+        exec("emptytrash").await.unwrap();
+        let content = exec("cat small/f42.txt").await.unwrap();
+        assert_eq!(content.unwrap(), "content of file 42");
+    }
+}
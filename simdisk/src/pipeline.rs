@@ -0,0 +1,373 @@
+//! server内置的命令管道
+//!
+//! 如 `cat f | grep x | wc -l`，第一段为普通命令，之后各段为内置的过滤器，
+//! 在server内依次处理前一段的输出，只把最终结果返回给client；
+//! cat的输出还可以交给白名单中的宿主程序，如 `cat f | <host>md5sum`
+use std::{
+    io::{Error, ErrorKind},
+    process::Stdio,
+    time::Duration,
+};
+
+use tokio::{io::AsyncWriteExt, process::Command, time::timeout};
+
+use crate::args::{self, CommandSpec, ParsedArgs};
+
+/// 内置过滤器的参数定义
+const FILTER_SPECS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "grep",
+        usage: "grep (-v) (-i) (-c) [pattern]",
+        flags: &["-v", "-i", "-c"],
+        options: &[],
+        min_args: 1,
+        max_args: Some(1),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "wc",
+        usage: "wc (-l) (-w) (-c)",
+        flags: &["-l", "-w", "-c"],
+        options: &[],
+        min_args: 0,
+        max_args: Some(0),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "head",
+        usage: "head (-n lines)",
+        flags: &[],
+        options: &["-n"],
+        min_args: 0,
+        max_args: Some(0),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "tail",
+        usage: "tail (-n lines)",
+        flags: &[],
+        options: &["-n"],
+        min_args: 0,
+        max_args: Some(0),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "sort",
+        usage: "sort (-r)",
+        flags: &["-r"],
+        options: &[],
+        min_args: 0,
+        max_args: Some(0),
+        mutating: false,
+    },
+    CommandSpec {
+        name: "uniq",
+        usage: "uniq (-c)",
+        flags: &["-c"],
+        options: &[],
+        min_args: 0,
+        max_args: Some(0),
+        mutating: false,
+    },
+];
+
+const DEFAULT_LINES: usize = 10; // head、tail默认输出的行数
+
+/// 可以用 `| <host>程序名` 调用的宿主程序及其固定的命令行，不接受用户给出的参数，
+/// 只从标准输入读取内容，不会读写宿主上的文件
+const HOST_PROGRAMS: &[(&str, &[&str])] = &[
+    ("sort", &["sort"]),
+    ("uniq", &["uniq"]),
+    ("wc", &["wc"]),
+    ("md5sum", &["md5sum"]),
+    ("sha256sum", &["sha256sum"]),
+    ("base64", &["base64"]),
+];
+const HOST_PREFIX: &str = "<host>";
+const HOST_PATH: &str = "/usr/bin:/bin"; // 查找宿主程序的路径，不受server环境变量影响
+const HOST_TIMEOUT: Duration = Duration::from_secs(5); // 宿主程序的最长运行时间
+
+/// 按 | 拆分命令，返回第一段命令和之后解析好的过滤器
+pub fn split(commands: &[String]) -> Result<(Vec<String>, Vec<ParsedArgs>), Error> {
+    let mut segments = commands.split(|arg| arg == "|");
+    let first = segments.next().unwrap_or_default().to_vec();
+    let mut filters = Vec::new();
+    for segment in segments {
+        let name = segment.first().ok_or(Error::new(
+            ErrorKind::InvalidInput,
+            "missing command after |",
+        ))?;
+        if let Some(program) = name.strip_prefix(HOST_PREFIX) {
+            filters.push(parse_host(program, segment)?);
+            continue;
+        }
+        let spec = FILTER_SPECS
+            .iter()
+            .find(|spec| spec.name == name)
+            .ok_or(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{} cannot be used in pipeline, available: {}",
+                    name,
+                    FILTER_SPECS
+                        .iter()
+                        .map(|spec| spec.name)
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                ),
+            ))?;
+        filters.push(args::parse_with(spec, segment)?);
+    }
+    Ok((first, filters))
+}
+
+/// 检查宿主程序在白名单中且没有参数
+fn parse_host(program: &str, segment: &[String]) -> Result<ParsedArgs, Error> {
+    if !HOST_PROGRAMS.iter().any(|(name, _)| *name == program) {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "{}{} is not allowed, available: {}",
+                HOST_PREFIX,
+                program,
+                HOST_PROGRAMS
+                    .iter()
+                    .map(|(name, _)| [HOST_PREFIX, name].concat())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+        ));
+    }
+    if segment.len() > 1 {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{}{}: arguments are not allowed", HOST_PREFIX, program),
+        ));
+    }
+    Ok(ParsedArgs {
+        name: segment[0].to_owned(),
+        ..Default::default()
+    })
+}
+
+/// 管道中是否有宿主程序
+pub fn has_host_program(filters: &[ParsedArgs]) -> bool {
+    filters
+        .iter()
+        .any(|filter| filter.name.starts_with(HOST_PREFIX))
+}
+
+/// 依次用过滤器处理输入，返回最终的输出
+pub async fn run(filters: &[ParsedArgs], input: String) -> Result<String, Error> {
+    let mut output = input;
+    for filter in filters {
+        output = match filter.name.strip_prefix(HOST_PREFIX) {
+            Some(program) => run_host(program, output).await?,
+            None => run_filter(filter, &output)?,
+        };
+    }
+    Ok(output)
+}
+
+/// 以固定的命令行运行宿主程序，input作为其标准输入，返回标准输出；
+/// 不继承server的环境变量和工作目录，超时后结束程序
+async fn run_host(program: &str, input: String) -> Result<String, Error> {
+    // split中已经检查过白名单
+    let (_, argv) = HOST_PROGRAMS
+        .iter()
+        .find(|(name, _)| *name == program)
+        .unwrap();
+    let mut child = Command::new(argv[0])
+        .args(&argv[1..])
+        .env_clear()
+        .env("PATH", HOST_PATH)
+        .env("LC_ALL", "C")
+        .current_dir("/")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    // 边写入边读取输出，避免管道写满后互相等待
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = tokio::spawn(async move { stdin.write_all(input.as_bytes()).await });
+    let output = timeout(HOST_TIMEOUT, child.wait_with_output())
+        .await
+        .map_err(|_| {
+            Error::new(
+                ErrorKind::TimedOut,
+                format!("{}{}: timed out", HOST_PREFIX, program),
+            )
+        })??;
+    // 程序可以不读完输入就退出，写入失败不影响结果
+    let _ = writer.await;
+    if !output.status.success() {
+        return Err(Error::other(format!(
+            "{}{}: {}",
+            HOST_PREFIX,
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn run_filter(filter: &ParsedArgs, input: &str) -> Result<String, Error> {
+    let lines = input.lines();
+    let output = match filter.name.as_str() {
+        "grep" => {
+            let ignore_case = filter.has_flag("-i");
+            let pattern = if ignore_case {
+                filter.arg(0).unwrap().to_lowercase()
+            } else {
+                filter.arg(0).unwrap().to_string()
+            };
+            let invert = filter.has_flag("-v");
+            let matched: Vec<_> = lines
+                .filter(|line| {
+                    let found = if ignore_case {
+                        line.to_lowercase().contains(&pattern)
+                    } else {
+                        line.contains(&pattern)
+                    };
+                    found != invert
+                })
+                .collect();
+            if filter.has_flag("-c") {
+                matched.len().to_string()
+            } else {
+                matched.join("\n")
+            }
+        }
+        "wc" => {
+            let counts = [
+                ("-l", lines.count()),
+                ("-w", input.split_whitespace().count()),
+                ("-c", input.len()),
+            ];
+            // 没有指定时输出行数、单词数和字节数
+            let all = counts.iter().all(|(flag, _)| !filter.has_flag(flag));
+            counts
+                .iter()
+                .filter(|(flag, _)| all || filter.has_flag(flag))
+                .map(|(_, count)| count.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+        "head" | "tail" => {
+            let n = match filter.option("-n") {
+                Some(n) => n.parse::<usize>().map_err(|_| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{}: invalid number of lines: {}", filter.name, n),
+                    )
+                })?,
+                None => DEFAULT_LINES,
+            };
+            let lines: Vec<_> = lines.collect();
+            let output = if filter.name == "head" {
+                &lines[..n.min(lines.len())]
+            } else {
+                &lines[lines.len().saturating_sub(n)..]
+            };
+            output.join("\n")
+        }
+        "sort" => {
+            let mut lines: Vec<_> = lines.collect();
+            lines.sort_unstable();
+            if filter.has_flag("-r") {
+                lines.reverse();
+            }
+            lines.join("\n")
+        }
+        "uniq" => {
+            // 只合并相邻的重复行
+            let mut groups: Vec<(&str, usize)> = Vec::new();
+            for line in lines {
+                match groups.last_mut() {
+                    Some((last, count)) if *last == line => *count += 1,
+                    _ => groups.push((line, 1)),
+                }
+            }
+            groups
+                .iter()
+                .map(|(line, count)| {
+                    if filter.has_flag("-c") {
+                        format!("{:>7} {}", count, line)
+                    } else {
+                        line.to_string()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown filter: {}", filter.name),
+            ))
+        }
+    };
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{exec, mktree, setup};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cat_pipes_to_whitelisted_host_programs() {
+        let _fs = setup().await;
+        mktree(&["notes.txt:b\na\nc\n"]).await;
+        let sorted = exec("cat notes.txt | <host>sort").await.unwrap().unwrap();
+        assert_eq!(sorted, "a\nb\nc\n");
+        let counted = exec("cat notes.txt | <host>sort | <host>wc")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            counted.split_whitespace().collect::<Vec<_>>(),
+            ["3", "3", "6"]
+        );
+        // 和内置过滤器组合
+        let first = exec("cat notes.txt | <host>sort | head -n 1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first, "a");
+        assert_eq!(
+            exec("cat notes.txt | sort").await.unwrap().unwrap(),
+            "a\nb\nc"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn host_programs_run_only_as_whitelisted() {
+        let _fs = setup().await;
+        mktree(&["notes.txt:b\na\nc\n"]).await;
+        let refused = |line: &str, reason: &str| {
+            let line = line.to_string();
+            let reason = reason.to_string();
+            async move {
+                let err = exec(&line).await.unwrap_err();
+                assert!(err.to_string().contains(&reason), "{}: {}", line, err);
+            }
+        };
+        // 不在白名单中、带路径或带参数的程序都不会启动
+        refused("cat notes.txt | <host>sh", "is not allowed").await;
+        refused("cat notes.txt | <host>/usr/bin/sort", "is not allowed").await;
+        refused(
+            "cat notes.txt | <host>sort -o /tmp/out",
+            "arguments are not allowed",
+        )
+        .await;
+        refused(
+            "cat notes.txt | <host>wc /etc/passwd",
+            "arguments are not allowed",
+        )
+        .await;
+        // 只有cat的输出可以交给宿主程序
+        refused("dir | <host>sort", "only cat").await;
+        refused("cat notes.txt | sh", "cannot be used in pipeline").await;
+    }
+}
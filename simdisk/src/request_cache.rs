@@ -0,0 +1,42 @@
+//! 已执行请求的结果缓存
+//!
+//! client 重发带有相同请求id的命令时，直接返回缓存的结果而不是重复执行
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+use tokio::sync::RwLock;
+
+use crate::fs_constants::REQUEST_CACHE_SIZE;
+
+#[derive(Default)]
+pub struct RequestCache {
+    results: HashMap<String, Option<String>>, // 请求id -> 执行结果
+    order: VecDeque<String>,                  // 按插入顺序记录请求id，用于淘汰最旧的结果
+}
+
+impl RequestCache {
+    /// 获取已执行请求的结果，未执行过则返回None
+    pub fn get(&self, request_id: &str) -> Option<Option<String>> {
+        self.results.get(request_id).cloned()
+    }
+
+    /// 记录请求的执行结果，超出容量时淘汰最旧的结果
+    pub fn insert(&mut self, request_id: String, result: Option<String>) {
+        if self.results.insert(request_id.clone(), result).is_none() {
+            self.order.push_back(request_id);
+        }
+        while self.order.len() > REQUEST_CACHE_SIZE {
+            if let Some(oldest) = self.order.pop_front() {
+                self.results.remove(&oldest);
+            }
+        }
+    }
+}
+
+//延迟加载全局变量 REQUEST_CACHE
+lazy_static! {
+    pub static ref REQUEST_CACHE: Arc<RwLock<RequestCache>> =
+        Arc::new(RwLock::new(RequestCache::default()));
+}
@@ -0,0 +1,73 @@
+//! 在线会话
+//!
+//! 记录每个已登录连接的用户和登录时间，连接登出或断开时移除，root可以用sessions命令查看
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::SystemTime};
+
+use tokio::sync::RwLock;
+
+struct SessionInfo {
+    username: String,
+    login_time: u64, // 登录时的时间戳（秒）
+}
+
+#[derive(Default)]
+pub struct Sessions {
+    sessions: HashMap<SocketAddr, SessionInfo>, // 连接的对端地址 -> 会话信息
+}
+
+impl Sessions {
+    /// 按登录时间列出所有会话，current为发出命令的连接
+    fn list(&self, current: Option<SocketAddr>) -> String {
+        let mut sessions: Vec<_> = self.sessions.iter().collect();
+        sessions.sort_by_key(|(addr, info)| (info.login_time, **addr));
+        let mut lines = vec!["ADDR\tUSER\tLOGIN".to_string()];
+        for (addr, info) in sessions {
+            let login_time = chrono::DateTime::from_timestamp(info.login_time as i64, 0)
+                .map_or("?".to_string(), |time| {
+                    time.format("%Y-%m-%d %H:%M:%S").to_string()
+                });
+            let mark = if Some(*addr) == current {
+                " (current)"
+            } else {
+                ""
+            };
+            lines.push(format!(
+                "{}\t{}\t{}{}",
+                addr, info.username, login_time, mark
+            ));
+        }
+        lines.push(format!("{} sessions", self.sessions.len()));
+        lines.join("\n")
+    }
+}
+
+/// 连接登录成功后记录会话，同一连接重新登录时覆盖原来的会话
+pub async fn login(addr: SocketAddr, username: &str) {
+    let login_time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let info = SessionInfo {
+        username: username.to_owned(),
+        login_time,
+    };
+    Arc::clone(&SESSIONS)
+        .write()
+        .await
+        .sessions
+        .insert(addr, info);
+}
+
+/// 连接登出（如格式化后需要重新登录）或断开时移除会话
+pub async fn logout(addr: SocketAddr) {
+    Arc::clone(&SESSIONS).write().await.sessions.remove(&addr);
+}
+
+/// 列出所有在线会话
+pub async fn list(current: Option<SocketAddr>) -> String {
+    Arc::clone(&SESSIONS).read().await.list(current)
+}
+
+//延迟加载全局变量 SESSIONS
+lazy_static! {
+    pub static ref SESSIONS: Arc<RwLock<Sessions>> = Arc::new(RwLock::new(Sessions::default()));
+}
@@ -1,15 +1,23 @@
 #[allow(unused)]
-use log::{debug, error, info, trace};
-use std::{fs::File, io::Error, sync::Arc};
+use log::{debug, error, info, trace, warn};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Error, ErrorKind},
+    sync::Arc,
+};
 use tokio::sync::RwLock;
 
 use crate::{
-    bitmap::{count_data_blocks, count_inodes, BITMAP_MANAGER},
+    bitmap::{self, count_data_blocks, count_inodes, BitmapType, BITMAP_MANAGER},
     block::{self, BLOCK_CACHE_MANAGER},
+    config::CONFIG,
+    device,
+    dirent::{self, DirEntry},
     fs_constants::*,
-    inode::{self, Inode},
+    inode::{self, Inode, INODE_CACHE},
+    packing,
     super_block::SuperBlock,
-    user::{User, UserIdGroup, UserIdType, UserInfo},
+    user::{self, User, UserIdGroup, UserIdType, UserInfo},
 };
 
 #[allow(unused)]
@@ -36,8 +44,36 @@ impl SimpleFileSystem {
     }
     ///初始化SFS
     pub async fn init(&mut self) -> Result<(), Error> {
+        // 布局不同的镜像无法正确读取，也不能直接格式化覆盖；
+        // 先只读出版本号，避免按当前的结构反序列化旧布局时在深处失败
+        if let Some(version) = SuperBlock::read_layout_version().await? {
+            if version != LAYOUT_VERSION {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "unsupported image version {}, expected {}, reformat needed",
+                        version, LAYOUT_VERSION
+                    ),
+                ));
+            }
+        }
         let sp = SuperBlock::read().await?;
         if sp.valid() {
+            // 镜像被截断或由更小的配置创建时，之后读到末尾的块才会失败，启动时就报错
+            let (name, image_size) = Arc::clone(&BLOCK_CACHE_MANAGER)
+                .read()
+                .await
+                .describe_device()?;
+            let fs_size = (sp.get_fs_size() * BLOCK_SIZE) as u64;
+            if image_size < fs_size {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "image size mismatch, reformat needed: {} is {} bytes, expected {} bytes",
+                        name, image_size, fs_size
+                    ),
+                ));
+            }
             self.read().await;
             // 读入位图缓存
             Arc::clone(&BITMAP_MANAGER)
@@ -46,6 +82,12 @@ impl SimpleFileSystem {
                 .read()
                 .await
                 .unwrap();
+            // 申请位置和用户信息共用0号块，旧镜像的用户信息需要先移出；只读模式下不改动镜像
+            if !CONFIG.readonly {
+                self.user_infos.move_out_of_cursor_area().await?;
+            }
+            // 重建小文件打包块的使用情况
+            packing::rebuild().await?;
             trace!("no need to init fs");
             return Ok(());
         }
@@ -54,7 +96,7 @@ impl SimpleFileSystem {
 
     /// 打印文件系统的信息
     pub async fn info(&self) -> String {
-        let (fs_size, fs_unit) = show_unit(DATA_BLOCK_MAX_NUM * BLOCK_SIZE);
+        let (fs_size, fs_unit) = show_unit(bitmap::data_block_num().await * BLOCK_SIZE);
         let (alloced_inodes, valid_inodes) = count_inodes().await;
         let (alloced, valid) = count_data_blocks().await;
         let (used_size, used_unit) = show_unit(alloced * BLOCK_SIZE);
@@ -65,7 +107,7 @@ impl SimpleFileSystem {
             String::from(
                 "Filesystem\tSize\tUsed\tAVail\tUse%\tInodes\tIUsed\tIFree\tIUse%\tMounted on\n",
             ),
-            format!("SimpleFS\t{}{}\t", fs_size, fs_unit,),
+            format!("SimpleFS\t{:.1}{}\t", fs_size, fs_unit,),
             format!(
                 "{:.1}{}\t{:.1}{}\t{:.1}%\t",
                 used_size, used_unit, valid_size, valid_unit, use_percent
@@ -82,13 +124,16 @@ impl SimpleFileSystem {
     /// 强制覆盖一份新的FS文件，可以看作是格式化
     pub async fn force_clear(&mut self) {
         info!("init fs");
-        create_fs_file().unwrap();
-
-        // 单纯清空缓存，不写入本地文件，用于格式化
+        // 清空块设备，并单纯清空缓存，不写入本地文件，用于格式化
         let blk = Arc::clone(&BLOCK_CACHE_MANAGER);
-        blk.write().await.block_cache.clear();
+        blk.write().await.reset_device().unwrap();
+        Arc::clone(&INODE_CACHE).write().await.clear();
+        packing::clear().await;
+
+        // 创建超级块
+        SuperBlock::init().await.unwrap();
 
-        // 读入位图缓存
+        // 按超级块的布局读入位图缓存
         Arc::clone(&BITMAP_MANAGER)
             .write()
             .await
@@ -96,14 +141,19 @@ impl SimpleFileSystem {
             .await
             .unwrap();
 
-        // 创建超级块
-        SuperBlock::init().await;
-
         // 创建root_inode
         let root_inode = Inode::new_root().await;
 
         // 初始化用户信息
-        let user_info = User::init().await;
+        let user_info = User::init().await.unwrap();
+
+        // 位图也写入镜像，格式化后马上停止的镜像仍然可以挂载
+        Arc::clone(&BITMAP_MANAGER)
+            .read()
+            .await
+            .cache_to_block()
+            .await
+            .unwrap();
 
         // 更新缓存
         blk.write().await.sync_and_clear_cache().await.unwrap();
@@ -136,11 +186,6 @@ impl SimpleFileSystem {
         }
     }
 
-    /// 根据uid获取用户名
-    pub fn get_username(&self, uid: UserIdType) -> Result<String, Error> {
-        self.user_infos.get_user_name(uid)
-    }
-
     /// 根据用户名获取id组
     pub fn get_user_ids(&self, username: &str) -> Result<UserIdGroup, Error> {
         let info = self.user_infos.info.get(username).ok_or(Error::new(
@@ -156,15 +201,326 @@ impl SimpleFileSystem {
     }
 }
 
-/// 检查位图对应的区域是否出错
-pub async fn check_bitmaps_and_fix() -> Result<(), Error> {
-    inode::check_inodes_and_fix().await?;
-    block::check_data_and_fix().await
+/// 检查位图对应的区域是否出错，fix为true时把不必要的1修复为0，否则只报告，返回没有修复的问题
+pub async fn check_bitmaps_and_fix(fix: bool) -> Result<Vec<String>, Error> {
+    let inodes = inode::check_inodes_and_fix(fix).await?;
+    let blocks = block::check_data_and_fix(fix).await?;
+    let mut problems = Vec::new();
+    if !fix && !inodes.is_empty() {
+        problems.push(format!("inode bitmap: set for invalid inodes {:?}", inodes));
+    }
+    if !fix && !blocks.is_empty() {
+        problems.push(format!("data bitmap: set for empty blocks {:?}", blocks));
+    }
+    Ok(problems)
+}
+
+/// 检查位图的大小和已申请的数量是否符合超级块记录的各区大小，返回发现的问题，全部一致时为空
+pub async fn check_bitmap_counts() -> Result<Vec<String>, Error> {
+    let sb = SuperBlock::read().await?;
+    let mut problems = Vec::new();
+    let inode_bits = bitmap::bitmap_bits(BitmapType::Inode).await;
+    let expected_inode_bits = sb.get_inode_bitmap_size() * BLOCK_SIZE * 8;
+    if inode_bits != expected_inode_bits || inode_bits != INODE_MAX_NUM {
+        problems.push(format!(
+            "inode bitmap has {} bits, super block declares {}, expected {}",
+            inode_bits, expected_inode_bits, INODE_MAX_NUM
+        ));
+    }
+    // 扩容后数据区会超过DATA_BLOCK_MAX_NUM，以超级块记录的大小为准
+    let data_bits = bitmap::data_block_num().await;
+    if data_bits != sb.data_block_num() {
+        problems.push(format!(
+            "data bitmap has {} bits, super block declares {} data blocks",
+            data_bits,
+            sb.data_block_num()
+        ));
+    }
+    let (alloced_inodes, _) = count_inodes().await;
+    let max_inodes = INODE_MAX_NUM.min(sb.inode_capacity());
+    if alloced_inodes > max_inodes {
+        problems.push(format!(
+            "{} inodes allocated, but at most {} fit in the inode area",
+            alloced_inodes, max_inodes
+        ));
+    }
+    let (alloced_blocks, _) = count_data_blocks().await;
+    if alloced_blocks > sb.data_block_num() {
+        problems.push(format!(
+            "{} data blocks allocated, but the data area has {} blocks",
+            alloced_blocks,
+            sb.data_block_num()
+        ));
+    }
+    Ok(problems)
+}
+
+/// fsck遍历目录树时收集的信息
+#[derive(Default)]
+struct FsckWalk {
+    inode_ids: HashSet<usize>,         // 可达的inode
+    block_bits: HashSet<usize>,        // 可达的数据块在data位图中的位置
+    file_links: HashMap<usize, usize>, // 每个文件inode被多少个目录项指向，应当等于其硬连接数
+    dangling: usize,                   // 指向无效inode的目录项
+    mismatched: usize,                 // 类型和inode不一致的目录项
+}
+
+impl FsckWalk {
+    /// 从根目录遍历整个目录树，重新统计可达的inode和数据块；
+    /// 发现的问题累加计数，repair为true时原地修正目录项的类型
+    async fn walk(&mut self, repair: bool) -> Result<(), Error> {
+        self.inode_ids = HashSet::from([0]);
+        // 存放用户信息的数据块不属于任何inode
+        self.block_bits = user::get_user_blocks()
+            .await?
+            .iter()
+            .map(|id| *id as usize - DATA_START_BLOCK)
+            .collect();
+        self.file_links.clear();
+        let mut stack = vec![Inode::read(0).await?];
+        while let Some(inode) = stack.pop() {
+            for block_id in block::get_owned_block_ids(&inode).await? {
+                self.block_bits.insert(block_id - DATA_START_BLOCK);
+            }
+            if !inode.is_dir() {
+                continue;
+            }
+            for (_, block_id, dirent) in DirEntry::get_all_dirent(&inode).await? {
+                let inode_id = dirent.inode_id as usize;
+                // 跳过.和..
+                if dirent.is_special() {
+                    continue;
+                }
+                let child = if inode_id < INODE_MAX_NUM {
+                    Inode::read(inode_id).await.ok()
+                } else {
+                    None
+                };
+                match child {
+                    Some(child) if child.inode_id as usize == inode_id => {
+                        // 目录项记录的类型以inode为准，硬连接的每个目录项都要检查
+                        if dirent.is_dir != child.is_dir() {
+                            warn!(
+                                "entry {} -> inode {}: is_dir {} but inode is {:?}",
+                                dirent.get_filename(),
+                                inode_id,
+                                dirent.is_dir,
+                                child.inode_type
+                            );
+                            self.mismatched += 1;
+                            if repair {
+                                dirent::fix_is_dir(&dirent, child.is_dir(), block_id).await?;
+                            }
+                        }
+                        if !child.is_dir() {
+                            *self.file_links.entry(inode_id).or_default() += 1;
+                        }
+                        // 已经访问过的硬连接不再重复遍历
+                        if self.inode_ids.insert(inode_id) {
+                            stack.push(child);
+                        }
+                    }
+                    _ => {
+                        warn!(
+                            "dangling entry {} -> inode {}",
+                            dirent.get_filename(),
+                            inode_id
+                        );
+                        self.dangling += 1;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 找出孤立的inode：位图为1、inode有效但不可达，且不在其他孤立目录之下（随上级目录一起找回）
+async fn find_orphans(reachable: &HashSet<usize>) -> Result<Vec<Inode>, Error> {
+    let mut orphans = Vec::new();
+    for inode_id in bitmap::diff_bits(BitmapType::Inode, reachable).await.0 {
+        match Inode::read(inode_id).await {
+            Ok(inode) if inode.inode_id as usize == inode_id => orphans.push(inode),
+            _ => {}
+        }
+    }
+    let mut children = HashSet::new();
+    for inode in orphans.iter().filter(|inode| inode.is_dir()) {
+        for (_, _, dirent) in DirEntry::get_all_dirent(inode).await? {
+            if !dirent.is_special() {
+                children.insert(dirent.inode_id);
+            }
+        }
+    }
+    orphans.retain(|inode| !children.contains(&inode.inode_id));
+    Ok(orphans)
+}
+
+/// 将孤立的inode以inode号为名挂到根目录下的lost+found中，lost+found不存在时创建
+async fn relink_orphans(orphans: &[Inode]) -> Result<(), Error> {
+    let mut root = Inode::read(0).await?;
+    if dirent::lookup(LOST_FOUND_DIR_NAME, &root).await.is_none() {
+        dirent::make_directory(LOST_FOUND_DIR_NAME, &mut root, 0, 0).await?;
+        root = Inode::read(0).await?;
+    }
+    let mut lost_found = dirent::find_inode(LOST_FOUND_DIR_NAME, &root).await?;
+    for orphan in orphans {
+        dirent::check_dir_not_full(&lost_found).await?;
+        let mut dirent = DirEntry::new_temp(&orphan.inode_id.to_string(), "", orphan.is_dir())?;
+        dirent.inode_id = orphan.inode_id;
+        block::insert_object(&dirent, &mut lost_found).await?;
+        if orphan.is_dir() {
+            dirent::set_parent(orphan, &mut lost_found).await?;
+        }
+        info!(
+            "relinked inode {} into {}",
+            orphan.inode_id, LOST_FOUND_DIR_NAME
+        );
+    }
+    Arc::clone(&SFS).write().await.update().await;
+    Ok(())
+}
+
+/// 从根目录遍历整个目录树，按可达的inode和数据块检查位图；
+/// repair为true时按可达性重建位图，回收泄漏的inode和数据块；
+/// 同时为lost_found时先把孤立的inode挂到lost+found下，不回收其中的数据
+pub async fn fsck(repair: bool, lost_found: bool) -> Result<String, Error> {
+    let mut walk = FsckWalk::default();
+    walk.walk(repair).await?;
+    let orphans = find_orphans(&walk.inode_ids).await?;
+    if repair && lost_found && !orphans.is_empty() {
+        relink_orphans(&orphans).await?;
+        // 找回的inode和lost+found本身都变为可达
+        walk.walk(repair).await?;
+    }
+    let FsckWalk {
+        inode_ids,
+        block_bits,
+        file_links,
+        dangling,
+        mismatched,
+    } = walk;
+
+    // 如移动文件中途失败留下的重复目录项，按实际的目录项数修正硬连接数，
+    // 否则删除其中一个目录项时inode会被释放，另一个目录项就失效了
+    let mut bad_links = 0;
+    for (inode_id, links) in file_links {
+        let mut inode = Inode::read(inode_id).await?;
+        if inode.get_nlink() as usize != links {
+            warn!(
+                "inode {} has {} links but {} entries",
+                inode_id,
+                inode.get_nlink(),
+                links
+            );
+            bad_links += 1;
+            if repair {
+                inode.set_nlink(links.min(u8::MAX as usize) as u8).await?;
+            }
+        }
+    }
+
+    let ((leaked_inodes, unmarked_inodes), (leaked_bits, unmarked_bits)) = if repair {
+        let inodes = bitmap::reset_bits(BitmapType::Inode, &inode_ids).await;
+        let blocks = bitmap::reset_bits(BitmapType::Data, &block_bits).await;
+        // 清空回收的块，并丢弃和位图不再一致的缓存
+        let block_ids: Vec<_> = blocks.0.iter().map(|bit| bit + DATA_START_BLOCK).collect();
+        block::clear_blocks(&block_ids).await?;
+        Arc::clone(&INODE_CACHE).write().await.clear();
+        packing::rebuild().await?;
+        if block::is_sync_immediately().await {
+            block::sync_all_block_cache().await?;
+        }
+        (inodes, blocks)
+    } else {
+        (
+            bitmap::diff_bits(BitmapType::Inode, &inode_ids).await,
+            bitmap::diff_bits(BitmapType::Data, &block_bits).await,
+        )
+    };
+    let (leaked, unmarked, fixed) = if repair {
+        ("reclaimed", "marked", "fixed")
+    } else {
+        (
+            "run fsck --repair to reclaim",
+            "run fsck --repair to mark",
+            "run fsck --repair to fix",
+        )
+    };
+    let relinked = match (repair, lost_found) {
+        (true, true) => "relinked into ~/lost+found",
+        (true, false) => "reclaimed",
+        (false, _) => "run fsck --repair --lost-found to relink",
+    };
+    Ok(format!(
+        "reachable: {} inodes, {} blocks\n\
+         leaked: {} inodes, {} blocks ({})\n\
+         in use but not marked: {} inodes, {} blocks ({})\n\
+         dangling entries: {}\n\
+         entry type mismatches: {} ({})\n\
+         link count mismatches: {} ({})\n\
+         orphaned inodes: {} ({})",
+        inode_ids.len(),
+        block_bits.len(),
+        leaked_inodes.len(),
+        leaked_bits.len(),
+        leaked,
+        unmarked_inodes.len(),
+        unmarked_bits.len(),
+        unmarked,
+        dangling,
+        mismatched,
+        fixed,
+        bad_links,
+        fixed,
+        orphans.len(),
+        relinked
+    ))
+}
+
+/// 挂载宿主上已有的镜像文件：写回当前镜像的缓存后换上新的块设备并读入文件系统，
+/// 新镜像无效时换回原来的设备
+pub async fn mount(path: &str) -> Result<(), Error> {
+    if !std::path::Path::new(path).is_file() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("no such image file: {}", path),
+        ));
+    }
+    block::sync_all_block_cache().await?;
+    let blk = Arc::clone(&BLOCK_CACHE_MANAGER);
+    let old = blk
+        .write()
+        .await
+        .replace_device(Box::new(device::FileDevice::new(path)));
+    Arc::clone(&INODE_CACHE).write().await.clear();
+    packing::clear().await;
+    let fs = Arc::clone(&SFS);
+    let mut w = fs.write().await;
+    if let Err(e) = w.init().await {
+        blk.write().await.replace_device(old);
+        Arc::clone(&INODE_CACHE).write().await.clear();
+        // 原来已经卸载时不需要重新读入
+        if block::is_attached().await {
+            w.init().await?;
+        }
+        return Err(e);
+    }
+    info!("mounted {}", path);
+    Ok(())
 }
 
-/// 创建100MB空文件
-pub fn create_fs_file() -> Result<(), Error> {
-    File::create(FS_FILE_NAME)?.set_len(FS_SIZE as u64)
+/// 写回缓存后卸载当前镜像，之后只能mount新的镜像；保留内存中的用户信息，用于登录
+pub async fn umount() -> Result<(), Error> {
+    block::sync_all_block_cache().await?;
+    Arc::clone(&BLOCK_CACHE_MANAGER)
+        .write()
+        .await
+        .replace_device(Box::new(device::DetachedDevice));
+    Arc::clone(&INODE_CACHE).write().await.clear();
+    packing::clear().await;
+    info!("image unmounted");
+    Ok(())
 }
 
 //延迟加载全局变量 SFS
@@ -173,6 +529,82 @@ lazy_static! {
         Arc::new(RwLock::new(SimpleFileSystem::default()));
 }
 
+/// 在线扩容到fs_size块：扩大块设备，扩展data位图和数据区，更新超级块，原有数据不变
+pub async fn grow(fs_size: usize) -> Result<(), Error> {
+    let mut sb = SuperBlock::read().await?;
+    if fs_size > MAX_FS_SIZE / BLOCK_SIZE {
+        let (size, unit) = show_unit(MAX_FS_SIZE);
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("file system cannot be larger than {:.1}{}", size, unit),
+        ));
+    }
+    if fs_size <= sb.get_fs_size() {
+        let (size, unit) = show_unit(sb.get_fs_size() * BLOCK_SIZE);
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "only growing is supported, current size is {:.1}{}",
+                size, unit
+            ),
+        ));
+    }
+    info!("grow fs to {} blocks", fs_size);
+    // 先将缓存写入设备，再扩大设备
+    block::sync_all_block_cache().await?;
+    let blk = Arc::clone(&BLOCK_CACHE_MANAGER);
+    blk.write()
+        .await
+        .grow_device((fs_size * BLOCK_SIZE) as u64)?;
+
+    let old_bitmap_blocks = sb.data_bitmap_blocks();
+    sb.grow(fs_size).await?;
+    Arc::clone(&BITMAP_MANAGER).write().await.grow(&sb);
+    // 原来扩展的data位图块落入新的数据区，清空后作为空闲块
+    let new_bitmap_blocks = sb.data_bitmap_blocks();
+    let reused: Vec<_> = old_bitmap_blocks
+        .into_iter()
+        .filter(|block_id| !new_bitmap_blocks.contains(block_id))
+        .collect();
+    block::clear_blocks(&reused).await?;
+    block::sync_all_block_cache().await
+}
+
+/// 魔数完好时按布局常量重建超级块，fs_size按镜像大小计算，之后按新的超级块重新读入位图；
+/// 返回重建前和布局不一致的字段及其存储值和期望值
+pub async fn rebuild_super_block() -> Result<Vec<(&'static str, String, String)>, Error> {
+    let sb = SuperBlock::read().await?;
+    if !sb.valid() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "super block magic is broken, restart the server with --format to reformat",
+        ));
+    }
+    let (_, image_size) = Arc::clone(&BLOCK_CACHE_MANAGER)
+        .read()
+        .await
+        .describe_device()?;
+    let fs_size = image_size as usize / BLOCK_SIZE;
+    if !(FS_SIZE / BLOCK_SIZE..=MAX_FS_SIZE / BLOCK_SIZE).contains(&fs_size) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "image size {} bytes is out of the supported range",
+                image_size
+            ),
+        ));
+    }
+    let mismatches = sb.verify(image_size as usize);
+    // 位图缓存先写回，重建后按新的布局重新读入
+    block::sync_all_block_cache().await?;
+    sb.rebuild(fs_size).await?;
+    block::sync_all_block_cache().await?;
+    Arc::clone(&BITMAP_MANAGER).write().await.read().await?;
+    Arc::clone(&SFS).write().await.update().await;
+    info!("super block rebuilt, {} fields restored", mismatches.len());
+    Ok(mismatches)
+}
+
 pub fn show_unit(size: usize) -> (f32, String) {
     match size {
         0..=1023 => (size as f32, "B".to_string()),
@@ -180,3 +612,184 @@ pub fn show_unit(size: usize) -> (f32, String) {
         _ => (size as f32 / (1024.0 * 1024.0), "MiB".to_string()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{add_user, exec, exec_as, mktree, setup, used_blocks};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn repair_reclaims_leaked_block() {
+        let _fs = setup().await;
+        mktree(&["a/b.txt:content", &format!("c.txt:{}", "c".repeat(3000))]).await;
+        let used = used_blocks().await;
+        // 置位但不链接到任何inode
+        bitmap::alloc_bit(BitmapType::Data).await.unwrap();
+        assert_eq!(used_blocks().await, used + 1);
+
+        let report = exec("fsck").await.unwrap().unwrap();
+        assert!(report.contains("leaked: 0 inodes, 1 blocks"), "{}", report);
+        assert_eq!(used_blocks().await, used + 1);
+
+        let report = exec("fsck --repair").await.unwrap().unwrap();
+        assert!(
+            report.contains("leaked: 0 inodes, 1 blocks (reclaimed)"),
+            "{}",
+            report
+        );
+        assert_eq!(used_blocks().await, used);
+        // 可达的文件不受影响
+        assert_eq!(exec("cat a/b.txt").await.unwrap().unwrap(), "content");
+        assert_eq!(exec("cat c.txt").await.unwrap().unwrap(), "c".repeat(3000));
+        let report = exec("fsck").await.unwrap().unwrap();
+        assert!(report.contains("leaked: 0 inodes, 0 blocks"), "{}", report);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn grow_keeps_files_and_adds_free_space() {
+        let _fs = setup().await;
+        let big = "g".repeat(20 * BLOCK_SIZE);
+        let big_file = format!("a/big.txt:{}", big);
+        mktree(&["a/", "a/small.txt:small", &big_file]).await;
+        add_user("alice").await;
+        let (_, free) = count_data_blocks().await;
+        let total = bitmap::data_block_num().await;
+
+        // 只有root能扩容，且只能扩大
+        assert!(exec_as("alice", "~", "resize 200M", &[]).await.is_err());
+        assert!(exec("resize 50M").await.is_err());
+        let info = exec("resize 160M").await.unwrap().unwrap();
+        assert!(info.contains("SimpleFS\t159.5MiB"), "{}", info);
+        let added = bitmap::data_block_num().await - total;
+        assert!(added >= 60 * 1024 - DATA_BITMAP_NUM, "{}", added);
+        assert_eq!(count_data_blocks().await.1, free + added);
+
+        assert_eq!(exec("cat a/small.txt").await.unwrap().unwrap(), "small");
+        assert_eq!(exec("cat a/big.txt").await.unwrap().unwrap(), big);
+        // 新增的空间可以使用，超过原来的总容量
+        exec("mkfile x.bin 50M").await.unwrap();
+        exec("mkfile y.bin 50M").await.unwrap();
+        assert!(used_blocks().await > DATA_BLOCK_MAX_NUM);
+        exec("check").await.unwrap();
+        let report = exec("fsck").await.unwrap().unwrap();
+        assert!(report.contains("leaked: 0 inodes, 0 blocks"), "{}", report);
+    }
+
+    /// 目录d下名为name的目录项记录的类型
+    async fn entry_is_dir(d: &Inode, name: &str) -> bool {
+        DirEntry::get_all_dirent(d)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|(_, _, dirent)| dirent.get_filename() == name)
+            .unwrap()
+            .2
+            .is_dir
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fsck_fixes_entry_type_mismatches() {
+        let _fs = setup().await;
+        let root = mktree(&["a.txt:x", "d/", "d/e.txt:e"]).await;
+
+        // 把文件的目录项标为目录，把目录的目录项标为文件
+        for (_, block_id, dirent) in DirEntry::get_all_dirent(&root).await.unwrap() {
+            match dirent.get_filename().as_str() {
+                "a.txt" => dirent::fix_is_dir(&dirent, true, block_id).await.unwrap(),
+                "d" => dirent::fix_is_dir(&dirent, false, block_id).await.unwrap(),
+                _ => {}
+            }
+        }
+        assert!(entry_is_dir(&root, "a.txt").await);
+        assert!(!entry_is_dir(&root, "d").await);
+
+        let report = exec("fsck").await.unwrap().unwrap();
+        assert!(
+            report.contains("entry type mismatches: 2 (run fsck --repair to fix)"),
+            "{}",
+            report
+        );
+        let report = exec("fsck --repair").await.unwrap().unwrap();
+        assert!(
+            report.contains("entry type mismatches: 2 (fixed)"),
+            "{}",
+            report
+        );
+        let report = exec("fsck").await.unwrap().unwrap();
+        assert!(report.contains("entry type mismatches: 0"), "{}", report);
+
+        let root = Inode::read(0).await.unwrap();
+        assert!(!entry_is_dir(&root, "a.txt").await);
+        assert!(entry_is_dir(&root, "d").await);
+        assert_eq!(exec("cat a.txt").await.unwrap().unwrap(), "x");
+        assert_eq!(exec("cat d/e.txt").await.unwrap().unwrap(), "e");
+        assert!(exec("cd a.txt").await.is_err());
+    }
+
+    /// 删除根目录下名为name的目录项，不释放inode，制造孤立的inode，返回其inode号
+    async fn orphan(name: &str) -> usize {
+        let root = Inode::read(0).await.unwrap();
+        let (_, block_id, dirent) = DirEntry::get_all_dirent(&root)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|(_, _, dirent)| dirent.get_filename() == name)
+            .unwrap();
+        block::remove_object(&dirent, block_id as usize)
+            .await
+            .unwrap();
+        dirent.inode_id as usize
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn lost_found_relinks_orphans_with_content() {
+        let _fs = setup().await;
+        let content = "o".repeat(3000);
+        mktree(&[&format!("a.txt:{}", content), "d/", "d/e.txt:inner"]).await;
+        let file_id = orphan("a.txt").await;
+        let dir_id = orphan("d").await;
+        assert!(exec("cat a.txt").await.is_err());
+
+        let report = exec("fsck").await.unwrap().unwrap();
+        assert!(
+            report.contains("orphaned inodes: 2 (run fsck --repair --lost-found to relink)"),
+            "{}",
+            report
+        );
+        let report = exec("fsck --repair --lost-found").await.unwrap().unwrap();
+        assert!(
+            report.contains("orphaned inodes: 2 (relinked into ~/lost+found)"),
+            "{}",
+            report
+        );
+        assert!(report.contains("leaked: 0 inodes, 0 blocks"), "{}", report);
+
+        // 以inode号为名找回，内容不变；孤立目录下的内容随目录一起找回
+        let cat = |path: String| async move { exec(&format!("cat {}", path)).await };
+        let found = cat(format!("lost+found/{}", file_id)).await.unwrap();
+        assert_eq!(found.unwrap(), content);
+        let found = cat(format!("lost+found/{}/e.txt", dir_id)).await.unwrap();
+        assert_eq!(found.unwrap(), "inner");
+        let listing = exec(&format!("dir lost+found/{}/..", dir_id))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(listing.contains(&file_id.to_string()), "{}", listing);
+
+        let report = exec("fsck").await.unwrap().unwrap();
+        assert!(report.contains("orphaned inodes: 0"), "{}", report);
+        assert!(report.contains("leaked: 0 inodes, 0 blocks"), "{}", report);
+
+        // 没有--lost-found时孤立的inode随泄漏的inode一起回收
+        mktree(&[&format!("b.txt:{}", content)]).await;
+        let used = used_blocks().await;
+        orphan("b.txt").await;
+        let report = exec("fsck --repair").await.unwrap().unwrap();
+        assert!(
+            report.contains("orphaned inodes: 1 (reclaimed)"),
+            "{}",
+            report
+        );
+        assert!(used_blocks().await < used);
+    }
+}
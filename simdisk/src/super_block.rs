@@ -1,9 +1,10 @@
 use crate::{
     block::{deserialize, get_block_buffer, write_block},
     fs_constants::*,
+    inode::now_secs,
 };
 use serde::{Deserialize, Serialize};
-use std::{fmt::Debug, io::Error};
+use std::{cmp::min, fmt::Debug, io::Error};
 
 /// 共100K块，SB一块
 ///
@@ -14,6 +15,8 @@ use std::{fmt::Debug, io::Error};
 /// inode区 1K块，每个inode 64B，共1K*1K/64=8K个文件
 ///
 /// 剩下的都是data区块
+///
+/// 扩容后data位图超出13块的部分紧跟在数据区之后
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct SuperBlock {
     magic: usize,   //魔数
@@ -30,32 +33,56 @@ pub struct SuperBlock {
     data_bitmap_size: usize,           // 数据块位图大小 ，块为单位
     first_data_block: usize,           // 数据区第一块的块号，放置根目录
     data_size: usize,                  // 数据区大小，块为单位
+
+    // 以下字段在旧镜像中为0
+    layout_version: usize, // 磁盘布局版本
+    created_at: u64,       // 格式化时的时间戳
 }
 
 #[allow(unused)]
 impl SuperBlock {
     /// 初始化超级块
-    pub async fn init() {
+    pub async fn init() -> Result<(), Error> {
         trace!("init super block");
+        Self::layout().cache().await
+    }
+
+    /// 按布局常量重建超级块并写入块缓存，fs_size为镜像的块数，扩容过的镜像data位图随之扩展；
+    /// 创建时间不属于布局，保持不变
+    pub async fn rebuild(&self, fs_size: usize) -> Result<Self, Error> {
+        let mut sb = Self {
+            created_at: self.created_at,
+            ..Self::layout()
+        };
+        if fs_size > sb.fs_size {
+            sb.grow(fs_size).await?;
+        } else {
+            sb.cache().await?;
+        }
+        Ok(sb)
+    }
+
+    /// 按布局常量生成的未扩容的超级块
+    fn layout() -> Self {
         Self {
             fs_size: FS_SIZE / BLOCK_SIZE,
             first_inode: INODE_START_BLOCK,
             inode_area_size: INODE_BLOCK_NUM,
             first_block_of_inode_bitmap: INODE_BITMAP_START_BLOCK,
             inode_bitmap_size: INODE_BITMAP_NUM,
-            data_size: FS_SIZE - DATA_START_BLOCK,
+            data_size: data_block_num(FS_SIZE / BLOCK_SIZE, DATA_BITMAP_NUM),
             first_data_block: DATA_START_BLOCK,
             first_block_of_data_bitmap: DATA_BITMAP_START_BLOCK,
             data_bitmap_size: DATA_BITMAP_NUM,
             magic: MAGIC,
+            layout_version: LAYOUT_VERSION,
+            created_at: now_secs(),
         }
-        .cache()
-        .await;
     }
 
-    async fn cache(&self) {
+    async fn cache(&self) -> Result<(), Error> {
         trace!("write super block to cache");
-        write_block(self, 0, 0).await;
+        write_block(self, 0, 0).await
     }
 
     pub async fn read() -> Result<Self, Error> {
@@ -64,7 +91,208 @@ impl SuperBlock {
         deserialize(&buffer)
     }
 
+    /// 只读出超级块中记录的布局版本，不反序列化整个超级块，魔数不对时为None；
+    /// magic和layout_version的位置不随版本改变，布局不同的镜像也能读出
+    pub async fn read_layout_version() -> Result<Option<usize>, Error> {
+        let buffer = get_block_buffer(0, 0, BLOCK_SIZE).await?;
+        // bincode将usize存为8字节，magic为第0个字段，layout_version为第10个字段
+        let field =
+            |i: usize| u64::from_le_bytes(buffer[i * 8..(i + 1) * 8].try_into().unwrap()) as usize;
+        if field(0) != MAGIC {
+            return Ok(None);
+        }
+        Ok(Some(field(10)))
+    }
+
     pub fn valid(&self) -> bool {
         self.magic == MAGIC
     }
+
+    /// 磁盘布局版本，旧镜像为0
+    pub fn get_layout_version(&self) -> usize {
+        self.layout_version
+    }
+
+    /// 格式化时的时间戳，旧镜像为0
+    pub fn get_created_at(&self) -> u64 {
+        self.created_at
+    }
+
+    /// 文件系统大小，块为单位
+    pub fn get_fs_size(&self) -> usize {
+        self.fs_size
+    }
+
+    /// inode位图的块数
+    pub fn get_inode_bitmap_size(&self) -> usize {
+        self.inode_bitmap_size
+    }
+
+    /// inode区能存放的inode数
+    pub fn inode_capacity(&self) -> usize {
+        self.inode_area_size * INODES_PER_BLOCK
+    }
+
+    /// 数据区的块数
+    pub fn data_block_num(&self) -> usize {
+        data_block_num(self.fs_size, self.data_bitmap_size)
+    }
+
+    /// data位图所在的块号：固定的DATA_BITMAP_NUM块，以及扩容后紧跟在数据区之后的块
+    pub fn data_bitmap_blocks(&self) -> Vec<usize> {
+        let extra_start = DATA_START_BLOCK + self.data_block_num();
+        let extra_num = self.data_bitmap_size - DATA_BITMAP_NUM;
+        (DATA_BITMAP_START_BLOCK..DATA_BITMAP_START_BLOCK + DATA_BITMAP_NUM)
+            .chain(extra_start..extra_start + extra_num)
+            .collect()
+    }
+
+    /// 按当前的布局常量逐项检查超级块，image_size为镜像文件的大小（字节），
+    /// 返回不一致的字段及其存储值和期望值，全部一致时为空
+    pub fn verify(&self, image_size: usize) -> Vec<(&'static str, String, String)> {
+        let mut mismatches = Vec::new();
+        let mut expect = |field, stored: usize, expected: usize| {
+            if stored != expected {
+                mismatches.push((field, stored.to_string(), expected.to_string()));
+            }
+        };
+        expect("magic", self.magic, MAGIC);
+        expect(
+            "first_block_of_inode_bitmap",
+            self.first_block_of_inode_bitmap,
+            INODE_BITMAP_START_BLOCK,
+        );
+        expect(
+            "inode_bitmap_size",
+            self.inode_bitmap_size,
+            INODE_BITMAP_NUM,
+        );
+        expect("first_inode", self.first_inode, INODE_START_BLOCK);
+        expect("inode_area_size", self.inode_area_size, INODE_BLOCK_NUM);
+        expect(
+            "first_block_of_data_bitmap",
+            self.first_block_of_data_bitmap,
+            DATA_BITMAP_START_BLOCK,
+        );
+        expect("first_data_block", self.first_data_block, DATA_START_BLOCK);
+        // 扩容后fs_size和data位图会变大，以下字段按镜像大小和范围检查
+        if self.fs_size != image_size / BLOCK_SIZE {
+            mismatches.push((
+                "fs_size",
+                self.fs_size.to_string(),
+                (image_size / BLOCK_SIZE).to_string(),
+            ));
+        } else if self.fs_size < FS_SIZE / BLOCK_SIZE || self.fs_size > MAX_FS_SIZE / BLOCK_SIZE {
+            mismatches.push((
+                "fs_size",
+                self.fs_size.to_string(),
+                format!("{}..={}", FS_SIZE / BLOCK_SIZE, MAX_FS_SIZE / BLOCK_SIZE),
+            ));
+        }
+        let data_bitmap_max = MAX_FS_SIZE / BLOCK_SIZE / (BLOCK_SIZE * 8) + 1;
+        if self.data_bitmap_size < DATA_BITMAP_NUM || self.data_bitmap_size > data_bitmap_max {
+            mismatches.push((
+                "data_bitmap_size",
+                self.data_bitmap_size.to_string(),
+                format!("{}..={}", DATA_BITMAP_NUM, data_bitmap_max),
+            ));
+        } else if self.data_size != self.data_block_num()
+            && self.data_size != FS_SIZE - DATA_START_BLOCK
+        {
+            // 旧版本格式化时data_size误按字节数计算，只要没有扩容过就不视为错误
+            mismatches.push((
+                "data_size",
+                self.data_size.to_string(),
+                self.data_block_num().to_string(),
+            ));
+        }
+        if self.layout_version != LAYOUT_VERSION {
+            mismatches.push((
+                "layout_version",
+                self.layout_version.to_string(),
+                LAYOUT_VERSION.to_string(),
+            ));
+        }
+        mismatches
+    }
+
+    /// 扩容到fs_size块，data位图扩展到能表示剩余所有块为止，写入块缓存
+    pub async fn grow(&mut self, fs_size: usize) -> Result<(), Error> {
+        let mut data_bitmap_size = self.data_bitmap_size;
+        while data_bitmap_size * BLOCK_SIZE * 8
+            < fs_size - DATA_START_BLOCK - (data_bitmap_size - DATA_BITMAP_NUM)
+        {
+            data_bitmap_size += 1;
+        }
+        self.fs_size = fs_size;
+        self.data_bitmap_size = data_bitmap_size;
+        self.data_size = self.data_block_num();
+        self.cache().await
+    }
+}
+
+/// 数据区的块数：不超过data位图能表示的数量，也不超过扣除扩展位图块后剩余的块数，按8对齐
+fn data_block_num(fs_size: usize, data_bitmap_size: usize) -> usize {
+    let extra_num = data_bitmap_size - DATA_BITMAP_NUM;
+    let space = fs_size.saturating_sub(DATA_START_BLOCK + extra_num);
+    min(data_bitmap_size * BLOCK_SIZE * 8, space) / 8 * 8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{add_user, exec, exec_as, mktree, setup};
+
+    /// first_inode在超级块中的偏移：前面是magic、fs_size和inode位图的两个字段
+    const FIRST_INODE_BYTE: usize = 4 * std::mem::size_of::<u64>();
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn verify_flags_exactly_the_corrupted_field() {
+        let _fs = setup().await;
+        assert_eq!(
+            SuperBlock::read().await.unwrap().first_inode,
+            INODE_START_BLOCK
+        );
+
+        write_block(&999usize, 0, FIRST_INODE_BYTE).await.unwrap();
+        assert_eq!(SuperBlock::read().await.unwrap().first_inode, 999);
+        let report = exec("verify").await.unwrap().unwrap();
+        let expected = format!(
+            "first_inode: stored 999, expected {}\n1 fields mismatched",
+            INODE_START_BLOCK
+        );
+        assert_eq!(report, expected);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resetsb_restores_corrupted_fields() {
+        let _fs = setup().await;
+        add_user("alice").await;
+        mktree(&["a/b.txt:kept"]).await;
+        let created_at = SuperBlock::read().await.unwrap().created_at;
+
+        write_block(&999usize, 0, FIRST_INODE_BYTE).await.unwrap();
+        assert!(exec_as("alice", "~", "resetsb", &[]).await.is_err());
+        assert_eq!(SuperBlock::read().await.unwrap().first_inode, 999);
+
+        let output = exec("resetsb").await.unwrap().unwrap();
+        let expected = format!(
+            "first_inode: 999 -> {}\nsuper block rebuilt, 1 fields restored",
+            INODE_START_BLOCK
+        );
+        assert_eq!(output, expected);
+        let sb = SuperBlock::read().await.unwrap();
+        assert_eq!(sb.first_inode, INODE_START_BLOCK);
+        assert_eq!(sb.created_at, created_at);
+        let report = exec("verify").await.unwrap().unwrap();
+        assert_eq!(report, "super block ok");
+        assert_eq!(exec("cat a/b.txt").await.unwrap().unwrap(), "kept");
+
+        // 魔数损坏时拒绝重建，要求重新格式化
+        write_block(&0usize, 0, 0).await.unwrap();
+        let err = exec("resetsb").await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("--format"), "{}", err);
+        assert_eq!(SuperBlock::read().await.unwrap().magic, 0);
+    }
 }
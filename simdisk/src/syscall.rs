@@ -1,14 +1,34 @@
-use std::{future::Future, io, pin::Pin, sync::Arc};
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use tokio::net::TcpStream;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
 
 use crate::{
+    bitmap,
     block::{self, sync_all_block_cache, BLOCK_CACHE_MANAGER},
-    dirent, file,
-    fs_constants::SYNC_BLOCK_DURATION,
-    inode::{FileMode, Inode},
+    config::CONFIG,
+    cwd_cache,
+    dirent::{self, DirEntry},
+    file,
+    fs_constants::{
+        BENCH_FILE_NAME, BLOCK_SIZE, INODE_MAX_NUM, MAX_FILE_SIZE, QUOTA_GRACE_PERIOD,
+        SYNC_BLOCK_DURATION,
+    },
+    fs_lock, history,
+    inode::{self, FileMode, Inode},
     simple_fs::{self, SFS},
+    super_block::SuperBlock,
     user::{able_to_modify, UserIdType},
+    webhook::{self, EventKind},
 };
 
 /// 打印
@@ -21,18 +41,347 @@ pub async fn info() -> io::Result<Option<String>> {
 }
 
 /// 展示目录信息
-pub async fn ls(username: &str, path: &str, detail: bool) -> io::Result<Option<String>> {
+pub async fn ls(
+    username: &str,
+    path: &str,
+    detail: bool,
+    inode_ids: bool,
+    all: bool,
+) -> io::Result<Option<String>> {
     let absolute_path = [path, "/"].concat();
     let infos = temp_cd_and_do(&absolute_path, false, |_, current_inode| {
-        Box::pin(async move { Ok(Some(current_inode.ls(username, detail).await)) })
+        Box::pin(async move {
+            Ok(Some(
+                current_inode.ls(username, detail, inode_ids, all).await,
+            ))
+        })
     })
     .await?;
     trace!("finished cmd: ls_dir");
     Ok(infos)
 }
 
+/// 统计路径下文件占用的空间，apparent为true时统计文件大小之和
+pub async fn du(path: &str, apparent: bool) -> io::Result<Option<String>> {
+    let inode = resolve_inode(path).await?;
+    let (size, unit) = simple_fs::show_unit(inode.disk_usage(apparent, 1).await?);
+    trace!("finished cmd: du");
+    Ok(Some(format!("{:.1}{}\t{}", size, unit, path)))
+}
+
+/// 展示路径对应inode的详细信息
+pub async fn stat(path: &str, view_path: &str) -> io::Result<Option<String>> {
+    let inode = resolve_inode(path).await?;
+    let user_names = Arc::clone(&SFS).read().await.user_infos.get_user_names();
+    let kind = if inode.is_dir() { "directory" } else { "file" };
+    let mut storage = Vec::new();
+    if inode.is_compressed() {
+        storage.push("compressed");
+    }
+    if inode.is_packed() {
+        storage.push("packed");
+    }
+    let storage = if storage.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", storage.join(", "))
+    };
+    let owner = user_names
+        .get(&inode.get_uid())
+        .map_or("?", |name| name.as_str());
+    let modified = chrono::DateTime::from_timestamp(inode.get_created_at() as i64, 0)
+        .map_or("?".to_string(), |time| {
+            time.format("%Y-%m-%d %H:%M:%S").to_string()
+        });
+    let blocks = block::get_owned_block_ids(&inode).await?.len();
+    let infos = format!(
+        "  File: {}\n  Type: {}{}\n  Size: {}\tBlocks: {}\n Inode: {}\tLinks: {}\n  Mode: {:?}\tOwner: {} (uid {}, gid {})\nModify: {}",
+        view_path,
+        kind,
+        storage,
+        inode.computed_size().await?,
+        blocks,
+        inode.inode_id,
+        inode.get_nlink(),
+        inode.get_mode(),
+        owner,
+        inode.get_uid(),
+        inode.gid,
+        modified
+    );
+    trace!("finished cmd: stat [{}]", path);
+    Ok(Some(infos))
+}
+
+/// 按inode号直接读出inode并展示全部字段和位图中的占用情况，不需要从目录树可达，仅root可用
+pub async fn stat_inode(username: &str, inode_id: usize) -> io::Result<Option<String>> {
+    let gid = get_current_user_gid(username).await;
+    if !able_to_modify(gid, 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "not in root",
+        ));
+    }
+    if inode_id >= INODE_MAX_NUM {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("inode id must be less than {}", INODE_MAX_NUM),
+        ));
+    }
+    let inode = Inode::read_raw(inode_id).await?;
+    let bit = if bitmap::is_inode_allocated(inode_id).await {
+        "set"
+    } else {
+        "clear"
+    };
+    let mut infos = format!("inode {}: bitmap bit {}\n", inode_id, bit);
+    if inode.inode_id as usize != inode_id {
+        infos.push_str(&format!(
+            "stored inode id {} does not match\n",
+            inode.inode_id
+        ));
+    }
+    infos.push_str(&format!("{:#?}", inode));
+    trace!("finished cmd: stat --inode {}", inode_id);
+    Ok(Some(infos))
+}
+
+/// 按逻辑顺序列出文件（或目录）的每个数据块所在的层级和块号，最后一行统计数据块和间接块的数量
+pub async fn blocks(path: &str) -> io::Result<Option<String>> {
+    let inode = resolve_inode(path).await?;
+    if inode.is_packed() {
+        let (block_id, offset) = inode.get_packed_location();
+        trace!("finished cmd: blocks [{}]", path);
+        return Ok(Some(format!(
+            "packed in block {} at offset {}",
+            block_id, offset
+        )));
+    }
+    let data_blocks = block::get_all_blocks(&inode).await?;
+    // 占用的块中除数据块以外的都是间接块
+    let index_blocks = block::get_owned_block_ids(&inode)
+        .await?
+        .len()
+        .saturating_sub(data_blocks.len());
+    let mut lines: Vec<_> = data_blocks
+        .iter()
+        .map(|(level, block_id, _)| format!("{:?}\t{}", level, block_id))
+        .collect();
+    lines.push(format!(
+        "{} data blocks, {} index blocks",
+        data_blocks.len(),
+        index_blocks
+    ));
+    trace!("finished cmd: blocks [{}]", path);
+    Ok(Some(lines.join("\n")))
+}
+
+/// 判断路径是否存在以及是文件还是目录，路径不存在时不报错
+pub async fn exists(absolute_path: &str) -> io::Result<Option<String>> {
+    let root = Arc::clone(&SFS).read().await.root_inode.clone();
+    let path = absolute_path.trim_end_matches('/');
+    let found = match path.rsplit_once('/') {
+        Some((parent_path, name)) => match cwd_cache::cd(parent_path, &root).await {
+            Ok(parent_inode) => dirent::lookup(name, &parent_inode).await,
+            // 上级目录不存在或不是目录
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+                ) =>
+            {
+                None
+            }
+            Err(e) => return Err(e),
+        },
+        // 只有~，即根目录
+        None => Some(true),
+    };
+    let output = match found {
+        Some(true) => "true dir",
+        Some(false) => "true file",
+        None => "false",
+    };
+    trace!("finished cmd: exists [{}]", absolute_path);
+    Ok(Some(output.to_string()))
+}
+
+/// 计算目录树（或文件）的merkle根哈希
+pub async fn treehash(path: &str) -> io::Result<Option<String>> {
+    let inode = resolve_inode(path).await?;
+    let hash = inode.tree_hash(1).await?;
+    let hex: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+    trace!("finished cmd: treehash");
+    Ok(Some(format!("{}\t{}", hex, path)))
+}
+
+/// 以树形展示目录结构，level为最多展开的层数
+pub async fn tree(path: &str, level: Option<usize>) -> io::Result<Option<String>> {
+    let inode = resolve_inode(path).await?;
+    if !inode.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a directory", path),
+        ));
+    }
+    let lines = inode.tree("", 1, level).await?;
+    trace!("finished cmd: tree");
+    Ok(Some([path, "\n", &lines].concat()))
+}
+
+/// 在目录下递归查找名字匹配pattern的文件和目录，返回匹配项的绝对路径
+pub async fn find(path: &str, pattern: &str) -> io::Result<Vec<String>> {
+    let inode = resolve_inode(path).await?;
+    if !inode.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a directory", path),
+        ));
+    }
+    let paths = inode.find(path.trim_end_matches('/'), pattern, 1).await?;
+    trace!("finished cmd: find");
+    Ok(paths)
+}
+
+/// 将最后一段含有通配符的路径展开为父目录下所有匹配的路径，不会匹配.和..
+pub async fn expand_wildcard(absolute_path: &str) -> io::Result<Vec<String>> {
+    let (parent_path, pattern) = absolute_path
+        .rsplit_once('/')
+        .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "invalid path"))?;
+    let parent_inode = resolve_inode(parent_path).await?;
+    if !parent_inode.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a directory", parent_path),
+        ));
+    }
+    let paths: Vec<_> = DirEntry::get_all_dirent(&parent_inode)
+        .await?
+        .into_iter()
+        .filter(|(_, _, dirent)| !dirent.is_special())
+        .map(|(_, _, dirent)| dirent.get_filename())
+        .filter(|name| dirent::wildcard_match(pattern, name))
+        .map(|name| [parent_path, "/", &name].concat())
+        .collect();
+    if paths.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no match for {}", pattern),
+        ));
+    }
+    Ok(paths)
+}
+
+/// 按当前的布局常量检查超级块，逐项报告不一致的字段
+pub async fn verify() -> io::Result<Option<String>> {
+    let sb = SuperBlock::read().await?;
+    let (_, image_size) = Arc::clone(&BLOCK_CACHE_MANAGER)
+        .read()
+        .await
+        .describe_device()?;
+    let mismatches = sb.verify(image_size as usize);
+    trace!("finished cmd: verify");
+    if mismatches.is_empty() {
+        return Ok(Some("super block ok".to_string()));
+    }
+    let mut lines: Vec<_> = mismatches
+        .iter()
+        .map(|(field, stored, expected)| {
+            format!("{}: stored {}, expected {}", field, stored, expected)
+        })
+        .collect();
+    lines.push(format!("{} fields mismatched", mismatches.len()));
+    Ok(Some(lines.join("\n")))
+}
+
+/// 按布局常量重建超级块，用于魔数完好但布局字段损坏的镜像，仅root可用
+pub async fn resetsb(username: &str) -> io::Result<Option<String>> {
+    let gid = get_current_user_gid(username).await;
+    if !able_to_modify(gid, 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "not in root",
+        ));
+    }
+    let _lock = fs_lock::write().await;
+    let mismatches = simple_fs::rebuild_super_block().await?;
+    trace!("finished cmd: resetsb");
+    let mut lines: Vec<_> = mismatches
+        .iter()
+        .map(|(field, stored, expected)| format!("{}: {} -> {}", field, stored, expected))
+        .collect();
+    lines.push(format!(
+        "super block rebuilt, {} fields restored",
+        mismatches.len()
+    ));
+    Ok(Some(lines.join("\n")))
+}
+
+/// 显示当前挂载的镜像信息
+pub async fn mount_info() -> io::Result<Option<String>> {
+    let sb = SuperBlock::read().await?;
+    let (path, size) = Arc::clone(&BLOCK_CACHE_MANAGER)
+        .read()
+        .await
+        .describe_device()?;
+    let (size, unit) = simple_fs::show_unit(size as usize);
+    // 旧镜像没有记录布局版本和创建时间
+    let version = match sb.get_layout_version() {
+        0 => "unknown".to_string(),
+        version => version.to_string(),
+    };
+    let created = chrono::DateTime::from_timestamp(sb.get_created_at() as i64, 0)
+        .filter(|_| sb.get_created_at() != 0)
+        .map_or("unknown".to_string(), |time| {
+            time.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+        });
+    let infos = [
+        format!("image:\t\t{}\n", path),
+        format!("size:\t\t{:.1}{}\n", size, unit),
+        format!("layout version:\t{}\n", version),
+        format!("block size:\t{}B\n", BLOCK_SIZE),
+        format!("created:\t{}\n", created),
+        format!("readonly:\t{}", CONFIG.readonly),
+    ];
+    trace!("finished cmd: mount-info");
+    Ok(Some(infos.concat()))
+}
+
+/// 挂载宿主上已有的镜像文件，替换当前的镜像，仅root可用
+pub async fn mount(username: &str, path: &str) -> io::Result<Option<String>> {
+    let gid = get_current_user_gid(username).await;
+    if !able_to_modify(gid, 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "not in root",
+        ));
+    }
+    let _lock = fs_lock::write().await;
+    simple_fs::mount(path).await?;
+    trace!("finished cmd: mount");
+    mount_info().await
+}
+
+/// 写回缓存并卸载当前镜像，仅root可用
+pub async fn umount(username: &str) -> io::Result<Option<String>> {
+    let gid = get_current_user_gid(username).await;
+    if !able_to_modify(gid, 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "not in root",
+        ));
+    }
+    let _lock = fs_lock::write().await;
+    simple_fs::umount().await?;
+    trace!("finished cmd: umount");
+    Ok(None)
+}
+
 /// 创建目录
 pub async fn mkdir(username: &str, dir_name_absolute: &str) -> io::Result<()> {
+    // 新目录不能超过最大深度
+    dirent::check_path_depth(dir_name_absolute.split('/').count() - 1)?;
+    let _lock = fs_lock::write().await;
     temp_cd_and_do(dir_name_absolute, true, |name, mut current_inode| {
         Box::pin(async move {
             let (gid, uid) = get_current_user_ids(username).await;
@@ -40,6 +389,7 @@ pub async fn mkdir(username: &str, dir_name_absolute: &str) -> io::Result<()> {
         })
     })
     .await?;
+    webhook::notify(EventKind::Create, dir_name_absolute, username);
     trace!("finished cmd: mkdir");
     Ok(())
 }
@@ -50,13 +400,16 @@ pub async fn rmdir(
     dir_name_absolute: &str,
     socket: &mut TcpStream,
 ) -> io::Result<()> {
-    temp_cd_and_do(dir_name_absolute, true, |name, mut current_inode| {
+    let removed = temp_cd_and_do(dir_name_absolute, true, |name, mut current_inode| {
         Box::pin(async move {
             let gid = get_current_user_gid(username).await;
             dirent::remove_directory(name, &mut current_inode, socket, gid).await
         })
     })
     .await?;
+    if removed {
+        webhook::notify(EventKind::Delete, dir_name_absolute, username);
+    }
     trace!("finished cmd: rmdir");
     Ok(())
 }
@@ -65,27 +418,27 @@ pub async fn rmdir(
 pub async fn cd(absolute_path: &str) -> io::Result<()> {
     // 目录不存在会抛出err
     let root = Arc::clone(&SFS).read().await.root_inode.clone();
-    dirent::cd(absolute_path, &root).await?;
+    cwd_cache::cd(absolute_path, &root).await?;
     trace!("finished cmd: cd");
     Ok(())
 }
 
-/// 创建新文件
+/// 创建新文件，超过软配额时返回告警信息
 pub async fn new_file(
     username: &str,
     filename_absolute: &str,
     mode: FileMode,
     socket: &mut TcpStream,
-) -> io::Result<()> {
-    temp_cd_and_do(filename_absolute, true, |filename, mut current_inode| {
+) -> io::Result<Option<String>> {
+    let warning = temp_cd_and_do(filename_absolute, true, |filename, mut current_inode| {
         Box::pin(async move {
             let user_id = get_current_user_ids(username).await;
             file::create_file(
                 filename,
                 mode,
                 &mut current_inode,
+                None,
                 false,
-                "",
                 socket,
                 user_id,
             )
@@ -93,20 +446,168 @@ pub async fn new_file(
         })
     })
     .await?;
+    webhook::notify(EventKind::Create, filename_absolute, username);
     trace!("finished cmd: newfile");
+    Ok(warning)
+}
+
+/// 原地替换文件内容，内容从client读取，超过软配额时返回告警信息
+pub async fn edit(
+    username: &str,
+    filename_absolute: &str,
+    socket: &mut TcpStream,
+) -> io::Result<Option<String>> {
+    let warning = temp_cd_and_do(filename_absolute, true, |filename, current_inode| {
+        Box::pin(async move {
+            let gid = get_current_user_gid(username).await;
+            file::edit_file(filename, &current_inode, socket, gid).await
+        })
+    })
+    .await?;
+    webhook::notify(EventKind::Modify, filename_absolute, username);
+    trace!("finished cmd: edit");
+    Ok(warning)
+}
+
+/// 在文件末尾追加client输入的内容，超过软配额时返回告警信息
+pub async fn append(
+    username: &str,
+    filename_absolute: &str,
+    socket: &mut TcpStream,
+) -> io::Result<Option<String>> {
+    let warning = temp_cd_and_do(filename_absolute, true, |filename, current_inode| {
+        Box::pin(async move {
+            let gid = get_current_user_gid(username).await;
+            file::append_file(filename, &current_inode, socket, gid).await
+        })
+    })
+    .await?;
+    webhook::notify(EventKind::Modify, filename_absolute, username);
+    trace!("finished cmd: append");
+    Ok(warning)
+}
+
+/// 创建指定大小、内容全为0的文件，超过软配额时返回告警信息
+pub async fn mkfile(
+    username: &str,
+    filename_absolute: &str,
+    size: usize,
+) -> io::Result<Option<String>> {
+    let warning = temp_cd_and_do(filename_absolute, true, |filename, mut current_inode| {
+        Box::pin(async move {
+            let user_id = get_current_user_ids(username).await;
+            file::create_zero_file(filename, &mut current_inode, size, user_id).await
+        })
+    })
+    .await?;
+    webhook::notify(EventKind::Create, filename_absolute, username);
+    trace!("finished cmd: mkfile");
+    Ok(warning)
+}
+
+/// 删除文件：移入回收站trash_path，回收站中的文件直接删除
+pub async fn del(username: &str, filename_absolute: &str, trash_path: &str) -> io::Result<()> {
+    let _lock = fs_lock::write().await;
+    let in_trash = filename_absolute
+        .rsplit_once('/')
+        .is_some_and(|(parent, _)| parent == trash_path);
+    if in_trash {
+        temp_cd_and_do(filename_absolute, true, |filename, mut current_inode| {
+            Box::pin(async move {
+                let gid = get_current_user_gid(username).await;
+                file::remove_file(filename, &mut current_inode, gid).await
+            })
+        })
+        .await?;
+    } else {
+        let mut trash_inode = open_trash(trash_path, true).await?;
+        temp_cd_and_do(filename_absolute, true, |filename, mut current_inode| {
+            Box::pin(async move {
+                let gid = get_current_user_gid(username).await;
+                // 回收站中已有同名文件时，先彻底删除旧的
+                if file::remove_file(filename, &mut trash_inode, gid)
+                    .await
+                    .is_ok()
+                {
+                    info!("replace {} in trash", filename);
+                }
+                file::move_file(filename, &mut current_inode, &mut trash_inode, gid).await
+            })
+        })
+        .await?;
+    }
+    webhook::notify(EventKind::Delete, filename_absolute, username);
+    trace!("finished cmd: del [{}]", filename_absolute);
     Ok(())
 }
 
-/// 删除文件
-pub async fn del(username: &str, filename_absolute: &str) -> io::Result<()> {
+/// 将文件从回收站恢复到filename_absolute
+pub async fn undelete(username: &str, filename_absolute: &str, trash_path: &str) -> io::Result<()> {
+    let _lock = fs_lock::write().await;
+    let mut trash_inode = open_trash(trash_path, false).await?;
     temp_cd_and_do(filename_absolute, true, |filename, mut current_inode| {
         Box::pin(async move {
             let gid = get_current_user_gid(username).await;
-            file::remove_file(filename, &mut current_inode, gid).await
+            file::move_file(filename, &mut trash_inode, &mut current_inode, gid).await
         })
     })
     .await?;
-    trace!("finished cmd: del [{}]", filename_absolute);
+    webhook::notify(EventKind::Create, filename_absolute, username);
+    trace!("finished cmd: undelete [{}]", filename_absolute);
+    Ok(())
+}
+
+/// 彻底删除回收站中的文件，释放其占用的块
+pub async fn empty_trash(username: &str, trash_path: &str) -> io::Result<Option<String>> {
+    let _lock = fs_lock::write().await;
+    let mut trash_inode = open_trash(trash_path, false).await?;
+    let gid = get_current_user_gid(username).await;
+    let count = file::empty_trash(&mut trash_inode, gid).await?;
+    if block::is_sync_immediately().await {
+        sync_all_block_cache().await?;
+    }
+    trace!("finished cmd: emptytrash");
+    Ok(Some(format!("{} files removed", count)))
+}
+
+/// 进入回收站目录，create为true时不存在则创建
+async fn open_trash(trash_path: &str, create: bool) -> io::Result<Inode> {
+    let root = Arc::clone(&SFS).read().await.root_inode.clone();
+    if let Ok(inode) = cwd_cache::cd(trash_path, &root).await {
+        return Ok(inode);
+    }
+    if !create {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "trash is empty"));
+    }
+    // 回收站属于root，所有用户共用
+    temp_cd_and_do(trash_path, true, |name, mut current_inode| {
+        Box::pin(async move { dirent::make_directory(name, &mut current_inode, 0, 0).await })
+    })
+    .await?;
+    cwd_cache::cd(trash_path, &root).await
+}
+
+/// 只修改文件的扩展名，new_name为新的文件名（不含路径）
+pub async fn rename(username: &str, filename_absolute: &str, new_name: &str) -> io::Result<()> {
+    if new_name.contains('/') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "new name should not contain a path",
+        ));
+    }
+    let _lock = fs_lock::write().await;
+    temp_cd_and_do(filename_absolute, true, |filename, current_inode| {
+        Box::pin(async move {
+            let gid = get_current_user_gid(username).await;
+            dirent::change_extension(filename, new_name, &current_inode, gid).await
+        })
+    })
+    .await?;
+    trace!(
+        "finished cmd: rename [{}] to [{}]",
+        filename_absolute,
+        new_name
+    );
     Ok(())
 }
 
@@ -120,52 +621,177 @@ pub async fn cat(filename_absolute: &str) -> io::Result<Option<String>> {
     Ok(Some(content))
 }
 
-/// 复制文件
+/// 只读取文件从start字节开始的len个字节
+pub async fn cat_range(
+    filename_absolute: &str,
+    start: usize,
+    len: usize,
+) -> io::Result<Option<String>> {
+    let content = temp_cd_and_do(filename_absolute, false, |filename, current_inode| {
+        Box::pin(async move { file::get_file_range(filename, &current_inode, start, len).await })
+    })
+    .await?;
+    trace!(
+        "finished cmd: cat [{}] --range {}:{}",
+        filename_absolute,
+        start,
+        len
+    );
+    Ok(Some(content))
+}
+
+/// 逐行比较两个文件，相同时返回identical，否则返回统一格式的差异
+pub async fn diff(path1: &str, path2: &str) -> io::Result<Option<String>> {
+    let mut contents = Vec::new();
+    for path in [path1, path2] {
+        // 目录或不存在的文件由cat报错，错误信息中注明是哪个路径
+        let content = cat(path)
+            .await
+            .map_err(|e| io::Error::new(e.kind(), format!("diff: {}: {}", path, e)))?;
+        contents.push(content.unwrap_or_default());
+    }
+    let output = crate::diff::unified(path1, path2, &contents[0], &contents[1])
+        .unwrap_or_else(|| "identical".to_string());
+    trace!("finished cmd: diff [{}] [{}]", path1, path2);
+    Ok(Some(output))
+}
+
+/// 复制文件，source_path为 - 时从client读取内容，force为true时覆盖已存在的目标文件，
+/// 超过软配额时返回告警信息
 pub async fn copy(
     username: &str,
     source_path: &str,
     target_path: &str,
+    force: bool,
+    preserve: bool,
     socket: &mut TcpStream,
-) -> io::Result<()> {
-    let content = if source_path.starts_with("<host>") {
-        // 访问host目录
-        let path = source_path.strip_prefix("<host>").unwrap();
-        std::fs::read_to_string(path)?
+) -> io::Result<Option<String>> {
+    let target_path = &copy_target(source_path, target_path).await?;
+    // 需要保留元数据时先检查源文件，避免复制完才失败
+    let source_inode = if preserve {
+        if source_path.starts_with("<host>") || source_path == "-" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--preserve does not support <host> files or -",
+            ));
+        }
+        let source_inode = resolve_inode(source_path).await?;
+        let gid = get_current_user_gid(username).await;
+        // 只有root才能保留其他用户的属主
+        if source_inode.gid != gid && !able_to_modify(gid, 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "only root can preserve the ownership of other users",
+            ));
+        }
+        Some(source_inode)
     } else {
-        // 从系统中取出内容
-        temp_cd_and_do(source_path, false, |name, current_inode| {
-            Box::pin(async move { file::get_file_content(name, &current_inode).await })
+        None
+    };
+    let warning = if source_path == "-" || source_path.starts_with("<host>") {
+        // 访问host目录；源为 - 时和newfile一样从client读取内容
+        let content = match source_path.strip_prefix("<host>") {
+            Some(path) => Some(std::fs::read_to_string(path)?),
+            None => None,
+        };
+        trace!("finished get source contents");
+        temp_cd_and_do(target_path, true, |name, mut current_inode| {
+            Box::pin(async move {
+                let user_id = get_current_user_ids(username).await;
+                file::create_file(
+                    name,
+                    FileMode::RDWR,
+                    &mut current_inode,
+                    content.as_deref(),
+                    force,
+                    socket,
+                    user_id,
+                )
+                .await
+            })
         })
         .await?
-    };
-    trace!("finished get source contents");
-    temp_cd_and_do(target_path, true, |name, mut current_inode| {
-        Box::pin(async move {
-            let user_id = get_current_user_ids(username).await;
-            file::create_file(
-                name,
-                FileMode::RDWR,
-                &mut current_inode,
-                true,
-                &content,
-                socket,
-                user_id,
-            )
-            .await
+    } else {
+        // 文件系统内的复制直接按块复制源文件存储的字节
+        let source = temp_cd_and_do(source_path, false, |name, current_inode| {
+            Box::pin(async move { file::open_file(name, &current_inode).await })
         })
-    })
-    .await?;
+        .await?;
+        temp_cd_and_do(target_path, true, |name, mut current_inode| {
+            Box::pin(async move {
+                let user_id = get_current_user_ids(username).await;
+                file::copy_file(&source, name, &mut current_inode, force, user_id).await
+            })
+        })
+        .await?
+    };
+    if let Some(source_inode) = source_inode {
+        let _lock = fs_lock::write().await;
+        let mut target_inode = resolve_inode(target_path).await?;
+        target_inode.copy_metadata(&source_inode).await?;
+        if block::is_sync_immediately().await {
+            sync_all_block_cache().await?;
+        }
+    }
+    webhook::notify(EventKind::Create, target_path, username);
     trace!("finished cmd: copy [{}] to [{}]", source_path, target_path);
-    Ok(())
+    Ok(warning)
+}
+
+/// 确定复制的目标路径：目标为已存在的目录时，复制到目录下和源文件同名的文件（和cp相同）；
+/// 目标以/结尾时必须是已存在的目录
+async fn copy_target(source_path: &str, target_path: &str) -> io::Result<String> {
+    let (path, must_be_dir) = match target_path.strip_suffix('/') {
+        Some(path) => (path, true),
+        None => (target_path, false),
+    };
+    match resolve_inode(path).await {
+        Ok(inode) if inode.is_dir() => {
+            let source_name = source_path
+                .strip_prefix("<host>")
+                .unwrap_or(source_path)
+                .rsplit('/')
+                .next()
+                .filter(|name| !name.is_empty() && *name != "-")
+                .ok_or(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "copy: a file name is needed when copying - into a directory",
+                ))?;
+            Ok([path, "/", source_name].concat())
+        }
+        _ if must_be_dir => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("copy: no such directory: {}", target_path),
+        )),
+        _ => Ok(path.to_string()),
+    }
 }
 
 /// 查看超级块是否损坏，并查看位图是否出错
 pub async fn check() -> io::Result<()> {
-    simple_fs::check_bitmaps_and_fix().await?;
+    let _lock = fs_lock::write().await;
+    // 只读模式下和不带--repair的fsck一样只报告，不修改位图
+    let mut problems = simple_fs::check_bitmaps_and_fix(!CONFIG.readonly).await?;
+    // 位图和超级块的大小对不上时无法自动修复，只报告
+    problems.extend(simple_fs::check_bitmap_counts().await?);
     trace!("finished cmd: check");
+    if !problems.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            problems.join("\n"),
+        ));
+    }
     Ok(())
 }
 
+/// 遍历目录树检查位图，repair为true时按可达性重建位图，同时为lost_found时把孤立的inode挂到lost+found下
+pub async fn fsck(repair: bool, lost_found: bool) -> io::Result<Option<String>> {
+    let _lock = fs_lock::write().await;
+    let report = simple_fs::fsck(repair, lost_found).await?;
+    trace!("finished cmd: fsck");
+    Ok(Some(report))
+}
+
 /// 获取所有用户信息
 pub async fn get_users_info(username: &str) -> io::Result<Option<String>> {
     let fs = Arc::clone(&SFS);
@@ -176,8 +802,8 @@ pub async fn get_users_info(username: &str) -> io::Result<Option<String>> {
     Ok(Some(format!("{:#?}", users)))
 }
 
-/// 格式化
-pub async fn formatting(username: &str) -> io::Result<()> {
+/// 列出当前连接的会话，仅root可用
+pub async fn sessions(username: &str, current: Option<SocketAddr>) -> io::Result<Option<String>> {
     let gid = get_current_user_gid(username).await;
     if !able_to_modify(gid, 0) {
         return Err(io::Error::new(
@@ -185,12 +811,296 @@ pub async fn formatting(username: &str) -> io::Result<()> {
             "not in root",
         ));
     }
+    trace!("finished cmd: sessions");
+    Ok(Some(crate::sessions::list(current).await))
+}
+
+/// 将用户限制在专属根目录下，path为None时取消限制
+pub async fn chroot(username: &str, target_user: &str, path: Option<&str>) -> io::Result<()> {
+    let gid = get_current_user_gid(username).await;
+    if !able_to_modify(gid, 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "not in root",
+        ));
+    }
+    if let Some(path) = path {
+        // 专属根目录必须是已经存在的目录
+        if !resolve_inode(path).await?.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a directory", path),
+            ));
+        }
+    }
+    let _lock = fs_lock::write().await;
+    let fs = Arc::clone(&SFS);
+    fs.write()
+        .await
+        .user_infos
+        .set_root(target_user, path)
+        .await?;
+    if block::is_sync_immediately().await {
+        sync_all_block_cache().await?;
+    }
+    trace!("finished cmd: chroot {} {:?}", target_user, path);
+    Ok(())
+}
+
+/// 查看或设置配额：limits为None时查看target_user（默认为自己）的配额和用量，
+/// 否则由root设置target_user的(软限制, 硬限制)
+pub async fn quota(
+    username: &str,
+    target_user: Option<&str>,
+    limits: Option<(u64, u64)>,
+) -> io::Result<Option<String>> {
+    let target_user = target_user.unwrap_or(username);
+    let gid = get_current_user_gid(username).await;
+    // 只有root能设置配额或查看其他用户的配额
+    if (limits.is_some() || target_user != username) && !able_to_modify(gid, 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "not in root",
+        ));
+    }
+    let fs = Arc::clone(&SFS);
+    if let Some((soft, hard)) = limits {
+        let _lock = fs_lock::write().await;
+        fs.write()
+            .await
+            .user_infos
+            .set_quota(target_user, soft, hard)
+            .await?;
+        if block::is_sync_immediately().await {
+            sync_all_block_cache().await?;
+        }
+        trace!("finished cmd: quota {} {} {}", target_user, soft, hard);
+        return Ok(None);
+    }
+    let (uid, quota) = {
+        let read_lock = fs.read().await;
+        let uid = read_lock.get_user_ids(target_user)?.uid;
+        (uid, read_lock.user_infos.get_quota(uid).unwrap_or_default())
+    };
+    let show = |limit: u64| match limit {
+        0 => "unlimited".to_string(),
+        limit => {
+            let (size, unit) = simple_fs::show_unit(limit as usize);
+            format!("{:.1}{}", size, unit)
+        }
+    };
+    let (size, unit) = simple_fs::show_unit(inode::user_usage(uid).await?);
+    let mut infos = format!(
+        "user:\t{}\nusage:\t{:.1}{}\nsoft:\t{}\nhard:\t{}",
+        target_user,
+        size,
+        unit,
+        show(quota.soft),
+        show(quota.hard)
+    );
+    if quota.exceeded_at != 0 {
+        let left = (quota.exceeded_at + QUOTA_GRACE_PERIOD).saturating_sub(inode::now_secs());
+        infos.push_str(&format!("\ngrace:\t{}s left", left));
+    }
+    trace!("finished cmd: quota");
+    Ok(Some(infos))
+}
+
+/// 列出用户的历史命令，term为Some时只列出包含term的命令
+pub async fn history(username: &str, term: Option<&str>) -> io::Result<Option<String>> {
+    let lines: Vec<_> = history::search(username, term)
+        .await
+        .into_iter()
+        .map(|(i, command)| format!("{:>5}  {}", i, command))
+        .collect();
+    trace!("finished cmd: history");
+    if lines.is_empty() {
+        return Ok(Some("no history".to_string()));
+    }
+    Ok(Some(lines.join("\n")))
+}
+
+/// 格式化，client必须回复`FORMAT_CONFIRM_TOKEN`才会执行
+pub async fn formatting(username: &str, socket: &mut TcpStream) -> io::Result<()> {
+    let gid = get_current_user_gid(username).await;
+    if !able_to_modify(gid, 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "not in root",
+        ));
+    }
+    // 向client请求确认
+    socket.write_all(utils::FORMAT_CONFIRM.as_bytes()).await?;
+    let mut response = [0; utils::SOCKET_BUFFER_SIZE];
+    let n = socket.read(&mut response).await?;
+    if n == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "cannot read from client",
+        ));
+    }
+    let response = String::from_utf8_lossy(&response[..n]);
+    if response.trim() != utils::FORMAT_CONFIRM_TOKEN {
+        info!("formatting cancel, input {}", response);
+        return Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            "formatting cancelled",
+        ));
+    }
+    let _lock = fs_lock::write().await;
     let fs = Arc::clone(&SFS);
     fs.write().await.force_clear().await;
     trace!("finished cmd: formatting");
     Ok(())
 }
 
+/// 在server内创建size字节的文件、读回后删除，测量读写速度和块缓存命中率，仅root可用；
+/// 写入的耗时包含按缓存策略写回磁盘的时间，统计中包含期间其他连接的读写
+pub async fn bench(
+    username: &str,
+    size: usize,
+    socket: &mut TcpStream,
+) -> io::Result<Option<String>> {
+    let gid = get_current_user_gid(username).await;
+    if !able_to_modify(gid, 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "not in root",
+        ));
+    }
+    if size == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "bench size must be positive",
+        ));
+    }
+    // 构造内容之前先检查大小，超出单个文件上限或空闲空间时直接拒绝
+    if size > MAX_FILE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::OutOfMemory,
+            "File size limit exceed",
+        ));
+    }
+    let free = bitmap::count_valid_data_blocks().await * BLOCK_SIZE;
+    if size > free {
+        return Err(io::Error::new(
+            io::ErrorKind::OutOfMemory,
+            format!("bench size exceeds free space of {} bytes", free),
+        ));
+    }
+    let path = ["~/", BENCH_FILE_NAME].concat();
+    let content: String = (0..size).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+    let stats = || async { Arc::clone(&BLOCK_CACHE_MANAGER).read().await.stats };
+
+    // 写入
+    let before = stats().await;
+    let start = Instant::now();
+    temp_cd_and_do(&path, true, |name, mut current_inode| {
+        Box::pin(async move {
+            file::create_file(
+                name,
+                FileMode::RDWR,
+                &mut current_inode,
+                Some(&content),
+                false,
+                socket,
+                (gid, gid),
+            )
+            .await
+        })
+    })
+    .await?;
+    let write_time = start.elapsed();
+    let after_write = stats().await;
+
+    // 读回
+    let start = Instant::now();
+    let read = {
+        let _lock = fs_lock::read().await;
+        temp_cd_and_do(&path, false, |name, current_inode| {
+            Box::pin(async move { file::get_file_content(name, &current_inode).await })
+        })
+        .await
+    };
+    let read_time = start.elapsed();
+    let after_read = stats().await;
+
+    // 删除，读回失败时也要删除
+    {
+        let _lock = fs_lock::write().await;
+        temp_cd_and_do(&path, true, |name, mut current_inode| {
+            Box::pin(async move { file::remove_file(name, &mut current_inode, gid).await })
+        })
+        .await?;
+    }
+    if read?.len() != size {
+        return Err(io::Error::other("bench file read back with wrong size"));
+    }
+
+    let speed = |time: Duration| size as f64 / (1024.0 * 1024.0) / time.as_secs_f64();
+    let hit_rate = |from: block::BlockIoStats, to: block::BlockIoStats| {
+        let hits = to.cache_hits - from.cache_hits;
+        let reads = to.disk_reads - from.disk_reads;
+        if hits + reads == 0 {
+            0.0
+        } else {
+            hits as f64 / (hits + reads) as f64 * 100.0
+        }
+    };
+    let (size, unit) = simple_fs::show_unit(size);
+    let infos = [
+        format!("size:\t\t{:.1}{}\n", size, unit),
+        format!(
+            "write:\t\t{:.1}MB/s ({:?}), cache hit {:.1}%\n",
+            speed(write_time),
+            write_time,
+            hit_rate(before, after_write)
+        ),
+        format!(
+            "read:\t\t{:.1}MB/s ({:?}), cache hit {:.1}%\n",
+            speed(read_time),
+            read_time,
+            hit_rate(after_write, after_read)
+        ),
+        format!("cache hit:\t{:.1}%", hit_rate(before, after_read)),
+    ];
+    trace!("finished cmd: bench");
+    Ok(Some(infos.concat()))
+}
+
+/// 在线扩容到new_size字节，仅root可用
+pub async fn resize(username: &str, new_size: usize) -> io::Result<Option<String>> {
+    let gid = get_current_user_gid(username).await;
+    if !able_to_modify(gid, 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "not in root",
+        ));
+    }
+    let _lock = fs_lock::write().await;
+    simple_fs::grow(new_size / BLOCK_SIZE).await?;
+    trace!("finished cmd: resize");
+    Ok(Some(Arc::clone(&SFS).read().await.info().await))
+}
+
+/// 将文件的数据块整理到一段连续的块中，仅root可用
+pub async fn defrag(username: &str, path: &str) -> io::Result<Option<String>> {
+    let gid = get_current_user_gid(username).await;
+    if !able_to_modify(gid, 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "not in root",
+        ));
+    }
+    let _lock = fs_lock::write().await;
+    let output = temp_cd_and_do(path, true, |name, current_inode| {
+        Box::pin(async move { file::defrag_file(name, &current_inode).await })
+    })
+    .await?;
+    trace!("finished cmd: defrag");
+    Ok(Some(output))
+}
+
 pub async fn set_block_cache_method(method: &str) -> io::Result<()> {
     let manager = Arc::clone(&BLOCK_CACHE_MANAGER);
     let mut write_lock = manager.write().await;
@@ -230,7 +1140,7 @@ where
     let mut name = None;
     if let Some((path, filename)) = absolute_path.rsplit_once('/') {
         // 尝试进入目录
-        current_inode = dirent::cd(path, &current_inode).await?;
+        current_inode = cwd_cache::cd(path, &current_inode).await?;
         name = Some(filename)
     }
     // 执行f的操作，失败则f的错误信息
@@ -245,6 +1155,20 @@ where
     }
 }
 
+/// 根据绝对路径找到对应的inode，路径可以指向文件或目录
+async fn resolve_inode(absolute_path: &str) -> io::Result<Inode> {
+    let root = Arc::clone(&SFS).read().await.root_inode.clone();
+    let path = absolute_path.trim_end_matches('/');
+    match path.rsplit_once('/') {
+        Some((parent_path, name)) => {
+            let parent_inode = cwd_cache::cd(parent_path, &root).await?;
+            dirent::find_inode(name, &parent_inode).await
+        }
+        // 只有~，即根目录
+        None => Ok(root),
+    }
+}
+
 /// 获取当前用户的id
 async fn get_current_user_ids(username: &str) -> (UserIdType, UserIdType) {
     let fs = Arc::clone(&SFS);
@@ -259,3 +1183,424 @@ async fn get_current_user_gid(username: &str) -> UserIdType {
     let r = fs.read().await;
     r.get_user_gid(username).unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_constants::DIRECT_BLOCK_NUM;
+    use crate::test_utils::{
+        add_user, exec, exec_as, exec_declining, lookup, mktree, redirect_dirent, setup,
+        used_blocks,
+    };
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn delete_then_undelete_restores_content() {
+        let _fs = setup().await;
+        let content = "t".repeat(3000);
+        mktree(&["a/", &format!("a/x.txt:{}", content)]).await;
+        let used = used_blocks().await;
+
+        exec("del a/x.txt").await.unwrap();
+        assert!(exec("cat a/x.txt").await.is_err());
+        let trash = exec("dir ~/.trash").await.unwrap().unwrap();
+        assert!(trash.contains("x.txt"), "{}", trash);
+        // 回收站中的文件仍然占用空间，新建的回收站目录另外占用一个块
+        assert_eq!(used_blocks().await, used + 1);
+
+        exec("undelete a/x.txt").await.unwrap();
+        assert_eq!(exec("cat a/x.txt").await.unwrap().unwrap(), content);
+        let trash = exec("dir ~/.trash").await.unwrap().unwrap();
+        assert!(!trash.contains("x.txt"), "{}", trash);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn delete_then_emptytrash_frees_blocks() {
+        let _fs = setup().await;
+        mktree(&["a/"]).await;
+        let before = used_blocks().await;
+        mktree(&[&format!("a/x.txt:{}", "t".repeat(3000))]).await;
+        assert!(used_blocks().await > before);
+
+        exec("del a/x.txt").await.unwrap();
+        let with_trash = used_blocks().await;
+        exec("emptytrash").await.unwrap();
+        // 文件的块被释放，只剩回收站目录本身的块
+        let after = used_blocks().await;
+        assert!(after < with_trash);
+        assert!(after <= before + 1, "before {}, after {}", before, after);
+        let trash = exec("dir ~/.trash").await.unwrap().unwrap();
+        assert!(!trash.contains("x.txt"), "{}", trash);
+        assert!(exec("undelete a/x.txt").await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn copy_refuses_existing_target_by_default() {
+        let _fs = setup().await;
+        mktree(&["a.txt:new", "b.txt:old"]).await;
+
+        let err = exec("copy a.txt b.txt").await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists, "{}", err);
+        assert_eq!(exec("cat b.txt").await.unwrap().unwrap(), "old");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn copy_force_overwrites_and_frees_old_blocks() {
+        let _fs = setup().await;
+        mktree(&["a.txt:new", &format!("b.txt:{}", "o".repeat(5000))]).await;
+        let used = used_blocks().await;
+
+        exec("copy -f a.txt b.txt").await.unwrap();
+        assert_eq!(exec("cat b.txt").await.unwrap().unwrap(), "new");
+        // 旧内容占用的块被释放
+        assert!(used_blocks().await < used);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn copy_preserve_keeps_metadata() {
+        let _fs = setup().await;
+        add_user("alice").await;
+        let ids = SFS.read().await.get_user_ids("alice").unwrap();
+        let mut root = mktree(&["dst/"]).await;
+        file::create_file_with_content(
+            "a.txt",
+            FileMode::RDONLY,
+            &mut root,
+            "hello",
+            false,
+            (ids.gid, ids.uid),
+        )
+        .await
+        .unwrap();
+        // 让复制时的时间和源文件不同
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+        exec("copy a.txt dst/plain.txt").await.unwrap();
+        exec("copy --preserve a.txt dst/kept.txt").await.unwrap();
+        let source = lookup("a.txt").await;
+        let metadata = |inode: &Inode| {
+            (
+                inode.get_mode(),
+                inode.gid,
+                inode.get_uid(),
+                inode.get_created_at(),
+            )
+        };
+        assert_eq!(metadata(&lookup("dst/kept.txt").await), metadata(&source));
+        let plain = lookup("dst/plain.txt").await;
+        assert_ne!(plain.gid, source.gid);
+        assert_ne!(plain.get_created_at(), source.get_created_at());
+        assert_eq!(exec("cat dst/kept.txt").await.unwrap().unwrap(), "hello");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn only_root_preserves_others_ownership() {
+        let _fs = setup().await;
+        mktree(&["a.txt:root's"]).await;
+        add_user("alice").await;
+
+        let err = exec_as(
+            "alice",
+            "~",
+            "copy --preserve ~/a.txt ~/home/alice/a.txt",
+            &[],
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied, "{}", err);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn declined_formatting_keeps_files() {
+        let _fs = setup().await;
+        mktree(&["a/b.txt:kept"]).await;
+        let used = used_blocks().await;
+
+        // 只有完全一致的确认口令才会格式化
+        let err = exec_declining("formatting").await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Interrupted, "{}", err);
+        assert_eq!(exec("cat a/b.txt").await.unwrap().unwrap(), "kept");
+        assert_eq!(used_blocks().await, used);
+
+        exec("formatting").await.unwrap();
+        assert!(exec("cat a/b.txt").await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn treehash_compares_directory_contents() {
+        let _fs = setup().await;
+        let big = "z".repeat(3000);
+        let tree = |root: &str| {
+            [
+                format!("{}/x.txt:same", root),
+                format!("{}/sub/y.txt:{}", root, big),
+                format!("{}/empty/", root),
+            ]
+        };
+        let (a, b) = (tree("a"), tree("b"));
+        let spec: Vec<_> = a.iter().chain(b.iter()).map(|s| s.as_str()).collect();
+        mktree(&spec).await;
+        mktree(&["other.txt:changed"]).await;
+        let hash = |path: &'static str| async move {
+            let output = exec(&format!("treehash {}", path)).await.unwrap().unwrap();
+            output.split('\t').next().unwrap().to_string()
+        };
+
+        assert_eq!(hash("a").await, hash("b").await);
+        exec("copy -f other.txt b/sub/y.txt").await.unwrap();
+        assert_ne!(hash("a").await, hash("b").await);
+        // 改回原内容后再次相等
+        exec("copy -f a/sub/y.txt b/sub/y.txt").await.unwrap();
+        assert_eq!(hash("a").await, hash("b").await);
+        exec("rename b/x.txt x.md").await.unwrap();
+        assert_ne!(hash("a").await, hash("b").await);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mkfile_allocates_direct_and_indirect_blocks() {
+        let _fs = setup().await;
+        let per_block = BLOCK_SIZE / std::mem::size_of::<crate::block::BlockIDType>();
+        // 文件大小（K）和应占用的块数（数据块加上各级索引块）
+        let cases = [
+            ("direct.bin", 8, 8),
+            ("first.bin", 8 + per_block, 8 + per_block + 1),
+            (
+                "second.bin",
+                2048,
+                2048 + 1 + 1 + (2048 - 8 - per_block).div_ceil(per_block),
+            ),
+        ];
+        for (name, size, blocks) in cases {
+            let used = used_blocks().await;
+            exec(&format!("mkfile {} {}K", name, size)).await.unwrap();
+            assert_eq!(used_blocks().await - used, blocks, "{}", name);
+            assert_eq!(lookup(name).await.get_size(), size * BLOCK_SIZE);
+        }
+        assert!(exec("mkfile direct.bin 1K").await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn exists_reports_kind_without_erroring() {
+        let _fs = setup().await;
+        mktree(&["a/b/", "a/x.txt:x"]).await;
+        let exists = |path: &'static str| async move {
+            exec(&format!("exists {}", path)).await.unwrap().unwrap()
+        };
+
+        assert_eq!(exists("a/x.txt").await, "true file");
+        assert_eq!(exists("a").await, "true dir");
+        assert_eq!(exists("a/b/").await, "true dir");
+        assert_eq!(exists("~").await, "true dir");
+        // 不存在的路径、上级目录不存在或不是目录时都返回false
+        assert_eq!(exists("a/y.txt").await, "false");
+        assert_eq!(exists("missing/x.txt").await, "false");
+        assert_eq!(exists("a/x.txt/y").await, "false");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stat_shows_the_inode_of_the_path() {
+        let _fs = setup().await;
+        mktree(&["a/", "a/x.txt:hello"]).await;
+        let (dir, file) = (lookup("a").await, lookup("a/x.txt").await);
+        assert_ne!(dir.inode_id, file.inode_id);
+
+        let output = exec("stat a/x.txt").await.unwrap().unwrap();
+        assert!(
+            output.starts_with("  File: ~/a/x.txt\n  Type: file"),
+            "{}",
+            output
+        );
+        assert!(output.contains("  Size: 5\t"), "{}", output);
+        assert!(
+            output.contains(&format!(" Inode: {}\tLinks: 1", file.inode_id)),
+            "{}",
+            output
+        );
+        assert!(output.contains("Owner: root"), "{}", output);
+        let output = exec("stat a").await.unwrap().unwrap();
+        assert!(output.contains("  Type: directory"), "{}", output);
+        assert!(
+            output.contains(&format!(" Inode: {}\t", dir.inode_id)),
+            "{}",
+            output
+        );
+
+        // 没有符号链接，不接受--follow
+        assert!(exec("stat --follow a/x.txt").await.is_err());
+        assert!(exec("stat a/missing.txt").await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bench_reports_plausible_numbers() {
+        let _fs = setup().await;
+        add_user("alice").await;
+        let blocks = used_blocks().await;
+
+        let report = exec("bench 1M").await.unwrap().unwrap();
+        let field = |name: &str| {
+            let line = report
+                .lines()
+                .find(|line| line.starts_with(name))
+                .unwrap_or_else(|| panic!("{} missing in {}", name, report));
+            line.split_once(':').unwrap().1.trim().to_string()
+        };
+        assert_eq!(field("size"), "1.0MiB");
+        for name in ["write", "read"] {
+            let value = field(name);
+            let speed: f64 = value.split_once("MB/s").unwrap().0.parse().unwrap();
+            assert!(speed > 0.0 && speed.is_finite(), "{}", report);
+        }
+        let hit_rate: f64 = field("cache hit").trim_end_matches('%').parse().unwrap();
+        assert!((0.0..=100.0).contains(&hit_rate), "{}", report);
+
+        // 测试文件已删除，不占用空间
+        assert!(exec(&["cat ", BENCH_FILE_NAME].concat()).await.is_err());
+        assert_eq!(used_blocks().await, blocks);
+
+        assert!(exec("bench 0").await.is_err());
+        let err = exec_as("alice", "~", "bench 1M", &[]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stat_inode_reads_root_and_orphans() {
+        let _fs = setup().await;
+        mktree(&["a.txt:hello", "b.txt:bye"]).await;
+        add_user("alice").await;
+
+        let root = exec("stat --inode 0").await.unwrap().unwrap();
+        assert!(root.starts_with("inode 0: bitmap bit set\n"), "{}", root);
+        assert!(root.contains("inode_type: Diretory"), "{}", root);
+        assert!(root.contains("addr: ["), "{}", root);
+
+        // a.txt的目录项改为指向b.txt后，a.txt的inode仍然占用但不可达
+        let id = lookup("a.txt").await.inode_id;
+        redirect_dirent("a.txt", lookup("b.txt").await.inode_id).await;
+        let output = exec(&format!("stat --inode {}", id))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(
+            output.starts_with(&format!("inode {}: bitmap bit set\n", id)),
+            "{}",
+            output
+        );
+        assert!(output.contains("inode_type: File"), "{}", output);
+        assert!(output.contains("size: 5,"), "{}", output);
+        assert!(!output.contains("does not match"), "{}", output);
+
+        // 没有申请过的inode
+        let unused = INODE_MAX_NUM - 1;
+        let output = exec(&format!("stat --inode {}", unused))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(output.contains("bitmap bit clear"), "{}", output);
+
+        assert!(exec(&format!("stat --inode {}", INODE_MAX_NUM))
+            .await
+            .is_err());
+        let err = exec_as("alice", "~", "stat --inode 0", &[])
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn copy_from_client_creates_the_target() {
+        let _fs = setup().await;
+        mktree(&["docs/"]).await;
+        let body = "first line\nsecond line\n\nlast line";
+        exec_as("root", "~", "copy - docs/out.txt", &[body])
+            .await
+            .unwrap();
+        assert_eq!(exec("cat docs/out.txt").await.unwrap().unwrap(), body);
+
+        // 已存在时和其他来源一样需要-f
+        assert!(exec_as("root", "~", "copy - docs/out.txt", &["again"])
+            .await
+            .is_err());
+        exec_as("root", "~", "copy -f - docs/out.txt", &["again"])
+            .await
+            .unwrap();
+        assert_eq!(exec("cat docs/out.txt").await.unwrap().unwrap(), "again");
+        assert!(exec("copy --preserve - docs/p.txt").await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn blocks_shows_the_indirect_levels() {
+        let _fs = setup().await;
+        let content = "k".repeat((DIRECT_BLOCK_NUM + 3) * BLOCK_SIZE);
+        exec_as("root", "~", "newfile big.txt", &[&content])
+            .await
+            .unwrap();
+        let output = exec("blocks big.txt").await.unwrap().unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), DIRECT_BLOCK_NUM + 4, "{}", output);
+
+        // 按逻辑顺序：先是直接块，再是一级间接块指向的块
+        let inode = lookup("big.txt").await;
+        let data_blocks = block::get_all_blocks(&inode).await.unwrap();
+        for (i, (line, (_, block_id, _))) in lines.iter().zip(&data_blocks).enumerate() {
+            let level = if i < DIRECT_BLOCK_NUM {
+                "Direct"
+            } else {
+                "FirstIndirect"
+            };
+            assert_eq!(*line, format!("{}\t{}", level, block_id));
+        }
+        assert_eq!(
+            lines.last().unwrap(),
+            &format!("{} data blocks, 1 index blocks", DIRECT_BLOCK_NUM + 3)
+        );
+
+        // 只用直接块的文件没有间接块
+        exec_as(
+            "root",
+            "~",
+            "newfile small.txt",
+            &[&"s".repeat(2 * BLOCK_SIZE)],
+        )
+        .await
+        .unwrap();
+        let output = exec("blocks small.txt").await.unwrap().unwrap();
+        assert!(output
+            .lines()
+            .take(2)
+            .all(|line| line.starts_with("Direct\t")));
+        assert!(
+            output.ends_with("2 data blocks, 0 index blocks"),
+            "{}",
+            output
+        );
+        assert!(exec("blocks missing.txt").await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn copy_into_a_directory_keeps_the_name() {
+        let _fs = setup().await;
+        mktree(&["a.txt:original", "dir/", "sub/b.txt:nested"]).await;
+
+        exec("copy a.txt ~/dir/").await.unwrap();
+        assert_eq!(exec("cat dir/a.txt").await.unwrap().unwrap(), "original");
+        // 没有结尾的/时，已存在的目录同样作为目标目录
+        exec("copy sub/b.txt dir").await.unwrap();
+        assert_eq!(exec("cat dir/b.txt").await.unwrap().unwrap(), "nested");
+        assert!(lookup("dir").await.is_dir());
+
+        // 明确给出文件名时照旧
+        exec("copy a.txt dir/renamed.txt").await.unwrap();
+        assert_eq!(
+            exec("cat dir/renamed.txt").await.unwrap().unwrap(),
+            "original"
+        );
+        exec("copy a.txt c.txt").await.unwrap();
+        assert_eq!(exec("cat c.txt").await.unwrap().unwrap(), "original");
+
+        // 以/结尾但不是已存在的目录
+        let err = exec("copy a.txt missing/").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound, "{}", err);
+        assert!(exec("copy a.txt c.txt/").await.is_err());
+        // 目录下已有同名文件时和普通的复制一样报错
+        assert!(exec("copy a.txt dir/").await.is_err());
+    }
+}
@@ -0,0 +1,233 @@
+//! 测试用的辅助函数
+//!
+//! 各模块的测试共享全局的文件系统，`setup`独占并重新格式化内存中的文件系统，
+//! `mktree`按描述直接建立目录树，`exec`像client一样执行一条命令
+use std::io::Error;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{Mutex, MutexGuard},
+    task::JoinHandle,
+};
+
+use crate::{
+    bitmap,
+    block::{self, BLOCK_CACHE_MANAGER},
+    cwd_cache, device,
+    dirent::{self, DirEntry},
+    do_command, file,
+    fs_constants::{BLOCK_SIZE, DIRENTRY_SIZE},
+    fs_lock,
+    inode::{FileMode, Inode, InodeIdType},
+    simple_fs::SFS,
+};
+
+lazy_static! {
+    static ref FS_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// 独占文件系统并格式化，返回的guard释放前其他测试等待
+pub async fn setup() -> MutexGuard<'static, ()> {
+    let guard = FS_LOCK.lock().await;
+    // 之前的测试可能umount了镜像
+    BLOCK_CACHE_MANAGER
+        .write()
+        .await
+        .replace_device(device::open());
+    SFS.write().await.force_clear().await;
+    guard
+}
+
+/// 按描述建立目录树并返回根目录的inode：以/结尾的为目录，`路径:内容`为文件，
+/// 路径相对于根目录，缺少的上级目录自动创建，已存在的目录跳过
+pub async fn mktree(spec: &[&str]) -> Inode {
+    for entry in spec {
+        let (path, content) = match entry.split_once(':') {
+            Some((path, content)) => (path, Some(content)),
+            None => (entry.trim_end_matches('/'), None),
+        };
+        let components: Vec<_> = path.split('/').collect();
+        let (name, dirs) = components.split_last().unwrap();
+        let mut parent = Inode::read(0).await.unwrap();
+        for dir in dirs {
+            parent = enter_or_make(dir, parent).await;
+        }
+        match content {
+            Some(content) => {
+                file::create_file_with_content(
+                    name,
+                    FileMode::RDWR,
+                    &mut parent,
+                    content,
+                    false,
+                    (0, 0),
+                )
+                .await
+                .unwrap();
+            }
+            None => {
+                enter_or_make(name, parent).await;
+            }
+        }
+    }
+    Inode::read(0).await.unwrap()
+}
+
+/// 进入parent下名为name的目录，不存在时以root的身份创建
+async fn enter_or_make(name: &str, mut parent: Inode) -> Inode {
+    let path = ["~/", name].concat();
+    if let Ok(inode) = dirent::cd(&path, &parent).await {
+        return inode;
+    }
+    let _lock = fs_lock::write().await;
+    dirent::make_directory(name, &mut parent, 0, 0)
+        .await
+        .unwrap();
+    dirent::cd(&path, &parent).await.unwrap()
+}
+
+/// 注册一个普通用户，密码和用户名相同
+pub async fn add_user(username: &str) {
+    let _lock = fs_lock::write().await;
+    SFS.write().await.sign_up(username, username).await.unwrap();
+}
+
+/// 查找相对于根目录的路径所指的inode
+pub async fn lookup(path: &str) -> Inode {
+    let root = Inode::read(0).await.unwrap();
+    let (parent, name) = match path.rsplit_once('/') {
+        Some((parent, name)) => (["~/", parent].concat(), name),
+        None => ("~".to_string(), path),
+    };
+    let parent = dirent::cd(&parent, &root).await.unwrap();
+    dirent::find_inode(name, &parent).await.unwrap()
+}
+
+/// 把相对于根目录的路径所对应的目录项改为指向inode_id，用于制造损坏的目录项
+pub async fn redirect_dirent(path: &str, inode_id: InodeIdType) {
+    let (parent, name) = match path.rsplit_once('/') {
+        Some((parent, name)) => (lookup(parent).await, name),
+        None => (Inode::read(0).await.unwrap(), path),
+    };
+    for (_, block_id, dirent) in DirEntry::get_all_dirent(&parent).await.unwrap() {
+        if dirent.get_filename() != name {
+            continue;
+        }
+        for i in 0..BLOCK_SIZE / DIRENTRY_SIZE {
+            let start = i * DIRENTRY_SIZE;
+            let buffer = block::get_block_buffer(block_id as usize, start, start + DIRENTRY_SIZE)
+                .await
+                .unwrap();
+            if buffer[0] != 0 && block::deserialize::<DirEntry>(&buffer).unwrap() == dirent {
+                let mut redirected = dirent.clone();
+                redirected.inode_id = inode_id;
+                block::write_block(&redirected, block_id as usize, start)
+                    .await
+                    .unwrap();
+                return;
+            }
+        }
+    }
+    panic!("{} not found", path);
+}
+
+/// 已申请的数据块数
+pub async fn used_blocks() -> usize {
+    bitmap::count_data_blocks().await.0
+}
+
+/// 以root的身份在根目录下执行一条命令，返回命令的输出
+pub async fn exec(line: &str) -> Result<Option<String>, Error> {
+    exec_as("root", "~", line, &[]).await
+}
+
+/// 以username的身份在cwd下执行一条命令；命令需要输入文件内容时依次发送inputs，需要确认时总是确认
+pub async fn exec_as(
+    username: &str,
+    cwd: &str,
+    line: &str,
+    inputs: &[&str],
+) -> Result<Option<String>, Error> {
+    run(username, cwd, line, inputs, true).await
+}
+
+/// 以root的身份在根目录下执行一条命令，需要确认时总是拒绝
+pub async fn exec_declining(line: &str) -> Result<Option<String>, Error> {
+    run("root", "~", line, &[], false).await
+}
+
+async fn run(
+    username: &str,
+    cwd: &str,
+    line: &str,
+    inputs: &[&str],
+    confirm: bool,
+) -> Result<Option<String>, Error> {
+    let (mut socket, client) = connect(inputs, confirm).await;
+    let mut args = vec![username, cwd];
+    args.extend(line.split_whitespace());
+    let result = cwd_cache::scope(do_command(args, username, &mut socket)).await;
+    drop(socket);
+    client.await.unwrap();
+    result
+}
+
+/// 建立本机的一对socket，返回server端和在后台应答server的模拟client，
+/// confirm为false时拒绝所有确认
+async fn connect(inputs: &[&str], confirm: bool) -> (TcpStream, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut inputs: Vec<String> = inputs.iter().rev().map(|input| input.to_string()).collect();
+    let client = tokio::spawn(async move {
+        let mut buffer = [0; utils::SOCKET_BUFFER_SIZE];
+        loop {
+            let n = match client.read(&mut buffer).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            let msg = String::from_utf8_lossy(&buffer[..n]).to_string();
+            if let Some(addr) = msg.strip_prefix(utils::INPUT_FILE_CONTENT) {
+                let input = inputs.pop().expect("command asks for more inputs");
+                utils::send_content(input, addr).await.unwrap();
+            } else if msg.starts_with(utils::COMMAND_CONFIRM) {
+                let answer = if confirm { "y" } else { "n" };
+                client.write_all(answer.as_bytes()).await.unwrap();
+            } else if msg.starts_with(utils::FORMAT_CONFIRM) {
+                let answer = if confirm {
+                    utils::FORMAT_CONFIRM_TOKEN
+                } else {
+                    "yes"
+                };
+                client.write_all(answer.as_bytes()).await.unwrap();
+            }
+        }
+    });
+    (socket, client)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mktree_builds_the_described_tree() {
+    let _fs = setup().await;
+    let root = mktree(&["a/", "a/b.txt:hello", "c.txt:world", "d/e/"]).await;
+    assert_eq!(root.inode_id, 0);
+
+    let listing = exec("dir").await.unwrap().unwrap();
+    for name in ["a", "c.txt", "d"] {
+        assert!(listing.contains(name), "{} missing in {}", name, listing);
+    }
+    let listing = exec("dir a").await.unwrap().unwrap();
+    assert!(listing.contains("b.txt"), "{}", listing);
+    assert!(exec("dir d/e").await.is_ok());
+
+    assert_eq!(exec("cat a/b.txt").await.unwrap().unwrap(), "hello");
+    assert_eq!(exec("cat c.txt").await.unwrap().unwrap(), "world");
+
+    // 已存在的目录跳过
+    mktree(&["a/", "a/f.txt:again"]).await;
+    assert_eq!(exec("cat a/f.txt").await.unwrap().unwrap(), "again");
+    assert_eq!(exec("cat a/b.txt").await.unwrap().unwrap(), "hello");
+}
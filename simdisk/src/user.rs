@@ -2,8 +2,17 @@ use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, io::Error};
 
 use crate::{
-    block::{deserialize, get_block_buffer, write_block},
-    fs_constants::{BLOCK_SIZE, USER_START_BYTE},
+    bitmap::{alloc_bits, dealloc_data_bits, BitmapType},
+    block::{
+        deserialize, get_block_buffer, get_blocks_buffers, write_block, write_bytes_to_block,
+        write_file_content_to_blocks, BlockIDType,
+    },
+    fs_constants::{
+        ALLOC_CURSOR_START_BYTE, BLOCK_SIZE, DATA_START_BLOCK, QUOTA_GRACE_PERIOD,
+        USER_BLOCKS_MARKER, USER_START_BYTE,
+    },
+    inode::now_secs,
+    simple_fs::show_unit,
 };
 
 pub type UserIdType = u16;
@@ -17,29 +26,61 @@ pub struct UserIdGroup {
 // map{username: (password, (gid,uid))}
 pub type UserInfo = HashMap<String, (String, UserIdGroup)>;
 
+/// 用户的空间配额（字节），为0表示不限制
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Quota {
+    pub soft: u64,        // 软限制，超过时告警，宽限期内仍允许写入
+    pub hard: u64,        // 硬限制，超过时拒绝写入
+    pub exceeded_at: u64, // 开始超过软限制的时间戳，未超过时为0
+}
+
+/// 用户信息放不下0号块时，0号块的USER_START_BYTE处改为存放该索引，
+/// 序列化后的用户信息依次存放在数据区的blocks中
+#[derive(Serialize, Deserialize)]
+struct UserBlocks {
+    marker: u64,              // 恒为USER_BLOCKS_MARKER
+    len: u64,                 // 序列化后的字节数
+    blocks: Vec<BlockIDType>, // 存放用户信息的数据块
+}
+
 #[derive(Serialize, Deserialize, Default)]
 pub struct User {
     pub info: UserInfo, // 存储所有用户的信息
     max_id: UserIdType,
+    roots: HashMap<String, String>, // 被限制在专属根目录下的用户 map{username: 根目录绝对路径}
+    quotas: HashMap<UserIdType, Quota>, // 设置了配额的用户 map{uid: 配额}
 }
 
 impl User {
     /// 初始化创建root用户
-    pub async fn init() -> Self {
+    pub async fn init() -> Result<Self, Error> {
         let mut s = Self {
             info: HashMap::new(),
             max_id: 1,
+            roots: HashMap::new(),
+            quotas: HashMap::new(),
         };
         let info = UserIdGroup { gid: 0, uid: 0 };
         s.info.insert("root".to_owned(), ("admin".to_owned(), info));
-        s.cache().await;
-        s
+        s.cache().await?;
+        Ok(s)
     }
 
     /// 从磁盘中读取用户信息
     pub async fn read() -> Result<Self, Error> {
         let buffer = get_block_buffer(0, USER_START_BYTE, BLOCK_SIZE).await?;
-        deserialize(&buffer)
+        let index = match read_index(&buffer)? {
+            Some(index) => index,
+            None => return deserialize(&buffer),
+        };
+        let args: Vec<_> = index
+            .blocks
+            .iter()
+            .map(|id| (*id as usize, 0, BLOCK_SIZE))
+            .collect();
+        let mut bytes = get_blocks_buffers(&args).await?.concat();
+        bytes.truncate(index.len as usize);
+        deserialize(&bytes)
     }
 
     /// 注册用户
@@ -57,7 +98,12 @@ impl User {
         self.max_id += 1;
         self.info
             .insert(username.to_owned(), (password.to_owned(), info));
-        self.cache().await;
+        // 写入失败时撤销注册，内存中的用户信息和磁盘保持一致
+        if let Err(e) = self.cache().await {
+            self.info.remove(username);
+            self.max_id -= 1;
+            return Err(e);
+        }
         Ok(())
     }
 
@@ -80,26 +126,299 @@ impl User {
         }
     }
 
-    /// 根据uid得到用户名
-    pub fn get_user_name(&self, uid: UserIdType) -> Result<String, Error> {
-        match self.info.iter().find_map(|(username, (_, ids))| {
-            if ids.uid == uid {
-                Some(username.to_string())
-            } else {
-                None
+    /// 获取所有用户的 map{uid: username}
+    pub fn get_user_names(&self) -> HashMap<UserIdType, String> {
+        self.info
+            .iter()
+            .map(|(username, (_, ids))| (ids.uid, username.to_string()))
+            .collect()
+    }
+
+    /// 获取用户的专属根目录，没有限制时返回None
+    pub fn get_root(&self, username: &str) -> Option<String> {
+        self.roots.get(username).cloned()
+    }
+
+    /// 设置用户的专属根目录，root为None时取消限制
+    pub async fn set_root(&mut self, username: &str, root: Option<&str>) -> Result<(), Error> {
+        if !self.info.contains_key(username) {
+            return Err(Error::new(std::io::ErrorKind::NotFound, "user not exists"));
+        }
+        if username == "root" {
+            return Err(Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "cannot chroot root",
+            ));
+        }
+        match root {
+            Some(root) => self.roots.insert(username.to_owned(), root.to_owned()),
+            None => self.roots.remove(username),
+        };
+        self.cache().await
+    }
+
+    /// 获取用户的配额，没有设置时返回None
+    pub fn get_quota(&self, uid: UserIdType) -> Option<Quota> {
+        self.quotas.get(&uid).cloned()
+    }
+
+    /// 设置用户的配额，soft和hard都为0时取消限制
+    pub async fn set_quota(&mut self, username: &str, soft: u64, hard: u64) -> Result<(), Error> {
+        let uid = match self.info.get(username) {
+            Some((_, ids)) => ids.uid,
+            None => return Err(Error::new(std::io::ErrorKind::NotFound, "user not exists")),
+        };
+        if username == "root" {
+            return Err(Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "cannot set quota for root",
+            ));
+        }
+        if hard != 0 && soft > hard {
+            return Err(Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "soft limit should not exceed hard limit",
+            ));
+        }
+        if soft == 0 && hard == 0 {
+            self.quotas.remove(&uid);
+        } else {
+            let quota = self.quotas.entry(uid).or_default();
+            quota.soft = soft;
+            quota.hard = hard;
+            quota.exceeded_at = 0;
+        }
+        self.cache().await
+    }
+
+    /// 按写入后的用量usage检查用户的配额：超过硬限制或软限制的宽限期已过时err，
+    /// 超过软限制但仍在宽限期内时返回告警信息
+    pub async fn check_quota(
+        &mut self,
+        uid: UserIdType,
+        usage: u64,
+    ) -> Result<Option<String>, Error> {
+        let quota = match self.quotas.get_mut(&uid) {
+            Some(quota) => quota,
+            None => return Ok(None),
+        };
+        if quota.hard != 0 && usage > quota.hard {
+            let (size, unit) = show_unit(quota.hard as usize);
+            return Err(Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("disk quota exceeded, hard limit {:.1}{}", size, unit),
+            ));
+        }
+        if quota.soft == 0 || usage <= quota.soft {
+            // 回到软限制以内，重新计算宽限期
+            if quota.exceeded_at != 0 {
+                quota.exceeded_at = 0;
+                self.cache().await?;
             }
-        }) {
-            Some(username) => Ok(username),
-            None => Err(Error::new(std::io::ErrorKind::NotFound, "user not exists")),
+            return Ok(None);
+        }
+        let now = now_secs();
+        if quota.exceeded_at == 0 {
+            quota.exceeded_at = now;
+            self.cache().await?;
+        } else if now - quota.exceeded_at > QUOTA_GRACE_PERIOD {
+            return Err(Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "disk quota exceeded, soft limit grace period expired",
+            ));
+        }
+        let quota = &self.quotas[&uid];
+        let (size, unit) = show_unit(quota.soft as usize);
+        let left = (quota.exceeded_at + QUOTA_GRACE_PERIOD).saturating_sub(now);
+        Ok(Some(format!(
+            "warning: over soft limit {:.1}{}, {}s of grace period left",
+            size, unit, left
+        )))
+    }
+
+    /// 旧镜像中0号块里的用户信息可能占用了记录申请位置的字节，此时改为写入数据区的块中
+    pub async fn move_out_of_cursor_area(&self) -> Result<(), Error> {
+        let buffer = get_block_buffer(0, USER_START_BYTE, BLOCK_SIZE).await?;
+        if read_index(&buffer)?.is_some() {
+            return Ok(());
+        }
+        let len = bincode::serialized_size(self).map_err(Error::other)? as usize;
+        if USER_START_BYTE + len > ALLOC_CURSOR_START_BYTE {
+            info!("move user info out of block 0");
+            self.cache().await?;
+        }
+        Ok(())
+    }
+
+    /// 写入用户信息：能放进0号块时按原格式直接写在超级块之后，
+    /// 否则写入数据区的块中，沿用已有的块，多退少补
+    async fn cache(&self) -> Result<(), Error> {
+        let bytes = bincode::serialize(self).map_err(Error::other)?;
+        let old_blocks = get_user_blocks().await?;
+        let block_num = if bytes.len() <= ALLOC_CURSOR_START_BYTE - USER_START_BYTE {
+            0
+        } else {
+            bytes.len().div_ceil(BLOCK_SIZE)
+        };
+        let mut blocks: Vec<_> = old_blocks.iter().take(block_num).copied().collect();
+        let index = UserBlocks {
+            marker: USER_BLOCKS_MARKER,
+            len: bytes.len() as u64,
+            blocks: vec![0; block_num],
+        };
+        // 先检查索引本身能否放进0号块，再申请新块
+        let index_size = bincode::serialized_size(&index).map_err(Error::other)? as usize;
+        if block_num > 0 && index_size > ALLOC_CURSOR_START_BYTE - USER_START_BYTE {
+            return Err(Error::new(
+                std::io::ErrorKind::OutOfMemory,
+                "too many users to store",
+            ));
+        }
+        if blocks.len() < block_num {
+            let start = DATA_START_BLOCK as BlockIDType;
+            let ids = alloc_bits(BitmapType::Data, block_num - blocks.len()).await?;
+            blocks.extend(ids.into_iter().map(|bit_id| bit_id + start));
+        }
+
+        if block_num == 0 {
+            write_bytes_to_block(&bytes, 0, USER_START_BYTE).await?;
+        } else {
+            let chunks: Vec<_> = bytes.chunks(BLOCK_SIZE).collect();
+            let block_ids: Vec<_> = blocks.iter().map(|id| *id as usize).collect();
+            write_file_content_to_blocks(&chunks, &block_ids).await?;
+            write_block(&UserBlocks { blocks, ..index }, 0, USER_START_BYTE).await?;
         }
+        // 释放不再需要的块
+        let unused: Vec<_> = old_blocks
+            .iter()
+            .skip(block_num)
+            .map(|id| *id as usize)
+            .collect();
+        dealloc_data_bits(&unused).await;
+        Ok(())
     }
+}
 
-    async fn cache(&self) {
-        write_block(self, 0, USER_START_BYTE).await.unwrap();
+/// 0号块中为用户信息的索引时返回索引，为原格式的用户信息时返回None
+fn read_index(buffer: &[u8]) -> Result<Option<UserBlocks>, Error> {
+    if deserialize::<u64>(buffer)? != USER_BLOCKS_MARKER {
+        return Ok(None);
     }
+    deserialize(buffer).map(Some)
+}
+
+/// 存放用户信息的数据块，用户信息在0号块中时为空
+pub async fn get_user_blocks() -> Result<Vec<BlockIDType>, Error> {
+    let buffer = get_block_buffer(0, USER_START_BYTE, BLOCK_SIZE).await?;
+    Ok(read_index(&buffer)?.map_or(Vec::new(), |index| index.blocks))
 }
 
 /// 判断当前uid是否有权限修改other uid创建的文件
 pub fn able_to_modify(this: UserIdType, other: UserIdType) -> bool {
     this <= other
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block::BLOCK_CACHE_MANAGER,
+        file,
+        inode::FileMode,
+        simple_fs::SFS,
+        test_utils::{add_user, exec, lookup, mktree, setup, used_blocks},
+    };
+
+    /// 以alice的身份在~/alice下新建大小为size的文件
+    async fn write_as_alice(name: &str, size: usize) -> Result<Option<String>, Error> {
+        let ids = SFS.read().await.get_user_ids("alice").unwrap();
+        let mut parent = lookup("alice").await;
+        file::create_file_with_content(
+            name,
+            FileMode::RDWR,
+            &mut parent,
+            &"q".repeat(size),
+            false,
+            (ids.gid, ids.uid),
+        )
+        .await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn soft_limit_warns_and_hard_limit_refuses() {
+        let _fs = setup().await;
+        mktree(&["alice/"]).await;
+        add_user("alice").await;
+        exec("quota alice 4K 8K").await.unwrap();
+
+        assert_eq!(write_as_alice("a.txt", 3000).await.unwrap(), None);
+        // 用量5KiB超过软限制，允许写入并告警
+        let warning = write_as_alice("b.txt", 2000).await.unwrap().unwrap();
+        assert!(
+            warning.starts_with("warning: over soft limit 4.0KiB"),
+            "{}",
+            warning
+        );
+        // 用量9KiB超过硬限制，拒绝写入且不占用空间
+        let used = used_blocks().await;
+        let err = write_as_alice("c.txt", 4000).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("hard limit 8.0KiB"), "{}", err);
+        assert_eq!(used_blocks().await, used);
+        assert!(exec("cat alice/c.txt").await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn soft_limit_refuses_after_grace_period() {
+        let _fs = setup().await;
+        mktree(&["alice/"]).await;
+        add_user("alice").await;
+        exec("quota alice 2K 100K").await.unwrap();
+        assert!(write_as_alice("a.txt", 3000).await.unwrap().is_some());
+
+        // 宽限期已过
+        let uid = SFS.read().await.get_user_ids("alice").unwrap().uid;
+        SFS.write()
+            .await
+            .user_infos
+            .quotas
+            .get_mut(&uid)
+            .unwrap()
+            .exceeded_at = now_secs() - QUOTA_GRACE_PERIOD - 1;
+        let err = write_as_alice("b.txt", 100).await.unwrap_err();
+        assert!(err.to_string().contains("grace period expired"), "{}", err);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn user_table_spills_into_data_blocks_without_corruption() {
+        let _fs = setup().await;
+        mktree(&["a/x.txt:kept"]).await;
+        let used = used_blocks().await;
+        let names: Vec<_> = (0..150)
+            .map(|i| format!("user_with_a_long_name_{}", i))
+            .collect();
+        for name in &names {
+            add_user(name).await;
+        }
+        let blocks = get_user_blocks().await.unwrap();
+        assert!(blocks.len() >= 2, "{:?}", blocks);
+        assert_eq!(used_blocks().await, used + blocks.len());
+
+        // 从设备重新读入：超级块、已有文件和所有用户都完好
+        BLOCK_CACHE_MANAGER
+            .write()
+            .await
+            .sync_and_clear_cache()
+            .await
+            .unwrap();
+        assert_eq!(exec("verify").await.unwrap().unwrap(), "super block ok");
+        let users = User::read().await.unwrap();
+        for name in names.iter().chain(["root".to_string()].iter()) {
+            let password = if name == "root" { "admin" } else { name };
+            users.sign_in(name, password).unwrap();
+        }
+        assert_eq!(exec("cat a/x.txt").await.unwrap().unwrap(), "kept");
+        let report = exec("fsck").await.unwrap().unwrap();
+        assert!(report.contains("leaked: 0 inodes, 0 blocks"), "{}", report);
+    }
+}
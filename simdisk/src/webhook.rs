@@ -0,0 +1,69 @@
+//! 文件系统事件的webhook通知
+//!
+//! 启动时通过`--webhook [url]`配置，文件创建、删除、修改等事件会以json POST到该url
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::{config::CONFIG, fs_constants::*, inode::now_secs};
+
+/// 文件系统事件类型
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Create,
+    Delete,
+    Modify,
+}
+
+/// 发送给webhook的事件内容
+#[derive(Serialize, Debug)]
+pub struct Event {
+    pub kind: EventKind,
+    pub path: String,
+    pub user: String,
+    pub time: u64, // 时间戳（秒）
+}
+
+/// 通知一个事件，后台发送，不阻塞命令的执行
+pub fn notify(kind: EventKind, path: &str, user: &str) {
+    let url = match &CONFIG.webhook {
+        Some(url) => url.clone(),
+        None => return,
+    };
+    let event = Event {
+        kind,
+        path: path.to_owned(),
+        user: user.to_owned(),
+        time: now_secs(),
+    };
+    tokio::spawn(async move { send(&url, &event).await });
+}
+
+/// 发送事件，失败时重试有限次
+async fn send(url: &str, event: &Event) {
+    for i in 0..WEBHOOK_RETRY_TIMES {
+        match HTTP_CLIENT.post(url).json(event).send().await {
+            Ok(response) if response.status().is_success() => {
+                trace!("webhook sent: {:?}", event);
+                return;
+            }
+            Ok(response) => warn!("webhook responded {}: {:?}", response.status(), event),
+            Err(e) => warn!("failed to send webhook: {}", e),
+        }
+        // 等待一段时间后重试
+        tokio::time::sleep(Duration::from_secs(1 << i)).await;
+    }
+    error!(
+        "give up sending webhook after {} times: {:?}",
+        WEBHOOK_RETRY_TIMES, event
+    );
+}
+
+//延迟加载全局变量 HTTP_CLIENT
+lazy_static! {
+    static ref HTTP_CLIENT: reqwest::Client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(WEBHOOK_TIMEOUT))
+        .build()
+        .unwrap();
+}
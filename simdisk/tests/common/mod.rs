@@ -0,0 +1,224 @@
+//! 集成测试的辅助函数：在临时目录中启动simdisk，按client的协议登录和执行命令
+#![allow(dead_code)]
+use std::{
+    fs,
+    io::{Error, ErrorKind, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use utils::*;
+
+static SERVERS: AtomicUsize = AtomicUsize::new(0);
+
+/// 在临时目录中运行的simdisk，drop时结束进程并删除目录
+pub struct Server {
+    child: Child,
+    pub addr: String,
+    pub dir: PathBuf,
+    keep_dir: bool, // 结束进程后保留目录，用于在同一镜像上重新启动
+}
+
+impl Server {
+    /// 在新的临时目录中启动
+    pub fn start(args: &[&str]) -> Self {
+        Self::start_in(temp_dir(), args)
+    }
+
+    /// 在dir中启动，监听一个空闲的本机端口，等到可以连接时返回
+    pub fn start_in(dir: PathBuf, args: &[&str]) -> Self {
+        let addr = free_addr();
+        let mut server = Self::spawn(dir, args, &addr);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while TcpStream::connect(&server.addr).is_err() {
+            if let Ok(Some(status)) = server.child.try_wait() {
+                panic!("simdisk exited with {}: {}", status, server.log());
+            }
+            assert!(Instant::now() < deadline, "simdisk did not start");
+            thread::sleep(Duration::from_millis(20));
+        }
+        server
+    }
+
+    /// 启动但不等待，用于检查启动失败的情况
+    pub fn spawn(dir: PathBuf, args: &[&str], addr: &str) -> Self {
+        let log = fs::File::create(dir.join("simdisk.log")).unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_simdisk"))
+            .args(args)
+            .args(["--addr", addr])
+            .current_dir(&dir)
+            .stdout(Stdio::null())
+            .stderr(log)
+            .spawn()
+            .unwrap();
+        Self {
+            child,
+            addr: addr.to_string(),
+            dir,
+            keep_dir: false,
+        }
+    }
+
+    /// 结束进程并保留目录，返回目录
+    pub fn stop(mut self) -> PathBuf {
+        self.keep_dir = true;
+        self.dir.clone()
+    }
+
+    /// 等待进程退出，返回是否成功退出
+    pub fn wait(&mut self) -> bool {
+        self.child.wait().unwrap().success()
+    }
+
+    /// server到目前为止输出的日志
+    pub fn log(&self) -> String {
+        fs::read_to_string(self.dir.join("simdisk.log")).unwrap_or_default()
+    }
+
+    /// 以root的身份登录
+    pub fn root(&self) -> Client {
+        Client::login(&self.addr, "root", "admin").unwrap()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        if !self.keep_dir {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+/// 新建一个空的临时目录
+pub fn temp_dir() -> PathBuf {
+    let n = SERVERS.fetch_add(1, Ordering::SeqCst);
+    let dir = std::env::temp_dir().join(format!("simdisk-test-{}-{}", std::process::id(), n));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// 本机上一个空闲的端口
+pub fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+/// 按client的协议和server通信
+pub struct Client {
+    pub stream: TcpStream,
+    pub username: String,
+    pub cwd: String,
+}
+
+impl Client {
+    /// 连接但不登录
+    pub fn connect(addr: &str) -> Self {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        Self {
+            stream,
+            username: String::new(),
+            cwd: "~".to_string(),
+        }
+    }
+
+    /// 连接并登录，登录失败时返回server的回复
+    pub fn login(addr: &str, username: &str, password: &str) -> Result<Self, String> {
+        let mut client = Self::connect(addr);
+        let reply = client.request(&format!("login\n{}\n{}\n", username, password));
+        if reply != LOGIN_SUCCESS {
+            return Err(reply);
+        }
+        client.username = username.to_string();
+        Ok(client)
+    }
+
+    /// 发送一条消息并读取一次回复
+    pub fn request(&mut self, msg: &str) -> String {
+        self.send(msg);
+        self.read_msg().unwrap_or_default()
+    }
+
+    pub fn send(&mut self, msg: &str) {
+        self.stream.write_all(msg.as_bytes()).unwrap();
+    }
+
+    /// 读取一次消息，连接关闭时返回None
+    pub fn read_msg(&mut self) -> Option<String> {
+        let mut buffer = [0; SOCKET_BUFFER_SIZE];
+        match self.stream.read(&mut buffer) {
+            Ok(0) | Err(_) => None,
+            Ok(n) => Some(String::from_utf8_lossy(&buffer[..n]).replace('\0', "")),
+        }
+    }
+
+    /// 在cwd下执行命令，成功时返回输出，失败时返回错误信息
+    pub fn run(&mut self, command: &str) -> Result<String, String> {
+        self.run_with_input(command, "")
+    }
+
+    /// 执行命令，需要输入文件内容时发送input
+    pub fn run_with_input(&mut self, command: &str, input: &str) -> Result<String, String> {
+        let line = format!("{} {} {}", self.username, self.cwd, command);
+        self.exchange(&line, input)
+    }
+
+    /// 发送完整的命令行（可以带请求id），处理server的应答直到命令结束
+    pub fn exchange(&mut self, line: &str, input: impl AsRef<[u8]>) -> Result<String, String> {
+        self.send(line);
+        let mut output = Ok(String::new());
+        loop {
+            let msg = self.read_msg().ok_or("connection closed".to_string())?;
+            if let Some(addr) = msg.strip_prefix(INPUT_FILE_CONTENT) {
+                let mut stream = TcpStream::connect(addr.trim()).unwrap();
+                stream.write_all(input.as_ref()).unwrap();
+                stream.shutdown(Shutdown::Write).unwrap();
+            } else if msg == COMMAND_CONFIRM {
+                self.send("y");
+            } else if msg == FORMAT_CONFIRM {
+                self.send(FORMAT_CONFIRM_TOKEN);
+            } else if msg == RECEIVE_CONTENTS {
+                let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                self.send(&listener.local_addr().unwrap().to_string());
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut contents = String::new();
+                stream.read_to_string(&mut contents).unwrap();
+                output = match contents.strip_prefix(ERROR_MESSAGE_PREFIX) {
+                    Some(err) => Err(err.to_string()),
+                    None => Ok(contents),
+                };
+            } else if msg == COMMAND_FINISHED {
+                return output;
+            } else {
+                return Err(format!("unexpected message: {}", msg));
+            }
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.stream.write_all(EXIT_MSG.as_bytes());
+    }
+}
+
+/// 连接被server关闭前读到的全部内容
+pub fn read_until_closed(stream: &mut TcpStream) -> Result<String, Error> {
+    let mut contents = Vec::new();
+    match stream.read_to_end(&mut contents) {
+        Ok(_) => Ok(String::from_utf8_lossy(&contents).to_string()),
+        Err(e) if e.kind() == ErrorKind::ConnectionReset => {
+            Ok(String::from_utf8_lossy(&contents).to_string())
+        }
+        Err(e) => Err(e),
+    }
+}
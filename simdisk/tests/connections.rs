@@ -0,0 +1,37 @@
+//! 同时保持的连接数受--max-connections限制
+mod common;
+
+use std::{thread, time::Duration};
+
+use common::{Client, Server};
+use utils::TOO_MANY_CONNECTIONS;
+
+#[test]
+fn connection_past_the_limit_is_refused() {
+    let server = Server::start(&["--memory", "--format", "--max-connections", "2"]);
+    let first = server.root();
+    let mut second = server.root();
+
+    // 第3个连接收到提示后被关闭
+    let mut third = Client::connect(&server.addr);
+    assert_eq!(third.read_msg().as_deref(), Some(TOO_MANY_CONNECTIONS));
+    assert_eq!(third.read_msg(), None);
+    // 已有的连接不受影响
+    second.run("dir").unwrap();
+
+    // 断开一个连接后可以再连接
+    drop(first);
+    let mut retries = 0;
+    let mut client = loop {
+        match Client::login(&server.addr, "root", "admin") {
+            Ok(client) => break client,
+            Err(reply) => {
+                assert_eq!(reply, TOO_MANY_CONNECTIONS);
+                retries += 1;
+                assert!(retries < 100, "connection permit not released");
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+    };
+    client.run("dir").unwrap();
+}
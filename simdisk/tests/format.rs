@@ -0,0 +1,32 @@
+//! --format在启动时格式化已有的镜像
+mod common;
+
+use common::Server;
+
+#[test]
+fn format_flag_empties_an_existing_image() {
+    let server = Server::start(&[]);
+    let mut root = server.root();
+    root.run("md docs").unwrap();
+    root.run_with_input("newfile a.txt", "kept").unwrap();
+    // umount写回缓存，确保内容已在镜像中
+    root.run("umount").unwrap();
+    drop(root);
+
+    // 不带--format重启时内容还在
+    let server = Server::start_in(server.stop(), &[]);
+    let mut root = server.root();
+    assert_eq!(root.run("cat a.txt").unwrap(), "kept");
+    let listing = root.run("dir").unwrap();
+    assert!(listing.contains("docs"), "{}", listing);
+    drop(root);
+
+    let server = Server::start_in(server.stop(), &["--format"]);
+    let mut root = server.root();
+    let listing = root.run("dir").unwrap();
+    for name in ["a.txt", "docs"] {
+        assert!(!listing.contains(name), "{} left in {}", name, listing);
+    }
+    assert!(root.run("cat a.txt").is_err());
+    assert!(root.run("cd docs").is_err());
+}
@@ -0,0 +1,34 @@
+//! --help打印用法后直接退出，不启动server也不创建镜像
+mod common;
+
+use std::{fs, process::Command};
+
+use common::temp_dir;
+
+#[test]
+fn help_lists_the_flags_and_exits_zero() {
+    for flag in ["--help", "-h"] {
+        let dir = temp_dir();
+        let output = Command::new(env!("CARGO_BIN_EXE_simdisk"))
+            .arg(flag)
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        let usage = String::from_utf8_lossy(&output.stdout);
+        assert!(usage.starts_with("usage: simdisk"), "{}", usage);
+        for known in [
+            "--addr",
+            "--format",
+            "--image",
+            "--memory",
+            "--readonly",
+            "--log-level",
+            "--help",
+        ] {
+            assert!(usage.contains(known), "{} missing in {}", known, usage);
+        }
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
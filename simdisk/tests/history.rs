@@ -0,0 +1,42 @@
+//! 命令历史按用户保存在宿主文件中，重启server后仍然可以搜索
+mod common;
+
+use common::{Client, Server};
+
+#[test]
+fn history_survives_restart() {
+    let server = Server::start(&["--memory", "--format"]);
+    let mut root = server.root();
+    root.run("md docs").unwrap();
+    root.run("md photos").unwrap();
+    root.run("dir docs").unwrap();
+    drop(root);
+    let dir = server.stop();
+
+    let server = Server::start_in(dir, &["--memory"]);
+    let mut root = server.root();
+    let found = root.run("history search docs").unwrap();
+    assert!(found.contains("md docs"), "{}", found);
+    assert!(found.contains("dir docs"), "{}", found);
+    assert!(!found.contains("photos"), "{}", found);
+    let all = root.run("history").unwrap();
+    assert!(all.contains("md photos"), "{}", all);
+
+    // 历史按用户区分（内存中的文件系统重启后为空，重新注册）
+    let mut client = Client::connect(&server.addr);
+    client.request("regist\nalice\nalice\n");
+    let mut alice = Client::login(&server.addr, "alice", "alice").unwrap();
+    let found = alice.run("history search md").unwrap();
+    assert!(!found.contains("docs"), "{}", found);
+}
+
+#[test]
+fn history_is_kept_in_the_given_file() {
+    let path = common::temp_dir().join("custom_history");
+    let server = Server::start(&["--memory", "--format", "--history", path.to_str().unwrap()]);
+    let mut root = server.root();
+    root.run("md docs").unwrap();
+    let saved = std::fs::read_to_string(&path).unwrap();
+    assert!(saved.contains("md docs"), "{}", saved);
+    assert!(!server.dir.join("SIMPLE_FS_HISTORY").exists());
+}
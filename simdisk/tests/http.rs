@@ -0,0 +1,79 @@
+//! --http 提供只读的网页目录浏览器
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::{free_addr, Server};
+
+/// 发送一个HTTP请求，返回状态行、头部和正文
+fn request(addr: &str, method: &str, target: &str) -> (String, String, String) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut stream = loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => break stream,
+            Err(e) => assert!(Instant::now() < deadline, "{}", e),
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    let request = format!("{} {} HTTP/1.1\r\nHost: {}\r\n\r\n", method, target, addr);
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let (status, headers) = head.split_once("\r\n").unwrap_or((head, ""));
+    (status.to_string(), headers.to_string(), body.to_string())
+}
+
+#[test]
+fn browses_directories_and_files() {
+    let http_addr = free_addr();
+    let port = http_addr.rsplit_once(':').unwrap().1;
+    let server = Server::start(&["--format", "--http", port]);
+    let mut root = server.root();
+    root.run("md docs").unwrap();
+    root.run_with_input("newfile docs/a.txt", "hello http")
+        .unwrap();
+    root.run_with_input("newfile top.txt", "top").unwrap();
+
+    // 根目录列出条目，目录带/，文件带下载链接
+    let (status, headers, body) = request(&http_addr, "GET", "/");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(headers.contains("text/html"), "{}", headers);
+    assert!(body.contains("<a href=\"docs/\">docs/</a>"), "{}", body);
+    assert!(
+        body.contains("<a href=\"top.txt?download\">download</a>"),
+        "{}",
+        body
+    );
+    assert!(!body.contains("../"), "{}", body);
+
+    let (status, _, body) = request(&http_addr, "GET", "/docs/");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(body.contains("<a href=\"a.txt\">a.txt</a>"), "{}", body);
+    assert!(body.contains("<a href=\"../\">../</a>"), "{}", body);
+
+    let (status, _, body) = request(&http_addr, "GET", "/docs/a.txt");
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert_eq!(body, "hello http");
+    let (_, headers, _) = request(&http_addr, "GET", "/docs/a.txt?download");
+    assert!(
+        headers.contains("attachment; filename=\"a.txt\""),
+        "{}",
+        headers
+    );
+
+    // 不带/访问目录时跳转，不存在的路径返回404，不支持修改
+    let (status, headers, _) = request(&http_addr, "GET", "/docs");
+    assert_eq!(status, "HTTP/1.1 301 Moved Permanently");
+    assert!(headers.contains("Location: /docs/"), "{}", headers);
+    let (status, _, _) = request(&http_addr, "GET", "/missing.txt");
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+    let (status, _, _) = request(&http_addr, "DELETE", "/top.txt");
+    assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+    assert_eq!(root.run("cat top.txt").unwrap(), "top");
+}
@@ -0,0 +1,51 @@
+//! 空闲超过--idle-timeout的连接被关闭并释放资源
+mod common;
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::{Client, Server};
+
+/// 唯一的连接许可被占用时重试登录，Server::start探测端口的连接要等server发现断开后才归还许可
+fn login_when_free(server: &Server) -> Client {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        match Client::login(&server.addr, "root", "admin") {
+            Ok(client) => return client,
+            Err(reply) if reply.contains("too many connections") && Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(20));
+            }
+            Err(reply) => panic!("{}", reply),
+        }
+    }
+}
+
+#[test]
+fn idle_connection_is_dropped_and_released() {
+    let server = Server::start(&[
+        "--memory",
+        "--format",
+        "--idle-timeout",
+        "1",
+        "--max-connections",
+        "1",
+    ]);
+    let mut idle = login_when_free(&server);
+    idle.run("dir").unwrap();
+
+    thread::sleep(Duration::from_millis(1500));
+    // server已经关闭连接
+    assert_eq!(idle.read_msg(), None);
+    assert!(
+        server.log().contains("idle for 1s, close"),
+        "{}",
+        server.log()
+    );
+
+    // 连接许可和会话都已释放
+    let mut client = server.root();
+    let sessions = client.run("sessions").unwrap();
+    assert_eq!(sessions.matches("root").count(), 1, "{}", sessions);
+}
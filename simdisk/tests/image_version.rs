@@ -0,0 +1,57 @@
+//! 超级块记录的布局版本和当前版本不同时启动失败，不自动格式化
+mod common;
+
+use std::{
+    fs,
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+use common::{free_addr, Server};
+
+/// 超级块中layout_version的偏移：bincode将usize存为8字节，layout_version为第10个字段
+const VERSION_OFFSET: u64 = 10 * 8;
+
+#[test]
+fn unknown_version_is_rejected_at_startup() {
+    let server = Server::start(&["--format"]);
+    server.root().run("umount").unwrap();
+    let dir = server.stop();
+    let image = dir.join("SIMPLE_FS");
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&image)
+        .unwrap();
+    let mut field = [0; 8];
+    file.seek(SeekFrom::Start(VERSION_OFFSET)).unwrap();
+    file.read_exact(&mut field).unwrap();
+    let current = u64::from_le_bytes(field);
+    let unknown = current + 7;
+    file.seek(SeekFrom::Start(VERSION_OFFSET)).unwrap();
+    file.write_all(&unknown.to_le_bytes()).unwrap();
+    drop(file);
+    let image_bytes = fs::read(&image).unwrap();
+
+    let mut server = Server::spawn(dir, &[], &free_addr());
+    assert!(!server.wait());
+    let log = server.log();
+    let expected = format!(
+        "unsupported image version {}, expected {}, reformat needed",
+        unknown, current
+    );
+    assert!(log.contains(&expected), "{}", log);
+    // 镜像保持原样，留给用户决定是否格式化
+    assert!(fs::read(&image).unwrap() == image_bytes);
+
+    // --format按当前版本重新格式化
+    let dir = server.stop();
+    let server = Server::start_in(dir, &["--format"]);
+    assert!(server.root().run("dir").is_ok());
+    server.root().run("umount").unwrap();
+    let dir = server.stop();
+    let bytes = fs::read(dir.join("SIMPLE_FS")).unwrap();
+    let start = VERSION_OFFSET as usize;
+    let version = u64::from_le_bytes(bytes[start..start + 8].try_into().unwrap());
+    assert_eq!(version, current);
+    fs::remove_dir_all(dir).unwrap();
+}
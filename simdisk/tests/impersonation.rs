@@ -0,0 +1,28 @@
+//! 命令中的用户名必须是连接登录的用户，不能冒充其他用户
+mod common;
+
+use common::{Client, Server};
+
+#[test]
+fn jailed_session_cannot_send_another_username() {
+    let server = Server::start(&["--memory", "--format"]);
+    let mut anonymous = Client::connect(&server.addr);
+    anonymous.request("regist\nalice\nalice\n");
+    let mut root = server.root();
+    root.run_with_input("newfile secret.txt", "root only")
+        .unwrap();
+    root.run("md home").unwrap();
+    root.run("md home/alice").unwrap();
+    root.run("chroot alice ~/home/alice").unwrap();
+
+    let mut alice = Client::login(&server.addr, "alice", "alice").unwrap();
+    assert!(alice.run("cat ~/secret.txt").is_err());
+    // 冒充root的请求被拒绝，既不能越过专属根目录，也不能使用root的命令
+    let err = alice.exchange("root ~ cat ~/secret.txt", "").unwrap_err();
+    assert!(err.contains("not the logged in user"), "{}", err);
+    assert!(alice.exchange("root ~ chroot alice", "").is_err());
+    assert!(alice.run("cat ~/secret.txt").is_err());
+    // 拒绝后连接仍然可用
+    assert!(alice.run("dir").is_ok());
+    assert_eq!(root.run("cat secret.txt").unwrap(), "root only");
+}
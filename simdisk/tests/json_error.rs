@@ -0,0 +1,32 @@
+//! --json时失败的命令返回可解析的错误对象
+mod common;
+
+use common::Server;
+use serde_json::Value;
+
+#[test]
+fn failed_commands_return_json_errors() {
+    let server = Server::start(&["--memory", "--format"]);
+    let mut client = server.root();
+
+    let err = client.run("cat --json missing.txt").unwrap_err();
+    let error: Value = serde_json::from_str(&err).unwrap();
+    assert_eq!(error["code"], "NotFound");
+    assert_eq!(error["path"], "missing.txt");
+    assert!(!error["message"].as_str().unwrap().is_empty());
+
+    client.run("md docs").unwrap();
+    let err = client.run("md docs --json").unwrap_err();
+    let error: Value = serde_json::from_str(&err).unwrap();
+    assert_eq!(error["code"], "AlreadyExists");
+    assert_eq!(error["path"], "docs");
+
+    // 没有位置参数时path为null
+    let err = client.run("nosuchcommand --json").unwrap_err();
+    let error: Value = serde_json::from_str(&err).unwrap();
+    assert_eq!(error["path"], Value::Null);
+
+    // 不带--json时仍返回纯文本
+    let err = client.run("cat missing.txt").unwrap_err();
+    assert!(serde_json::from_str::<Value>(&err).is_err(), "{}", err);
+}
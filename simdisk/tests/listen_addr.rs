@@ -0,0 +1,45 @@
+//! --addr 指定server监听的地址，传输内容的临时socket和监听的地址使用同一个网卡
+mod common;
+
+use std::{
+    io::Write,
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::{free_addr, temp_dir, Client, Server};
+use utils::{COMMAND_FINISHED, INPUT_FILE_CONTENT};
+
+#[test]
+fn serves_on_the_given_address() {
+    // 127.0.0.0/8 都是本机地址，换一个不是127.0.0.1的地址和非默认的端口
+    let port = free_addr().rsplit_once(':').unwrap().1.to_string();
+    let addr = format!("127.0.0.2:{}", port);
+    let server = Server::spawn(temp_dir(), &["--format"], &addr);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while TcpStream::connect(&addr).is_err() {
+        assert!(
+            Instant::now() < deadline,
+            "simdisk did not start: {}",
+            server.log()
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert!(TcpStream::connect(format!("127.0.0.1:{}", port)).is_err());
+
+    let mut root = Client::login(&addr, "root", "admin").unwrap();
+    // 接收文件内容的临时socket监听在同一个地址上
+    root.send("root ~ newfile a.txt");
+    let msg = root.read_msg().unwrap();
+    let content_addr = msg.strip_prefix(INPUT_FILE_CONTENT).unwrap();
+    assert!(content_addr.starts_with("127.0.0.2:"), "{}", content_addr);
+    let mut stream = TcpStream::connect(content_addr).unwrap();
+    stream.write_all(b"on another address").unwrap();
+    drop(stream);
+    assert_eq!(root.read_msg().unwrap(), COMMAND_FINISHED);
+
+    assert_eq!(root.run("cat a.txt").unwrap(), "on another address");
+    drop(root);
+    assert!(server.log().contains(&addr), "{}", server.log());
+}
@@ -0,0 +1,37 @@
+//! --log-level决定server输出的日志级别
+mod common;
+
+use common::{free_addr, temp_dir, Server};
+
+/// 以args启动，执行一条dir之后返回日志
+fn log_of_dir(args: &[&str]) -> String {
+    let mut all_args = vec!["--memory", "--format"];
+    all_args.extend(args);
+    let server = Server::start(&all_args);
+    server.root().run("dir").unwrap();
+    // 命令的trace在返回结果之前输出
+    server.log()
+}
+
+#[test]
+fn trace_level_shows_command_traces() {
+    let log = log_of_dir(&["--log-level", "trace"]);
+    assert!(log.contains("TRACE"), "{}", log);
+    assert!(log.contains("finished cmd: ls_dir"), "{}", log);
+
+    // 默认为info
+    let log = log_of_dir(&[]);
+    assert!(log.contains("INFO"), "{}", log);
+    assert!(!log.contains("finished cmd: ls_dir"), "{}", log);
+
+    let log = log_of_dir(&["--log-level", "error"]);
+    assert!(!log.contains("INFO"), "{}", log);
+}
+
+#[test]
+fn invalid_level_is_rejected() {
+    let mut server = Server::spawn(temp_dir(), &["--log-level", "loud"], &free_addr());
+    assert!(!server.wait());
+    let log = server.log();
+    assert!(log.contains("invalid --log-level loud"), "{}", log);
+}
@@ -0,0 +1,87 @@
+//! 残缺或格式错误的报文不会让server的任务panic
+mod common;
+
+use common::{Client, Server};
+
+#[test]
+fn malformed_login_messages_are_rejected() {
+    let server = Server::start(&["--memory", "--format"]);
+    for msg in ["login", "login\nroot", "regist\n", "regist\nbob"] {
+        let mut client = Client::connect(&server.addr);
+        let reply = client.request(msg);
+        assert!(reply.contains("incomplete"), "{:?}: {}", msg, reply);
+        // 同一个连接仍然可以登录
+        let reply = client.request("login\nroot\nadmin\n");
+        assert_eq!(reply, utils::LOGIN_SUCCESS, "after {:?}", msg);
+    }
+    // 未知的报文直接断开连接
+    for msg in ["\n\n\n", "hello", "\u{fffd}\u{fffd}"] {
+        let mut client = Client::connect(&server.addr);
+        client.send(msg);
+        assert_eq!(client.read_msg(), None, "{:?}", msg);
+    }
+    server.root().run("dir").unwrap();
+}
+
+#[test]
+fn malformed_commands_return_errors() {
+    let server = Server::start(&["--memory", "--format"]);
+    let mut client = server.root();
+    client.run_with_input("newfile a.txt", "hello\n").unwrap();
+
+    let lines = [
+        "root",
+        "root ~",
+        "REQ#1",
+        "REQ#1 root",
+        "REQ#2 root ~ append",
+        "nobody ~ dir",
+        "root ~ nosuchcommand",
+        "root ~ cat",
+        "root ~ copy a.txt",
+        "root ~ rename a.txt",
+        "root ~ stat --inode",
+        "root ~ stat --inode x",
+        "root ~ cat --range",
+        "root ~ cat --range 1 a.txt",
+        "root ~ tree -L",
+        "root ~ find -name",
+        "root ~ find ~ -exec",
+        "root ~ --json",
+        "root ~ chmod a.txt",
+        "root ~ mkfile z",
+    ];
+    for line in lines {
+        let result = client.exchange(line, "");
+        // 连接保持，报错后可以继续执行命令
+        assert!(
+            result != Err("connection closed".to_string()),
+            "{:?} closed the connection",
+            line
+        );
+        assert!(result.is_err(), "{:?}: {:?}", line, result);
+        assert_eq!(
+            client.run("cat a.txt").unwrap(),
+            "hello\n",
+            "after {:?}",
+            line
+        );
+    }
+    assert!(!server.log().contains("panicked"), "{}", server.log());
+}
+
+#[test]
+fn binary_file_content_is_rejected() {
+    let server = Server::start(&["--memory", "--format"]);
+    let mut root = server.root();
+    let err = root
+        .exchange("root ~ newfile b.dat", [0xff, 0x00, 0xfe])
+        .unwrap_err();
+    assert!(err.contains("not valid UTF-8"), "{}", err);
+    assert!(root.run("cat b.dat").is_err());
+    // 空内容同样放弃执行
+    assert!(root.exchange("root ~ newfile e.dat", "").is_err());
+    assert!(root.run("cat e.dat").is_err());
+    root.run_with_input("newfile t.txt", "text").unwrap();
+    assert_eq!(root.run("cat t.txt").unwrap(), "text");
+}
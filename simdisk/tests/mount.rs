@@ -0,0 +1,54 @@
+//! mount、umount在运行时切换镜像文件
+mod common;
+
+use std::fs;
+
+use common::Server;
+
+/// 镜像文件中是否出现了needle
+fn image_contains(image: &std::path::Path, needle: &str) -> bool {
+    let bytes = fs::read(image).unwrap();
+    bytes
+        .windows(needle.len())
+        .any(|window| window == needle.as_bytes())
+}
+
+#[test]
+fn operations_hit_the_mounted_image() {
+    // 另一个新格式化的镜像
+    let second_dir = Server::start(&["--format"]).stop();
+    let second = second_dir.join("SIMPLE_FS");
+
+    let server = Server::start(&["--format"]);
+    let first = server.dir.join("SIMPLE_FS");
+    let mut root = server.root();
+    root.run_with_input("newfile a.txt", "on the first image")
+        .unwrap();
+
+    let info = root.run(&format!("mount {}", second.display())).unwrap();
+    assert!(info.contains(&*second.to_string_lossy()), "{}", info);
+    assert!(root.run("cat a.txt").is_err());
+    root.run_with_input("newfile b.txt", "on the second image")
+        .unwrap();
+
+    // umount写回缓存后命令不可用，内容只写入了第二个镜像
+    root.run("umount").unwrap();
+    let err = root.run("dir").unwrap_err();
+    assert!(err.contains("no image mounted"), "{}", err);
+    assert!(image_contains(&second, "on the second image"));
+    assert!(!image_contains(&first, "on the second image"));
+
+    root.run(&format!("mount {}", first.display())).unwrap();
+    assert_eq!(root.run("cat a.txt").unwrap(), "on the first image");
+    assert!(root.run("cat b.txt").is_err());
+
+    root.run(&format!("mount {}", second.display())).unwrap();
+    assert_eq!(root.run("cat b.txt").unwrap(), "on the second image");
+
+    // 不存在的镜像不会替换当前挂载的镜像
+    assert!(root.run("mount /nonexistent/SIMPLE_FS").is_err());
+    assert_eq!(root.run("cat b.txt").unwrap(), "on the second image");
+    drop(root);
+    drop(server);
+    fs::remove_dir_all(second_dir).unwrap();
+}
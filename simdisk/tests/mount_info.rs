@@ -0,0 +1,37 @@
+//! mount-info返回当前镜像的元信息
+mod common;
+
+use std::collections::HashMap;
+
+use common::Server;
+
+/// 把mount-info的输出解析为字段到值的映射
+fn mount_info(server: &Server) -> HashMap<String, String> {
+    let info = server.root().run("mount-info").unwrap();
+    info.lines()
+        .map(|line| {
+            let (key, value) = line.split_once(':').unwrap();
+            (key.to_string(), value.trim().to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn reports_image_metadata() {
+    let server = Server::start(&["--format"]);
+    let info = mount_info(&server);
+    assert!(info["image"].ends_with("SIMPLE_FS"), "{:?}", info);
+    assert_eq!(info["size"], "100.0MiB");
+    assert_eq!(info["layout version"], "2");
+    assert_eq!(info["block size"], "1024B");
+    assert_eq!(info["readonly"], "false");
+    let created = info["created"].clone();
+    assert!(created.ends_with("UTC"), "{}", created);
+
+    // 重新以只读模式挂载同一镜像，创建时间不变
+    let dir = server.stop();
+    let server = Server::start_in(dir, &["--readonly"]);
+    let info = mount_info(&server);
+    assert_eq!(info["readonly"], "true");
+    assert_eq!(info["created"], created);
+}
@@ -0,0 +1,56 @@
+//! 只读模式拒绝修改文件系统的命令，读命令照常执行
+mod common;
+
+use std::fs;
+
+use common::{Client, Server};
+
+/// 在镜像中建立文件后以只读模式重新启动
+fn readonly_server() -> Server {
+    let server = Server::start(&["--format"]);
+    let mut client = server.root();
+    client.run("md docs").unwrap();
+    client
+        .run_with_input("newfile docs/a.txt", "hello\n")
+        .unwrap();
+    drop(client);
+    let dir = server.stop();
+    Server::start_in(dir, &["--readonly"])
+}
+
+#[test]
+fn mutating_commands_fail_and_reads_succeed() {
+    let server = readonly_server();
+    let image = server.dir.join("SIMPLE_FS");
+    let before = fs::read(&image).unwrap();
+    let mut client = server.root();
+
+    assert_eq!(client.run("cat docs/a.txt").unwrap(), "hello\n");
+    assert!(client.run("dir docs").unwrap().contains("a.txt"));
+    for command in [
+        "md x",
+        "del docs/a.txt",
+        "rename docs/a.txt b.txt",
+        "mkfile z 1K",
+    ] {
+        let err = client.run(command).unwrap_err();
+        assert!(err.contains("readonly"), "{}: {}", command, err);
+    }
+    let err = client
+        .run_with_input("newfile docs/b.txt", "new\n")
+        .unwrap_err();
+    assert!(err.contains("readonly"), "{}", err);
+    // find -exec 执行会修改的命令也被拒绝
+    let err = client.run("find docs -name *.txt -exec del").unwrap_err();
+    assert!(err.contains("readonly"), "{}", err);
+    // 注册用户也会修改文件系统
+    let mut anonymous = Client::connect(&server.addr);
+    let reply = anonymous.request("regist\nbob\npw\n");
+    assert!(reply.contains("readonly"), "{}", reply);
+
+    // check只报告不修复
+    client.run("check").unwrap();
+    assert_eq!(client.run("cat docs/a.txt").unwrap(), "hello\n");
+    drop(client);
+    assert!(fs::read(&image).unwrap() == before, "image changed");
+}
@@ -0,0 +1,36 @@
+//! 带请求id的命令重发时只执行一次
+mod common;
+
+use common::Server;
+
+#[test]
+fn resent_append_runs_once() {
+    let server = Server::start(&["--memory", "--format"]);
+    let mut client = server.root();
+    client.run_with_input("newfile log.txt", "first\n").unwrap();
+
+    let append = "REQ#42-1 root ~ append log.txt";
+    client.exchange(append, "second\n").unwrap();
+    // 重发相同的请求id时直接返回缓存的结果，不再要求输入
+    client.exchange(append, "ignored\n").unwrap();
+    assert_eq!(client.run("cat log.txt").unwrap(), "first\nsecond\n");
+
+    // 新的请求id正常执行
+    client
+        .exchange("REQ#42-2 root ~ append log.txt", "third\n")
+        .unwrap();
+    assert_eq!(client.run("cat log.txt").unwrap(), "first\nsecond\nthird\n");
+
+    // 请求id按用户区分，读命令不缓存
+    assert_eq!(
+        client.exchange("REQ#42-3 root ~ cat log.txt", "").unwrap(),
+        "first\nsecond\nthird\n"
+    );
+    client
+        .exchange("REQ#42-4 root ~ append log.txt", "fourth\n")
+        .unwrap();
+    assert!(client
+        .exchange("REQ#42-3 root ~ cat log.txt", "")
+        .unwrap()
+        .ends_with("fourth\n"));
+}
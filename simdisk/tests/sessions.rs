@@ -0,0 +1,41 @@
+//! root用sessions查看所有已登录的连接
+mod common;
+
+use std::{thread, time::Duration};
+
+use common::{Client, Server};
+
+#[test]
+fn root_sees_every_connected_session() {
+    let server = Server::start(&["--memory", "--format"]);
+    let mut anonymous = Client::connect(&server.addr);
+    anonymous.request("regist\nalice\nalice\n");
+    let mut root = server.root();
+    let mut alice = Client::login(&server.addr, "alice", "alice").unwrap();
+
+    let listing = root.run("sessions").unwrap();
+    let lines: Vec<_> = listing.lines().collect();
+    assert_eq!(lines[0], "ADDR\tUSER\tLOGIN");
+    assert_eq!(*lines.last().unwrap(), "2 sessions", "{}", listing);
+    let root_addr = root.stream.local_addr().unwrap().to_string();
+    let alice_addr = alice.stream.local_addr().unwrap().to_string();
+    let session = |addr: &str| lines.iter().find(|line| line.starts_with(addr)).copied();
+    let root_line = session(&root_addr).expect(&listing);
+    assert!(root_line.contains("\troot\t") && root_line.ends_with(" (current)"));
+    let alice_line = session(&alice_addr).expect(&listing);
+    assert!(alice_line.contains("\talice\t") && !alice_line.ends_with(" (current)"));
+    // 没有登录的连接不算会话
+    let anonymous_addr = anonymous.stream.local_addr().unwrap().to_string();
+    assert!(session(&anonymous_addr).is_none(), "{}", listing);
+
+    // 只有root可以查看
+    assert!(alice.run("sessions").is_err());
+    // 断开的连接被移除
+    drop(alice);
+    let mut retries = 0;
+    while !root.run("sessions").unwrap().ends_with("1 sessions") {
+        retries += 1;
+        assert!(retries < 100, "session not removed");
+        thread::sleep(Duration::from_millis(50));
+    }
+}
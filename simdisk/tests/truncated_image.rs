@@ -0,0 +1,41 @@
+//! 镜像比超级块记录的大小小时启动失败，不自动格式化
+mod common;
+
+use std::fs;
+
+use common::{free_addr, Server};
+
+#[test]
+fn truncated_image_fails_with_clear_error() {
+    let server = Server::start(&["--format"]);
+    server.root().run("umount").unwrap();
+    let dir = server.stop();
+    let image = dir.join("SIMPLE_FS");
+    let size = fs::metadata(&image).unwrap().len();
+    let truncated = size / 2;
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&image)
+        .unwrap()
+        .set_len(truncated)
+        .unwrap();
+
+    let mut server = Server::spawn(dir, &[], &free_addr());
+    assert!(!server.wait());
+    let log = server.log();
+    let expected = format!(
+        "image size mismatch, reformat needed: {} is {} bytes, expected {} bytes",
+        image.display(),
+        truncated,
+        size
+    );
+    assert!(log.contains(&expected), "{}", log);
+    // 镜像保持原样，留给用户决定是否格式化
+    assert_eq!(fs::metadata(&image).unwrap().len(), truncated);
+
+    // --format可以重新格式化
+    let dir = server.stop();
+    let server = Server::start_in(dir, &["--format"]);
+    assert!(server.root().run("dir").is_ok());
+    assert_eq!(fs::metadata(&image).unwrap().len(), size);
+}
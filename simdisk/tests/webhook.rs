@@ -0,0 +1,86 @@
+//! 文件系统事件POST到配置的webhook
+mod common;
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Duration,
+};
+
+use common::Server;
+
+/// 本机的mock webhook，依次用statuses中的状态码应答，之后都返回200；
+/// 返回其url和收到的请求体
+fn mock_webhook(statuses: &[u16]) -> (String, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let mut statuses = statuses.to_vec();
+    statuses.reverse();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut reader = BufReader::new(stream.unwrap());
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                let line = line.to_ascii_lowercase();
+                if let Some(value) = line.strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let status = statuses.pop().unwrap_or(200);
+            let response = format!("HTTP/1.1 {} X\r\nContent-Length: 0\r\n\r\n", status);
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+            if sender.send(String::from_utf8(body).unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+    (url, receiver)
+}
+
+fn next_event(receiver: &Receiver<String>) -> serde_json::Value {
+    let body = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    serde_json::from_str(&body).unwrap()
+}
+
+#[test]
+fn create_and_delete_post_events() {
+    let (url, events) = mock_webhook(&[]);
+    let server = Server::start(&["--memory", "--format", "--webhook", &url]);
+    let mut client = server.root();
+
+    client.run_with_input("newfile a.txt", "hello\n").unwrap();
+    let event = next_event(&events);
+    assert_eq!(event["kind"], "create");
+    assert_eq!(event["path"], "~/a.txt");
+    assert_eq!(event["user"], "root");
+    assert!(event["time"].as_u64().unwrap() > 0);
+
+    client.run("del a.txt").unwrap();
+    let event = next_event(&events);
+    assert_eq!(event["kind"], "delete");
+    assert_eq!(event["path"], "~/a.txt");
+}
+
+#[test]
+fn failed_delivery_is_retried() {
+    let (url, events) = mock_webhook(&[500]);
+    let server = Server::start(&["--memory", "--format", "--webhook", &url]);
+    server.root().run("md docs").unwrap();
+
+    // 第一次返回500，重试时收到同样的事件
+    let first = next_event(&events);
+    let second = next_event(&events);
+    assert_eq!(first, second);
+    assert_eq!(first["kind"], "create");
+    assert_eq!(first["path"], "~/docs");
+}